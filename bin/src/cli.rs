@@ -109,6 +109,14 @@ pub enum SubCmd {
         #[clap(long = "hard", help = "do not wait for connections to finish")]
         hard: bool,
     },
+    #[clap(
+        name = "lame-duck",
+        about = "closes the new connections and the sessions after their current request, for rolling restarts"
+    )]
+    LameDuck {
+        #[clap(long = "disable", help = "accept the connections again")]
+        disable: bool,
+    },
     #[clap(name = "upgrade", about = "upgrade the proxy")]
     Upgrade {
         #[clap(long = "worker", help = "upgrade a specific worker")]
@@ -117,6 +125,29 @@ pub enum SubCmd {
 
     #[clap(name = "status", about = "gets information on the running workers")]
     Status,
+    #[clap(
+        name = "sessions",
+        about = "lists the active sessions of the workers, with their readiness and state"
+    )]
+    Sessions,
+    #[clap(
+        name = "route",
+        about = "shows the frontend, cluster and backends a HTTP request would be routed to"
+    )]
+    Route {
+        #[clap(long = "host", help = "hostname of the request")]
+        host: String,
+        #[clap(long = "path", default_value = "/", help = "path of the request")]
+        path: String,
+        #[clap(long = "method", default_value = "GET", help = "method of the request")]
+        method: String,
+        #[clap(
+            short = 'a',
+            long = "address",
+            help = "only show the route of the listener on this address"
+        )]
+        address: Option<SocketAddr>,
+    },
     #[clap(
         name = "metrics",
         about = "gets statistics on the main process and its workers"
@@ -178,7 +209,13 @@ pub enum SubCmd {
         cmd: ConfigCmd,
     },
     #[clap(name = "events", about = "receive sozu events")]
-    Events,
+    Events {
+        #[clap(
+            long = "request-errors",
+            help = "also receive request errors (parsing errors, backend connection errors, timeouts)"
+        )]
+        request_errors: bool,
+    },
 }
 
 #[derive(Subcommand, PartialEq, Eq, Clone, Debug)]
@@ -342,6 +379,17 @@ pub enum BackendCmd {
         sticky_id: Option<String>,
         #[clap(short = 'b', long = "backup", help = "set backend as a backup backend")]
         backup: Option<bool>,
+        #[clap(
+            short = 'w',
+            long = "weight",
+            help = "weight used by the load balancing algorithm (default: 100)"
+        )]
+        weight: Option<u8>,
+        #[clap(
+            long = "max-connections",
+            help = "the backend is not selected while it has this many open connections"
+        )]
+        max_connections: Option<u32>,
     },
 }
 
@@ -430,6 +478,23 @@ pub enum HttpFrontendCmd {
         method: Option<String>,
         #[clap(long = "tags", help = "Specify tag (key-value pair) to apply on front-end (example: 'key=value, other-key=other-value')", value_parser = parse_tags)]
         tags: Option<BTreeMap<String, String>>,
+        #[clap(
+            long = "allowed-methods",
+            help = "answer 405 to requests with another HTTP method (example: 'GET,POST')",
+            value_delimiter = ','
+        )]
+        allowed_methods: Vec<String>,
+        #[clap(
+            long = "head-as-get",
+            help = "forward HEAD requests to the backends as GET, without sending the response body"
+        )]
+        head_as_get: bool,
+        #[clap(
+            long = "websocket-subprotocols",
+            help = "drop the other subprotocols offered by websocket upgrades, answer 400 if none of these is offered (example: 'chat,superchat')",
+            value_delimiter = ','
+        )]
+        websocket_subprotocols: Vec<String>,
     },
     #[clap(name = "remove")]
     Remove {
@@ -476,6 +541,11 @@ pub enum TcpFrontendCmd {
             help = "frontend address, format: IP:port"
         )]
         address: SocketAddr,
+        #[clap(
+            long = "hostname",
+            help = "route the TLS connections with this server name (SNI), without terminating TLS"
+        )]
+        hostname: Option<String>,
         #[clap(
             long = "tags",
             help = "Specify tag (key-value pair) to apply on front-end (example: 'key=value, other-key=other-value')",
@@ -497,6 +567,11 @@ pub enum TcpFrontendCmd {
             help = "frontend address, format: IP:port"
         )]
         address: SocketAddr,
+        #[clap(
+            long = "hostname",
+            help = "server name (SNI) of the frontend to remove"
+        )]
+        hostname: Option<String>,
     },
 }
 
@@ -596,6 +671,11 @@ pub enum HttpListenerCmd {
             help = "listener address, format: IP:port"
         )]
         address: SocketAddr,
+        #[clap(
+            long = "drain",
+            help = "close the sessions of the listener, once their current request is answered with \"Connection: close\""
+        )]
+        drain: bool,
     },
 }
 
@@ -627,6 +707,11 @@ pub enum HttpsListenerCmd {
             help = "List of TLS cipher list to use (TLSv1.2 and TLSv1.3)"
         )]
         cipher_list: Option<Vec<String>>,
+        #[clap(
+            long = "tls-groups-list",
+            help = "List of key exchange groups to use, like x25519, secp256r1 or secp384r1"
+        )]
+        groups_list: Option<Vec<String>>,
         #[clap(
             long = "expect-proxy",
             help = "Configures the client socket to receive a PROXY protocol header"
@@ -681,6 +766,11 @@ pub enum HttpsListenerCmd {
             help = "listener address, format: IP:port"
         )]
         address: SocketAddr,
+        #[clap(
+            long = "drain",
+            help = "close the sessions of the listener, once their current request is answered with \"Connection: close\""
+        )]
+        drain: bool,
     },
 }
 
@@ -761,6 +851,11 @@ Use the --json option to get a much more verbose result, with certificate conten
             help = "Show results for each worker (slower)"
         )]
         query_workers: bool,
+        #[clap(
+            long = "expiring-within",
+            help = "only list the certificates expiring within this number of days"
+        )]
+        expiring_within: Option<u32>,
     },
     #[clap(name = "add", about = "Add a certificate")]
     Add {
@@ -770,12 +865,30 @@ Use the --json option to get a much more verbose result, with certificate conten
             help = "listener address, format: IP:port"
         )]
         address: SocketAddr,
-        #[clap(long = "certificate", help = "path to the certificate")]
-        certificate: String,
-        #[clap(long = "certificate-chain", help = "path to the certificate chain")]
-        chain: String,
-        #[clap(long = "key", help = "path to the key")]
-        key: String,
+        #[clap(
+            long = "certificate",
+            help = "path to the certificate",
+            required_unless_present = "dir"
+        )]
+        certificate: Option<String>,
+        #[clap(
+            long = "certificate-chain",
+            help = "path to the certificate chain",
+            required_unless_present = "dir"
+        )]
+        chain: Option<String>,
+        #[clap(
+            long = "key",
+            help = "path to the key",
+            required_unless_present = "dir"
+        )]
+        key: Option<String>,
+        #[clap(
+            long = "dir",
+            help = "add all the certificates of a directory, each as NAME.crt, NAME.chain.crt and NAME.key",
+            conflicts_with_all = ["certificate", "chain", "key"]
+        )]
+        dir: Option<String>,
         #[clap(long = "tls-versions", help = "accepted TLS versions for this certificate",
                 value_parser = parse_tls_versions)]
         tls_versions: Vec<TlsVersion>,
@@ -826,6 +939,32 @@ Use the --json option to get a much more verbose result, with certificate conten
                 value_parser = parse_tls_versions)]
         tls_versions: Vec<TlsVersion>,
     },
+    #[clap(
+        name = "replace-by-name",
+        about = "Replace the certificate serving a name, the old one is removed if it serves no other name"
+    )]
+    ReplaceByName {
+        #[clap(
+            short = 'a',
+            long = "address",
+            help = "listener address, format: IP:port"
+        )]
+        address: SocketAddr,
+        #[clap(long = "name", help = "the name served by the certificate to replace")]
+        name: String,
+        #[clap(long = "new-certificate", help = "path to the new certificate")]
+        certificate: String,
+        #[clap(
+            long = "new-certificate-chain",
+            help = "path to the new certificate chain"
+        )]
+        chain: String,
+        #[clap(long = "new-key", help = "path to the new key")]
+        key: String,
+        #[clap(long = "tls-versions", help = "accepted TLS versions for this certificate",
+                value_parser = parse_tls_versions)]
+        tls_versions: Vec<TlsVersion>,
+    },
 }
 
 #[derive(Subcommand, PartialEq, Eq, Clone, Debug)]
@@ -217,6 +217,8 @@ pub struct CommandServer {
         ),
     >,
     event_subscribers: HashSet<String>,
+    /// the subscribers that also receive the request level error events
+    request_error_subscribers: HashSet<String>,
     state: ConfigState,
     config: Config,
     /// id of the next worker to be spawned
@@ -289,6 +291,7 @@ impl CommandServer {
             clients: HashMap::new(),
             workers,
             event_subscribers: HashSet::new(),
+            request_error_subscribers: HashSet::new(),
             in_flight: HashMap::new(),
             next_worker_id: next_id,
             executable_path,
@@ -311,6 +314,7 @@ impl CommandServer {
                     debug!("removing client {}", client_id);
                     self.clients.remove(&client_id);
                     self.event_subscribers.remove(&client_id);
+                    self.request_error_subscribers.remove(&client_id);
                     Ok(Success::ClientClose(client_id))
                 }
                 CommandMessage::ClientRequest { client_id, request } => {
@@ -468,6 +472,7 @@ impl CommandServer {
             clients: HashMap::new(),
             workers,
             event_subscribers: HashSet::new(),
+            request_error_subscribers: HashSet::new(),
             in_flight: HashMap::new(),
             next_worker_id: next_id,
             executable_path,
@@ -732,7 +737,12 @@ impl CommandServer {
             content_type: Some(ContentType::Event(event)),
         }) = response.content
         {
-            for client_id in self.event_subscribers.iter() {
+            let subscribers = if event.is_request_error() {
+                &self.request_error_subscribers
+            } else {
+                &self.event_subscribers
+            };
+            for client_id in subscribers.iter() {
                 if let Some(client_tx) = self.clients.get_mut(client_id) {
                     let event = Response::new(
                         ResponseStatus::Processing,
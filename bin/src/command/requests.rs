@@ -1,7 +1,7 @@
 use std::{
     collections::{BTreeMap, HashSet},
     fs::File,
-    io::{ErrorKind, Read},
+    io::{BufReader, ErrorKind},
     os::unix::io::{FromRawFd, IntoRawFd},
     os::unix::net::UnixStream,
     time::{Duration, Instant},
@@ -10,22 +10,20 @@ use std::{
 use anyhow::{bail, Context};
 use async_io::Async;
 use futures::{channel::mpsc::*, SinkExt, StreamExt};
-use nom::{HexDisplay, Offset};
 
 use sozu_command_lib::{
-    buffer::fixed::Buffer,
     config::Config,
     logging,
-    parser::parse_several_requests,
     proto::command::{
         request::RequestType, response_content::ContentType, AggregatedMetrics, AvailableMetrics,
         CertificatesWithFingerprints, ClusterHashes, ClusterInformations, FrontendFilters,
-        MetricsConfiguration, QueryCertificatesFilters, Request, Response, ResponseContent,
-        ResponseStatus, ReturnListenSockets, RunState, SoftStop, Status, WorkerInfo, WorkerInfos,
-        WorkerResponses,
+        MetricsConfiguration, QueryCertificatesFilters, ReloadAnswers, Request, Response,
+        ResponseContent, ResponseStatus, ReturnListenSockets, RunState, SoftStop, Status,
+        WorkerInfo, WorkerInfos, WorkerResponses,
     },
-    request::WorkerRequest,
+    request::{read_requests, WorkerRequest},
     scm_socket::Listeners,
+    state::ConfigState,
 };
 
 use sozu::metrics::METRICS;
@@ -50,7 +48,9 @@ impl CommandServer {
         let result: anyhow::Result<Option<Success>> = match request.request_type {
             Some(RequestType::SaveState(path)) => self.save_state(&path).await,
             Some(RequestType::ListWorkers(_)) => self.list_workers().await,
-            Some(RequestType::ListFrontends(filters)) => self.list_frontends(filters).await,
+            Some(RequestType::ListFrontends(filters)) => {
+                self.list_frontends(client_id, filters).await
+            }
             Some(RequestType::ListListeners(_)) => self.list_listeners(),
             Some(RequestType::LoadState(path)) => self.load_state(Some(client_id), &path).await,
             Some(RequestType::LaunchWorker(tag)) => self.launch_worker(client_id, &tag).await,
@@ -67,8 +67,11 @@ impl CommandServer {
             Some(RequestType::Logging(logging_filter)) => {
                 self.set_logging_level(logging_filter, client_id).await
             }
-            Some(RequestType::SubscribeEvents(_)) => {
+            Some(RequestType::SubscribeEvents(subscribe)) => {
                 self.event_subscribers.insert(client_id.clone());
+                if subscribe.request_errors() {
+                    self.request_error_subscribers.insert(client_id.clone());
+                }
                 Ok(Some(Success::SubscribeEvent(client_id.clone())))
             }
             Some(RequestType::ReloadConfiguration(path)) => {
@@ -83,6 +86,8 @@ impl CommandServer {
             | Some(RequestType::QueryCertificatesFromWorkers(_))
             | Some(RequestType::QueryClustersByDomain(_))
             | Some(RequestType::QueryClustersHashes(_))
+            | Some(RequestType::QuerySessions(_))
+            | Some(RequestType::QueryRouteMatch(_))
             | Some(RequestType::QueryMetrics(_)) => self.query(client_id, request).await,
 
             // any other case is an request for the workers, except for SoftStop and HardStop.
@@ -141,7 +146,7 @@ impl CommandServer {
         client_id: Option<String>,
         path: &str,
     ) -> anyhow::Result<Option<Success>> {
-        let mut file = match File::open(path) {
+        let file = match File::open(path) {
             Ok(file) => file,
             Err(err) if matches!(err.kind(), ErrorKind::NotFound) => {
                 info!("The state file does not exists, skipping the loading.");
@@ -154,87 +159,42 @@ impl CommandServer {
             }
         };
 
-        let mut buffer = Buffer::with_capacity(200000);
-
         info!("starting to load state from {}", path);
 
         let mut message_counter = 0usize;
         let mut diff_counter = 0usize;
 
         let (load_state_tx, mut load_state_rx) = futures::channel::mpsc::channel(10000);
-        loop {
-            let previous = buffer.available_data();
-
-            //FIXME: we should read in streaming here
-            let bytes_read = file
-                .read(buffer.space())
-                .with_context(|| "Error reading the saved state file")?;
-
-            buffer.fill(bytes_read);
-
-            if buffer.available_data() == 0 {
-                debug!("Empty buffer");
-                break;
-            }
-
-            let mut offset = 0usize;
-            match parse_several_requests::<WorkerRequest>(buffer.data()) {
-                Ok((i, requests)) => {
-                    if !i.is_empty() {
-                        debug!("could not parse {} bytes", i.len());
-                        if previous == buffer.available_data() {
-                            bail!("error consuming load state message");
-                        }
-                    }
-                    offset = buffer.data().offset(i);
-
-                    for request in requests {
-                        message_counter += 1;
+        for request in read_requests(BufReader::new(file)) {
+            let request = request.with_context(|| "saved state parse error")?;
+            message_counter += 1;
 
-                        if self.state.dispatch(&request.content).is_ok() {
-                            diff_counter += 1;
+            if self.state.dispatch(&request.content).is_ok() {
+                diff_counter += 1;
 
-                            let mut found = false;
-                            let id = format!("LOAD-STATE-{}-{diff_counter}", request.id);
+                let mut found = false;
+                let id = format!("LOAD-STATE-{}-{diff_counter}", request.id);
 
-                            for worker in
-                                self.workers.iter_mut().filter(|worker| worker.is_active())
-                            {
-                                let worker_message_id = format!("{}-{}", id, worker.id);
-                                worker
-                                    .send(worker_message_id.clone(), request.content.clone())
-                                    .await;
-                                self.in_flight
-                                    .insert(worker_message_id, (load_state_tx.clone(), 1));
-
-                                found = true;
-                            }
+                for worker in self.workers.iter_mut().filter(|worker| worker.is_active()) {
+                    let worker_message_id = format!("{}-{}", id, worker.id);
+                    worker
+                        .send(worker_message_id.clone(), request.content.clone())
+                        .await;
+                    self.in_flight
+                        .insert(worker_message_id, (load_state_tx.clone(), 1));
 
-                            if !found {
-                                bail!("no worker found");
-                            }
-                        }
-                    }
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    if buffer.available_data() == buffer.capacity() {
-                        error!(
-                            "message too big, stopping parsing:\n{}",
-                            buffer.data().to_hex(16)
-                        );
-                        break;
-                    }
+                    found = true;
                 }
-                Err(parse_error) => {
-                    bail!("saved state parse error: {:?}", parse_error);
+
+                if !found {
+                    bail!("no worker found");
                 }
             }
-            buffer.consume(offset);
         }
 
         info!(
-            "stopped loading data from file, remaining: {} bytes, saw {} messages, generated {} diff messages",
-            buffer.available_data(), message_counter, diff_counter
+            "stopped loading data from file, saw {} messages, generated {} diff messages",
+            message_counter, diff_counter
         );
 
         if diff_counter > 0 {
@@ -314,6 +274,7 @@ impl CommandServer {
 
     pub async fn list_frontends(
         &mut self,
+        client_id: String,
         filters: FrontendFilters,
     ) -> anyhow::Result<Option<Success>> {
         info!(
@@ -321,11 +282,71 @@ impl CommandServer {
             filters
         );
 
-        let listed_frontends = self.state.list_frontends(filters);
+        let mut listed_frontends = self.state.list_frontends(filters);
 
-        Ok(Some(Success::ListFrontends(
-            ContentType::FrontendList(listed_frontends).into(),
-        )))
+        // the certificate serving each HTTPS frontend is the one the resolver
+        // of a worker finds for its hostname, the state cannot tell which it is
+        let worker = self
+            .workers
+            .iter_mut()
+            .find(|worker| worker.run_state == RunState::Running);
+        let worker = match worker {
+            Some(worker) if !listed_frontends.https_frontends.is_empty() => worker,
+            _ => {
+                return Ok(Some(Success::ListFrontends(
+                    ContentType::FrontendList(listed_frontends).into(),
+                )))
+            }
+        };
+
+        let (certificates_tx, mut certificates_rx) = futures::channel::mpsc::channel(2);
+        let request_id = format!("{client_id}-frontend-certificates");
+        self.in_flight
+            .insert(request_id.clone(), (certificates_tx, 1));
+        worker
+            .send(
+                request_id,
+                RequestType::QueryCertificatesFromWorkers(QueryCertificatesFilters::default())
+                    .into(),
+            )
+            .await;
+
+        let command_tx = self.command_tx.clone();
+        smol::spawn(async move {
+            while let Some((worker_response, _)) = certificates_rx.next().await {
+                match worker_response.status {
+                    ResponseStatus::Processing => continue,
+                    ResponseStatus::Ok => {
+                        if let Some(ContentType::CertificatesByAddress(resolvers)) = worker_response
+                            .content
+                            .and_then(|content| content.content_type)
+                        {
+                            listed_frontends.https_certificates =
+                                ConfigState::frontend_certificates(
+                                    &listed_frontends.https_frontends,
+                                    &resolvers,
+                                );
+                        }
+                    }
+                    ResponseStatus::Failure => {
+                        error!(
+                            "could not list the certificates of the worker: {}",
+                            worker_response.message
+                        );
+                    }
+                }
+                break;
+            }
+            return_success(
+                command_tx,
+                client_id,
+                Success::ListFrontends(ContentType::FrontendList(listed_frontends).into()),
+            )
+            .await;
+        })
+        .detach();
+
+        Ok(None)
     }
 
     fn list_listeners(&self) -> anyhow::Result<Option<Success>> {
@@ -740,7 +761,21 @@ impl CommandServer {
         )
         .await;
 
-        for request in new_config.generate_config_messages()? {
+        let mut requests = new_config.generate_config_messages()?;
+        // the listeners are already there, but their answer files may have changed
+        let has_answer_files = new_config.http_listeners.iter().any(|listener| {
+            listener.answer_404_path.is_some() || listener.answer_503_path.is_some()
+        }) || new_config.https_listeners.iter().any(|listener| {
+            listener.answer_404_path.is_some() || listener.answer_503_path.is_some()
+        });
+        if has_answer_files {
+            requests.push(WorkerRequest::new(
+                "RELOAD-ANSWERS".to_owned(),
+                RequestType::ReloadAnswers(ReloadAnswers {}).into(),
+            ));
+        }
+
+        for request in requests {
             if self.state.dispatch(&request.content).is_ok() {
                 diff_counter += 1;
 
@@ -1091,6 +1126,12 @@ impl CommandServer {
                     })
                     .into()
                 }
+                &Some(RequestType::QuerySessions(_)) | &Some(RequestType::QueryRouteMatch(_)) => {
+                    ContentType::WorkerResponses(WorkerResponses {
+                        map: worker_responses,
+                    })
+                    .into()
+                }
                 &Some(RequestType::QueryCertificatesFromWorkers(_)) => {
                     info!(
                         "Received a response to the certificates query: {:?}",
@@ -21,9 +21,17 @@ impl CommandManager {
     }
 
     pub fn send_request(&mut self, request: Request) -> Result<(), anyhow::Error> {
-        self.channel
-            .write_message(&request)
-            .with_context(|| "Could not write the request")?;
+        let response = self.send_request_get_response(request)?;
+        if !self.json {
+            info!("{}", response.message);
+        }
+        response.display(self.json)?;
+        Ok(())
+    }
+
+    /// send a request and wait for its final response, without displaying it
+    pub fn send_request_get_response(&mut self, request: Request) -> anyhow::Result<Response> {
+        self.write_request_on_channel(request)?;
 
         loop {
             let response = self.read_channel_message_with_timeout()?;
@@ -35,16 +43,9 @@ impl CommandManager {
                     }
                 }
                 ResponseStatus::Failure => bail!("Request failed: {}", response.message),
-                ResponseStatus::Ok => {
-                    if !self.json {
-                        info!("{}", response.message);
-                    }
-                    response.display(self.json)?;
-                    break;
-                }
+                ResponseStatus::Ok => return Ok(response),
             }
         }
-        Ok(())
     }
 
     // 1. Request a list of workers
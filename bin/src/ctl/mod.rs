@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use sozu_command_lib::{
     channel::Channel,
     config::Config,
@@ -66,11 +66,19 @@ impl CommandManager {
                     self.soft_stop()
                 }
             }
+            SubCmd::LameDuck { disable } => self.lame_duck(!disable),
             SubCmd::Upgrade { worker } => match worker {
                 None => self.upgrade_main(),
                 Some(worker_id) => self.upgrade_worker(worker_id),
             },
             SubCmd::Status {} => self.status(),
+            SubCmd::Sessions {} => self.query_sessions(),
+            SubCmd::Route {
+                host,
+                path,
+                method,
+                address,
+            } => self.query_route_match(host, path, method, address),
             SubCmd::Metrics { cmd } => match cmd {
                 MetricsCmd::Get {
                     list,
@@ -112,15 +120,24 @@ impl CommandManager {
                     certificate,
                     chain,
                     key,
+                    dir,
                     address,
                     tls_versions,
-                } => self.add_certificate(
-                    address.to_string(),
-                    &certificate,
-                    &chain,
-                    &key,
-                    tls_versions,
-                ),
+                } => match (dir, certificate, chain, key) {
+                    (Some(dir), ..) => {
+                        self.add_certificates_from_dir(address.to_string(), &dir, tls_versions)
+                    }
+                    (None, Some(certificate), Some(chain), Some(key)) => self.add_certificate(
+                        address.to_string(),
+                        &certificate,
+                        &chain,
+                        &key,
+                        tls_versions,
+                    ),
+                    _ => {
+                        bail!("Please provide a certificate, its chain and its key, or a directory")
+                    }
+                },
                 CertificateCmd::Remove {
                     certificate,
                     address,
@@ -147,14 +164,30 @@ impl CommandManager {
                     old_fingerprint.as_deref(),
                     tls_versions,
                 ),
+                CertificateCmd::ReplaceByName {
+                    address,
+                    name,
+                    certificate,
+                    chain,
+                    key,
+                    tls_versions,
+                } => self.replace_certificate_by_name(
+                    address.to_string(),
+                    name,
+                    &certificate,
+                    &chain,
+                    &key,
+                    tls_versions,
+                ),
                 CertificateCmd::List {
                     fingerprint,
                     domain,
                     query_workers,
-                } => self.query_certificates(fingerprint, domain, query_workers),
+                    expiring_within,
+                } => self.query_certificates(fingerprint, domain, query_workers, expiring_within),
             },
             SubCmd::Config { cmd: _ } => Ok(()), // noop, handled at the beginning of the method
-            SubCmd::Events => self.events(),
+            SubCmd::Events { request_errors } => self.events(request_errors),
             rest => {
                 panic!("that command should have been handled earlier: {rest:x?}")
             }
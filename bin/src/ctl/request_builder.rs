@@ -1,19 +1,29 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    net::SocketAddr,
+    path::Path,
+};
 
 use anyhow::{bail, Context};
+use prettytable::{row, Table};
+use time::OffsetDateTime;
 
 use sozu_command_lib::{
     certificate::{
-        decode_fingerprint, get_fingerprint_from_certificate_path, load_full_certificate,
+        decode_fingerprint, get_cn_and_san_attributes, get_fingerprint_from_certificate_path,
+        load_full_certificate, parse_pem,
     },
     config::ListenerBuilder,
     proto::command::{
-        request::RequestType, ActivateListener, AddBackend, AddCertificate, Cluster, CountRequests,
-        DeactivateListener, FrontendFilters, HardStop, ListListeners, ListenerType,
-        LoadBalancingParams, MetricsConfiguration, PathRule, ProxyProtocolConfig,
-        QueryCertificatesFilters, QueryClusterByDomain, QueryClustersHashes, RemoveBackend,
-        RemoveCertificate, RemoveListener, ReplaceCertificate, RequestHttpFrontend,
-        RequestTcpFrontend, RulePosition, SoftStop, Status, SubscribeEvents, TlsVersion,
+        request::RequestType, response_content::ContentType, ActivateListener, AddBackend,
+        AddCertificate, CertificateAndKey, Cluster, CountRequests, DeactivateListener,
+        FrontendFilters, HardStop, LameDuck, ListListeners, ListenerType, LoadBalancingParams,
+        MetricsConfiguration, PathRule, ProxyProtocolConfig, QueryCertificatesFilters,
+        QueryClusterByDomain, QueryClustersHashes, QueryRouteMatch, QuerySessions, RemoveBackend,
+        RemoveCertificate, RemoveListener, ReplaceCertificate, ReplaceCertificateByName,
+        RequestHttpFrontend, RequestTcpFrontend, ResponseContent, RulePosition, SoftStop, Status,
+        SubscribeEvents, TlsVersion,
     },
 };
 
@@ -54,12 +64,44 @@ impl CommandManager {
         self.send_request(RequestType::HardStop(HardStop {}).into())
     }
 
+    pub fn lame_duck(&mut self, enabled: bool) -> anyhow::Result<()> {
+        debug!("setting the lame duck mode: {}", enabled);
+
+        self.send_request(RequestType::LameDuck(LameDuck { enabled }).into())
+    }
+
     pub fn status(&mut self) -> anyhow::Result<()> {
         debug!("Requesting status…");
 
         self.send_request(RequestType::Status(Status {}).into())
     }
 
+    pub fn query_sessions(&mut self) -> anyhow::Result<()> {
+        debug!("Requesting the active sessions…");
+
+        self.send_request(RequestType::QuerySessions(QuerySessions {}).into())
+    }
+
+    pub fn query_route_match(
+        &mut self,
+        host: String,
+        path: String,
+        method: String,
+        address: Option<SocketAddr>,
+    ) -> anyhow::Result<()> {
+        debug!("Requesting the route of {} {}{}…", method, host, path);
+
+        self.send_request(
+            RequestType::QueryRouteMatch(QueryRouteMatch {
+                host,
+                path,
+                method,
+                address: address.map(|address| address.to_string()),
+            })
+            .into(),
+        )
+    }
+
     pub fn configure_metrics(&mut self, cmd: MetricsCmd) -> anyhow::Result<()> {
         debug!("Configuring metrics: {:?}", cmd);
 
@@ -102,8 +144,13 @@ impl CommandManager {
         )
     }
 
-    pub fn events(&mut self) -> anyhow::Result<()> {
-        self.send_request(RequestType::SubscribeEvents(SubscribeEvents {}).into())
+    pub fn events(&mut self, request_errors: bool) -> anyhow::Result<()> {
+        self.send_request(
+            RequestType::SubscribeEvents(SubscribeEvents {
+                request_errors: Some(request_errors),
+            })
+            .into(),
+        )
     }
 
     pub fn backend_command(&mut self, cmd: BackendCmd) -> anyhow::Result<()> {
@@ -114,15 +161,18 @@ impl CommandManager {
                 address,
                 sticky_id,
                 backup,
+                weight,
+                max_connections,
             } => self.send_request(
-                RequestType::AddBackend(AddBackend {
-                    cluster_id: id,
-                    address: address.to_string(),
+                RequestType::AddBackend(add_backend(
+                    id,
                     backend_id,
-                    load_balancing_parameters: Some(LoadBalancingParams::default()),
+                    address,
                     sticky_id,
                     backup,
-                })
+                    weight,
+                    max_connections,
+                ))
                 .into(),
             ),
             BackendCmd::Remove {
@@ -207,18 +257,29 @@ impl CommandManager {
 
     pub fn tcp_frontend_command(&mut self, cmd: TcpFrontendCmd) -> anyhow::Result<()> {
         match cmd {
-            TcpFrontendCmd::Add { id, address, tags } => self.send_request(
+            TcpFrontendCmd::Add {
+                id,
+                address,
+                hostname,
+                tags,
+            } => self.send_request(
                 RequestType::AddTcpFrontend(RequestTcpFrontend {
                     cluster_id: id,
                     address: address.to_string(),
                     tags: tags.unwrap_or(BTreeMap::new()),
+                    hostname,
                 })
                 .into(),
             ),
-            TcpFrontendCmd::Remove { id, address } => self.send_request(
+            TcpFrontendCmd::Remove {
+                id,
+                address,
+                hostname,
+            } => self.send_request(
                 RequestType::RemoveTcpFrontend(RequestTcpFrontend {
                     cluster_id: id,
                     address: address.to_string(),
+                    hostname,
                     ..Default::default()
                 })
                 .into(),
@@ -237,6 +298,9 @@ impl CommandManager {
                 method,
                 cluster_id: route,
                 tags,
+                allowed_methods,
+                head_as_get,
+                websocket_subprotocols,
             } => self.send_request(
                 RequestType::AddHttpFrontend(RequestHttpFrontend {
                     cluster_id: route.into(),
@@ -249,6 +313,9 @@ impl CommandManager {
                         Some(tags) => tags,
                         None => BTreeMap::new(),
                     },
+                    allowed_methods,
+                    head_as_get: head_as_get.then_some(true),
+                    websocket_subprotocols,
                 })
                 .into(),
             ),
@@ -285,6 +352,9 @@ impl CommandManager {
                 method,
                 cluster_id: route,
                 tags,
+                allowed_methods,
+                head_as_get,
+                websocket_subprotocols,
             } => self.send_request(
                 RequestType::AddHttpsFrontend(RequestHttpFrontend {
                     cluster_id: route.into(),
@@ -297,6 +367,9 @@ impl CommandManager {
                         Some(tags) => tags,
                         None => BTreeMap::new(),
                     },
+                    allowed_methods,
+                    head_as_get: head_as_get.then_some(true),
+                    websocket_subprotocols,
                 })
                 .into(),
             ),
@@ -331,6 +404,7 @@ impl CommandManager {
                 answer_503,
                 tls_versions,
                 cipher_list,
+                groups_list,
                 expect_proxy,
                 sticky_name,
                 front_timeout,
@@ -338,18 +412,26 @@ impl CommandManager {
                 request_timeout,
                 connect_timeout,
             } => {
-                let https_listener = ListenerBuilder::new_https(address)
+                let mut builder = ListenerBuilder::new_https(address);
+                builder
                     .with_public_address(public_address)
                     .with_answer_404_path(answer_404)
                     .with_answer_503_path(answer_503)
-                    .with_tls_versions(tls_versions)
                     .with_cipher_list(cipher_list)
+                    .with_groups_list(groups_list)
                     .with_expect_proxy(expect_proxy)
                     .with_sticky_name(sticky_name)
                     .with_front_timeout(front_timeout)
                     .with_back_timeout(back_timeout)
                     .with_request_timeout(request_timeout)
-                    .with_connect_timeout(connect_timeout)
+                    .with_connect_timeout(connect_timeout);
+
+                // without --tls-versions, use the default versions
+                if !tls_versions.is_empty() {
+                    builder.with_tls_versions(tls_versions);
+                }
+
+                let https_listener = builder
                     .to_tls(Some(&self.config))
                     .with_context(|| "Error creating HTTPS listener")?;
 
@@ -361,8 +443,8 @@ impl CommandManager {
             HttpsListenerCmd::Activate { address } => {
                 self.activate_listener(address.to_string(), ListenerType::Https)
             }
-            HttpsListenerCmd::Deactivate { address } => {
-                self.deactivate_listener(address.to_string(), ListenerType::Https)
+            HttpsListenerCmd::Deactivate { address, drain } => {
+                self.deactivate_listener(address.to_string(), ListenerType::Https, drain)
             }
         }
     }
@@ -401,8 +483,8 @@ impl CommandManager {
             HttpListenerCmd::Activate { address } => {
                 self.activate_listener(address.to_string(), ListenerType::Http)
             }
-            HttpListenerCmd::Deactivate { address } => {
-                self.deactivate_listener(address.to_string(), ListenerType::Http)
+            HttpListenerCmd::Deactivate { address, drain } => {
+                self.deactivate_listener(address.to_string(), ListenerType::Http, drain)
             }
         }
     }
@@ -429,7 +511,7 @@ impl CommandManager {
                 self.activate_listener(address.to_string(), ListenerType::Tcp)
             }
             TcpListenerCmd::Deactivate { address } => {
-                self.deactivate_listener(address.to_string(), ListenerType::Tcp)
+                self.deactivate_listener(address.to_string(), ListenerType::Tcp, false)
             }
         }
     }
@@ -471,12 +553,14 @@ impl CommandManager {
         &mut self,
         address: String,
         listener_type: ListenerType,
+        drain_sessions: bool,
     ) -> anyhow::Result<()> {
         self.send_request(
             RequestType::DeactivateListener(DeactivateListener {
                 address: address.parse().with_context(|| "wrong socket address")?,
                 proxy: listener_type.into(),
                 to_scm: false,
+                drain_sessions: drain_sessions.then_some(true),
             })
             .into(),
         )
@@ -513,6 +597,51 @@ impl CommandManager {
         )
     }
 
+    /// Add all the certificates found in a directory, see `load_certificates_from_dir`.
+    /// A certificate that can't be loaded or added does not stop the others
+    pub fn add_certificates_from_dir(
+        &mut self,
+        address: String,
+        dir: &str,
+        versions: Vec<TlsVersion>,
+    ) -> anyhow::Result<()> {
+        let certificates = load_certificates_from_dir(dir, versions)?;
+        if certificates.is_empty() {
+            bail!("No certificate found in {}", dir);
+        }
+
+        let total = certificates.len();
+        let mut failures = Vec::new();
+        for (name, certificate) in certificates {
+            let result = certificate.and_then(|certificate| {
+                self.send_request(
+                    RequestType::AddCertificate(AddCertificate {
+                        address: address.clone(),
+                        certificate,
+                        expired_at: None,
+                    })
+                    .into(),
+                )
+            });
+            if let Err(error) = result {
+                failures.push((name, error));
+            }
+        }
+
+        println!(
+            "Added {} certificates out of {}",
+            total - failures.len(),
+            total
+        );
+        if failures.is_empty() {
+            return Ok(());
+        }
+        for (name, error) in &failures {
+            println!("\t{name}: {error:#}");
+        }
+        bail!("{} certificates could not be added", failures.len());
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn replace_certificate(
         &mut self,
@@ -559,6 +688,37 @@ impl CommandManager {
         Ok(())
     }
 
+    pub fn replace_certificate_by_name(
+        &mut self,
+        address: String,
+        name: String,
+        new_certificate_path: &str,
+        new_certificate_chain_path: &str,
+        new_key_path: &str,
+        versions: Vec<TlsVersion>,
+    ) -> anyhow::Result<()> {
+        let new_certificate = load_full_certificate(
+            new_certificate_path,
+            new_certificate_chain_path,
+            new_key_path,
+            versions,
+            vec![],
+        )
+        .with_context(|| "Could not load the full certificate")?;
+
+        self.send_request(
+            RequestType::ReplaceCertificateByName(ReplaceCertificateByName {
+                address,
+                name,
+                new_certificate,
+                new_expired_at: None,
+            })
+            .into(),
+        )?;
+
+        Ok(())
+    }
+
     pub fn remove_certificate(
         &mut self,
         address: String,
@@ -592,16 +752,469 @@ impl CommandManager {
         fingerprint: Option<String>,
         domain: Option<String>,
         query_workers: bool,
+        expiring_within: Option<u32>,
     ) -> Result<(), anyhow::Error> {
         let filters = QueryCertificatesFilters {
             domain,
             fingerprint,
         };
 
-        if query_workers {
-            self.send_request(RequestType::QueryCertificatesFromWorkers(filters).into())
+        let request = if query_workers {
+            RequestType::QueryCertificatesFromWorkers(filters).into()
         } else {
-            self.send_request(RequestType::QueryCertificatesFromTheState(filters).into())
+            RequestType::QueryCertificatesFromTheState(filters).into()
+        };
+
+        if self.json {
+            return self.send_request(request);
+        }
+
+        let response = self.send_request_get_response(request)?;
+        let certificates = response
+            .content
+            .map(certificate_expiries)
+            .unwrap_or_default();
+        match certificate_expiry_table(
+            certificates,
+            OffsetDateTime::now_utc().unix_timestamp(),
+            expiring_within,
+        ) {
+            Some(table) => {
+                table.printstd();
+            }
+            None => println!("No certificates match your request."),
+        }
+        Ok(())
+    }
+}
+
+/// certificates expiring within this number of days are highlighted,
+/// unless `--expiring-within` is given
+const DEFAULT_EXPIRY_WARNING_DAYS: u32 = 30;
+
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+
+/// A certificate as listed by `sozu certificate list`
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CertificateExpiry {
+    names: BTreeSet<String>,
+    /// unix timestamp
+    expires_at: Option<i64>,
+}
+
+/// Gathers the certificates of the state or of the workers, by fingerprint
+fn certificate_expiries(content: ResponseContent) -> BTreeMap<String, CertificateExpiry> {
+    let mut certificates: BTreeMap<String, CertificateExpiry> = BTreeMap::new();
+    match content.content_type {
+        Some(ContentType::CertificatesWithFingerprints(with_fingerprints)) => {
+            for (fingerprint, certificate) in with_fingerprints.certs {
+                let mut expiry = CertificateExpiry {
+                    names: certificate.names.into_iter().collect(),
+                    expires_at: None,
+                };
+                if let Ok(pem) = parse_pem(certificate.certificate.as_bytes()) {
+                    if let Ok(x509) = pem.parse_x509() {
+                        expiry.expires_at = Some(x509.validity().not_after.timestamp());
+                    }
+                    if expiry.names.is_empty() {
+                        expiry.names = get_cn_and_san_attributes(&pem.contents)
+                            .unwrap_or_default()
+                            .into_iter()
+                            .collect();
+                    }
+                }
+                certificates.insert(fingerprint, expiry);
+            }
+        }
+        Some(ContentType::WorkerResponses(worker_responses)) => {
+            for content in worker_responses.map.into_values() {
+                for (fingerprint, expiry) in certificate_expiries(content) {
+                    let entry = certificates.entry(fingerprint).or_default();
+                    entry.names.extend(expiry.names);
+                    entry.expires_at = entry.expires_at.or(expiry.expires_at);
+                }
+            }
+        }
+        Some(ContentType::CertificatesByAddress(by_address)) => {
+            for summary in by_address
+                .certificates
+                .into_iter()
+                .flat_map(|certificates| certificates.certificate_summaries)
+            {
+                let entry = certificates.entry(summary.fingerprint).or_default();
+                entry.names.insert(summary.domain);
+                entry.expires_at = entry.expires_at.or(summary.expired_at);
+            }
+        }
+        _ => {}
+    }
+    certificates
+}
+
+/// "1 day", "2 days"
+fn days(count: u32) -> String {
+    if count == 1 {
+        String::from("1 day")
+    } else {
+        format!("{count} days")
+    }
+}
+
+/// A table of the certificates, sorted by soonest expiry, with a column
+/// highlighting the ones expiring within `expiring_within` days.
+/// If it is set, only those are listed. None if no certificate is listed
+fn certificate_expiry_table(
+    certificates: BTreeMap<String, CertificateExpiry>,
+    now: i64,
+    expiring_within: Option<u32>,
+) -> Option<Table> {
+    let warning_days = expiring_within.unwrap_or(DEFAULT_EXPIRY_WARNING_DAYS);
+    let is_expiring = |expiry: &CertificateExpiry| match expiry.expires_at {
+        Some(expires_at) => expires_at - now <= i64::from(warning_days) * SECONDS_PER_DAY,
+        None => false,
+    };
+
+    let mut certificates: Vec<(String, CertificateExpiry)> = certificates
+        .into_iter()
+        .filter(|(_, expiry)| expiring_within.is_none() || is_expiring(expiry))
+        .collect();
+    if certificates.is_empty() {
+        return None;
+    }
+    certificates.sort_by_key(|(fingerprint, expiry)| {
+        (
+            expiry.expires_at.is_none(),
+            expiry.expires_at,
+            fingerprint.clone(),
+        )
+    });
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_BOX_CHARS);
+    table.add_row(row![
+        "fingerprint",
+        "expires",
+        "days left",
+        format!("within {}", days(warning_days)),
+        "domain names"
+    ]);
+    for (fingerprint, expiry) in &certificates {
+        let (expires, days_left) = match expiry.expires_at {
+            Some(expires_at) => (
+                OffsetDateTime::from_unix_timestamp(expires_at)
+                    .map(|datetime| datetime.date().to_string())
+                    .unwrap_or_else(|_| expires_at.to_string()),
+                if expires_at <= now {
+                    String::from("expired")
+                } else {
+                    (expires_at - now).div_euclid(SECONDS_PER_DAY).to_string()
+                },
+            ),
+            None => (String::from("-"), String::from("-")),
+        };
+        table.add_row(row![
+            fingerprint,
+            expires,
+            days_left,
+            if is_expiring(expiry) { "yes" } else { "" },
+            expiry.names.iter().cloned().collect::<Vec<_>>().join(", ")
+        ]);
+    }
+    Some(table)
+}
+
+/// the weight of the backends added with the command line, like in the configuration file
+const DEFAULT_BACKEND_WEIGHT: u8 = 100;
+
+fn add_backend(
+    cluster_id: String,
+    backend_id: String,
+    address: SocketAddr,
+    sticky_id: Option<String>,
+    backup: Option<bool>,
+    weight: Option<u8>,
+    max_connections: Option<u32>,
+) -> AddBackend {
+    AddBackend {
+        cluster_id,
+        address: address.to_string(),
+        backend_id,
+        load_balancing_parameters: Some(LoadBalancingParams {
+            weight: weight.unwrap_or(DEFAULT_BACKEND_WEIGHT) as i32,
+            max_connections,
+        }),
+        sticky_id,
+        backup,
+        hostname: None,
+    }
+}
+
+/// Pairs the files of a directory: `NAME.crt` is a certificate,
+/// `NAME.chain.crt` its chain and `NAME.key` its key.
+/// Returns each certificate name, sorted, with the result of loading its files
+fn load_certificates_from_dir(
+    dir: &str,
+    versions: Vec<TlsVersion>,
+) -> anyhow::Result<Vec<(String, anyhow::Result<CertificateAndKey>)>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Could not read directory {dir}"))? {
+        let path = entry
+            .with_context(|| format!("Could not read directory {dir}"))?
+            .path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("crt") {
+            continue;
+        }
+        match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) if !name.ends_with(".chain") => names.push(name.to_owned()),
+            _ => {}
         }
     }
+    names.sort();
+
+    let dir = Path::new(dir);
+    let path = |file_name: String| dir.join(file_name).to_string_lossy().into_owned();
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let certificate = load_full_certificate(
+                &path(format!("{name}.crt")),
+                &path(format!("{name}.chain.crt")),
+                &path(format!("{name}.key")),
+                versions.clone(),
+                vec![],
+            )
+            .with_context(|| "Could not load the full certificate");
+            (name, certificate)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use sozu_command_lib::proto::command::{
+        CertificateSummary, CertificatesByAddress, ListOfCertificatesByAddress, WorkerResponses,
+    };
+
+    use super::*;
+    use crate::cli::{Args, SubCmd};
+
+    #[test]
+    fn add_backend_with_weight_and_max_connections() {
+        let args = Args::try_parse_from([
+            "sozu",
+            "backend",
+            "add",
+            "--id",
+            "cluster_1",
+            "--backend-id",
+            "cluster_1-0",
+            "--address",
+            "127.0.0.1:1026",
+            "--weight",
+            "50",
+            "--max-connections",
+            "200",
+        ])
+        .expect("could not parse the arguments");
+
+        let add_backend = match args.cmd {
+            SubCmd::Backend {
+                cmd:
+                    BackendCmd::Add {
+                        id,
+                        backend_id,
+                        address,
+                        sticky_id,
+                        backup,
+                        weight,
+                        max_connections,
+                    },
+            } => add_backend(
+                id,
+                backend_id,
+                address,
+                sticky_id,
+                backup,
+                weight,
+                max_connections,
+            ),
+            other => panic!("unexpected command {other:?}"),
+        };
+
+        assert_eq!(
+            add_backend,
+            AddBackend {
+                cluster_id: String::from("cluster_1"),
+                backend_id: String::from("cluster_1-0"),
+                address: String::from("127.0.0.1:1026"),
+                sticky_id: None,
+                load_balancing_parameters: Some(LoadBalancingParams {
+                    weight: 50,
+                    max_connections: Some(200),
+                }),
+                backup: None,
+                hostname: None,
+            }
+        );
+    }
+
+    #[test]
+    fn load_certificates_from_a_directory() {
+        let dir = tempfile::tempdir().expect("could not create a temporary directory");
+        let certificate = include_str!("../../../lib/assets/certificate.pem");
+        let chain = include_str!("../../../lib/assets/certificate_chain.pem");
+        let key = include_str!("../../../lib/assets/key.pem");
+        for name in ["lolcatho.st", "example.com"] {
+            fs::write(dir.path().join(format!("{name}.crt")), certificate).unwrap();
+            fs::write(dir.path().join(format!("{name}.chain.crt")), chain).unwrap();
+            fs::write(dir.path().join(format!("{name}.key")), key).unwrap();
+        }
+        // a set without its key is reported without stopping the others
+        fs::write(dir.path().join("no-key.crt"), certificate).unwrap();
+        fs::write(dir.path().join("no-key.chain.crt"), chain).unwrap();
+        fs::write(dir.path().join("README"), "not a certificate").unwrap();
+
+        let certificates =
+            load_certificates_from_dir(dir.path().to_str().unwrap(), vec![TlsVersion::TlsV13])
+                .expect("could not read the directory");
+
+        let names: Vec<&str> = certificates.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["example.com", "lolcatho.st", "no-key"]);
+
+        for (_, loaded) in &certificates[..2] {
+            let loaded = loaded.as_ref().expect("could not load the certificate");
+            assert_eq!(loaded.certificate, certificate);
+            assert_eq!(loaded.key, key);
+            assert_eq!(loaded.versions, vec![TlsVersion::TlsV13 as i32]);
+        }
+        assert!(certificates[2].1.is_err());
+    }
+
+    fn certificates_of_a_worker(summaries: Vec<(&str, &str, Option<i64>)>) -> ResponseContent {
+        ContentType::CertificatesByAddress(ListOfCertificatesByAddress {
+            certificates: vec![CertificatesByAddress {
+                address: String::from("127.0.0.1:8443"),
+                certificate_summaries: summaries
+                    .into_iter()
+                    .map(|(fingerprint, domain, expired_at)| CertificateSummary {
+                        domain: domain.to_owned(),
+                        fingerprint: fingerprint.to_owned(),
+                        expired_at,
+                    })
+                    .collect(),
+            }],
+        })
+        .into()
+    }
+
+    #[test]
+    fn format_certificates_by_soonest_expiry() {
+        // 2023-11-14T00:00:00Z
+        let now = 1_699_920_000;
+        let content: ResponseContent = ContentType::WorkerResponses(WorkerResponses {
+            map: BTreeMap::from([
+                (
+                    String::from("0"),
+                    certificates_of_a_worker(vec![
+                        ("aaaa", "lolcatho.st", Some(now + 100 * SECONDS_PER_DAY)),
+                        ("bbbb", "example.com", Some(now - 2 * SECONDS_PER_DAY)),
+                        (
+                            "cccc",
+                            "api.example.com",
+                            Some(now + 10 * SECONDS_PER_DAY + 3600),
+                        ),
+                    ]),
+                ),
+                (
+                    String::from("1"),
+                    certificates_of_a_worker(vec![
+                        ("bbbb", "www.example.com", Some(now - 2 * SECONDS_PER_DAY)),
+                        ("dddd", "unknown.com", None),
+                    ]),
+                ),
+            ]),
+        })
+        .into();
+
+        let rows = |expiring_within| {
+            certificate_expiry_table(certificate_expiries(content.clone()), now, expiring_within)
+                .expect("some certificates should be listed")
+                .row_iter()
+                .map(|row| row.iter().map(|cell| cell.get_content()).collect())
+                .collect::<Vec<Vec<String>>>()
+        };
+
+        assert_eq!(
+            rows(None),
+            vec![
+                vec![
+                    "fingerprint",
+                    "expires",
+                    "days left",
+                    "within 30 days",
+                    "domain names"
+                ],
+                vec![
+                    "bbbb",
+                    "2023-11-12",
+                    "expired",
+                    "yes",
+                    "example.com, www.example.com"
+                ],
+                vec!["cccc", "2023-11-24", "10", "yes", "api.example.com"],
+                vec!["aaaa", "2024-02-22", "100", "", "lolcatho.st"],
+                vec!["dddd", "-", "-", "", "unknown.com"],
+            ]
+        );
+
+        assert_eq!(
+            rows(Some(15)),
+            vec![
+                vec![
+                    "fingerprint",
+                    "expires",
+                    "days left",
+                    "within 15 days",
+                    "domain names"
+                ],
+                vec![
+                    "bbbb",
+                    "2023-11-12",
+                    "expired",
+                    "yes",
+                    "example.com, www.example.com"
+                ],
+                vec!["cccc", "2023-11-24", "10", "yes", "api.example.com"],
+            ]
+        );
+
+        assert_eq!(
+            rows(Some(1)),
+            vec![
+                vec![
+                    "fingerprint",
+                    "expires",
+                    "days left",
+                    "within 1 day",
+                    "domain names"
+                ],
+                vec![
+                    "bbbb",
+                    "2023-11-12",
+                    "expired",
+                    "yes",
+                    "example.com, www.example.com"
+                ],
+            ]
+        );
+
+        // a certificate expiring in a year is not listed with --expiring-within
+        let content = certificates_of_a_worker(vec![(
+            "aaaa",
+            "lolcatho.st",
+            Some(now + 365 * SECONDS_PER_DAY),
+        )]);
+        assert!(certificate_expiry_table(certificate_expiries(content), now, Some(30)).is_none());
+    }
 }
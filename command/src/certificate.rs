@@ -83,6 +83,54 @@ pub fn get_cn_and_san_attributes(pem_bytes: &[u8]) -> Result<HashSet<String>, Ce
     Ok(names)
 }
 
+// -----------------------------------------------------------------------------
+// name_matches
+
+/// whether a certificate name serves a name sent in SNI, a wildcard
+/// certificate name like "*.example.com" matches a single label
+pub fn name_matches(certificate_name: &str, name: &str) -> bool {
+    if certificate_name.eq_ignore_ascii_case(name) {
+        return true;
+    }
+    match (certificate_name.strip_prefix("*."), name.split_once('.')) {
+        (Some(domain), Some((_label, name_domain))) => domain.eq_ignore_ascii_case(name_domain),
+        _ => false,
+    }
+}
+
+/// The certificates of a listener replaced by a certificate for `name`, from the names
+/// of each certificate: the ones having `name` itself or, if there are none, the ones
+/// covering it with a wildcard. The config state and the workers both select them so,
+/// each replaced certificate is then removed if [`is_fully_replaced`]
+pub fn certificates_serving_name<'a>(
+    certificate_names: impl IntoIterator<Item = (&'a str, &'a Fingerprint)>,
+    name: &str,
+) -> HashSet<Fingerprint> {
+    let mut exact = HashSet::new();
+    let mut wildcard = HashSet::new();
+    for (certificate_name, fingerprint) in certificate_names {
+        if certificate_name.eq_ignore_ascii_case(name) {
+            exact.insert(fingerprint.to_owned());
+        } else if name_matches(certificate_name, name) {
+            wildcard.insert(fingerprint.to_owned());
+        }
+    }
+    if exact.is_empty() {
+        wildcard
+    } else {
+        exact
+    }
+}
+
+/// whether a certificate replaced by name is removed: the new certificate must have
+/// all of its names, otherwise it is kept to serve the others
+pub fn is_fully_replaced<'a>(
+    old_names: impl IntoIterator<Item = &'a String>,
+    new_names: &HashSet<String>,
+) -> bool {
+    old_names.into_iter().all(|name| new_names.contains(name))
+}
+
 // -----------------------------------------------------------------------------
 // TlsVersion
 
@@ -62,11 +62,11 @@ use toml;
 use crate::{
     certificate::split_certificate_chain,
     proto::command::{
-        request::RequestType, ActivateListener, AddBackend, AddCertificate, CertificateAndKey,
-        Cluster, HttpListenerConfig, HttpsListenerConfig, ListenerType, LoadBalancingAlgorithms,
-        LoadBalancingParams, LoadMetric, MetricsConfiguration, PathRule, ProxyProtocolConfig,
-        Request, RequestHttpFrontend, RequestTcpFrontend, RulePosition, TcpListenerConfig,
-        TlsVersion,
+        request::RequestType, AccessLogFormat, ActivateListener, AddBackend, AddCertificate,
+        AddedHeader, CertificateAndKey, Cluster, HttpListenerConfig, HttpsListenerConfig,
+        ListenerType, LoadBalancingAlgorithms, LoadBalancingParams, LoadMetric,
+        MetricsConfiguration, PathRule, ProxyProtocolConfig, Request, RequestHttpFrontend,
+        RequestTcpFrontend, RulePosition, TcpListenerConfig, TlsVersion,
     },
     request::WorkerRequest,
     ObjectKind,
@@ -109,7 +109,8 @@ pub const DEFAULT_SIGNATURE_ALGORITHMS: [&str; 9] = [
     "RSA-PSS+SHA512",
 ];
 
-pub const DEFAULT_GROUPS_LIST: [&str; 4] = ["P-521", "P-384", "P-256", "x25519"];
+/// key exchange groups of the HTTPS listeners, in order of preference
+pub const DEFAULT_GROUPS_LIST: [&str; 3] = ["x25519", "P-256", "P-384"];
 
 /// maximum time of inactivity for a frontend socket (60 seconds)
 pub const DEFAULT_FRONT_TIMEOUT: u32 = 60;
@@ -123,6 +124,15 @@ pub const DEFAULT_CONNECT_TIMEOUT: u32 = 3;
 /// maximum time to receive a request since the connection started (10 seconds)
 pub const DEFAULT_REQUEST_TIMEOUT: u32 = 10;
 
+/// maximum length of the URI of a request, answered with a 414 beyond it (8192 bytes)
+pub const DEFAULT_MAX_URI_LENGTH: u32 = 8192;
+
+/// maximum length of a chunk header line, extensions included (1024 bytes)
+pub const DEFAULT_MAX_CHUNK_HEADER_LENGTH: u32 = 1024;
+
+/// maximum size declared by a single chunk of a chunked request (4GiB)
+pub const DEFAULT_MAX_CHUNK_SIZE: u64 = 1 << 32;
+
 /// maximum time to wait for a worker to respond, until it is deemed NotAnswering (10 seconds)
 pub const DEFAULT_WORKER_TIMEOUT: u32 = 10;
 
@@ -132,6 +142,9 @@ pub const DEFAULT_STICKY_NAME: &str = "SOZUBALANCEID";
 /// Interval between checking for zombie sessions, (30 minutes)
 pub const DEFAULT_ZOMBIE_CHECK_INTERVAL: u32 = 1_800;
 
+/// Interval between resolutions of the backend hostnames (60 seconds)
+pub const DEFAULT_DNS_REFRESH_INTERVAL: u32 = 60;
+
 /// timeout to accept connection events in the accept queue (60 seconds)
 pub const DEFAULT_ACCEPT_QUEUE_TIMEOUT: u32 = 60;
 
@@ -162,8 +175,20 @@ pub const DEFAULT_MAX_COMMAND_BUFFER_SIZE: usize = 2_000_000;
 /// wether to avoid register cluster metrics in the local drain
 pub const DEFAULT_DISABLE_CLUSTER_METRICS: bool = false;
 
+/// iterations of the loop handling the events of a session, past which the session
+/// is closed as stuck in an infinite loop. Also bounds the reads and writes of a socket
 pub const MAX_LOOP_ITERATIONS: usize = 100000;
 
+/// longest delay, in seconds, a backend answering 503 can ask with a Retry-After
+/// header before receiving new connections (1 hour)
+pub const DEFAULT_MAX_RETRY_AFTER: u64 = 3600;
+
+/// seconds a backend stays out of the rotation once ejected by outlier detection
+pub const DEFAULT_OUTLIER_EJECTION_TIME: u32 = 30;
+
+/// most connection retries a cluster can set, the sessions count their attempts on a u8
+pub const MAX_CONNECTION_RETRIES: u32 = u8::MAX as u32;
+
 /// Number of TLS 1.3 tickets to send to a client when establishing a connection.
 /// The tickets allow the client to resume a session. This protects the client
 /// agains session tracking. Increases the number of getrandom syscalls,
@@ -228,6 +253,19 @@ pub enum ConfigError {
         expected: ListenerProtocol,
         found: Option<ListenerProtocol>,
     },
+    #[error("the TLS version {0:?} is insecure, it is refused unless allow_insecure_tls_versions is set")]
+    InsecureTlsVersion(TlsVersion),
+    #[error("none of the ciphers {0:?} is supported, the listener would reject all clients")]
+    NoSupportedCipher(Vec<String>),
+    #[error("none of the TLS versions {0:?} is supported, the listener would reject all clients")]
+    NoSupportedTlsVersion(Vec<TlsVersion>),
+    #[error("none of the ciphers {ciphers:?} can be used with the TLS versions {versions:?}, the listener would reject all clients")]
+    NoCipherForTlsVersions {
+        ciphers: Vec<String>,
+        versions: Vec<TlsVersion>,
+    },
+    #[error("the connection_retries of the cluster {cluster_id} must be between 1 and {MAX_CONNECTION_RETRIES}, got {retries}")]
+    InvalidConnectionRetries { cluster_id: String, retries: u32 },
 }
 
 /// An HTTP, HTTPS or TCP listener as parsed from the `Listeners` section in the toml
@@ -244,6 +282,10 @@ pub struct ListenerBuilder {
     pub tls_versions: Option<Vec<TlsVersion>>,
     pub cipher_list: Option<Vec<String>>,
     pub cipher_suites: Option<Vec<String>>,
+    /// key exchange groups offered to the clients, like "x25519" or "secp256r1"
+    pub groups_list: Option<Vec<String>>,
+    /// accept the TLS versions older than TLS 1.2, which are refused by default
+    pub allow_insecure_tls_versions: Option<bool>,
     pub expect_proxy: Option<bool>,
     #[serde(default = "default_sticky_name")]
     pub sticky_name: String,
@@ -264,6 +306,76 @@ pub struct ListenerBuilder {
     /// The ticket allow the client to resume a session. This protects the client
     /// agains session tracking. Defaults to 4.
     pub send_tls13_tickets: Option<u64>,
+    /// maximum size of the TLS 1.3 early data (0-RTT) accepted on session resumption
+    pub max_early_data_size: Option<u32>,
+    /// answer a 421 to the requests whose host is not covered by the certificate
+    /// served for the server name of their TLS connection
+    pub strict_sni_binding: Option<bool>,
+    /// abort the TLS handshake of the clients sending no server name, defaults to true
+    pub require_sni: Option<bool>,
+    /// maximum time to complete the TLS handshake, in seconds
+    pub handshake_timeout: Option<u32>,
+    /// tokens of the Connection header whose named headers should be forwarded
+    /// instead of removed, like "HTTP2-Settings" for h2c upgrades
+    pub preserved_connection_tokens: Option<Vec<String>>,
+    /// check that the backend accepted the websocket key and subprotocol of an upgrade
+    pub validate_websocket_handshake: Option<bool>,
+    /// neither add nor forward the Forwarded header, keep the X-Forwarded-* ones
+    pub omit_forwarded_header: Option<bool>,
+    /// write the client IP address without its port in the Forwarded header
+    pub omit_forwarded_client_port: Option<bool>,
+    /// add a W3C Trace Context "traceparent" header to the requests without a valid one
+    pub trace_context: Option<bool>,
+    /// forward the requests with an absolute URI in origin-form (path and query)
+    pub rewrite_absolute_form: Option<bool>,
+    /// headers to remove before forwarding, in addition to the standard hop-by-hop headers
+    pub hop_by_hop_headers: Option<Vec<String>>,
+    /// maximum size of a decoded chunked request body, answer 413 beyond it
+    pub max_chunked_body_size: Option<u64>,
+    /// maximum length of the chunk header lines, answer 400 beyond it
+    pub max_chunk_header_length: Option<u32>,
+    /// maximum size declared by a chunk header, answer 400 beyond it
+    pub max_chunk_size: Option<u64>,
+    /// maximum number of bytes read from a client socket at once
+    pub max_front_read_size: Option<u32>,
+    /// minimum rate, in bytes per second, of the request headers sent by the clients
+    pub min_request_header_rate: Option<u32>,
+    /// number of pipelined requests parsed in a row at which reading from the clients stops
+    pub max_pipelined_requests: Option<u32>,
+    /// path answered with a 200 by the listener, without going to a cluster
+    pub health_check_path: Option<String>,
+    /// forward the canonical reason phrase of the known status codes instead of the backend's
+    pub normalize_reason_phrase: Option<bool>,
+    /// maximum length of the request URIs, answer 414 beyond it
+    pub max_uri_length: Option<u32>,
+    /// maximum size of the response heads, the backend is closed and a 502 answered beyond it
+    pub max_response_header_size: Option<u32>,
+    /// host routing the HTTP/1.0 requests without a Host header
+    pub http10_default_host: Option<String>,
+    /// number of buffered response bytes at which reading from the backend stops
+    pub back_read_high_watermark: Option<u32>,
+    /// number of buffered response bytes at which reading from the backend resumes
+    pub back_read_low_watermark: Option<u32>,
+    /// write the access log of one successful request out of this many
+    pub access_logs_sampling: Option<u32>,
+    /// format of the access logs, tab separated or RFC 5424 structured data
+    pub access_logs_format: Option<AccessLogFormat>,
+    /// maximum size of a response body delimited by the closing of the backend connection
+    pub max_close_delimited_response_size: Option<u64>,
+    /// size of the queue of connections waiting to be accepted
+    pub listen_backlog: Option<u32>,
+    /// maximum number of sessions open at once on this listener
+    pub max_sessions: Option<u32>,
+    /// network interface the listener is restricted to (Linux only)
+    pub bind_device: Option<String>,
+    /// size of the send buffer (SO_SNDBUF) of the frontend and backend sockets
+    pub send_buffer_size: Option<u32>,
+    /// size of the receive buffer (SO_RCVBUF) of the frontend and backend sockets
+    pub receive_buffer_size: Option<u32>,
+    /// headers whose value is written as REDACTED in the access logs
+    pub access_logs_redacted_headers: Option<Vec<String>>,
+    /// write the query string of the paths as REDACTED in the access logs
+    pub access_logs_redact_query: Option<bool>,
 }
 
 pub fn default_sticky_name() -> String {
@@ -331,6 +443,26 @@ impl ListenerBuilder {
         self
     }
 
+    pub fn with_http10_default_host<S>(&mut self, http10_default_host: Option<S>) -> &mut Self
+    where
+        S: ToString,
+    {
+        if let Some(host) = http10_default_host {
+            self.http10_default_host = Some(host.to_string());
+        }
+        self
+    }
+
+    pub fn with_health_check_path<S>(&mut self, health_check_path: Option<S>) -> &mut Self
+    where
+        S: ToString,
+    {
+        if let Some(path) = health_check_path {
+            self.health_check_path = Some(path.to_string());
+        }
+        self
+    }
+
     pub fn with_answer_503_path<S>(&mut self, answer_503_path: Option<S>) -> &mut Self
     where
         S: ToString,
@@ -356,6 +488,19 @@ impl ListenerBuilder {
         self
     }
 
+    pub fn with_groups_list(&mut self, groups_list: Option<Vec<String>>) -> &mut Self {
+        self.groups_list = groups_list;
+        self
+    }
+
+    pub fn with_allow_insecure_tls_versions(
+        &mut self,
+        allow_insecure_tls_versions: Option<bool>,
+    ) -> &mut Self {
+        self.allow_insecure_tls_versions = allow_insecure_tls_versions;
+        self
+    }
+
     pub fn with_expect_proxy(&mut self, expect_proxy: bool) -> &mut Self {
         self.expect_proxy = Some(expect_proxy);
         self
@@ -412,6 +557,199 @@ impl ListenerBuilder {
         self
     }
 
+    pub fn with_preserved_connection_tokens(
+        &mut self,
+        preserved_connection_tokens: Option<Vec<String>>,
+    ) -> &mut Self {
+        self.preserved_connection_tokens = preserved_connection_tokens;
+        self
+    }
+
+    pub fn with_validate_websocket_handshake(
+        &mut self,
+        validate_websocket_handshake: bool,
+    ) -> &mut Self {
+        self.validate_websocket_handshake = Some(validate_websocket_handshake);
+        self
+    }
+
+    pub fn with_omit_forwarded_header(&mut self, omit_forwarded_header: Option<bool>) -> &mut Self {
+        self.omit_forwarded_header = omit_forwarded_header;
+        self
+    }
+
+    pub fn with_omit_forwarded_client_port(
+        &mut self,
+        omit_forwarded_client_port: bool,
+    ) -> &mut Self {
+        self.omit_forwarded_client_port = Some(omit_forwarded_client_port);
+        self
+    }
+
+    pub fn with_trace_context(&mut self, trace_context: bool) -> &mut Self {
+        self.trace_context = Some(trace_context);
+        self
+    }
+
+    pub fn with_rewrite_absolute_form(&mut self, rewrite_absolute_form: bool) -> &mut Self {
+        self.rewrite_absolute_form = Some(rewrite_absolute_form);
+        self
+    }
+
+    pub fn with_normalize_reason_phrase(&mut self, normalize_reason_phrase: bool) -> &mut Self {
+        self.normalize_reason_phrase = Some(normalize_reason_phrase);
+        self
+    }
+
+    pub fn with_hop_by_hop_headers(
+        &mut self,
+        hop_by_hop_headers: Option<Vec<String>>,
+    ) -> &mut Self {
+        self.hop_by_hop_headers = hop_by_hop_headers;
+        self
+    }
+
+    pub fn with_max_chunked_body_size(&mut self, max_chunked_body_size: Option<u64>) -> &mut Self {
+        self.max_chunked_body_size = max_chunked_body_size;
+        self
+    }
+
+    pub fn with_max_chunk_header_length(
+        &mut self,
+        max_chunk_header_length: Option<u32>,
+    ) -> &mut Self {
+        self.max_chunk_header_length = max_chunk_header_length;
+        self
+    }
+
+    pub fn with_max_chunk_size(&mut self, max_chunk_size: Option<u64>) -> &mut Self {
+        self.max_chunk_size = max_chunk_size;
+        self
+    }
+
+    pub fn with_min_request_header_rate(
+        &mut self,
+        min_request_header_rate: Option<u32>,
+    ) -> &mut Self {
+        self.min_request_header_rate = min_request_header_rate;
+        self
+    }
+
+    pub fn with_max_pipelined_requests(
+        &mut self,
+        max_pipelined_requests: Option<u32>,
+    ) -> &mut Self {
+        self.max_pipelined_requests = max_pipelined_requests;
+        self
+    }
+
+    pub fn with_max_uri_length(&mut self, max_uri_length: Option<u32>) -> &mut Self {
+        self.max_uri_length = max_uri_length;
+        self
+    }
+
+    pub fn with_max_response_header_size(
+        &mut self,
+        max_response_header_size: Option<u32>,
+    ) -> &mut Self {
+        self.max_response_header_size = max_response_header_size;
+        self
+    }
+
+    pub fn with_max_front_read_size(&mut self, max_front_read_size: Option<u32>) -> &mut Self {
+        self.max_front_read_size = max_front_read_size;
+        self
+    }
+
+    pub fn with_back_read_watermarks(
+        &mut self,
+        low_watermark: Option<u32>,
+        high_watermark: Option<u32>,
+    ) -> &mut Self {
+        self.back_read_low_watermark = low_watermark;
+        self.back_read_high_watermark = high_watermark;
+        self
+    }
+
+    pub fn with_access_logs_sampling(&mut self, access_logs_sampling: Option<u32>) -> &mut Self {
+        self.access_logs_sampling = access_logs_sampling;
+        self
+    }
+
+    pub fn with_max_close_delimited_response_size(
+        &mut self,
+        max_close_delimited_response_size: Option<u64>,
+    ) -> &mut Self {
+        self.max_close_delimited_response_size = max_close_delimited_response_size;
+        self
+    }
+
+    pub fn with_access_logs_format(
+        &mut self,
+        access_logs_format: Option<AccessLogFormat>,
+    ) -> &mut Self {
+        self.access_logs_format = access_logs_format;
+        self
+    }
+
+    pub fn with_access_logs_redaction(
+        &mut self,
+        redacted_headers: Option<Vec<String>>,
+        redact_query: bool,
+    ) -> &mut Self {
+        self.access_logs_redacted_headers = redacted_headers;
+        self.access_logs_redact_query = Some(redact_query);
+        self
+    }
+
+    pub fn with_listen_backlog(&mut self, listen_backlog: Option<u32>) -> &mut Self {
+        self.listen_backlog = listen_backlog;
+        self
+    }
+
+    pub fn with_max_sessions(&mut self, max_sessions: Option<u32>) -> &mut Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    pub fn with_bind_device<S>(&mut self, bind_device: Option<S>) -> &mut Self
+    where
+        S: ToString,
+    {
+        self.bind_device = bind_device.map(|device| device.to_string());
+        self
+    }
+
+    pub fn with_send_buffer_size(&mut self, send_buffer_size: Option<u32>) -> &mut Self {
+        self.send_buffer_size = send_buffer_size;
+        self
+    }
+
+    pub fn with_receive_buffer_size(&mut self, receive_buffer_size: Option<u32>) -> &mut Self {
+        self.receive_buffer_size = receive_buffer_size;
+        self
+    }
+
+    pub fn with_max_early_data_size(&mut self, max_early_data_size: Option<u32>) -> &mut Self {
+        self.max_early_data_size = max_early_data_size;
+        self
+    }
+
+    pub fn with_strict_sni_binding(&mut self, strict_sni_binding: Option<bool>) -> &mut Self {
+        self.strict_sni_binding = strict_sni_binding;
+        self
+    }
+
+    pub fn with_require_sni(&mut self, require_sni: Option<bool>) -> &mut Self {
+        self.require_sni = require_sni;
+        self
+    }
+
+    pub fn with_handshake_timeout(&mut self, handshake_timeout: Option<u32>) -> &mut Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
     pub fn parse_address(&self) -> Result<SocketAddr, ConfigError> {
         parse_socket_address(&self.address)
     }
@@ -461,6 +799,46 @@ impl ListenerBuilder {
             request_timeout: self.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
             answer_404,
             answer_503,
+            preserved_connection_tokens: self
+                .preserved_connection_tokens
+                .clone()
+                .unwrap_or_default(),
+            validate_websocket_handshake: self.validate_websocket_handshake.unwrap_or(false),
+            omit_forwarded_header: self.omit_forwarded_header.unwrap_or(false),
+            omit_forwarded_client_port: self.omit_forwarded_client_port.unwrap_or(false),
+            trace_context: self.trace_context.unwrap_or(false),
+            rewrite_absolute_form: self.rewrite_absolute_form.unwrap_or(false),
+            hop_by_hop_headers: self.hop_by_hop_headers.clone().unwrap_or_default(),
+            answer_404_path: self.answer_404.clone(),
+            answer_503_path: self.answer_503.clone(),
+            max_chunked_body_size: self.max_chunked_body_size,
+            max_chunk_header_length: self
+                .max_chunk_header_length
+                .unwrap_or(DEFAULT_MAX_CHUNK_HEADER_LENGTH),
+            max_chunk_size: self.max_chunk_size.unwrap_or(DEFAULT_MAX_CHUNK_SIZE),
+            max_front_read_size: self.max_front_read_size,
+            min_request_header_rate: self.min_request_header_rate,
+            max_pipelined_requests: self.max_pipelined_requests,
+            health_check_path: self.health_check_path.clone(),
+            normalize_reason_phrase: self.normalize_reason_phrase.unwrap_or(false),
+            max_uri_length: self.max_uri_length.unwrap_or(DEFAULT_MAX_URI_LENGTH),
+            max_response_header_size: self.max_response_header_size,
+            http10_default_host: self.http10_default_host.clone(),
+            back_read_high_watermark: self.back_read_high_watermark,
+            back_read_low_watermark: self.back_read_low_watermark,
+            access_logs_sampling: self.access_logs_sampling,
+            access_logs_format: self.access_logs_format.map(|format| format as i32),
+            max_close_delimited_response_size: self.max_close_delimited_response_size,
+            listen_backlog: self.listen_backlog,
+            max_sessions: self.max_sessions,
+            bind_device: self.bind_device.clone(),
+            send_buffer_size: self.send_buffer_size,
+            receive_buffer_size: self.receive_buffer_size,
+            access_logs_redacted_headers: self
+                .access_logs_redacted_headers
+                .clone()
+                .unwrap_or_default(),
+            access_logs_redact_query: self.access_logs_redact_query.unwrap_or(false),
             ..Default::default()
         };
 
@@ -495,12 +873,22 @@ impl ListenerBuilder {
             .map(String::from)
             .collect();
 
-        let groups_list: Vec<String> = DEFAULT_GROUPS_LIST.into_iter().map(String::from).collect();
+        let default_groups_list = DEFAULT_GROUPS_LIST.into_iter().map(String::from).collect();
 
-        let versions = match self.tls_versions {
-            None => vec![TlsVersion::TlsV12 as i32, TlsVersion::TlsV13 as i32],
-            Some(ref v) => v.iter().map(|v| *v as i32).collect(),
-        };
+        let groups_list = self.groups_list.clone().unwrap_or(default_groups_list);
+
+        let versions = self
+            .tls_versions
+            .clone()
+            .unwrap_or_else(|| vec![TlsVersion::TlsV12, TlsVersion::TlsV13]);
+
+        let allow_insecure_tls_versions = self.allow_insecure_tls_versions.unwrap_or(false);
+
+        validate_tls_settings(&cipher_list, &versions)?;
+
+        let versions = apply_tls_versions_floor(versions, allow_insecure_tls_versions)?;
+
+        let versions = versions.iter().map(|v| *v as i32).collect();
 
         let key = self.key.as_ref().and_then(|path| {
             Config::load_file(path)
@@ -568,6 +956,51 @@ impl ListenerBuilder {
             send_tls13_tickets: self
                 .send_tls13_tickets
                 .unwrap_or(DEFAULT_SEND_TLS_13_TICKETS),
+            max_early_data_size: self.max_early_data_size,
+            strict_sni_binding: self.strict_sni_binding.unwrap_or(false),
+            require_sni: self.require_sni.unwrap_or(true),
+            handshake_timeout: self.handshake_timeout,
+            preserved_connection_tokens: self
+                .preserved_connection_tokens
+                .clone()
+                .unwrap_or_default(),
+            validate_websocket_handshake: self.validate_websocket_handshake.unwrap_or(false),
+            omit_forwarded_header: self.omit_forwarded_header.unwrap_or(false),
+            omit_forwarded_client_port: self.omit_forwarded_client_port.unwrap_or(false),
+            trace_context: self.trace_context.unwrap_or(false),
+            rewrite_absolute_form: self.rewrite_absolute_form.unwrap_or(false),
+            hop_by_hop_headers: self.hop_by_hop_headers.clone().unwrap_or_default(),
+            answer_404_path: self.answer_404.clone(),
+            answer_503_path: self.answer_503.clone(),
+            max_chunked_body_size: self.max_chunked_body_size,
+            max_chunk_header_length: self
+                .max_chunk_header_length
+                .unwrap_or(DEFAULT_MAX_CHUNK_HEADER_LENGTH),
+            max_chunk_size: self.max_chunk_size.unwrap_or(DEFAULT_MAX_CHUNK_SIZE),
+            max_front_read_size: self.max_front_read_size,
+            min_request_header_rate: self.min_request_header_rate,
+            max_pipelined_requests: self.max_pipelined_requests,
+            health_check_path: self.health_check_path.clone(),
+            normalize_reason_phrase: self.normalize_reason_phrase.unwrap_or(false),
+            max_uri_length: self.max_uri_length.unwrap_or(DEFAULT_MAX_URI_LENGTH),
+            max_response_header_size: self.max_response_header_size,
+            http10_default_host: self.http10_default_host.clone(),
+            back_read_high_watermark: self.back_read_high_watermark,
+            back_read_low_watermark: self.back_read_low_watermark,
+            access_logs_sampling: self.access_logs_sampling,
+            access_logs_format: self.access_logs_format.map(|format| format as i32),
+            max_close_delimited_response_size: self.max_close_delimited_response_size,
+            listen_backlog: self.listen_backlog,
+            max_sessions: self.max_sessions,
+            bind_device: self.bind_device.clone(),
+            send_buffer_size: self.send_buffer_size,
+            receive_buffer_size: self.receive_buffer_size,
+            access_logs_redacted_headers: self
+                .access_logs_redacted_headers
+                .clone()
+                .unwrap_or_default(),
+            access_logs_redact_query: self.access_logs_redact_query.unwrap_or(false),
+            allow_insecure_tls_versions,
         };
 
         Ok(https_listener_config)
@@ -598,6 +1031,11 @@ impl ListenerBuilder {
             back_timeout: self.back_timeout.unwrap_or(DEFAULT_BACK_TIMEOUT),
             connect_timeout: self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
             active: false,
+            listen_backlog: self.listen_backlog,
+            max_sessions: self.max_sessions,
+            bind_device: self.bind_device.clone(),
+            send_buffer_size: self.send_buffer_size,
+            receive_buffer_size: self.receive_buffer_size,
         })
     }
 
@@ -617,6 +1055,93 @@ impl ListenerBuilder {
     }
 }
 
+/// SSL versions are refused and TLS 1.0 and 1.1 are removed,
+/// unless the listener explicitly allows insecure versions.
+/// The workers apply it again to the listeners they are sent
+pub fn apply_tls_versions_floor(
+    versions: Vec<TlsVersion>,
+    allow_insecure: bool,
+) -> Result<Vec<TlsVersion>, ConfigError> {
+    let is_insecure = |version: &TlsVersion| {
+        matches!(
+            version,
+            TlsVersion::SslV2 | TlsVersion::SslV3 | TlsVersion::TlsV10 | TlsVersion::TlsV11
+        )
+    };
+
+    if allow_insecure {
+        for version in versions.iter().filter(|version| is_insecure(version)) {
+            warn!(
+                "insecure TLS version {:?} is allowed on this listener",
+                version
+            );
+        }
+        return Ok(versions);
+    }
+
+    if let Some(version) = versions
+        .iter()
+        .find(|version| matches!(version, TlsVersion::SslV2 | TlsVersion::SslV3))
+    {
+        return Err(ConfigError::InsecureTlsVersion(*version));
+    }
+
+    Ok(versions
+        .into_iter()
+        .filter(|version| {
+            if is_insecure(version) {
+                warn!(
+                    "TLS version {:?} is below the TLS 1.2 floor, it is ignored",
+                    version
+                );
+                return false;
+            }
+            true
+        })
+        .collect())
+}
+
+/// Check that a TLS listener accepts at least one of the cipher suites and
+/// protocol versions supported by the workers, otherwise it would only fail
+/// at handshake time
+fn validate_tls_settings(
+    cipher_list: &[String],
+    versions: &[TlsVersion],
+) -> Result<(), ConfigError> {
+    let ciphers: Vec<&String> = cipher_list
+        .iter()
+        .filter(|cipher| DEFAULT_RUSTLS_CIPHER_LIST.contains(&cipher.as_str()))
+        .collect();
+    if ciphers.is_empty() {
+        return Err(ConfigError::NoSupportedCipher(cipher_list.to_vec()));
+    }
+
+    let supported_versions: Vec<&TlsVersion> = versions
+        .iter()
+        .filter(|version| matches!(version, TlsVersion::TlsV12 | TlsVersion::TlsV13))
+        .collect();
+    if supported_versions.is_empty() {
+        return Err(ConfigError::NoSupportedTlsVersion(versions.to_vec()));
+    }
+
+    // TLS 1.3 cipher suites can not be negotiated in TLS 1.2, and conversely
+    let usable = ciphers.iter().any(|cipher| {
+        let tls13_cipher = cipher.starts_with("TLS13_");
+        supported_versions.iter().any(|version| match version {
+            TlsVersion::TlsV13 => tls13_cipher,
+            _ => !tls13_cipher,
+        })
+    });
+    if !usable {
+        return Err(ConfigError::NoCipherForTlsVersions {
+            ciphers: cipher_list.to_vec(),
+            versions: versions.to_vec(),
+        });
+    }
+
+    Ok(())
+}
+
 fn parse_socket_address(address: &str) -> Result<SocketAddr, ConfigError> {
     address
         .parse::<SocketAddr>()
@@ -679,13 +1204,20 @@ pub struct FileClusterFrontendConfig {
     #[serde(default)]
     pub position: RulePosition,
     pub tags: Option<BTreeMap<String, String>>,
+    /// if not empty, requests with another method get a 405 answer
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    /// forward the HEAD requests to the backends as GET, without the response body
+    #[serde(default)]
+    pub head_as_get: bool,
+    /// if not empty, the other subprotocols offered by websocket upgrades are dropped,
+    /// upgrades offering none of these get a 400 answer
+    #[serde(default)]
+    pub websocket_subprotocols: Vec<String>,
 }
 
 impl FileClusterFrontendConfig {
     pub fn to_tcp_front(&self) -> Result<TcpFrontendConfig, ConfigError> {
-        if self.hostname.is_some() {
-            return Err(ConfigError::InvalidFrontendConfig("hostname".to_string()));
-        }
         if self.path.is_some() {
             return Err(ConfigError::InvalidFrontendConfig(
                 "path_prefix".to_string(),
@@ -696,9 +1228,6 @@ impl FileClusterFrontendConfig {
                 "certificate".to_string(),
             ));
         }
-        if self.hostname.is_some() {
-            return Err(ConfigError::InvalidFrontendConfig("hostname".to_string()));
-        }
         if self.certificate_chain.is_some() {
             return Err(ConfigError::InvalidFrontendConfig(
                 "certificate_chain".to_string(),
@@ -708,6 +1237,7 @@ impl FileClusterFrontendConfig {
         Ok(TcpFrontendConfig {
             address: self.address,
             tags: self.tags.clone(),
+            hostname: self.hostname.clone(),
         })
     }
 
@@ -764,6 +1294,9 @@ impl FileClusterFrontendConfig {
             path,
             method: self.method.clone(),
             tags: self.tags.clone(),
+            allowed_methods: self.allowed_methods.clone(),
+            head_as_get: self.head_as_get,
+            websocket_subprotocols: self.websocket_subprotocols.clone(),
         })
     }
 }
@@ -798,6 +1331,36 @@ pub struct FileClusterConfig {
     pub answer_503: Option<String>,
     #[serde(default)]
     pub load_metric: Option<LoadMetric>,
+    #[serde(default)]
+    pub expect_continue_authorizations: Option<Vec<String>>,
+    #[serde(default)]
+    pub basic_auth_credentials: Option<Vec<String>>,
+    #[serde(default)]
+    pub bearer_tokens: Option<Vec<String>>,
+    #[serde(default)]
+    pub allowed_ips: Option<Vec<String>>,
+    #[serde(default)]
+    pub denied_ips: Option<Vec<String>>,
+    #[serde(default)]
+    pub ip_filter_trust_forwarded_for: Option<bool>,
+    #[serde(default)]
+    pub suppressed_headers: Option<Vec<AddedHeader>>,
+    #[serde(default)]
+    pub failure_status_codes: Option<Vec<u16>>,
+    #[serde(default)]
+    pub outlier_consecutive_5xx: Option<u32>,
+    #[serde(default)]
+    pub outlier_ejection_time: Option<u32>,
+    #[serde(default)]
+    pub rewrite_location: Option<bool>,
+    #[serde(default)]
+    pub rewrite_cookie_domain: Option<bool>,
+    #[serde(default)]
+    pub cookie_path_prefix: Option<String>,
+    #[serde(default)]
+    pub connection_retries: Option<u32>,
+    #[serde(default)]
+    pub authentication_realm: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -805,9 +1368,11 @@ pub struct FileClusterConfig {
 pub struct BackendConfig {
     pub address: SocketAddr,
     pub weight: Option<u8>,
+    pub max_connections: Option<u32>,
     pub sticky_id: Option<String>,
     pub backup: Option<bool>,
     pub backend_id: Option<String>,
+    pub hostname: Option<String>,
 }
 
 impl FileClusterConfig {
@@ -816,6 +1381,15 @@ impl FileClusterConfig {
         cluster_id: &str,
         expect_proxy: &HashSet<SocketAddr>,
     ) -> Result<ClusterConfig, ConfigError> {
+        if let Some(retries) = self.connection_retries {
+            if !(1..=MAX_CONNECTION_RETRIES).contains(&retries) {
+                return Err(ConfigError::InvalidConnectionRetries {
+                    cluster_id: cluster_id.to_owned(),
+                    retries,
+                });
+            }
+        }
+
         match self.protocol {
             FileClusterProtocolConfig::Tcp => {
                 let mut has_expect_proxy = None;
@@ -866,6 +1440,7 @@ impl FileClusterConfig {
                     proxy_protocol,
                     load_balancing: self.load_balancing,
                     load_metric: self.load_metric,
+                    connection_retries: self.connection_retries,
                 }))
             }
             FileClusterProtocolConfig::Http => {
@@ -893,6 +1468,23 @@ impl FileClusterConfig {
                     load_balancing: self.load_balancing,
                     load_metric: self.load_metric,
                     answer_503,
+                    expect_continue_authorizations: self
+                        .expect_continue_authorizations
+                        .unwrap_or_default(),
+                    basic_auth_credentials: self.basic_auth_credentials.unwrap_or_default(),
+                    bearer_tokens: self.bearer_tokens.unwrap_or_default(),
+                    allowed_ips: self.allowed_ips.unwrap_or_default(),
+                    denied_ips: self.denied_ips.unwrap_or_default(),
+                    ip_filter_trust_forwarded_for: self.ip_filter_trust_forwarded_for,
+                    suppressed_headers: self.suppressed_headers.unwrap_or_default(),
+                    failure_status_codes: self.failure_status_codes.unwrap_or_default(),
+                    outlier_consecutive_5xx: self.outlier_consecutive_5xx,
+                    outlier_ejection_time: self.outlier_ejection_time,
+                    rewrite_location: self.rewrite_location,
+                    rewrite_cookie_domain: self.rewrite_cookie_domain,
+                    cookie_path_prefix: self.cookie_path_prefix,
+                    connection_retries: self.connection_retries,
+                    authentication_realm: self.authentication_realm,
                 }))
             }
         }
@@ -914,6 +1506,12 @@ pub struct HttpFrontendConfig {
     #[serde(default)]
     pub position: RulePosition,
     pub tags: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    #[serde(default)]
+    pub head_as_get: bool,
+    #[serde(default)]
+    pub websocket_subprotocols: Vec<String>,
 }
 
 impl HttpFrontendConfig {
@@ -950,6 +1548,9 @@ impl HttpFrontendConfig {
                     method: self.method.clone(),
                     position: self.position.into(),
                     tags,
+                    allowed_methods: self.allowed_methods.clone(),
+                    head_as_get: self.head_as_get.then_some(true),
+                    websocket_subprotocols: self.websocket_subprotocols.clone(),
                 })
                 .into(),
             );
@@ -964,6 +1565,9 @@ impl HttpFrontendConfig {
                     method: self.method.clone(),
                     position: self.position.into(),
                     tags,
+                    allowed_methods: self.allowed_methods.clone(),
+                    head_as_get: self.head_as_get.then_some(true),
+                    websocket_subprotocols: self.websocket_subprotocols.clone(),
                 })
                 .into(),
             );
@@ -984,6 +1588,36 @@ pub struct HttpClusterConfig {
     pub load_balancing: LoadBalancingAlgorithms,
     pub load_metric: Option<LoadMetric>,
     pub answer_503: Option<String>,
+    #[serde(default)]
+    pub expect_continue_authorizations: Vec<String>,
+    #[serde(default)]
+    pub basic_auth_credentials: Vec<String>,
+    #[serde(default)]
+    pub bearer_tokens: Vec<String>,
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    #[serde(default)]
+    pub denied_ips: Vec<String>,
+    #[serde(default)]
+    pub ip_filter_trust_forwarded_for: Option<bool>,
+    #[serde(default)]
+    pub suppressed_headers: Vec<AddedHeader>,
+    #[serde(default)]
+    pub failure_status_codes: Vec<u16>,
+    #[serde(default)]
+    pub outlier_consecutive_5xx: Option<u32>,
+    #[serde(default)]
+    pub outlier_ejection_time: Option<u32>,
+    #[serde(default)]
+    pub rewrite_location: Option<bool>,
+    #[serde(default)]
+    pub rewrite_cookie_domain: Option<bool>,
+    #[serde(default)]
+    pub cookie_path_prefix: Option<String>,
+    #[serde(default)]
+    pub connection_retries: Option<u32>,
+    #[serde(default)]
+    pub authentication_realm: Option<String>,
 }
 
 impl HttpClusterConfig {
@@ -996,6 +1630,29 @@ impl HttpClusterConfig {
             load_balancing: self.load_balancing as i32,
             answer_503: self.answer_503.clone(),
             load_metric: self.load_metric.map(|s| s as i32),
+            expect_continue_authorizations: self.expect_continue_authorizations.clone(),
+            basic_auth_credentials: self.basic_auth_credentials.clone(),
+            bearer_tokens: self.bearer_tokens.clone(),
+            allowed_ips: self.allowed_ips.clone(),
+            denied_ips: self.denied_ips.clone(),
+            ip_filter_trust_forwarded_for: self.ip_filter_trust_forwarded_for,
+            suppressed_headers: self
+                .suppressed_headers
+                .iter()
+                .map(|header| *header as i32)
+                .collect(),
+            failure_status_codes: self
+                .failure_status_codes
+                .iter()
+                .map(|status| *status as u32)
+                .collect(),
+            outlier_consecutive_5xx: self.outlier_consecutive_5xx,
+            outlier_ejection_time: self.outlier_ejection_time,
+            rewrite_location: self.rewrite_location,
+            rewrite_cookie_domain: self.rewrite_cookie_domain,
+            cookie_path_prefix: self.cookie_path_prefix.clone(),
+            connection_retries: self.connection_retries,
+            authentication_realm: self.authentication_realm.clone(),
         })
         .into()];
 
@@ -1007,6 +1664,7 @@ impl HttpClusterConfig {
         for (backend_count, backend) in self.backends.iter().enumerate() {
             let load_balancing_parameters = Some(LoadBalancingParams {
                 weight: backend.weight.unwrap_or(100) as i32,
+                max_connections: backend.max_connections,
             });
 
             v.push(
@@ -1019,6 +1677,7 @@ impl HttpClusterConfig {
                     load_balancing_parameters,
                     sticky_id: backend.sticky_id.clone(),
                     backup: backend.backup,
+                    hostname: backend.hostname.clone(),
                 })
                 .into(),
             );
@@ -1032,6 +1691,8 @@ impl HttpClusterConfig {
 pub struct TcpFrontendConfig {
     pub address: SocketAddr,
     pub tags: Option<BTreeMap<String, String>>,
+    /// route the TLS connections with this server name (SNI), without terminating TLS
+    pub hostname: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -1043,6 +1704,8 @@ pub struct TcpClusterConfig {
     pub proxy_protocol: Option<ProxyProtocolConfig>,
     pub load_balancing: LoadBalancingAlgorithms,
     pub load_metric: Option<LoadMetric>,
+    #[serde(default)]
+    pub connection_retries: Option<u32>,
 }
 
 impl TcpClusterConfig {
@@ -1055,6 +1718,21 @@ impl TcpClusterConfig {
             load_balancing: self.load_balancing as i32,
             load_metric: self.load_metric.map(|s| s as i32),
             answer_503: None,
+            expect_continue_authorizations: Vec::new(),
+            basic_auth_credentials: Vec::new(),
+            bearer_tokens: Vec::new(),
+            allowed_ips: Vec::new(),
+            denied_ips: Vec::new(),
+            ip_filter_trust_forwarded_for: None,
+            suppressed_headers: Vec::new(),
+            failure_status_codes: Vec::new(),
+            outlier_consecutive_5xx: None,
+            outlier_ejection_time: None,
+            rewrite_location: None,
+            rewrite_cookie_domain: None,
+            cookie_path_prefix: None,
+            connection_retries: self.connection_retries,
+            authentication_realm: None,
         })
         .into()];
 
@@ -1064,6 +1742,7 @@ impl TcpClusterConfig {
                     cluster_id: self.cluster_id.clone(),
                     address: frontend.address.to_string(),
                     tags: frontend.tags.clone().unwrap_or(BTreeMap::new()),
+                    hostname: frontend.hostname.clone(),
                 })
                 .into(),
             );
@@ -1072,6 +1751,7 @@ impl TcpClusterConfig {
         for (backend_count, backend) in self.backends.iter().enumerate() {
             let load_balancing_parameters = Some(LoadBalancingParams {
                 weight: backend.weight.unwrap_or(100) as i32,
+                max_connections: backend.max_connections,
             });
 
             v.push(
@@ -1084,6 +1764,7 @@ impl TcpClusterConfig {
                     load_balancing_parameters,
                     sticky_id: backend.sticky_id.clone(),
                     backup: backend.backup,
+                    hostname: backend.hostname.clone(),
                 })
                 .into(),
             );
@@ -1144,11 +1825,17 @@ pub struct FileConfig {
     #[serde(default)]
     pub zombie_check_interval: Option<u32>,
     #[serde(default)]
+    pub dns_refresh_interval: Option<u32>,
+    #[serde(default)]
     pub accept_queue_timeout: Option<u32>,
     #[serde(default)]
     pub request_timeout: Option<u32>,
     #[serde(default)]
     pub worker_timeout: Option<u32>,
+    #[serde(default)]
+    pub max_loop_iterations: Option<usize>,
+    #[serde(default)]
+    pub max_retry_after: Option<u64>,
 }
 
 impl FileConfig {
@@ -1273,7 +1960,16 @@ impl ConfigBuilder {
             zombie_check_interval: file_config
                 .zombie_check_interval
                 .unwrap_or(DEFAULT_ZOMBIE_CHECK_INTERVAL),
+            dns_refresh_interval: file_config
+                .dns_refresh_interval
+                .unwrap_or(DEFAULT_DNS_REFRESH_INTERVAL),
             worker_timeout: file_config.worker_timeout.unwrap_or(DEFAULT_WORKER_TIMEOUT),
+            max_loop_iterations: file_config
+                .max_loop_iterations
+                .unwrap_or(MAX_LOOP_ITERATIONS),
+            max_retry_after: file_config
+                .max_retry_after
+                .unwrap_or(DEFAULT_MAX_RETRY_AFTER),
             ..Default::default()
         };
 
@@ -1509,12 +2205,18 @@ pub struct Config {
     pub connect_timeout: u32,
     #[serde(default = "default_zombie_check_interval")]
     pub zombie_check_interval: u32,
+    #[serde(default = "default_dns_refresh_interval")]
+    pub dns_refresh_interval: u32,
     #[serde(default = "default_accept_queue_timeout")]
     pub accept_queue_timeout: u32,
     #[serde(default = "default_request_timeout")]
     pub request_timeout: u32,
     #[serde(default = "default_worker_timeout")]
     pub worker_timeout: u32,
+    #[serde(default = "default_max_loop_iterations")]
+    pub max_loop_iterations: usize,
+    #[serde(default = "default_max_retry_after")]
+    pub max_retry_after: u64,
 }
 
 fn default_front_timeout() -> u32 {
@@ -1537,6 +2239,10 @@ fn default_zombie_check_interval() -> u32 {
     DEFAULT_ZOMBIE_CHECK_INTERVAL
 }
 
+fn default_dns_refresh_interval() -> u32 {
+    DEFAULT_DNS_REFRESH_INTERVAL
+}
+
 fn default_accept_queue_timeout() -> u32 {
     DEFAULT_ACCEPT_QUEUE_TIMEOUT
 }
@@ -1549,6 +2255,14 @@ fn default_worker_timeout() -> u32 {
     DEFAULT_WORKER_TIMEOUT
 }
 
+fn default_max_loop_iterations() -> usize {
+    MAX_LOOP_ITERATIONS
+}
+
+fn default_max_retry_after() -> u64 {
+    DEFAULT_MAX_RETRY_AFTER
+}
+
 impl Config {
     /// Parse a TOML file and build a config out of it
     pub fn load_from_path(path: &str) -> Result<Config, ConfigError> {
@@ -1827,6 +2541,172 @@ mod tests {
         println!("conf:\n{encoded}");
     }
 
+    #[test]
+    fn http_listener_defaults() {
+        let http_listener = ListenerBuilder::new_http("127.0.0.1:8080")
+            .to_http(None)
+            .expect("could not build the HTTP listener");
+
+        assert_eq!(http_listener.address, "127.0.0.1:8080");
+        assert_eq!(http_listener.public_address, None);
+        assert_eq!(http_listener.sticky_name, DEFAULT_STICKY_NAME);
+        assert_eq!(http_listener.front_timeout, DEFAULT_FRONT_TIMEOUT);
+        assert_eq!(http_listener.back_timeout, DEFAULT_BACK_TIMEOUT);
+        assert_eq!(http_listener.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+        assert_eq!(http_listener.request_timeout, DEFAULT_REQUEST_TIMEOUT);
+        assert!(!http_listener.expect_proxy);
+        assert!(!http_listener.active);
+    }
+
+    #[test]
+    fn http_listener_with_config_and_overrides() {
+        let config = Config {
+            front_timeout: 12,
+            back_timeout: 13,
+            ..Default::default()
+        };
+
+        let http_listener = ListenerBuilder::new_http("127.0.0.1:8080")
+            .with_public_address(Some("1.2.3.4:80"))
+            .with_back_timeout(Some(42))
+            .with_sticky_name(Some("STICKY"))
+            .to_http(Some(&config))
+            .expect("could not build the HTTP listener");
+
+        assert_eq!(http_listener.public_address, Some("1.2.3.4:80".to_string()));
+        assert_eq!(http_listener.sticky_name, "STICKY");
+        assert_eq!(http_listener.front_timeout, 12);
+        assert_eq!(http_listener.back_timeout, 42);
+    }
+
+    #[test]
+    fn http_listener_validation_errors() {
+        assert!(matches!(
+            ListenerBuilder::new_http("not an address").to_http(None),
+            Err(ConfigError::ParseSocketAddress { .. })
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_http("127.0.0.1:8080")
+                .with_public_address(Some("nowhere"))
+                .to_http(None),
+            Err(ConfigError::ParseSocketAddress { .. })
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443").to_http(None),
+            Err(ConfigError::WrongListenerProtocol {
+                expected: ListenerProtocol::Http,
+                found: Some(ListenerProtocol::Https),
+            })
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_http("127.0.0.1:8080")
+                .with_answer_404_path(Some("/does/not/exist.html"))
+                .to_http(None),
+            Err(ConfigError::FileOpen { .. })
+        ));
+    }
+
+    #[test]
+    fn https_listener_validation_errors() {
+        assert!(ListenerBuilder::new_https("127.0.0.1:8443")
+            .to_tls(None)
+            .is_ok());
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_answer_404_path(Some("/does/not/exist.html"))
+                .to_tls(None),
+            Err(ConfigError::FileOpen { .. })
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_answer_503_path(Some("/"))
+                .to_tls(None),
+            Err(ConfigError::FileRead { .. })
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_cipher_list(Some(vec![]))
+                .to_tls(None),
+            Err(ConfigError::NoSupportedCipher(_))
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_cipher_list(Some(vec!["TLS_RSA_WITH_RC4_128_MD5".to_string()]))
+                .to_tls(None),
+            Err(ConfigError::NoSupportedCipher(_))
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_tls_versions(vec![])
+                .to_tls(None),
+            Err(ConfigError::NoSupportedTlsVersion(_))
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_tls_versions(vec![TlsVersion::SslV3, TlsVersion::TlsV10])
+                .to_tls(None),
+            Err(ConfigError::NoSupportedTlsVersion(_))
+        ));
+
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_tls_versions(vec![TlsVersion::TlsV12])
+                .with_cipher_list(Some(vec!["TLS13_AES_256_GCM_SHA384".to_string()]))
+                .to_tls(None),
+            Err(ConfigError::NoCipherForTlsVersions { .. })
+        ));
+
+        assert!(ListenerBuilder::new_https("127.0.0.1:8443")
+            .with_tls_versions(vec![TlsVersion::TlsV13])
+            .with_cipher_list(Some(vec!["TLS13_AES_256_GCM_SHA384".to_string()]))
+            .to_tls(None)
+            .is_ok());
+    }
+
+    #[test]
+    fn tls_versions_floor() {
+        assert!(matches!(
+            ListenerBuilder::new_https("127.0.0.1:8443")
+                .with_tls_versions(vec![TlsVersion::SslV3, TlsVersion::TlsV12])
+                .to_tls(None),
+            Err(ConfigError::InsecureTlsVersion(TlsVersion::SslV3))
+        ));
+
+        let https_listener = ListenerBuilder::new_https("127.0.0.1:8443")
+            .with_tls_versions(vec![TlsVersion::TlsV12, TlsVersion::TlsV13])
+            .to_tls(None)
+            .expect("could not build the HTTPS listener");
+        assert_eq!(
+            https_listener.versions,
+            vec![TlsVersion::TlsV12 as i32, TlsVersion::TlsV13 as i32]
+        );
+
+        let https_listener = ListenerBuilder::new_https("127.0.0.1:8443")
+            .with_tls_versions(vec![TlsVersion::TlsV11, TlsVersion::TlsV12])
+            .to_tls(None)
+            .expect("could not build the HTTPS listener");
+        assert_eq!(https_listener.versions, vec![TlsVersion::TlsV12 as i32]);
+
+        let https_listener = ListenerBuilder::new_https("127.0.0.1:8443")
+            .with_tls_versions(vec![TlsVersion::SslV3, TlsVersion::TlsV12])
+            .with_allow_insecure_tls_versions(Some(true))
+            .to_tls(None)
+            .expect("could not build the HTTPS listener");
+        assert_eq!(
+            https_listener.versions,
+            vec![TlsVersion::SslV3 as i32, TlsVersion::TlsV12 as i32]
+        );
+    }
+
     #[test]
     fn parse() {
         let path = "assets/config.toml";
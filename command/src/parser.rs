@@ -103,7 +103,7 @@ mod test {
             ),
             WorkerRequest::new(
                 "Some other request".to_string(),
-                RequestType::SubscribeEvents(SubscribeEvents {}).into(),
+                RequestType::SubscribeEvents(SubscribeEvents::default()).into(),
             ),
             WorkerRequest::new(
                 "Yet another request".to_string(),
@@ -13,7 +13,8 @@ use crate::proto::{
         AvailableMetrics, CertificateAndKey, CertificateSummary, CertificatesWithFingerprints,
         ClusterMetrics, FilteredMetrics, ListOfCertificatesByAddress, ListedFrontends,
         ListenersList, QueryCertificatesFilters, RequestCounts, Response, ResponseContent,
-        ResponseStatus, RunState, TlsVersion, WorkerInfos, WorkerMetrics, WorkerResponses,
+        ResponseStatus, RouteMatches, RunState, SessionInfos, TlsVersion, WorkerInfos,
+        WorkerMetrics, WorkerResponses,
     },
     DisplayError,
 };
@@ -80,6 +81,7 @@ pub fn format_request_type(request_type: &RequestType) -> String {
         RequestType::RemoveHttpsFrontend(_) => "RemoveHttpsFrontend".to_owned(),
         RequestType::AddCertificate(_) => "AddCertificate".to_owned(),
         RequestType::ReplaceCertificate(_) => "ReplaceCertificate".to_owned(),
+        RequestType::ReplaceCertificateByName(_) => "ReplaceCertificateByName".to_owned(),
         RequestType::RemoveCertificate(_) => "RemoveCertificate".to_owned(),
         RequestType::AddTcpFrontend(_) => "AddTcpFrontend".to_owned(),
         RequestType::RemoveTcpFrontend(_) => "RemoveTcpFrontend".to_owned(),
@@ -102,6 +104,11 @@ pub fn format_request_type(request_type: &RequestType) -> String {
         RequestType::ReturnListenSockets(_) => "ReturnListenSockets".to_owned(),
         RequestType::QueryCertificatesFromTheState(_) => "QueryCertificatesFromTheState".to_owned(),
         RequestType::QueryCertificatesFromWorkers(_) => "QueryCertificatesFromWorkers".to_owned(),
+        RequestType::ReloadAnswers(_) => "ReloadAnswers".to_owned(),
+        RequestType::QuerySessions(_) => "QuerySessions".to_owned(),
+        RequestType::LameDuck(_) => "LameDuck".to_owned(),
+        RequestType::ApplyConfig(_) => "ApplyConfig".to_owned(),
+        RequestType::QueryRouteMatch(_) => "QueryRouteMatch".to_owned(),
     }
 }
 
@@ -156,6 +163,8 @@ impl ResponseContent {
             ContentType::WorkerMetrics(worker_metrics) => print_worker_metrics(&worker_metrics),
             ContentType::AvailableMetrics(list) => print_available_metrics(&list),
             ContentType::RequestCounts(request_counts) => print_request_counts(&request_counts),
+            ContentType::Sessions(sessions) => print_sessions(sessions),
+            ContentType::RouteMatches(route_matches) => print_route_matches(route_matches),
             ContentType::CertificatesWithFingerprints(certs) => {
                 print_certificates_with_validity(certs)
             }
@@ -434,9 +443,15 @@ fn print_frontends(frontends: &ListedFrontends) -> Result<(), DisplayError> {
             "path",
             "method",
             "position",
-            "tags"
+            "tags",
+            "certificate"
         ]);
-        for https_frontend in frontends.https_frontends.iter() {
+        for (index, https_frontend) in frontends.https_frontends.iter().enumerate() {
+            let certificate = frontends
+                .https_certificates
+                .get(index)
+                .and_then(|certificate| certificate.fingerprint.as_deref())
+                .unwrap_or("-");
             table.add_row(row!(
                 https_frontend
                     .cluster_id
@@ -447,7 +462,8 @@ fn print_frontends(frontends: &ListedFrontends) -> Result<(), DisplayError> {
                 format!("{:?}", https_frontend.path),
                 format!("{:?}", https_frontend.method),
                 format!("{:?}", https_frontend.position),
-                format_tags_to_string(&https_frontend.tags)
+                format_tags_to_string(&https_frontend.tags),
+                certificate
             ));
         }
         table.printstd();
@@ -458,11 +474,12 @@ fn print_frontends(frontends: &ListedFrontends) -> Result<(), DisplayError> {
         let mut table = Table::new();
         table.set_format(*prettytable::format::consts::FORMAT_BOX_CHARS);
         table.add_row(row!["TCP frontends  "]);
-        table.add_row(row!["Cluster ID", "address", "tags"]);
+        table.add_row(row!["Cluster ID", "address", "hostname", "tags"]);
         for tcp_frontend in frontends.tcp_frontends.iter() {
             table.add_row(row!(
                 tcp_frontend.cluster_id,
                 tcp_frontend.address,
+                tcp_frontend.hostname.as_deref().unwrap_or("-"),
                 format_tags_to_string(&tcp_frontend.tags)
             ));
         }
@@ -578,7 +595,7 @@ pub fn print_listeners(listeners_list: &ListenersList) -> Result<(), DisplayErro
 
 fn print_cluster_infos(worker_responses: &WorkerResponses) -> Result<(), DisplayError> {
     let mut cluster_table = create_cluster_table(
-        vec!["id", "sticky_session", "https_redirect"],
+        vec!["id", "sticky_session", "https_redirect", "load_balancing"],
         &worker_responses.map,
     );
 
@@ -595,6 +612,18 @@ fn print_cluster_infos(worker_responses: &WorkerResponses) -> Result<(), Display
         &worker_responses.map,
     );
 
+    let mut listener_table = create_cluster_table(
+        vec![
+            "address",
+            "protocol",
+            "front timeout",
+            "back timeout",
+            "connect timeout",
+            "request timeout",
+        ],
+        &worker_responses.map,
+    );
+
     let worker_ids: HashSet<&String> = worker_responses.map.keys().collect();
 
     let mut cluster_infos = HashMap::new();
@@ -602,6 +631,7 @@ fn print_cluster_infos(worker_responses: &WorkerResponses) -> Result<(), Display
     let mut https_frontends = HashMap::new();
     let mut tcp_frontends = HashMap::new();
     let mut backends = HashMap::new();
+    let mut listeners = HashMap::new();
 
     for (worker_id, response_content) in worker_responses.map.iter() {
         if let Some(ContentType::Clusters(clusters)) = &response_content.content_type {
@@ -630,6 +660,11 @@ fn print_cluster_infos(worker_responses: &WorkerResponses) -> Result<(), Display
                     let entry = backends.entry(backend).or_insert(Vec::new());
                     entry.push(worker_id.to_owned());
                 }
+
+                for listener in cluster.listeners.iter() {
+                    let entry = listeners.entry(listener).or_insert(Vec::new());
+                    entry.push(worker_id.to_owned());
+                }
             }
         }
     }
@@ -658,6 +693,11 @@ fn print_cluster_infos(worker_responses: &WorkerResponses) -> Result<(), Display
             .as_ref()
             .map(|conf| conf.https_redirect)
             .unwrap_or_else(|| false)));
+        row.push(cell!(cluster_info
+            .configuration
+            .as_ref()
+            .map(|conf| format!("{:?}", conf.load_balancing()))
+            .unwrap_or_default()));
 
         for worker in workers_the_cluster_is_present_on {
             if worker_ids.contains(worker) {
@@ -763,6 +803,34 @@ fn print_cluster_infos(worker_responses: &WorkerResponses) -> Result<(), Display
 
     backend_table.printstd();
 
+    println!("\nlisteners configuration:\n");
+
+    for (key, values) in listeners.iter() {
+        let mut row = vec![
+            cell!(key.address),
+            cell!(format!("{:?}", key.listener_type())),
+            cell!(key.front_timeout),
+            cell!(key.back_timeout),
+            cell!(key.connect_timeout),
+            cell!(key
+                .request_timeout
+                .map(|timeout| timeout.to_string())
+                .unwrap_or_default()),
+        ];
+
+        for val in values {
+            if worker_ids.contains(&val) {
+                row.push(cell!("X"));
+            } else {
+                row.push(cell!(""));
+            }
+        }
+
+        listener_table.add_row(Row::new(row));
+    }
+
+    listener_table.printstd();
+
     Ok(())
 }
 
@@ -884,6 +952,95 @@ fn print_request_counts(request_counts: &RequestCounts) -> Result<(), DisplayErr
     Ok(())
 }
 
+fn print_sessions(sessions: &SessionInfos) -> Result<(), DisplayError> {
+    if sessions.vec.is_empty() {
+        return Ok(println!("No active session"));
+    }
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_BOX_CHARS);
+    table.add_row(row![
+        "token",
+        "protocol",
+        "cluster",
+        "backend",
+        "front readiness",
+        "back readiness",
+        "last event (ms)",
+        "request",
+        "response",
+    ]);
+
+    for session in &sessions.vec {
+        table.add_row(row!(
+            session.token,
+            session.protocol,
+            session.cluster_id(),
+            session.backend_id(),
+            session.frontend_readiness(),
+            session.backend_readiness(),
+            session.last_event_age,
+            session.request_state(),
+            session.response_state(),
+        ));
+    }
+    table.printstd();
+    Ok(())
+}
+
+fn print_route_matches(route_matches: &RouteMatches) -> Result<(), DisplayError> {
+    if route_matches.vec.is_empty() {
+        return Ok(println!("No HTTP or HTTPS listener matches this request"));
+    }
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_BOX_CHARS);
+    table.add_row(row![
+        "listener",
+        "protocol",
+        "cluster_id",
+        "hostname",
+        "path",
+        "method",
+        "position",
+        "backends",
+    ]);
+
+    for route_match in &route_matches.vec {
+        let cluster_id = match (&route_match.cluster_id, route_match.deny) {
+            (Some(cluster_id), _) => cluster_id.to_owned(),
+            (None, true) => "Deny".to_owned(),
+            (None, false) => "No route".to_owned(),
+        };
+        let backends: Vec<String> = route_match
+            .backends
+            .iter()
+            .map(|backend| format!("{} {}", backend.backend_id, backend.address))
+            .collect();
+        let (hostname, path, method, position) = match &route_match.frontend {
+            Some(front) => (
+                front.hostname.to_owned(),
+                format!("{:?}", front.path),
+                format!("{:?}", front.method),
+                format!("{:?}", front.position),
+            ),
+            None => Default::default(),
+        };
+        table.add_row(row!(
+            route_match.listener_address,
+            format!("{:?}", route_match.listener_type()),
+            cluster_id,
+            hostname,
+            path,
+            method,
+            position,
+            list_string_vec(&backends),
+        ));
+    }
+    table.printstd();
+    Ok(())
+}
+
 fn format_tags_to_string(tags: &BTreeMap<String, String>) -> String {
     tags.iter()
         .map(|(k, v)| format!("{k}={v}"))
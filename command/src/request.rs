@@ -2,16 +2,12 @@ use std::{
     error,
     fmt::{self, Display},
     fs::File,
-    io::Read,
+    io::{BufRead, BufReader},
     net::SocketAddr,
     str::FromStr,
 };
 
-use nom::{HexDisplay, Offset};
-
 use crate::{
-    buffer::fixed::Buffer,
-    parser::parse_several_requests,
     proto::command::{
         request::RequestType, LoadBalancingAlgorithms, PathRuleKind, Request, RequestHttpFrontend,
         RulePosition,
@@ -49,11 +45,17 @@ impl Request {
                 proxy_destination.to_http_proxy = true
             }
 
+            RequestType::ReloadAnswers(_) | RequestType::QueryRouteMatch(_) => {
+                proxy_destination.to_http_proxy = true;
+                proxy_destination.to_https_proxy = true;
+            }
+
             RequestType::AddHttpsFrontend(_)
             | RequestType::RemoveHttpsFrontend(_)
             | RequestType::AddCertificate(_)
             | RequestType::QueryCertificatesFromWorkers(_)
             | RequestType::ReplaceCertificate(_)
+            | RequestType::ReplaceCertificateByName(_)
             | RequestType::RemoveCertificate(_) => proxy_destination.to_https_proxy = true,
 
             RequestType::AddTcpFrontend(_) | RequestType::RemoveTcpFrontend(_) => {
@@ -70,6 +72,7 @@ impl Request {
             | RequestType::QueryClusterById(_)
             | RequestType::QueryClustersByDomain(_)
             | RequestType::QueryClustersHashes(_)
+            | RequestType::QuerySessions(_)
             | RequestType::QueryMetrics(_)
             | RequestType::Logging(_) => {
                 proxy_destination.to_http_proxy = true;
@@ -78,7 +81,8 @@ impl Request {
             }
 
             // the Add***Listener and other Listener orders will be handled separately
-            // by the notify_proxys function, so we don't give them destinations
+            // by the notify_proxys function, so we don't give them destinations.
+            // Configuration diffs are split by the server into their inner requests
             RequestType::AddHttpsListener(_)
             | RequestType::AddHttpListener(_)
             | RequestType::AddTcpListener(_)
@@ -86,7 +90,9 @@ impl Request {
             | RequestType::ActivateListener(_)
             | RequestType::DeactivateListener(_)
             | RequestType::ConfigureMetrics(_)
-            | RequestType::ReturnListenSockets(_) => {}
+            | RequestType::ReturnListenSockets(_)
+            | RequestType::LameDuck(_)
+            | RequestType::ApplyConfig(_) => {}
 
             // These won't ever reach a worker anyway
             RequestType::SaveState(_)
@@ -112,6 +118,26 @@ impl Request {
             Some(RequestType::SoftStop(_)) | Some(RequestType::HardStop(_))
         )
     }
+
+    /// True if the request adds or removes a frontend, a backend or a certificate,
+    /// which are the only changes accepted in a configuration diff
+    pub fn is_a_config_diff_entry(&self) -> bool {
+        matches!(
+            self.request_type,
+            Some(RequestType::AddHttpFrontend(_))
+                | Some(RequestType::RemoveHttpFrontend(_))
+                | Some(RequestType::AddHttpsFrontend(_))
+                | Some(RequestType::RemoveHttpsFrontend(_))
+                | Some(RequestType::AddTcpFrontend(_))
+                | Some(RequestType::RemoveTcpFrontend(_))
+                | Some(RequestType::AddBackend(_))
+                | Some(RequestType::RemoveBackend(_))
+                | Some(RequestType::AddCertificate(_))
+                | Some(RequestType::ReplaceCertificate(_))
+                | Some(RequestType::ReplaceCertificateByName(_))
+                | Some(RequestType::RemoveCertificate(_))
+        )
+    }
 }
 
 /// This is sent only from Sōzu to Sōzu
@@ -134,52 +160,29 @@ impl fmt::Display for WorkerRequest {
 }
 
 pub fn read_requests_from_file(file: &mut File) -> Result<Vec<WorkerRequest>, RequestError> {
-    let mut acc = Vec::new();
-    let mut buffer = Buffer::with_capacity(200000);
-    loop {
-        let previous = buffer.available_data();
-
-        let bytes_read = file
-            .read(buffer.space())
-            .map_err(|e| RequestError::FileError(e))?;
-
-        buffer.fill(bytes_read);
-
-        if buffer.available_data() == 0 {
-            debug!("Empty buffer");
-            break;
-        }
-
-        let mut offset = 0usize;
-        match parse_several_requests::<WorkerRequest>(buffer.data()) {
-            Ok((i, requests)) => {
-                if !i.is_empty() {
-                    debug!("could not parse {} bytes", i.len());
-                    if previous == buffer.available_data() {
-                        break;
-                    }
-                }
-                offset = buffer.data().offset(i);
+    read_requests(BufReader::new(file)).collect()
+}
 
-                acc.push(requests);
-            }
-            Err(nom::Err::Incomplete(_)) => {
-                if buffer.available_data() == buffer.capacity() {
-                    error!(
-                        "message too big, stopping parsing:\n{}",
-                        buffer.data().to_hex(16)
-                    );
-                    break;
-                }
-            }
-            Err(parse_error) => {
-                return Err(RequestError::ParseError(parse_error.to_string()));
-            }
+/// Reads the requests written by `ConfigState::write_requests`, one JSON request
+/// per line, without loading the whole file in memory.
+/// The NUL separators of the files saved by previous versions are skipped.
+pub fn read_requests<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<WorkerRequest, RequestError>> {
+    reader.lines().filter_map(|line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(io_error) => return Some(Err(RequestError::FileError(io_error))),
+        };
+        let line = line.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if line.is_empty() {
+            return None;
         }
-        buffer.consume(offset);
-    }
-    let requests = acc.into_iter().flatten().collect();
-    Ok(requests)
+        Some(
+            serde_json::from_str::<WorkerRequest>(line)
+                .map_err(|serde_error| RequestError::ParseError(serde_error.to_string())),
+        )
+    })
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -210,6 +213,9 @@ impl RequestHttpFrontend {
                 }
             })?,
             tags: Some(self.tags),
+            allowed_methods: self.allowed_methods,
+            head_as_get: self.head_as_get.unwrap_or(false),
+            websocket_subprotocols: self.websocket_subprotocols,
         })
     }
 }
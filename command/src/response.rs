@@ -2,9 +2,9 @@ use std::{cmp::Ordering, collections::BTreeMap, default::Default, fmt, net::Sock
 
 use crate::{
     proto::command::{
-        AddBackend, FilteredTimeSerie, LoadBalancingParams, PathRule, PathRuleKind,
-        RequestHttpFrontend, RequestTcpFrontend, Response, ResponseContent, ResponseStatus,
-        RulePosition, RunState,
+        AddBackend, Event, EventKind, FilteredTimeSerie, LoadBalancingParams, PathRule,
+        PathRuleKind, RequestHttpFrontend, RequestTcpFrontend, Response, ResponseContent,
+        ResponseStatus, RulePosition, RunState,
     },
     state::ClusterId,
 };
@@ -39,6 +39,18 @@ pub struct HttpFrontend {
     #[serde(default)]
     pub position: RulePosition,
     pub tags: Option<BTreeMap<String, String>>,
+    /// if not empty, requests with another method get a 405 answer
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_methods: Vec<String>,
+    /// forward the HEAD requests to the backends as GET, without the response body
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub head_as_get: bool,
+    /// if not empty, websocket upgrades offering another subprotocol get a 400 answer
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub websocket_subprotocols: Vec<String>,
 }
 
 impl From<HttpFrontend> for RequestHttpFrontend {
@@ -55,6 +67,9 @@ impl From<HttpFrontend> for RequestHttpFrontend {
             method: val.method,
             position: val.position.into(),
             tags,
+            allowed_methods: val.allowed_methods,
+            head_as_get: val.head_as_get.then_some(true),
+            websocket_subprotocols: val.websocket_subprotocols,
         }
     }
 }
@@ -68,6 +83,7 @@ impl From<Backend> for AddBackend {
             sticky_id: val.sticky_id,
             load_balancing_parameters: val.load_balancing_parameters,
             backup: val.backup,
+            hostname: val.hostname,
         }
     }
 }
@@ -148,6 +164,10 @@ pub struct TcpFrontend {
     pub address: SocketAddr,
     /// custom tags to identify the frontend in the access logs
     pub tags: BTreeMap<String, String>,
+    /// server name (SNI) of the TLS connections routed to this frontend
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
 }
 
 impl From<TcpFrontend> for RequestTcpFrontend {
@@ -156,6 +176,7 @@ impl From<TcpFrontend> for RequestTcpFrontend {
             cluster_id: val.cluster_id,
             address: val.address.to_string(),
             tags: val.tags,
+            hostname: val.hostname,
         }
     }
 }
@@ -175,6 +196,9 @@ pub struct Backend {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub backup: Option<bool>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
 }
 
 impl Ord for Backend {
@@ -188,6 +212,7 @@ impl Ord for Backend {
                     .cmp(&o.load_balancing_parameters),
             )
             .then(self.backup.cmp(&o.backup))
+            .then(self.hostname.cmp(&o.hostname))
             .then(socketaddr_cmp(&self.address, &o.address))
     }
 }
@@ -207,10 +232,23 @@ impl Backend {
             backend_id: self.backend_id,
             load_balancing_parameters: self.load_balancing_parameters,
             backup: self.backup,
+            hostname: self.hostname,
         }
     }
 }
 
+impl Event {
+    /// request level errors are only sent to the clients that asked for them
+    pub fn is_request_error(&self) -> bool {
+        matches!(
+            self.kind(),
+            EventKind::RequestParseError
+                | EventKind::BackendConnectionError
+                | EventKind::RequestTimeout
+        )
+    }
+}
+
 impl fmt::Display for RunState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{self:?}")
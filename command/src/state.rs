@@ -5,7 +5,7 @@ use std::{
     },
     fs::File,
     hash::{Hash, Hasher},
-    io::Write,
+    io::{BufWriter, Write},
     iter::{repeat, FromIterator},
     net::SocketAddr,
 };
@@ -13,15 +13,20 @@ use std::{
 use prost::DecodeError;
 
 use crate::{
-    certificate::{self, calculate_fingerprint, Fingerprint},
+    certificate::{
+        self, calculate_fingerprint, certificates_serving_name, is_fully_replaced, name_matches,
+        Fingerprint,
+    },
+    config::MAX_CONNECTION_RETRIES,
     proto::{
         command::{
             request::RequestType, ActivateListener, AddBackend, AddCertificate, CertificateAndKey,
-            Cluster, ClusterInformation, DeactivateListener, FrontendFilters, HttpListenerConfig,
-            HttpsListenerConfig, ListedFrontends, ListenerType, ListenersList, PathRule,
-            QueryCertificatesFilters, RemoveBackend, RemoveCertificate, RemoveListener,
-            ReplaceCertificate, Request, RequestCounts, RequestHttpFrontend, RequestTcpFrontend,
-            TcpListenerConfig,
+            Cluster, ClusterInformation, ClusterListener, ConfigDiff, DeactivateListener,
+            FrontendCertificate, FrontendFilters, HttpListenerConfig, HttpsListenerConfig,
+            LameDuck, ListOfCertificatesByAddress, ListedFrontends, ListenerType, ListenersList,
+            PathRule, QueryCertificatesFilters, RemoveBackend, RemoveCertificate, RemoveListener,
+            ReplaceCertificate, ReplaceCertificateByName, Request, RequestCounts,
+            RequestHttpFrontend, RequestTcpFrontend, TcpListenerConfig,
         },
         display::format_request_type,
     },
@@ -96,6 +101,9 @@ pub struct ConfigState {
     pub certificates: HashMap<SocketAddr, HashMap<Fingerprint, CertificateAndKey>>,
     /// A census of requests that were received. Name of the request -> number of occurences
     pub request_counts: BTreeMap<String, i32>,
+    /// set by a LameDuck request, the workers launched meanwhile start in lame duck mode
+    #[serde(default)]
+    pub lame_duck: bool,
 }
 
 impl ConfigState {
@@ -125,12 +133,20 @@ impl ConfigState {
             RequestType::AddCertificate(add) => self.add_certificate(add),
             RequestType::RemoveCertificate(remove) => self.remove_certificate(remove),
             RequestType::ReplaceCertificate(replace) => self.replace_certificate(replace),
+            RequestType::ReplaceCertificateByName(replace) => {
+                self.replace_certificate_by_name(replace)
+            }
             RequestType::AddHttpsFrontend(front) => self.add_https_frontend(front),
             RequestType::RemoveHttpsFrontend(front) => self.remove_https_frontend(front),
             RequestType::AddTcpFrontend(front) => self.add_tcp_frontend(front),
             RequestType::RemoveTcpFrontend(front) => self.remove_tcp_frontend(front),
             RequestType::AddBackend(add_backend) => self.add_backend(add_backend),
             RequestType::RemoveBackend(backend) => self.remove_backend(backend),
+            RequestType::ApplyConfig(diff) => self.apply_config(diff),
+            RequestType::LameDuck(lame_duck) => {
+                self.lame_duck = lame_duck.enabled;
+                Ok(())
+            }
 
             // This is to avoid the error message
             &RequestType::Logging(_)
@@ -142,8 +158,11 @@ impl ConfigState {
             | &RequestType::QueryClustersByDomain(_)
             | &RequestType::QueryMetrics(_)
             | &RequestType::QueryClustersHashes(_)
+            | &RequestType::QuerySessions(_)
+            | &RequestType::QueryRouteMatch(_)
             | &RequestType::ConfigureMetrics(_)
             | &RequestType::ReturnListenSockets(_)
+            | &RequestType::ReloadAnswers(_)
             | &RequestType::HardStop(_) => Ok(()),
 
             _other_request => Err(StateError::UndispatchableRequest),
@@ -168,6 +187,15 @@ impl ConfigState {
     }
 
     fn add_cluster(&mut self, cluster: &Cluster) -> Result<(), StateError> {
+        if let Some(retries) = cluster.connection_retries {
+            if !(1..=MAX_CONNECTION_RETRIES).contains(&retries) {
+                return Err(StateError::WrongRequest(format!(
+                    "the connection_retries of the cluster {} must be between 1 and {MAX_CONNECTION_RETRIES}, got {retries}",
+                    cluster.cluster_id
+                )));
+            }
+        }
+
         let cluster = cluster.clone();
         self.clusters.insert(cluster.cluster_id.clone(), cluster);
         Ok(())
@@ -376,6 +404,32 @@ impl ConfigState {
         Ok(())
     }
 
+    /// Applies all the requests of the diff, or none of them if one fails
+    fn apply_config(&mut self, diff: &ConfigDiff) -> Result<(), StateError> {
+        let initial_state = self.clone();
+
+        for request in &diff.requests {
+            let result = if request.is_a_config_diff_entry() {
+                self.dispatch(request)
+            } else {
+                Err(StateError::WrongRequest(format!(
+                    "{} can not be part of a configuration diff",
+                    request
+                        .request_type
+                        .as_ref()
+                        .map(format_request_type)
+                        .unwrap_or_default()
+                )))
+            };
+
+            if let Err(error) = result {
+                *self = initial_state;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
     fn add_certificate(&mut self, add: &AddCertificate) -> Result<(), StateError> {
         let fingerprint = Fingerprint(
             calculate_fingerprint(add.certificate.certificate.as_bytes()).map_err(
@@ -464,25 +518,107 @@ impl ConfigState {
         Ok(())
     }
 
-    fn add_tcp_frontend(&mut self, front: &RequestTcpFrontend) -> Result<(), StateError> {
-        let tcp_frontends = self
-            .tcp_fronts
-            .entry(front.cluster_id.clone())
-            .or_insert_with(Vec::new);
+    /// - find the certificate the workers present for the name
+    /// - insert the new certificate, which has to serve the name
+    /// - remove the old certificate if the new one serves all its names
+    fn replace_certificate_by_name(
+        &mut self,
+        replace: &ReplaceCertificateByName,
+    ) -> Result<(), StateError> {
+        let address = parse_socket_address(&replace.address)?;
+
+        let new_fingerprint = Fingerprint(
+            calculate_fingerprint(replace.new_certificate.certificate.as_bytes()).map_err(
+                |fingerprint_err| StateError::ReplaceCertificate(fingerprint_err.to_string()),
+            )?,
+        );
+        let new_names: HashSet<String> = certificate_names(&replace.new_certificate)
+            .into_iter()
+            .collect();
+        if !new_names
+            .iter()
+            .any(|name| name_matches(name, &replace.name))
+        {
+            return Err(StateError::ReplaceCertificate(format!(
+                "the new certificate does not serve the name {}",
+                replace.name
+            )));
+        }
+
+        // the certificates are selected and removed like in the TLS resolvers of the workers
+        let certificates = self.certificates.entry(address).or_default();
+        let names: Vec<(&Fingerprint, Vec<String>)> = certificates
+            .iter()
+            .map(|(fingerprint, certificate_and_key)| {
+                (fingerprint, certificate_names(certificate_and_key))
+            })
+            .collect();
+        let removed_fingerprints: Vec<Fingerprint> = certificates_serving_name(
+            names.iter().flat_map(|(fingerprint, names)| {
+                names.iter().map(move |name| (name.as_str(), *fingerprint))
+            }),
+            &replace.name,
+        )
+        .into_iter()
+        .filter(|old_fingerprint| *old_fingerprint != new_fingerprint)
+        .filter(|old_fingerprint| {
+            names
+                .iter()
+                .find(|(fingerprint, _)| *fingerprint == old_fingerprint)
+                .map(|(_, old_names)| is_fully_replaced(old_names, &new_names))
+                .unwrap_or(false)
+        })
+        .collect();
+
+        for old_fingerprint in removed_fingerprints {
+            certificates.remove(&old_fingerprint);
+        }
+
+        certificates.insert(new_fingerprint, replace.new_certificate.clone());
+        Ok(())
+    }
 
+    fn add_tcp_frontend(&mut self, front: &RequestTcpFrontend) -> Result<(), StateError> {
         let tcp_frontend = TcpFrontend {
             cluster_id: front.cluster_id.clone(),
             address: parse_socket_address(&front.address)?,
             tags: front.tags.clone(),
+            hostname: front.hostname.clone(),
         };
-        if tcp_frontends.contains(&tcp_frontend) {
+        if self
+            .tcp_fronts
+            .get(&front.cluster_id)
+            .map(|tcp_frontends| tcp_frontends.contains(&tcp_frontend))
+            .unwrap_or(false)
+        {
             return Err(StateError::Exists {
                 kind: ObjectKind::TcpFrontend,
                 id: format!("{:?}", tcp_frontend),
             });
         }
 
-        tcp_frontends.push(tcp_frontend);
+        // a server name routes the connections of a listener to a single cluster
+        if let Some(hostname) = &tcp_frontend.hostname {
+            let is_duplicate = self.tcp_fronts.values().flatten().any(|existing| {
+                existing.address == tcp_frontend.address
+                    && existing
+                        .hostname
+                        .as_ref()
+                        .map(|existing| existing.eq_ignore_ascii_case(hostname))
+                        .unwrap_or(false)
+            });
+            if is_duplicate {
+                return Err(StateError::Exists {
+                    kind: ObjectKind::TcpFrontend,
+                    id: format!("{:?}", tcp_frontend),
+                });
+            }
+        }
+
+        self.tcp_fronts
+            .entry(front.cluster_id.clone())
+            .or_default()
+            .push(tcp_frontend);
         Ok(())
     }
 
@@ -501,7 +637,8 @@ impl ConfigState {
                 })?;
 
         let len = tcp_frontends.len();
-        tcp_frontends.retain(|front| front.address != address);
+        tcp_frontends
+            .retain(|front| front.address != address || front.hostname != front_to_remove.hostname);
         if tcp_frontends.len() == len {
             return Err(StateError::NoChange);
         }
@@ -516,6 +653,7 @@ impl ConfigState {
             sticky_id: add_backend.sticky_id.clone(),
             load_balancing_parameters: add_backend.load_balancing_parameters.clone(),
             backup: add_backend.backup,
+            hostname: add_backend.hostname.clone(),
         };
         let backends = self
             .backends
@@ -551,88 +689,97 @@ impl ConfigState {
     }
 
     pub fn generate_requests(&self) -> Vec<Request> {
-        let mut v: Vec<Request> = Vec::new();
-
-        for listener in self.http_listeners.values() {
-            v.push(RequestType::AddHttpListener(listener.clone()).into());
-            if listener.active {
-                v.push(
-                    RequestType::ActivateListener(ActivateListener {
-                        address: listener.address.clone(),
-                        proxy: ListenerType::Http.into(),
-                        from_scm: false,
-                    })
-                    .into(),
-                );
-            }
-        }
+        self.requests().collect()
+    }
 
-        for listener in self.https_listeners.values() {
-            v.push(RequestType::AddHttpsListener(listener.clone()).into());
-            if listener.active {
-                v.push(
-                    RequestType::ActivateListener(ActivateListener {
-                        address: listener.address.clone(),
-                        proxy: ListenerType::Https.into(),
-                        from_scm: false,
-                    })
-                    .into(),
-                );
-            }
+    /// the requests necessary to recreate the state, generated one at a time
+    /// so that a large state can be written without copying it whole
+    pub fn requests(&self) -> impl Iterator<Item = Request> + '_ {
+        fn add_listener(
+            add: RequestType,
+            address: &str,
+            active: bool,
+            proxy: ListenerType,
+        ) -> impl Iterator<Item = Request> {
+            let activate = active.then(|| {
+                Request::from(RequestType::ActivateListener(ActivateListener {
+                    address: address.to_owned(),
+                    proxy: proxy.into(),
+                    from_scm: false,
+                }))
+            });
+            std::iter::once(Request::from(add)).chain(activate)
         }
 
-        for listener in self.tcp_listeners.values() {
-            v.push(RequestType::AddTcpListener(listener.clone()).into());
-            if listener.active {
-                v.push(
-                    RequestType::ActivateListener(ActivateListener {
-                        address: listener.address.clone(),
-                        proxy: ListenerType::Tcp.into(),
-                        from_scm: false,
-                    })
-                    .into(),
-                );
-            }
-        }
+        let http_listeners = self.http_listeners.values().flat_map(|listener| {
+            add_listener(
+                RequestType::AddHttpListener(listener.clone()),
+                &listener.address,
+                listener.active,
+                ListenerType::Http,
+            )
+        });
+        let https_listeners = self.https_listeners.values().flat_map(|listener| {
+            add_listener(
+                RequestType::AddHttpsListener(listener.clone()),
+                &listener.address,
+                listener.active,
+                ListenerType::Https,
+            )
+        });
+        let tcp_listeners = self.tcp_listeners.values().flat_map(|listener| {
+            add_listener(
+                RequestType::AddTcpListener(listener.clone()),
+                &listener.address,
+                listener.active,
+                ListenerType::Tcp,
+            )
+        });
 
-        for cluster in self.clusters.values() {
-            v.push(RequestType::AddCluster(cluster.clone()).into());
-        }
+        let clusters = self
+            .clusters
+            .values()
+            .map(|cluster| Request::from(RequestType::AddCluster(cluster.clone())));
 
-        for front in self.http_fronts.values() {
-            v.push(RequestType::AddHttpFrontend(front.clone().into()).into());
-        }
+        let http_fronts = self
+            .http_fronts
+            .values()
+            .map(|front| Request::from(RequestType::AddHttpFrontend(front.clone().into())));
 
-        for (front, certs) in self.certificates.iter() {
-            for certificate_and_key in certs.values() {
-                v.push(
-                    RequestType::AddCertificate(AddCertificate {
-                        address: front.to_string(),
-                        certificate: certificate_and_key.clone(),
-                        expired_at: None,
-                    })
-                    .into(),
-                );
-            }
-        }
+        let certificates = self.certificates.iter().flat_map(|(address, certs)| {
+            certs.values().map(move |certificate_and_key| {
+                Request::from(RequestType::AddCertificate(AddCertificate {
+                    address: address.to_string(),
+                    certificate: certificate_and_key.clone(),
+                    expired_at: None,
+                }))
+            })
+        });
 
-        for front in self.https_fronts.values() {
-            v.push(RequestType::AddHttpsFrontend(front.clone().into()).into());
-        }
+        let https_fronts = self
+            .https_fronts
+            .values()
+            .map(|front| Request::from(RequestType::AddHttpsFrontend(front.clone().into())));
 
-        for front_list in self.tcp_fronts.values() {
-            for front in front_list {
-                v.push(RequestType::AddTcpFrontend(front.clone().into()).into());
-            }
-        }
+        let tcp_fronts = self
+            .tcp_fronts
+            .values()
+            .flatten()
+            .map(|front| Request::from(RequestType::AddTcpFrontend(front.clone().into())));
 
-        for backend_list in self.backends.values() {
-            for backend in backend_list {
-                v.push(RequestType::AddBackend(backend.clone().to_add_backend()).into());
-            }
-        }
+        let backends = self.backends.values().flatten().map(|backend| {
+            Request::from(RequestType::AddBackend(backend.clone().to_add_backend()))
+        });
 
-        v
+        http_listeners
+            .chain(https_listeners)
+            .chain(tcp_listeners)
+            .chain(clusters)
+            .chain(http_fronts)
+            .chain(certificates)
+            .chain(https_fronts)
+            .chain(tcp_fronts)
+            .chain(backends)
     }
 
     pub fn generate_activate_requests(&self) -> Vec<Request> {
@@ -684,6 +831,12 @@ impl ConfigState {
             );
         }
 
+        // not a part of the configuration, but a new worker must not accept
+        // connections the others refuse
+        if self.lame_duck {
+            v.push(RequestType::LameDuck(LameDuck { enabled: true }).into());
+        }
+
         v
     }
 
@@ -713,6 +866,7 @@ impl ConfigState {
                         address: address.to_string(),
                         proxy: ListenerType::Tcp.into(),
                         to_scm: false,
+                        drain_sessions: None,
                     })
                     .into(),
                 );
@@ -749,6 +903,7 @@ impl ConfigState {
                         address: address.to_string(),
                         proxy: ListenerType::Http.into(),
                         to_scm: false,
+                        drain_sessions: None,
                     })
                     .into(),
                 );
@@ -785,6 +940,7 @@ impl ConfigState {
                         address: address.to_string(),
                         proxy: ListenerType::Https.into(),
                         to_scm: false,
+                        drain_sessions: None,
                     })
                     .into(),
                 );
@@ -838,6 +994,7 @@ impl ConfigState {
                         address: addr.to_string(),
                         proxy: ListenerType::Tcp.into(),
                         to_scm: false,
+                        drain_sessions: None,
                     })
                     .into(),
                 );
@@ -879,6 +1036,7 @@ impl ConfigState {
                         address: addr.to_string(),
                         proxy: ListenerType::Http.into(),
                         to_scm: false,
+                        drain_sessions: None,
                     })
                     .into(),
                 );
@@ -920,6 +1078,7 @@ impl ConfigState {
                         address: addr.to_string(),
                         proxy: ListenerType::Https.into(),
                         to_scm: false,
+                        drain_sessions: None,
                     })
                     .into(),
                 );
@@ -1211,12 +1370,60 @@ impl ConfigState {
             .map(|backend| backend.clone().into())
             .collect();
 
+        let mut listeners = Vec::new();
+        let http_addresses: BTreeSet<&String> =
+            http_frontends.iter().map(|front| &front.address).collect();
+        for (address, listener) in http_addresses
+            .into_iter()
+            .filter_map(|address| Some((address, self.http_listeners.get(address)?)))
+        {
+            listeners.push(ClusterListener {
+                address: address.to_owned(),
+                listener_type: ListenerType::Http.into(),
+                front_timeout: listener.front_timeout,
+                back_timeout: listener.back_timeout,
+                connect_timeout: listener.connect_timeout,
+                request_timeout: Some(listener.request_timeout),
+            });
+        }
+        let https_addresses: BTreeSet<&String> =
+            https_frontends.iter().map(|front| &front.address).collect();
+        for (address, listener) in https_addresses
+            .into_iter()
+            .filter_map(|address| Some((address, self.https_listeners.get(address)?)))
+        {
+            listeners.push(ClusterListener {
+                address: address.to_owned(),
+                listener_type: ListenerType::Https.into(),
+                front_timeout: listener.front_timeout,
+                back_timeout: listener.back_timeout,
+                connect_timeout: listener.connect_timeout,
+                request_timeout: Some(listener.request_timeout),
+            });
+        }
+        let tcp_addresses: BTreeSet<&String> =
+            tcp_frontends.iter().map(|front| &front.address).collect();
+        for (address, listener) in tcp_addresses
+            .into_iter()
+            .filter_map(|address| Some((address, self.tcp_listeners.get(address)?)))
+        {
+            listeners.push(ClusterListener {
+                address: address.to_owned(),
+                listener_type: ListenerType::Tcp.into(),
+                front_timeout: listener.front_timeout,
+                back_timeout: listener.back_timeout,
+                connect_timeout: listener.connect_timeout,
+                request_timeout: None,
+            });
+        }
+
         Some(ClusterInformation {
             configuration,
             http_frontends,
             https_frontends,
             tcp_frontends,
             backends,
+            listeners,
         })
     }
 
@@ -1373,6 +1580,53 @@ impl ConfigState {
         listed_frontends
     }
 
+    /// The certificates the workers present for the hostnames of HTTPS frontends,
+    /// in the same order, looked up like the TLS resolvers do in the domains they
+    /// hold, as a worker listed them
+    pub fn frontend_certificates(
+        https_frontends: &[RequestHttpFrontend],
+        resolvers: &ListOfCertificatesByAddress,
+    ) -> Vec<FrontendCertificate> {
+        https_frontends
+            .iter()
+            .map(|front| {
+                let domains = resolvers
+                    .certificates
+                    .iter()
+                    .find(|resolver| resolver.address == front.address)
+                    .map(|resolver| resolver.certificate_summaries.as_slice())
+                    .unwrap_or_default();
+                // a wildcard domain serves a single label
+                let wildcard = front
+                    .hostname
+                    .split_once('.')
+                    .map(|(_label, domain)| format!("*.{domain}"));
+                let fingerprint = domains
+                    .iter()
+                    .find(|summary| summary.domain == front.hostname)
+                    .or_else(|| {
+                        domains
+                            .iter()
+                            .find(|summary| Some(&summary.domain) == wildcard.as_ref())
+                    })
+                    .map(|summary| summary.fingerprint.to_owned());
+
+                let mut names: Vec<String> = domains
+                    .iter()
+                    .filter(|summary| Some(&summary.fingerprint) == fingerprint.as_ref())
+                    .map(|summary| summary.domain.to_owned())
+                    .collect();
+                names.sort();
+                FrontendCertificate {
+                    address: front.address.to_owned(),
+                    hostname: front.hostname.to_owned(),
+                    fingerprint,
+                    names,
+                }
+            })
+            .collect()
+    }
+
     pub fn list_listeners(&self) -> ListenersList {
         ListenersList {
             http_listeners: self.http_listeners.clone(),
@@ -1382,32 +1636,32 @@ impl ConfigState {
     }
 
     /// generate requests necessary to recreate the state,
-    /// write them in a JSON form in a file, separated by \n\0,
+    /// write them in a JSON form in a file, one per line,
     /// returns the number of written requests
     pub fn write_requests_to_file(&self, file: &mut File) -> Result<usize, StateError> {
+        let counter = self.write_requests(BufWriter::new(&mut *file))?;
+        file.sync_all().map_err(StateError::FileError)?;
+        Ok(counter)
+    }
+
+    /// write the requests necessary to recreate the state as they are generated,
+    /// in a JSON form, one per line, and returns the number of written requests
+    pub fn write_requests<W: Write>(&self, mut writer: W) -> Result<usize, StateError> {
         let mut counter = 0usize;
-        let requests = self.generate_requests();
 
-        for request in requests {
+        for request in self.requests() {
             let message = WorkerRequest::new(format!("SAVE-{counter}"), request);
 
-            file.write_all(
-                &serde_json::to_string(&message)
-                    .map(|s| s.into_bytes())
-                    .unwrap_or_default(),
-            )
-            .map_err(StateError::FileError)?;
-
-            file.write_all(&b"\n\0"[..])
-                .map_err(StateError::FileError)?;
+            serde_json::to_writer(&mut writer, &message)
+                .map_err(|serde_error| StateError::FileError(serde_error.into()))?;
+            writer.write_all(b"\n").map_err(StateError::FileError)?;
 
             if counter % 1000 == 0 {
                 info!("writing command {}", counter);
-                file.sync_all().map_err(StateError::FileError)?;
             }
             counter += 1;
         }
-        file.sync_all().map_err(StateError::FileError)?;
+        writer.flush().map_err(StateError::FileError)?;
 
         Ok(counter)
     }
@@ -1439,6 +1693,18 @@ fn domain_check(
     true
 }
 
+/// the names overriding the certificate, or its common name and subject alternate names
+fn certificate_names(certificate_and_key: &CertificateAndKey) -> Vec<String> {
+    if !certificate_and_key.names.is_empty() {
+        return certificate_and_key.names.clone();
+    }
+    certificate::parse_pem(certificate_and_key.certificate.as_bytes())
+        .ok()
+        .and_then(|pem| certificate::get_cn_and_san_attributes(&pem.contents).ok())
+        .map(|names| names.into_iter().collect())
+        .unwrap_or_default()
+}
+
 struct DiffMap<'a, K: Ord, V, I1, I2> {
     my_it: I1,
     other_it: I2,
@@ -1526,7 +1792,10 @@ mod tests {
     use rand::{seq::SliceRandom, thread_rng, Rng};
 
     use super::*;
-    use crate::proto::command::{LoadBalancingParams, RequestHttpFrontend, RulePosition};
+    use crate::proto::command::{
+        CertificateSummary, CertificatesByAddress, LoadBalancingAlgorithms, LoadBalancingParams,
+        RequestHttpFrontend, RulePosition,
+    };
 
     #[test]
     fn serialize() {
@@ -1823,6 +2092,91 @@ mod tests {
         assert_eq!(diff, expected_diff);
     }
 
+    #[test]
+    fn apply_config_diff() {
+        let mut state: ConfigState = Default::default();
+        let front = RequestHttpFrontend {
+            cluster_id: Some(String::from("cluster_1")),
+            hostname: String::from("lolcatho.st"),
+            path: PathRule::prefix(String::from("/")),
+            address: "0.0.0.0:8080".to_string(),
+            ..Default::default()
+        };
+        let backend = AddBackend {
+            cluster_id: String::from("cluster_1"),
+            backend_id: String::from("cluster_1-0"),
+            address: "127.0.0.1:1026".to_string(),
+            ..Default::default()
+        };
+
+        state
+            .dispatch(
+                &RequestType::ApplyConfig(ConfigDiff {
+                    requests: vec![
+                        RequestType::AddHttpFrontend(front.clone()).into(),
+                        RequestType::AddBackend(backend).into(),
+                    ],
+                })
+                .into(),
+            )
+            .expect("Could not apply the configuration diff");
+
+        assert!(state.http_fronts.contains_key(&front.to_string()));
+        assert_eq!(state.count_backends(), 1);
+    }
+
+    #[test]
+    fn apply_config_diff_rollback() {
+        let mut state: ConfigState = Default::default();
+        state
+            .dispatch(
+                &RequestType::AddBackend(AddBackend {
+                    cluster_id: String::from("cluster_1"),
+                    backend_id: String::from("cluster_1-0"),
+                    address: "127.0.0.1:1026".to_string(),
+                    ..Default::default()
+                })
+                .into(),
+            )
+            .expect("Could not execute request");
+        let initial_state = state.clone();
+
+        let missing_front = RequestHttpFrontend {
+            cluster_id: Some(String::from("cluster_2")),
+            hostname: String::from("test.local"),
+            path: PathRule::prefix(String::from("/")),
+            address: "0.0.0.0:8080".to_string(),
+            ..Default::default()
+        };
+
+        let result = state.dispatch(
+            &RequestType::ApplyConfig(ConfigDiff {
+                requests: vec![
+                    RequestType::AddHttpFrontend(RequestHttpFrontend {
+                        cluster_id: Some(String::from("cluster_1")),
+                        hostname: String::from("lolcatho.st"),
+                        path: PathRule::prefix(String::from("/")),
+                        address: "0.0.0.0:8080".to_string(),
+                        ..Default::default()
+                    })
+                    .into(),
+                    RequestType::RemoveBackend(RemoveBackend {
+                        cluster_id: String::from("cluster_1"),
+                        backend_id: String::from("cluster_1-0"),
+                        address: "127.0.0.1:1026".to_string(),
+                    })
+                    .into(),
+                    RequestType::RemoveHttpFrontend(missing_front).into(),
+                ],
+            })
+            .into(),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(state.http_fronts, initial_state.http_fronts);
+        assert_eq!(state.backends, initial_state.backends);
+    }
+
     #[test]
     fn cluster_ids_by_domain() {
         let mut config = ConfigState::new();
@@ -1899,6 +2253,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cluster_state() {
+        let mut state = ConfigState::new();
+        let cluster = Cluster {
+            cluster_id: String::from("MyCluster"),
+            sticky_session: true,
+            load_balancing: LoadBalancingAlgorithms::LeastLoaded.into(),
+            ..Default::default()
+        };
+        let listener = HttpListenerConfig {
+            address: String::from("0.0.0.0:8080"),
+            front_timeout: 120,
+            request_timeout: 5,
+            ..Default::default()
+        };
+        let front = RequestHttpFrontend {
+            cluster_id: Some(String::from("MyCluster")),
+            hostname: String::from("lolcatho.st"),
+            path: PathRule::prefix(String::from("/api")),
+            address: String::from("0.0.0.0:8080"),
+            ..Default::default()
+        };
+        let backend = AddBackend {
+            cluster_id: String::from("MyCluster"),
+            backend_id: String::from("MyCluster-0"),
+            address: String::from("127.0.0.1:1026"),
+            ..Default::default()
+        };
+        for request in [
+            RequestType::AddCluster(cluster.clone()),
+            RequestType::AddHttpListener(listener.clone()),
+            RequestType::AddHttpFrontend(front.clone()),
+            RequestType::AddBackend(backend.clone()),
+        ] {
+            state
+                .dispatch(&request.into())
+                .expect("Could not execute request");
+        }
+
+        assert_eq!(
+            state.cluster_state("MyCluster"),
+            Some(ClusterInformation {
+                configuration: Some(cluster),
+                http_frontends: vec![front],
+                https_frontends: vec![],
+                tcp_frontends: vec![],
+                backends: vec![backend],
+                listeners: vec![ClusterListener {
+                    address: String::from("0.0.0.0:8080"),
+                    listener_type: ListenerType::Http.into(),
+                    front_timeout: 120,
+                    back_timeout: listener.back_timeout,
+                    connect_timeout: listener.connect_timeout,
+                    request_timeout: Some(5),
+                }],
+            })
+        );
+        assert_eq!(state.cluster_state("UnknownCluster"), None);
+    }
+
     #[test]
     fn duplicate_backends() {
         let mut state: ConfigState = Default::default();
@@ -1922,6 +2336,7 @@ mod tests {
             load_balancing_parameters: Some(LoadBalancingParams::default()),
             sticky_id: Some("sticky".to_string()),
             backup: None,
+            hostname: None,
         };
 
         state
@@ -2144,6 +2559,7 @@ mod tests {
                 address: "0.0.0.0:1234".parse().unwrap(),
                 proxy: ListenerType::Tcp.into(),
                 to_scm: false,
+                drain_sessions: None,
             })
             .into(),
             RequestType::RemoveListener(RemoveListener {
@@ -2234,4 +2650,325 @@ mod tests {
 
         assert!(certificate_found_by_domain_name.len() >= 1);
     }
+
+    #[test]
+    fn frontend_certificates() {
+        let mut state = ConfigState::default();
+        let fronts = [
+            ("127.0.0.1:8443", "lolcatho.st"),
+            ("127.0.0.1:8443", "api.example.com"),
+            ("127.0.0.1:8443", "www.example.com"),
+            ("127.0.0.1:8443", "deep.api.example.com"),
+            ("127.0.0.1:8443", "example.org"),
+            ("127.0.0.1:9443", "lolcatho.st"),
+        ];
+        for (address, hostname) in fronts {
+            state
+                .dispatch(
+                    &RequestType::AddHttpsFrontend(RequestHttpFrontend {
+                        cluster_id: Some(String::from("cluster_1")),
+                        hostname: hostname.to_string(),
+                        path: PathRule::prefix(String::from("/")),
+                        address: address.to_string(),
+                        ..Default::default()
+                    })
+                    .into(),
+                )
+                .expect("Could not add frontend");
+        }
+
+        let summary = |domain: &str, fingerprint: &str| CertificateSummary {
+            domain: domain.to_owned(),
+            fingerprint: fingerprint.to_owned(),
+            expired_at: None,
+        };
+        // the domains of the resolver of each listener, as a worker lists them
+        let resolvers = ListOfCertificatesByAddress {
+            certificates: vec![
+                CertificatesByAddress {
+                    address: "127.0.0.1:8443".to_owned(),
+                    certificate_summaries: vec![
+                        summary("lolcatho.st", "aa"),
+                        summary("*.example.com", "aa"),
+                        summary("api.example.com", "bb"),
+                    ],
+                },
+                CertificatesByAddress {
+                    address: "127.0.0.1:9443".to_owned(),
+                    certificate_summaries: vec![],
+                },
+            ],
+        };
+
+        let listed = state.list_frontends(FrontendFilters {
+            https: true,
+            ..Default::default()
+        });
+        assert_eq!(listed.https_frontends.len(), fronts.len());
+        let certificates = ConfigState::frontend_certificates(&listed.https_frontends, &resolvers);
+        assert_eq!(certificates.len(), fronts.len());
+
+        for (front, certificate) in listed.https_frontends.iter().zip(certificates.iter()) {
+            assert_eq!(front.address, certificate.address);
+            assert_eq!(front.hostname, certificate.hostname);
+            let expected = match (front.address.as_str(), front.hostname.as_str()) {
+                ("127.0.0.1:8443", "lolcatho.st") | ("127.0.0.1:8443", "www.example.com") => {
+                    Some("aa")
+                }
+                // a name of another certificate is looked up before the wildcard
+                ("127.0.0.1:8443", "api.example.com") => Some("bb"),
+                _ => None,
+            };
+            assert_eq!(
+                certificate.fingerprint.as_deref(),
+                expected,
+                "front {front:?}"
+            );
+            if expected == Some("aa") {
+                assert_eq!(certificate.names, vec!["*.example.com", "lolcatho.st"]);
+            }
+        }
+    }
+
+    #[test]
+    fn replace_certificate_by_name() {
+        let mut state = ConfigState::default();
+        let address = "127.0.0.1:8443";
+        let certificate = |pem: &str, names: &[&str]| CertificateAndKey {
+            certificate: pem.to_owned(),
+            names: names.iter().map(ToString::to_string).collect(),
+            ..Default::default()
+        };
+        let fingerprint = |pem: &str| Fingerprint(calculate_fingerprint(pem.as_bytes()).unwrap());
+        let fingerprints = |state: &ConfigState| -> HashSet<Fingerprint> {
+            state
+                .certificates
+                .get(&address.parse::<SocketAddr>().unwrap())
+                .map(|certificates| certificates.keys().cloned().collect())
+                .unwrap_or_default()
+        };
+        let replace = |state: &mut ConfigState, name: &str, new_certificate: CertificateAndKey| {
+            state.dispatch(
+                &RequestType::ReplaceCertificateByName(ReplaceCertificateByName {
+                    address: address.to_owned(),
+                    name: name.to_owned(),
+                    new_certificate,
+                    new_expired_at: None,
+                })
+                .into(),
+            )
+        };
+
+        let pem = include_str!("../assets/certificate.pem");
+        let pem_1y = include_str!("../assets/tests/certificate-1y.pem");
+        let pem_2y = include_str!("../assets/tests/certificate-2y.pem");
+        for (pem, names) in [
+            (pem, vec!["lolcatho.st", "*.example.com"]),
+            (pem_1y, vec!["api.example.com"]),
+        ] {
+            state
+                .dispatch(
+                    &RequestType::AddCertificate(AddCertificate {
+                        address: address.to_owned(),
+                        certificate: certificate(pem, &names),
+                        expired_at: None,
+                    })
+                    .into(),
+                )
+                .expect("Could not add certificate");
+        }
+
+        // the new certificate must serve the name
+        assert!(replace(
+            &mut state,
+            "localhost",
+            certificate(pem_2y, &["lolcatho.st"])
+        )
+        .is_err());
+
+        // the certificate having the name is replaced before the wildcard one,
+        // and removed because the new certificate has all its names
+        replace(
+            &mut state,
+            "api.example.com",
+            certificate(pem_2y, &["api.example.com"]),
+        )
+        .expect("Could not replace certificate");
+        assert_eq!(
+            fingerprints(&state),
+            HashSet::from([fingerprint(pem), fingerprint(pem_2y)])
+        );
+
+        // the wildcard certificate still serves another name, it is kept for it
+        replace(
+            &mut state,
+            "www.example.com",
+            certificate(pem_1y, &["*.example.com"]),
+        )
+        .expect("Could not replace certificate");
+        assert_eq!(
+            fingerprints(&state),
+            HashSet::from([fingerprint(pem), fingerprint(pem_1y), fingerprint(pem_2y)])
+        );
+    }
+
+    #[test]
+    fn duplicate_tcp_server_names() {
+        let mut state = ConfigState::default();
+        let front = |cluster_id: &str, address: &str, hostname: &str| {
+            Request::from(RequestType::AddTcpFrontend(RequestTcpFrontend {
+                cluster_id: cluster_id.to_owned(),
+                address: address.to_owned(),
+                hostname: Some(hostname.to_owned()),
+                ..Default::default()
+            }))
+        };
+
+        state
+            .dispatch(&front("cluster_1", "127.0.0.1:1443", "lolcatho.st"))
+            .expect("Could not add frontend");
+        // another server name, or the same one on another listener
+        state
+            .dispatch(&front("cluster_2", "127.0.0.1:1443", "example.com"))
+            .expect("Could not add frontend");
+        state
+            .dispatch(&front("cluster_2", "127.0.0.1:2443", "lolcatho.st"))
+            .expect("Could not add frontend");
+
+        assert!(state
+            .dispatch(&front("cluster_2", "127.0.0.1:1443", "LolCatHo.st"))
+            .is_err());
+        assert!(state
+            .dispatch(&front("cluster_1", "127.0.0.1:1443", "lolcatho.st"))
+            .is_err());
+        assert_eq!(state.tcp_fronts.values().flatten().count(), 3);
+    }
+
+    #[test]
+    fn connection_retries_bounds() {
+        let mut state = ConfigState::default();
+        let cluster = |connection_retries: Option<u32>| {
+            Request::from(RequestType::AddCluster(Cluster {
+                cluster_id: String::from("cluster_1"),
+                connection_retries,
+                ..Default::default()
+            }))
+        };
+
+        assert!(state.dispatch(&cluster(Some(0))).is_err());
+        assert!(state.dispatch(&cluster(Some(256))).is_err());
+        assert!(state.clusters.is_empty());
+
+        state
+            .dispatch(&cluster(Some(255)))
+            .expect("Could not add cluster");
+        state
+            .dispatch(&cluster(None))
+            .expect("Could not add cluster");
+    }
+
+    #[test]
+    fn lame_duck_is_inherited_by_new_workers() {
+        let mut state = ConfigState::default();
+        let lame_duck = |enabled| Request::from(RequestType::LameDuck(LameDuck { enabled }));
+
+        state
+            .dispatch(&lame_duck(true))
+            .expect("Could not enable the lame duck mode");
+        assert_eq!(
+            state.generate_activate_requests().last(),
+            Some(&lame_duck(true))
+        );
+        // it is not saved with the configuration
+        assert!(state.generate_requests().is_empty());
+
+        state
+            .dispatch(&lame_duck(false))
+            .expect("Could not disable the lame duck mode");
+        assert!(state.generate_activate_requests().is_empty());
+    }
+
+    #[test]
+    fn stream_large_state() {
+        let mut state = ConfigState::default();
+        for cluster in 0..2000 {
+            let cluster_id = format!("cluster_{cluster}");
+            state
+                .dispatch(
+                    &RequestType::AddCluster(Cluster {
+                        cluster_id: cluster_id.clone(),
+                        load_balancing: LoadBalancingAlgorithms::RoundRobin as i32,
+                        ..Default::default()
+                    })
+                    .into(),
+                )
+                .expect("Could not execute request");
+            state
+                .dispatch(
+                    &RequestType::AddHttpFrontend(RequestHttpFrontend {
+                        cluster_id: Some(cluster_id.clone()),
+                        hostname: format!("{cluster_id}.example.com"),
+                        path: PathRule::prefix(String::from("/")),
+                        address: "0.0.0.0:8080".to_string(),
+                        ..Default::default()
+                    })
+                    .into(),
+                )
+                .expect("Could not execute request");
+            for backend in 0..5 {
+                state
+                    .dispatch(
+                        &RequestType::AddBackend(AddBackend {
+                            cluster_id: cluster_id.clone(),
+                            backend_id: format!("{cluster_id}-{backend}"),
+                            address: format!(
+                                "10.{}.{}.{backend}:8080",
+                                cluster / 250,
+                                cluster % 250
+                            ),
+                            load_balancing_parameters: Some(LoadBalancingParams::default()),
+                            ..Default::default()
+                        })
+                        .into(),
+                    )
+                    .expect("Could not execute request");
+            }
+        }
+
+        let mut written = Vec::new();
+        let counter = state
+            .write_requests(&mut written)
+            .expect("could not write the state");
+        assert_eq!(counter, 2000 * 7);
+
+        let read = crate::request::read_requests(&written[..])
+            .collect::<Result<Vec<WorkerRequest>, _>>()
+            .expect("could not read the state");
+        let read_requests: Vec<Request> =
+            read.iter().map(|request| request.content.clone()).collect();
+        assert_eq!(read_requests, state.generate_requests());
+
+        let mut reloaded = ConfigState::default();
+        for request in &read {
+            reloaded
+                .dispatch(&request.content)
+                .expect("Could not execute request");
+        }
+        let mut rewritten = Vec::new();
+        reloaded
+            .write_requests(&mut rewritten)
+            .expect("could not write the state");
+        assert_eq!(written, rewritten);
+
+        // files saved by previous versions separate the requests with "\n\0"
+        let legacy: Vec<u8> = written
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .flat_map(|line| line.iter().copied().chain(b"\n\0".iter().copied()))
+            .collect();
+        let legacy_read = crate::request::read_requests(&legacy[..])
+            .collect::<Result<Vec<WorkerRequest>, _>>()
+            .expect("could not read the legacy state");
+        assert_eq!(legacy_read.len(), counter);
+    }
 }
@@ -1,10 +1,25 @@
 pub fn http_ok_response<S: Into<String>>(content: S) -> String {
+    http_response("HTTP/1.1 200 OK", content)
+}
+
+pub fn http_response<S: Into<String>>(status_line: &str, content: S) -> String {
     let content = content.into();
-    let status_line = "HTTP/1.1 200 OK";
     let length = content.len();
     format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{content}")
 }
 
+/// Checks that a response forwarded by Sōzu is the one of `http_ok_response`,
+/// Sōzu adds headers like "Sozu-Id" after the backend ones
+pub fn is_http_ok_response(response: Option<&str>, content: &str) -> bool {
+    let length = content.len();
+    response
+        .map(|response| {
+            response.starts_with(&format!("HTTP/1.1 200 OK\r\nContent-Length: {length}\r\n"))
+                && response.ends_with(&format!("\r\n\r\n{content}"))
+        })
+        .unwrap_or(false)
+}
+
 /// Creates an HTTP/1 raw request
 pub fn http_request<S1: Into<String>, S2: Into<String>, S3: Into<String>, S4: Into<String>>(
     method: S1,
@@ -8,7 +8,7 @@ use std::{
 use futures::channel::mpsc;
 
 use crate::{
-    http_utils::http_ok_response,
+    http_utils::http_response,
     mock::aggregator::{Aggregator, SimpleAggregator},
     BUFFER_SIZE,
 };
@@ -100,6 +100,15 @@ impl BackendHandle<SimpleAggregator> {
     /// and returns HTTP OK responses with the given content in the body
     /// it returns an updated aggregator
     pub fn http_handler<S: Into<String>>(content: S) -> RequestHandler<SimpleAggregator> {
+        Self::http_status_handler("HTTP/1.1 200 OK", content)
+    }
+
+    /// Like `http_handler`, the responses have the given status line
+    pub fn http_status_handler<S1: Into<String>, S2: Into<String>>(
+        status_line: S1,
+        content: S2,
+    ) -> RequestHandler<SimpleAggregator> {
+        let status_line = status_line.into();
         let content = content.into();
         Box::new(move |mut stream, backend_name, mut aggregator| {
             let mut buf = [0u8; BUFFER_SIZE];
@@ -115,7 +124,7 @@ impl BackendHandle<SimpleAggregator> {
                 }
             }
             aggregator.requests_received += 1;
-            let response = http_ok_response(&content);
+            let response = http_response(&status_line, &content);
             stream.write_all(response.as_bytes()).unwrap();
             aggregator.responses_sent += 1;
             aggregator
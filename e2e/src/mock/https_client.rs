@@ -1,4 +1,9 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use hyper::{
     self,
@@ -8,7 +13,7 @@ use hyper::{
 use hyper_rustls::HttpsConnector;
 use rustls::{
     client::{ClientConfig, ServerCertVerified, ServerCertVerifier},
-    Certificate, ServerName,
+    Certificate, ClientConnection, ServerName, Stream,
 };
 
 // We implement our own verifier to allow self-signed certificates
@@ -67,3 +72,123 @@ pub fn resolve_request(request: ResponseFuture) -> Option<(StatusCode, String)>
         Some((status, body))
     })
 }
+
+/// Build a TLS client configuration that supports self signed certificates
+/// and sends TLS 1.3 early data (0-RTT) when resuming a session
+pub fn build_early_data_client_config() -> Arc<ClientConfig> {
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(Verifier))
+        .with_no_client_auth();
+    config.enable_early_data = true;
+    Arc::new(config)
+}
+
+/// Build a TLS client configuration that supports self signed certificates
+/// and sends no server name (SNI) in its handshake
+pub fn build_no_sni_client_config() -> Arc<ClientConfig> {
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(Verifier))
+        .with_no_client_auth();
+    config.enable_sni = false;
+    Arc::new(config)
+}
+
+/// Sends a raw request over a new TLS connection, in early data if the client
+/// config holds a session to resume. Returns whether the early data was accepted,
+/// and the response
+pub fn send_tls_request(
+    config: Arc<ClientConfig>,
+    address: SocketAddr,
+    request: &str,
+) -> Option<(bool, String)> {
+    let server_name = ServerName::try_from("localhost").ok()?;
+    let mut connection = ClientConnection::new(config, server_name).ok()?;
+    if let Some(mut early_data) = connection.early_data() {
+        early_data.write_all(request.as_bytes()).ok()?;
+    }
+
+    let mut socket = TcpStream::connect(address).ok()?;
+    socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("could not set read timeout");
+    while connection.is_handshaking() {
+        if let Err(error) = connection.complete_io(&mut socket) {
+            println!("could not complete handshake: {error}");
+            return None;
+        }
+    }
+    let early_data_accepted = connection.is_early_data_accepted();
+
+    let mut stream = Stream::new(&mut connection, &mut socket);
+    if !early_data_accepted {
+        stream.write_all(request.as_bytes()).ok()?;
+    }
+    let mut response = [0u8; 4096];
+    match stream.read(&mut response) {
+        Ok(size) if size > 0 => {
+            let response = String::from_utf8_lossy(&response[..size]).to_string();
+            Some((early_data_accepted, response))
+        }
+        Ok(_) => None,
+        Err(error) => {
+            println!("could not read response: {error}");
+            None
+        }
+    }
+}
+
+/// Opens a new TLS connection and completes its handshake, without sending anything
+pub fn open_tls_connection(address: SocketAddr) -> Option<(ClientConnection, TcpStream)> {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(Verifier))
+        .with_no_client_auth();
+    let server_name = ServerName::try_from("localhost").ok()?;
+    let mut connection = ClientConnection::new(Arc::new(config), server_name).ok()?;
+
+    let mut socket = TcpStream::connect(address).ok()?;
+    socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("could not set read timeout");
+    while connection.is_handshaking() {
+        if let Err(error) = connection.complete_io(&mut socket) {
+            println!("could not complete handshake: {error}");
+            return None;
+        }
+    }
+    Some((connection, socket))
+}
+
+/// Reads what the server sends on a TLS connection until it closes it
+pub fn read_tls_until_close(
+    connection: &mut ClientConnection,
+    socket: &mut TcpStream,
+) -> Option<String> {
+    let mut response = String::new();
+    match Stream::new(connection, socket).read_to_string(&mut response) {
+        Ok(_) => Some(response),
+        Err(error) => {
+            println!("could not read until close: {error} (read {response:?})");
+            None
+        }
+    }
+}
+
+/// The first bytes a TLS client sends: a ClientHello for this server name
+pub fn build_client_hello(server_name: &str) -> Vec<u8> {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(Verifier))
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(server_name).expect("invalid server name");
+    let mut connection = ClientConnection::new(Arc::new(config), server_name)
+        .expect("could not create the TLS connection");
+
+    let mut client_hello = Vec::new();
+    connection
+        .write_tls(&mut client_hello)
+        .expect("could not write the ClientHello");
+    client_hello
+}
@@ -330,6 +330,7 @@ impl Worker {
             load_balancing_parameters: Some(LoadBalancingParams::default()),
             sticky_id,
             backup: None,
+            hostname: None,
         }
     }
 }
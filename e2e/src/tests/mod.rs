@@ -51,13 +51,37 @@ pub fn setup_test<S: Into<String>>(
     nb_backends: usize,
     should_stick: bool,
 ) -> (Worker, Vec<SocketAddr>) {
+    setup_test_with_listener(
+        name,
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address),
+        nb_backends,
+        should_stick,
+    )
+}
+
+/// Same as `setup_test`, with the HttpListener built from `listener`
+/// and its address used as `front_address`
+pub fn setup_test_with_listener<S: Into<String>>(
+    name: S,
+    config: Config,
+    listeners: Listeners,
+    state: ConfigState,
+    listener: ListenerBuilder,
+    nb_backends: usize,
+    should_stick: bool,
+) -> (Worker, Vec<SocketAddr>) {
+    let front_address: SocketAddr = listener
+        .address
+        .parse()
+        .expect("could not parse front address");
     let mut worker = Worker::start_new_worker(name, config, &listeners, state);
 
     worker.send_proxy_request(Request {
         request_type: Some(RequestType::AddHttpListener(
-            ListenerBuilder::new_http(front_address)
-                .to_http(None)
-                .unwrap(),
+            listener.to_http(None).unwrap(),
         )),
     });
     worker.send_proxy_request(Request {
@@ -150,12 +174,33 @@ pub fn setup_sync_test<S: Into<String>>(
     nb_backends: usize,
     should_stick: bool,
 ) -> (Worker, Vec<SyncBackend>) {
-    let (worker, backends) = setup_test(
+    setup_sync_test_with_listener(
         name,
         config,
         listeners,
         state,
-        front_address,
+        ListenerBuilder::new_http(front_address),
+        nb_backends,
+        should_stick,
+    )
+}
+
+/// Same as `setup_sync_test`, with the HttpListener built from `listener`
+pub fn setup_sync_test_with_listener<S: Into<String>>(
+    name: S,
+    config: Config,
+    listeners: Listeners,
+    state: ConfigState,
+    listener: ListenerBuilder,
+    nb_backends: usize,
+    should_stick: bool,
+) -> (Worker, Vec<SyncBackend>) {
+    let (worker, backends) = setup_test_with_listener(
+        name,
+        config,
+        listeners,
+        state,
+        listener,
         nb_backends,
         should_stick,
     );
@@ -173,6 +218,33 @@ pub fn setup_sync_test<S: Into<String>>(
     (worker, backends)
 }
 
+/// Setup a Sozu worker with
+/// - `config`
+/// - `listeners`
+/// - 1 active TcpListener built from `listener`
+pub fn setup_tcp_worker<S: Into<String>>(
+    name: S,
+    config: Config,
+    listeners: Listeners,
+    state: ConfigState,
+    listener: ListenerBuilder,
+) -> Worker {
+    let mut worker = Worker::start_new_worker(name, config, &listeners, state);
+
+    let address = listener.address.clone();
+    worker.send_proxy_request(Request {
+        request_type: Some(RequestType::AddTcpListener(listener.to_tcp(None).unwrap())),
+    });
+    worker.send_proxy_request(Request {
+        request_type: Some(RequestType::ActivateListener(ActivateListener {
+            address,
+            proxy: ListenerType::Tcp.into(),
+            from_scm: false,
+        })),
+    });
+    worker
+}
+
 pub fn repeat_until_error_or<F>(times: usize, test_description: &str, test: F) -> State
 where
     F: Fn() -> State + Sized,
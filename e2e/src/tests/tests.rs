@@ -1,5 +1,6 @@
 use std::{
-    net::SocketAddr,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
     thread,
     time::{Duration, Instant},
 };
@@ -9,23 +10,34 @@ use sozu_command_lib::{
     info, log,
     logging::setup_logging,
     proto::command::{
-        request::RequestType, ActivateListener, AddCertificate, CertificateAndKey, ListenerType,
-        RemoveBackend, RequestHttpFrontend,
+        request::RequestType, ActivateListener, AddCertificate, CertificateAndKey, Cluster,
+        DeactivateListener, ListenerType, PathRule, ProxyProtocolConfig, RemoveBackend,
+        RequestHttpFrontend,
     },
     state::ConfigState,
 };
+use sozu_lib::protocol::proxy_protocol::header::{Command, HeaderV2};
 
 use crate::{
-    http_utils::{default_404_answer, default_503_answer, http_ok_response, http_request},
+    http_utils::{
+        default_404_answer, default_503_answer, http_ok_response, http_request, is_http_ok_response,
+    },
     mock::{
         aggregator::SimpleAggregator,
         async_backend::BackendHandle as AsyncBackend,
         client::Client,
-        https_client::{build_https_client, resolve_request},
+        https_client::{
+            build_client_hello, build_early_data_client_config, build_https_client,
+            build_no_sni_client_config, open_tls_connection, read_tls_until_close, resolve_request,
+            send_tls_request,
+        },
         sync_backend::Backend as SyncBackend,
     },
     sozu::worker::Worker,
-    tests::{provide_port, repeat_until_error_or, setup_async_test, setup_sync_test, State},
+    tests::{
+        provide_port, repeat_until_error_or, setup_async_test, setup_sync_test,
+        setup_sync_test_with_listener, setup_tcp_worker, State,
+    },
 };
 
 fn create_local_address() -> SocketAddr {
@@ -353,53 +365,204 @@ pub fn try_issue_810_panic(part2: bool) -> State {
     }
 }
 
-pub fn try_tls_endpoint() -> State {
-    let front_port = provide_port();
-    let front_address: SocketAddr = format!("127.0.0.1:{}", front_port)
-        .parse()
-        .expect("could not parse front address");
-    let back_address = create_local_address();
+/// The first backend of the cluster is down: the TCP proxy should connect the client
+/// to the second one, or close the client connection if it is down too
+pub fn try_tcp_backend_failover(second_backend_up: bool) -> State {
+    let front_address = create_local_address();
 
     let (config, listeners, state) = Worker::empty_config();
-    let mut worker = Worker::start_new_worker("TLS-ENDPOINT", config, &listeners, state);
-
-    worker.send_proxy_request_type(RequestType::AddHttpsListener(
-        ListenerBuilder::new_https(front_address)
-            .to_tls(None)
-            .unwrap(),
-    ));
-
-    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
-        address: front_address.to_string(),
-        proxy: ListenerType::Https.into(),
-        from_scm: false,
-    }));
-
+    let mut worker = setup_tcp_worker(
+        "TCP-FAILOVER",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_tcp(front_address),
+    );
     worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
         "cluster_0",
         false,
     )));
+    worker.send_proxy_request_type(RequestType::AddTcpFrontend(Worker::default_tcp_frontend(
+        "cluster_0",
+        front_address.to_string(),
+    )));
 
-    let hostname = "localhost".to_string();
-    worker.send_proxy_request_type(RequestType::AddHttpsFrontend(RequestHttpFrontend {
-        hostname: hostname.to_owned(),
-        ..Worker::default_http_frontend("cluster_0", front_address)
-    }));
+    // nothing listens on the first backend address, connections to it are refused
+    let down_address = create_local_address();
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        down_address.to_string(),
+        None,
+    )));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-1",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
 
-    let certificate_and_key = CertificateAndKey {
-        certificate: String::from(include_str!("../../../lib/assets/local-certificate.pem")),
-        key: String::from(include_str!("../../../lib/assets/local-key.pem")),
-        certificate_chain: vec![], // in config.toml the certificate chain would be the same as the certificate
-        versions: vec![],
-        names: vec![],
-    };
-    let add_certificate = AddCertificate {
-        address: front_address.to_string(),
-        certificate: certificate_and_key,
-        expired_at: None,
+    let mut backend = SyncBackend::new("backend", back_address, "pong");
+    if second_backend_up {
+        backend.connect();
+    }
+
+    let mut client = Client::new("client", front_address, "ping");
+    client.connect();
+    client.send();
+
+    if second_backend_up {
+        backend.accept(0);
+        let request = backend.receive(0);
+        println!("request: {request:?}");
+        backend.send(0);
+    }
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    match (second_backend_up, response.as_deref()) {
+        (true, Some("pong")) | (false, None) => State::Success,
+        _ => State::Fail,
+    }
+}
+
+/// Accept a connection on a backend, or give up after a second
+fn accept_with_timeout(backend: &TcpListener) -> Option<TcpStream> {
+    backend
+        .set_nonblocking(true)
+        .expect("could not set the backend non blocking");
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        if let Ok((stream, _)) = backend.accept() {
+            stream
+                .set_nonblocking(false)
+                .expect("could not set the stream blocking");
+            return Some(stream);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    None
+}
+
+/// TLS passthrough: the TCP listener routes each connection by the server name of its
+/// ClientHello, to the cluster of the matching frontend or to the default one
+pub fn try_tcp_sni_routing(server_name: &str, expected_cluster: usize) -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = setup_tcp_worker(
+        "TCP-SNI",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_tcp(front_address),
+    );
+
+    let mut backends = Vec::new();
+    for (index, hostname) in [None, Some("lolcatho.st"), Some("example.com")]
+        .into_iter()
+        .enumerate()
+    {
+        let cluster_id = format!("cluster_{index}");
+        worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+            &cluster_id,
+            false,
+        )));
+        let mut frontend = Worker::default_tcp_frontend(&cluster_id, front_address.to_string());
+        frontend.hostname = hostname.map(String::from);
+        worker.send_proxy_request_type(RequestType::AddTcpFrontend(frontend));
+
+        let back_address = create_local_address();
+        worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+            &cluster_id,
+            format!("{cluster_id}-0"),
+            back_address.to_string(),
+            None,
+        )));
+        backends.push(TcpListener::bind(back_address).expect("could not bind the backend"));
+    }
+    worker.read_to_last();
+
+    let client_hello = build_client_hello(server_name);
+    let mut client = TcpStream::connect(front_address).expect("could not connect to sozu");
+    client
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("could not set read timeout");
+    client
+        .write_all(&client_hello)
+        .expect("could not send the ClientHello");
+
+    let mut backend_stream = match accept_with_timeout(&backends[expected_cluster]) {
+        Some(stream) => stream,
+        None => {
+            println!("the expected backend was not chosen");
+            worker.soft_stop();
+            worker.wait_for_server_stop();
+            return State::Fail;
+        }
     };
-    worker.send_proxy_request_type(RequestType::AddCertificate(add_certificate));
+    backend_stream
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("could not set read timeout");
+
+    // the ClientHello is relayed untouched, and the connection then works both ways
+    let mut received = vec![0; client_hello.len()];
+    let relayed = backend_stream.read_exact(&mut received).is_ok() && received == client_hello;
+    println!("ClientHello relayed unchanged: {relayed}");
+    backend_stream
+        .write_all(b"pong")
+        .expect("could not answer the client");
+    let mut response = [0; 4];
+    let answered = client.read_exact(&mut response).is_ok() && &response == b"pong";
+    println!("answer received: {answered}");
+
+    let other_backends_unused = backends
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != expected_cluster)
+        .all(|(_, backend)| {
+            backend
+                .set_nonblocking(true)
+                .expect("could not set the backend non blocking");
+            backend.accept().is_err()
+        });
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    if relayed && answered && other_backends_unused {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// The cluster picked by the server name of the ClientHello sends a proxy protocol
+/// header to its backend, in front of the relayed ClientHello
+pub fn try_tcp_sni_routing_send_proxy() -> State {
+    let front_address = create_local_address();
+    let back_address = create_local_address();
 
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = setup_tcp_worker(
+        "TCP-SNI-SEND-PROXY",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_tcp(front_address),
+    );
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        proxy_protocol: Some(ProxyProtocolConfig::SendHeader as i32),
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    let mut frontend = Worker::default_tcp_frontend("cluster_0", front_address.to_string());
+    frontend.hostname = Some("lolcatho.st".to_owned());
+    worker.send_proxy_request_type(RequestType::AddTcpFrontend(frontend));
     worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
         "cluster_0",
         "cluster_0-0",
@@ -407,106 +570,501 @@ pub fn try_tls_endpoint() -> State {
         None,
     )));
     worker.read_to_last();
+    let backend = TcpListener::bind(back_address).expect("could not bind the backend");
 
-    let mut backend = AsyncBackend::spawn_detached_backend(
-        "BACKEND",
-        back_address,
+    let client_hello = build_client_hello("lolcatho.st");
+    let mut client = TcpStream::connect(front_address).expect("could not connect to sozu");
+    client
+        .write_all(&client_hello)
+        .expect("could not send the ClientHello");
+
+    let Some(mut backend_stream) = accept_with_timeout(&backend) else {
+        println!("the backend was not chosen");
+        worker.soft_stop();
+        worker.wait_for_server_stop();
+        return State::Fail;
+    };
+    backend_stream
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("could not set read timeout");
+
+    // a proxy protocol v2 header: signature, version, family and length of the addresses
+    let mut header = [0; 16];
+    let mut has_header =
+        backend_stream.read_exact(&mut header).is_ok() && header[..12] == *b"\r\n\r\n\0\r\nQUIT\n";
+    if has_header {
+        let mut addresses = vec![0; u16::from_be_bytes([header[14], header[15]]) as usize];
+        has_header = backend_stream.read_exact(&mut addresses).is_ok();
+    }
+    println!("proxy protocol header received: {has_header}");
+
+    let mut received = vec![0; client_hello.len()];
+    let relayed = backend_stream.read_exact(&mut received).is_ok() && received == client_hello;
+    println!("ClientHello relayed after the header: {relayed}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    if has_header && relayed {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// A backend answering with a failure status of its cluster is taken out of the
+/// rotation: after its first 503, the requests all go to the other backend
+pub fn try_failure_status_codes() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, _) = setup_async_test(
+        "FAILURE-STATUS",
+        config,
+        listeners,
+        state,
+        front_address,
+        0,
+        false,
+    );
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        failure_status_codes: vec![503],
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+
+    let failing_address = create_local_address();
+    let healthy_address = create_local_address();
+    let mut failing = AsyncBackend::spawn_detached_backend(
+        "FAILING",
+        failing_address,
+        SimpleAggregator::default(),
+        AsyncBackend::http_status_handler("HTTP/1.1 503 Service Unavailable", "busy"),
+    );
+    let mut healthy = AsyncBackend::spawn_detached_backend(
+        "HEALTHY",
+        healthy_address,
         SimpleAggregator::default(),
         AsyncBackend::http_handler("pong"),
     );
+    for (index, address) in [failing_address, healthy_address].iter().enumerate() {
+        worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+            "cluster_0",
+            format!("cluster_0-{index}"),
+            address.to_string(),
+            None,
+        )));
+    }
+    worker.read_to_last();
 
-    let client = build_https_client();
-    let request = client.get(
-        format!("https://{hostname}:{front_port}/api")
-            .parse()
-            .unwrap(),
-    );
-    if let Some((status, body)) = resolve_request(request) {
-        println!("response status: {status:?}");
-        println!("response body: {body}");
-    } else {
-        return State::Fail;
+    let mut responses = Vec::new();
+    for index in 0..4 {
+        let mut client = Client::new(
+            format!("client{index}"),
+            front_address,
+            http_request("GET", "/api", "ping", "localhost"),
+        );
+        client.connect();
+        client.send();
+        let response = client.receive();
+        println!("response {index}: {response:?}");
+        responses.push(response);
     }
 
     worker.soft_stop();
-    let success = worker.wait_for_server_stop();
+    worker.wait_for_server_stop();
 
-    let aggregator = backend
+    let failing_aggregator = failing
+        .stop_and_get_aggregator()
+        .expect("Could not get aggregator");
+    let healthy_aggregator = healthy
         .stop_and_get_aggregator()
         .expect("Could not get aggregator");
     println!(
-        "{} sent: {}, received: {}",
-        backend.name, aggregator.responses_sent, aggregator.requests_received
+        "failing backend received {}, healthy backend received {}",
+        failing_aggregator.requests_received, healthy_aggregator.requests_received
     );
 
-    if success && aggregator.responses_sent == 1 {
+    let failures = responses
+        .iter()
+        .filter(|response| {
+            response
+                .as_deref()
+                .unwrap_or("")
+                .starts_with("HTTP/1.1 503")
+        })
+        .count();
+    let successes = responses
+        .iter()
+        .filter(|response| is_http_ok_response(response.as_deref(), "pong"))
+        .count();
+    if failing_aggregator.requests_received == 1 && failures == 1 && successes == 3 {
         State::Success
     } else {
         State::Fail
     }
 }
 
-pub fn test_upgrade() -> State {
+/// A redirect of the backend to its own address is rewritten to the host the client asked for
+pub fn try_rewrite_location() -> State {
     let front_address = create_local_address();
+    let back_address = create_local_address();
 
     let (config, listeners, state) = Worker::empty_config();
-    let (mut worker, mut backends) =
-        setup_sync_test("UPGRADE", config, listeners, state, front_address, 1, false);
+    let (mut worker, _) = setup_sync_test(
+        "REWRITE-LOCATION",
+        config,
+        listeners,
+        state,
+        front_address,
+        0,
+        false,
+    );
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        rewrite_location: Some(true),
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new(
+        "backend",
+        back_address,
+        format!(
+            "HTTP/1.1 302 Found\r\nLocation: http://{back_address}/login?next=/api\r\nContent-Length: 0\r\n\r\n"
+        ),
+    );
+    backend.connect();
 
-    let mut backend = backends.pop().expect("backend");
     let mut client = Client::new(
         "client",
         front_address,
         http_request("GET", "/api", "ping", "localhost"),
     );
-
-    backend.connect();
     client.connect();
     client.send();
     backend.accept(0);
     backend.receive(0);
     backend.send(0);
-    match client.receive() {
-        Some(msg) => println!("response: {msg}"),
-        None => return State::Fail,
-    }
+    let response = client.receive();
+    println!("response: {response:?}");
 
-    client.send();
-    backend.receive(0);
-    let mut new_worker = worker.upgrade("NEW_WORKER");
-    thread::sleep(Duration::from_millis(100));
-    backend.send(0);
-    match client.receive() {
-        Some(msg) => println!("response: {msg}"),
-        None => return State::Fail,
-    }
-    client.connect();
-    client.send();
-    println!("ACCEPTING...");
-    backend.accept(1);
-    backend.receive(1);
-    backend.send(1);
-    match client.receive() {
-        Some(msg) => println!("response: {msg}"),
-        None => return State::Fail,
-    }
+    worker.soft_stop();
+    worker.wait_for_server_stop();
 
-    new_worker.soft_stop();
-    if !worker.wait_for_server_stop() {
-        return State::Fail;
-    }
-    if !new_worker.wait_for_server_stop() {
-        return State::Fail;
+    match response {
+        Some(response)
+            if response.starts_with("HTTP/1.1 302 Found\r\n")
+                && response.contains("\r\nLocation: http://localhost/login?next=/api\r\n") =>
+        {
+            State::Success
+        }
+        _ => State::Fail,
     }
+}
 
-    println!(
-        "{} sent: {}, received: {}",
-        client.name, client.requests_sent, client.responses_received
-    );
-    println!(
-        "{} sent: {}, received: {}",
-        backend.name, backend.responses_sent, backend.requests_received
-    );
+/// The cookies set by the backend get the domain the client asked for and the path prefix of the cluster
+pub fn try_rewrite_set_cookie() -> State {
+    let front_address = create_local_address();
+    let back_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, _) = setup_sync_test(
+        "REWRITE-SET-COOKIE",
+        config,
+        listeners,
+        state,
+        front_address,
+        0,
+        false,
+    );
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        rewrite_cookie_domain: Some(true),
+        cookie_path_prefix: Some(String::from("/app")),
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new(
+        "backend",
+        back_address,
+        "HTTP/1.1 200 OK\r\nSet-Cookie: session=abc; Domain=backend.local; Path=/; HttpOnly\r\nSet-Cookie: theme=dark; Path=/settings\r\nContent-Length: 0\r\n\r\n",
+    );
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    backend.receive(0);
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    match response {
+        Some(response)
+            if response.starts_with("HTTP/1.1 200 OK\r\n")
+                && response.contains(
+                    "\r\nSet-Cookie: session=abc; Domain=localhost; Path=/app; HttpOnly\r\n",
+                )
+                && response.contains("\r\nSet-Cookie: theme=dark; Path=/app/settings\r\n") =>
+        {
+            State::Success
+        }
+        _ => State::Fail,
+    }
+}
+
+/// A TCP cluster expecting the proxy protocol strips the header
+/// and relays the rest of the stream to the backend
+pub fn try_tcp_expect_proxy_protocol() -> State {
+    let front_address = create_local_address();
+    let back_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("TCP-EXPECT-PROXY", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddTcpListener(
+        ListenerBuilder::new_tcp(front_address)
+            .with_expect_proxy(true)
+            .to_tcp(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Tcp.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        proxy_protocol: Some(ProxyProtocolConfig::ExpectHeader as i32),
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.send_proxy_request_type(RequestType::AddTcpFrontend(Worker::default_tcp_frontend(
+        "cluster_0",
+        front_address.to_string(),
+    )));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+    let backend = TcpListener::bind(back_address).expect("could not bind the backend");
+
+    let client_address: SocketAddr = "125.25.10.1:8080".parse().unwrap();
+    let mut request = HeaderV2::new(Command::Proxy, client_address, front_address).into_bytes();
+    request.extend_from_slice(b"ping");
+
+    let mut client = TcpStream::connect(front_address).expect("could not connect to sozu");
+    client
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("could not set read timeout");
+    client
+        .write_all(&request)
+        .expect("could not send the request");
+
+    let Some(mut backend_stream) = accept_with_timeout(&backend) else {
+        println!("the backend got no connection");
+        worker.soft_stop();
+        worker.wait_for_server_stop();
+        return State::Fail;
+    };
+    backend_stream
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("could not set read timeout");
+
+    let mut received = [0; 64];
+    let size = backend_stream.read(&mut received).unwrap_or(0);
+    println!("backend received: {:?}", &received[..size]);
+    backend_stream
+        .write_all(b"pong")
+        .expect("could not answer the client");
+    let mut response = [0; 4];
+    let answered = client.read_exact(&mut response).is_ok() && &response == b"pong";
+    println!("answer received: {answered}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    if &received[..size] == b"ping" && answered {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_tls_endpoint() -> State {
+    let front_port = provide_port();
+    let front_address: SocketAddr = format!("127.0.0.1:{}", front_port)
+        .parse()
+        .expect("could not parse front address");
+    let back_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("TLS-ENDPOINT", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpsListener(
+        ListenerBuilder::new_https(front_address)
+            .to_tls(None)
+            .unwrap(),
+    ));
+
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Https.into(),
+        from_scm: false,
+    }));
+
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+
+    let hostname = "localhost".to_string();
+    worker.send_proxy_request_type(RequestType::AddHttpsFrontend(RequestHttpFrontend {
+        hostname: hostname.to_owned(),
+        ..Worker::default_http_frontend("cluster_0", front_address)
+    }));
+
+    let certificate_and_key = CertificateAndKey {
+        certificate: String::from(include_str!("../../../lib/assets/local-certificate.pem")),
+        key: String::from(include_str!("../../../lib/assets/local-key.pem")),
+        certificate_chain: vec![], // in config.toml the certificate chain would be the same as the certificate
+        versions: vec![],
+        names: vec![],
+    };
+    let add_certificate = AddCertificate {
+        address: front_address.to_string(),
+        certificate: certificate_and_key,
+        expired_at: None,
+    };
+    worker.send_proxy_request_type(RequestType::AddCertificate(add_certificate));
+
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = AsyncBackend::spawn_detached_backend(
+        "BACKEND",
+        back_address,
+        SimpleAggregator::default(),
+        AsyncBackend::http_handler("pong"),
+    );
+
+    let client = build_https_client();
+    let request = client.get(
+        format!("https://{hostname}:{front_port}/api")
+            .parse()
+            .unwrap(),
+    );
+    if let Some((status, body)) = resolve_request(request) {
+        println!("response status: {status:?}");
+        println!("response body: {body}");
+    } else {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    let success = worker.wait_for_server_stop();
+
+    let aggregator = backend
+        .stop_and_get_aggregator()
+        .expect("Could not get aggregator");
+    println!(
+        "{} sent: {}, received: {}",
+        backend.name, aggregator.responses_sent, aggregator.requests_received
+    );
+
+    if success && aggregator.responses_sent == 1 {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn test_upgrade() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) =
+        setup_sync_test("UPGRADE", config, listeners, state, front_address, 1, false);
+
+    let mut backend = backends.pop().expect("backend");
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+
+    backend.connect();
+    client.connect();
+    client.send();
+    backend.accept(0);
+    backend.receive(0);
+    backend.send(0);
+    match client.receive() {
+        Some(msg) => println!("response: {msg}"),
+        None => return State::Fail,
+    }
+
+    client.send();
+    backend.receive(0);
+    let mut new_worker = worker.upgrade("NEW_WORKER");
+    thread::sleep(Duration::from_millis(100));
+    backend.send(0);
+    match client.receive() {
+        Some(msg) => println!("response: {msg}"),
+        None => return State::Fail,
+    }
+    client.connect();
+    client.send();
+    println!("ACCEPTING...");
+    backend.accept(1);
+    backend.receive(1);
+    backend.send(1);
+    match client.receive() {
+        Some(msg) => println!("response: {msg}"),
+        None => return State::Fail,
+    }
+
+    new_worker.soft_stop();
+    if !worker.wait_for_server_stop() {
+        return State::Fail;
+    }
+    if !new_worker.wait_for_server_stop() {
+        return State::Fail;
+    }
+
+    println!(
+        "{} sent: {}, received: {}",
+        client.name, client.requests_sent, client.responses_received
+    );
+    println!(
+        "{} sent: {}, received: {}",
+        backend.name, backend.responses_sent, backend.requests_received
+    );
 
     State::Success
 }
@@ -775,7 +1333,7 @@ fn try_http_behaviors() -> State {
             && response.ends_with(&expected_response_end)
     );
 
-    info!("server closes, expecting 503");
+    info!("server closes, expecting 502");
     // TODO: what if the client continue to use the closed stream
     client.connect();
     client.send();
@@ -783,10 +1341,13 @@ fn try_http_behaviors() -> State {
     let request = backend.receive(0);
     backend.close(0);
 
+    let expected_response = String::from(
+        "HTTP/1.1 502 Bad Gateway\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    );
     let response = client.receive();
     println!("request: {request:?}");
     println!("response: {response:?}");
-    assert_eq!(response, Some(default_503_answer()));
+    assert_eq!(response, Some(expected_response));
     assert_eq!(client.receive(), None);
 
     worker.send_proxy_request_type(RequestType::RemoveBackend(RemoveBackend {
@@ -1159,12 +1720,320 @@ pub fn try_keep_alive() -> State {
     State::Success
 }
 
-pub fn try_stick() -> State {
+/// A client sending "Connection: close" gets a single response, telling it the connection
+/// closes even if the backend would keep it alive, and the pipelined requests are dropped
+pub fn try_client_connection_close() -> State {
     let front_address = create_local_address();
 
     let (config, listeners, state) = Worker::empty_config();
-    let (mut worker, mut backends) =
-        setup_sync_test("STICK", config, listeners, state, front_address, 2, true);
+    let (mut worker, mut backends) = setup_sync_test(
+        "CLIENT-CLOSE",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+
+    let mut backend = backends.pop().unwrap();
+    backend
+        .set_response("HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: keep-alive\r\n\r\npong");
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\nGET /pipelined HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    // the session is closed instead of waiting for the next request
+    let next_response = client.receive();
+    println!("next response: {next_response:?}");
+    let backend_connected = backend.is_connected(0);
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let single_request = matches!(&request, Some(request) if !request.contains("/pipelined"));
+    match response {
+        Some(response)
+            if single_request
+                && next_response.is_none()
+                && !backend_connected
+                && client.responses_received == 1
+                && response.starts_with("HTTP/1.1 200 OK\r\n")
+                && response.contains("\r\nConnection: close\r\n")
+                && !response.contains("keep-alive") =>
+        {
+            State::Success
+        }
+        _ => State::Fail,
+    }
+}
+
+/// A backend closing the connection without answering anything is reported as a 502,
+/// and counted in the `backend.empty_response` metric of the backend
+pub fn try_backend_empty_response() -> State {
+    use sozu_command_lib::proto::command::{
+        filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+    };
+
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "EMPTY-RESPONSE",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.close(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.send_proxy_request_type(RequestType::QueryMetrics(QueryMetricsOptions {
+        list: false,
+        cluster_ids: vec![],
+        backend_ids: vec![String::from("cluster_0-0")],
+        metric_names: vec![String::from("backend.empty_response")],
+    }));
+    let metrics = worker.read_proxy_response();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let empty_responses = metrics
+        .and_then(|response| response.content)
+        .and_then(|content| content.content_type)
+        .and_then(|content_type| match content_type {
+            ContentType::WorkerMetrics(worker_metrics) => worker_metrics
+                .clusters
+                .get("cluster_0")
+                .and_then(|cluster| cluster.backends.first())
+                .and_then(|backend| backend.metrics.get("backend.empty_response"))
+                .and_then(|metric| metric.inner.clone()),
+            _ => None,
+        });
+    println!("backend.empty_response: {empty_responses:?}");
+
+    let expected_response =
+        "HTTP/1.1 502 Bad Gateway\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if request.is_some()
+        && response.as_deref() == Some(expected_response)
+        && empty_responses == Some(Inner::Count(1))
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// The cluster has three backends refusing the connections before a working one,
+/// round robin tries them first: the request only succeeds if the cluster allows
+/// more than three connection attempts
+pub fn try_cluster_connection_retries(connection_retries: Option<u32>) -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("CONNECTION-RETRIES", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        connection_retries,
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    // nothing listens on the addresses of the first backends
+    let back_address = create_local_address();
+    for i in 0..3 {
+        worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+            "cluster_0",
+            format!("cluster_0-{i}"),
+            create_local_address().to_string(),
+            None,
+        )));
+    }
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-3",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    // the connections to the other backends fail before this one is tried
+    if (0..10).any(|_| backend.accept(0)) {
+        let request = backend.receive(0);
+        println!("request: {request:?}");
+        backend.send(0);
+    }
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let reached_backend = backend.requests_received == 1;
+    match (connection_retries, response) {
+        (Some(retries), Some(response)) if retries > 3 => {
+            if reached_backend && response.starts_with("HTTP/1.1 200 OK") {
+                State::Success
+            } else {
+                State::Fail
+            }
+        }
+        (_, Some(response)) if !reached_backend && response == default_503_answer() => {
+            State::Success
+        }
+        _ => State::Fail,
+    }
+}
+
+/// the first listener accepts one session at a time, the second one has no limit
+pub fn try_listener_max_sessions() -> State {
+    let limited_address = create_local_address();
+    let other_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("MAX-SESSIONS", config, &listeners, state);
+
+    for (address, max_sessions) in [(limited_address, Some(1)), (other_address, None)] {
+        worker.send_proxy_request_type(RequestType::AddHttpListener(
+            ListenerBuilder::new_http(address)
+                .with_max_sessions(max_sessions)
+                .to_http(None)
+                .unwrap(),
+        ));
+        worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+            address: address.to_string(),
+            proxy: ListenerType::Http.into(),
+            from_scm: false,
+        }));
+        worker.send_proxy_request_type(RequestType::AddHttpFrontend(
+            Worker::default_http_frontend("cluster_0", address),
+        ));
+    }
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    let mut exchange = |client: &mut Client, backend_client: usize| {
+        client.connect();
+        client.send();
+        if (0..10).any(|_| backend.accept(backend_client)) {
+            backend.receive(backend_client);
+            backend.send(backend_client);
+        }
+        client.receive()
+    };
+    let request = http_request("GET", "/api", "ping", "localhost");
+
+    // the kept alive session of this client takes the only slot of the limited listener
+    let mut first_client = Client::new("first", limited_address, &request);
+    let first_response = exchange(&mut first_client, 0);
+
+    // the connection of this client waits in the listen queue
+    let mut waiting_client = Client::new("waiting", limited_address, &request);
+    waiting_client.connect();
+    waiting_client.send();
+    let waiting_response = waiting_client.receive();
+
+    // the other listener still accepts sessions
+    let mut other_client = Client::new("other", other_address, &request);
+    let other_response = exchange(&mut other_client, 1);
+
+    // the waiting connection is accepted once the first session is closed
+    first_client.disconnect();
+    let mut released_response = None;
+    if (0..10).any(|_| backend.accept(2)) {
+        backend.receive(2);
+        backend.send(2);
+        released_response = waiting_client.receive();
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    println!("first response: {first_response:?}");
+    println!("waiting response: {waiting_response:?}");
+    println!("other response: {other_response:?}");
+    println!("released response: {released_response:?}");
+    if is_http_ok_response(first_response.as_deref(), "pong")
+        && waiting_response.is_none()
+        && is_http_ok_response(other_response.as_deref(), "pong")
+        && is_http_ok_response(released_response.as_deref(), "pong")
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_stick() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) =
+        setup_sync_test("STICK", config, listeners, state, front_address, 2, true);
 
     let mut backend2 = backends.pop().unwrap();
     let mut backend1 = backends.pop().unwrap();
@@ -1233,6 +2102,55 @@ pub fn try_stick() -> State {
     State::Success
 }
 
+/// the backend of the sticky session is down: the request goes to the other
+/// backend and the client gets a new sticky cookie
+pub fn try_stick_fallback() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "STICK-FALLBACK",
+        config,
+        listeners,
+        state,
+        front_address,
+        2,
+        true,
+    );
+
+    // the first backend never listens
+    let mut backend2 = backends.pop().unwrap();
+    backend2.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nCookie: SOZUBALANCEID=sticky_cluster_0-0\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend2.accept(0);
+    let request = backend2.receive(0);
+    println!("request: {request:?}");
+    backend2.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    match response {
+        Some(response)
+            if response.starts_with(
+                "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nSet-Cookie: SOZUBALANCEID=sticky_cluster_0-1; Path=/\r\n",
+            ) =>
+        {
+            State::Success
+        }
+        _ => State::Fail,
+    }
+}
+
 fn try_max_connections() -> State {
     let front_address = create_local_address();
 
@@ -1455,310 +2373,4535 @@ pub fn try_status_header_split() -> State {
     State::Success
 }
 
-fn try_wildcard() -> State {
-    use sozu_command_lib::proto::command::{PathRule, RulePosition};
+pub fn try_expect_continue_authorization() -> State {
     let front_address = create_local_address();
 
     let (config, listeners, state) = Worker::empty_config();
-    let mut worker = Worker::start_new_worker("WLD_CRD", config, &listeners, state);
-    worker.send_proxy_request(
-        RequestType::AddHttpListener(
-            ListenerBuilder::new_http(front_address)
-                .to_http(None)
-                .unwrap(),
-        )
-        .into(),
-    );
-    worker.send_proxy_request(
-        RequestType::ActivateListener(ActivateListener {
-            address: front_address.to_string(),
-            proxy: ListenerType::Http.into(),
-            from_scm: false,
-        })
-        .into(),
-    );
+    let (mut worker, mut backends) =
+        setup_sync_test("EXPECT", config, listeners, state, front_address, 1, false);
+    let mut backend = backends.pop().unwrap();
 
-    worker.send_proxy_request(
-        RequestType::AddCluster(Worker::default_cluster("cluster_0", false)).into(),
-    );
-    worker.send_proxy_request(
-        RequestType::AddCluster(Worker::default_cluster("cluster_1", false)).into(),
-    );
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        expect_continue_authorizations: vec!["Bearer secret".to_string()],
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.read_to_last();
 
-    worker.send_proxy_request(
-        RequestType::AddHttpFrontend(RequestHttpFrontend {
-            cluster_id: Some("cluster_0".to_string()),
-            address: front_address.to_string(),
-            hostname: String::from("*.sozu.io"),
-            path: PathRule::prefix(String::from("")),
-            position: RulePosition::Tree.into(),
-            ..Default::default()
-        })
-        .into(),
-    );
+    backend.connect();
 
-    let back_address: SocketAddr = create_local_address();
-    worker.send_proxy_request(
-        RequestType::AddBackend(Worker::default_backend(
-            "cluster_0",
-            "cluster_0-0",
-            back_address.to_string(),
-            None,
-        ))
-        .into(),
+    let expect_request = |authorization: &str| {
+        format!(
+            "POST /api HTTP/1.1\r\nHost: localhost\r\nAuthorization: {authorization}\r\nExpect: 100-continue\r\nContent-Length: 4\r\n\r\n"
+        )
+    };
+
+    // unauthorized: answered before the body is sent, the backend sees nothing
+    let mut client = Client::new("client", front_address, expect_request("Bearer guess"));
+    client.connect();
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 403 Forbidden"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+    if backend.accept(0) {
+        println!("the backend should not receive an unauthorized request");
+        return State::Fail;
+    }
+
+    // authorized: the backend sends "100 Continue" and receives the body
+    let mut client = Client::new("client", front_address, expect_request("Bearer secret"));
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    if !request
+        .map(|request| request.contains("Expect: 100-continue"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    backend.set_response("HTTP/1.1 100 Continue\r\n\r\n");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 100 Continue"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    client.set_request("ping");
+    client.send();
+    let body = backend.receive(0);
+    println!("body: {body:?}");
+    if body.as_deref() != Some("ping") {
+        return State::Fail;
+    }
+
+    backend.set_response(http_ok_response("pong"));
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 200 OK"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_slow_request_headers() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("SLOW-HEADERS", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_min_request_header_rate(Some(100))
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+    let request = http_request("GET", "/api", "ping", "localhost");
+
+    // a request sent at once is not affected
+    let mut client = Client::new("client", front_address, &request);
+    client.connect();
+    client.send();
+    if backend.accept(0) {
+        backend.receive(0);
+        backend.send(0);
+    }
+    let fast_response = client.receive();
+    println!("fast response: {fast_response:?}");
+
+    // about 10 bytes per second, the receive timeout of the client paces the sends
+    let mut slow_client = Client::new("slow client", front_address, "");
+    slow_client.connect();
+    let mut slow_response = None;
+    for byte in request.chars() {
+        slow_client.set_request(byte.to_string());
+        slow_client.send();
+        slow_response = slow_client.receive();
+        if slow_response.is_some() {
+            break;
+        }
+    }
+    println!("slow response: {slow_response:?}");
+    // the session is closed after the answer
+    let closed = slow_client.receive().is_none();
+    let reached_backend = backend.accept(1);
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    if is_http_ok_response(fast_response.as_deref(), "pong")
+        && slow_response
+            .map(|response| response.starts_with("HTTP/1.1 408"))
+            .unwrap_or(false)
+        && closed
+        && !reached_backend
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// With max_pipelined_requests set, a client pipelining more requests than that
+/// is not read until the responses are sent, and gets all of them in order
+pub fn try_pipelined_requests_limit() -> State {
+    use sozu_command_lib::proto::command::{
+        filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+    };
+
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("PIPELINING", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_max_pipelined_requests(Some(1))
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    // the blank lines of the bodies are not request heads
+    let request = http_request("POST", "/api", "ping\r\n\r\npong", "localhost");
+    let mut client = Client::new("client", front_address, request.repeat(4));
+    client.connect();
+    client.send();
+
+    // the requests are forwarded one at a time, each once the previous one is answered
+    let mut forwarded = 0;
+    if backend.accept(0) {
+        while forwarded < 4 && backend.receive(0).is_some() {
+            forwarded += 1;
+            backend.send(0);
+        }
+    }
+
+    let mut responses = String::new();
+    while responses.matches("HTTP/1.1 200").count() < 4 {
+        match client.receive() {
+            Some(response) => responses.push_str(&response),
+            None => break,
+        }
+    }
+    println!("forwarded requests: {forwarded}");
+    println!("responses: {responses:?}");
+
+    worker.send_proxy_request_type(RequestType::QueryMetrics(QueryMetricsOptions {
+        list: false,
+        cluster_ids: vec![],
+        backend_ids: vec![],
+        metric_names: vec![String::from("http.pipelining_paused")],
+    }));
+    let metrics = worker.read_proxy_response();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let paused = metrics
+        .and_then(|response| response.content)
+        .and_then(|content| content.content_type)
+        .and_then(|content_type| match content_type {
+            ContentType::WorkerMetrics(worker_metrics) => worker_metrics
+                .proxy
+                .get("http.pipelining_paused")
+                .and_then(|metric| metric.inner.clone()),
+            _ => None,
+        });
+    println!("http.pipelining_paused: {paused:?}");
+
+    if forwarded == 4
+        && responses.matches("HTTP/1.1 200").count() == 4
+        && matches!(paused, Some(Inner::Count(count)) if count > 0)
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// In lame duck mode, a request in flight is answered with "Connection: close"
+/// and its session closed, new connections are closed right away until the mode
+/// is turned off
+pub fn try_lame_duck() -> State {
+    use sozu_command_lib::proto::command::LameDuck;
+
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "LAME-DUCK",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
     );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let request = http_request("GET", "/api", "ping", "localhost");
+    let mut client = Client::new("client", front_address, &request);
+    client.connect();
+    client.send();
+    let in_flight = backend.accept(0) && backend.receive(0).is_some();
+
+    worker.send_proxy_request_type(RequestType::LameDuck(LameDuck { enabled: true }));
+    worker.read_to_last();
+
+    // the connection of this client is closed instead of waiting in the listen queue
+    let mut refused_stream = TcpStream::connect(front_address).expect("could not connect");
+    refused_stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .expect("could not set the read timeout");
+    let _ = refused_stream.write_all(request.as_bytes());
+    let refused = match refused_stream.read(&mut [0; 1024]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(error) => !matches!(
+            error.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ),
+    };
+
+    backend.send(0);
+    let response = client.receive();
+    let closed = client.receive().is_none();
+
+    worker.send_proxy_request_type(RequestType::LameDuck(LameDuck { enabled: false }));
+    worker.read_to_last();
+    let mut new_client = Client::new("new client", front_address, &request);
+    new_client.connect();
+    new_client.send();
+    let mut new_response = None;
+    if (0..10).any(|_| backend.accept(1)) {
+        backend.receive(1);
+        backend.send(1);
+        new_response = new_client.receive();
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    println!("response: {response:?}");
+    println!("refused: {refused}");
+    println!("new response: {new_response:?}");
+    if in_flight
+        && response
+            .map(|response| {
+                response.starts_with("HTTP/1.1 200 OK")
+                    && response.contains("Connection: close\r\n")
+            })
+            .unwrap_or(false)
+        && closed
+        && refused
+        && is_http_ok_response(new_response.as_deref(), "pong0")
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// The health_check_path of the listener is answered without a cluster,
+/// the other paths are routed as usual
+pub fn try_listener_health_check() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("HEALTH-CHECK", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_health_check_path(Some("/_lb_health"))
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
     worker.read_to_last();
 
-    let mut backend0 = SyncBackend::new(
-        "BACKEND_0",
-        back_address,
-        http_ok_response(format!("pong0")),
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    // the health check does not need the cluster, nor a known hostname
+    let mut health_client = Client::new(
+        "health client",
+        front_address,
+        "GET /_lb_health?from=lb HTTP/1.1\r\nHost: 10.0.0.1\r\n\r\n",
+    );
+    health_client.connect();
+    health_client.send();
+    let health_response = health_client.receive();
+    let reached_backend = backend.accept(0);
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    if (0..10).any(|_| backend.accept(0)) {
+        backend.receive(0);
+        backend.send(0);
+    }
+    let response = client.receive();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    println!("health response: {health_response:?}");
+    println!("response: {response:?}");
+    if health_response
+        .map(|response| {
+            response.starts_with("HTTP/1.1 200 OK\r\n")
+                && response.ends_with("Content-Length: 0\r\n\r\n")
+        })
+        .unwrap_or(false)
+        && !reached_backend
+        && is_http_ok_response(response.as_deref(), "pong")
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// A client sends three requests on the same connection then closes it,
+/// the histogram of requests per connection records a single value of 3
+pub fn try_keepalive_requests_metric() -> State {
+    use sozu_command_lib::proto::command::{
+        filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+    };
+
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "KEEPALIVE-METRIC",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+
+    let mut responses = 0;
+    for i in 0..3 {
+        client.send();
+        if i == 0 {
+            backend.accept(0);
+        }
+        backend.receive(0);
+        backend.send(0);
+        if client.receive().is_some() {
+            responses += 1;
+        }
+    }
+    client.disconnect();
+    thread::sleep(Duration::from_millis(100));
+
+    worker.send_proxy_request_type(RequestType::QueryMetrics(QueryMetricsOptions {
+        list: false,
+        cluster_ids: vec![String::from("cluster_0")],
+        backend_ids: vec![],
+        metric_names: vec![String::from("http.keepalive.requests")],
+    }));
+    let metrics = worker.read_proxy_response();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let requests_per_connection = metrics
+        .and_then(|response| response.content)
+        .and_then(|content| content.content_type)
+        .and_then(|content_type| match content_type {
+            ContentType::WorkerMetrics(worker_metrics) => worker_metrics
+                .clusters
+                .get("cluster_0")
+                .and_then(|cluster| cluster.cluster.get("http.keepalive.requests"))
+                .and_then(|metric| metric.inner.clone()),
+            _ => None,
+        });
+    println!("http.keepalive.requests: {requests_per_connection:?}");
+
+    if responses == 3
+        && matches!(
+            requests_per_connection,
+            Some(Inner::Percentiles(percentiles))
+                if percentiles.samples == 1 && percentiles.p_100 == 3
+        )
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// Three requests on a keep-alive session: the first one opens a backend connection,
+/// the second one reuses it, the third one goes to another cluster and replaces it
+pub fn try_backend_connection_reuse() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "BACKEND-REUSE",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let other_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_1",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(RequestHttpFrontend {
+        hostname: "example.com".to_owned(),
+        ..Worker::default_http_frontend("cluster_1", front_address)
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_1",
+        "cluster_1-0",
+        other_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = backends.pop().unwrap();
+    let mut other_backend =
+        SyncBackend::new("OTHER_BACKEND", other_address, http_ok_response("pong1"));
+    backend.connect();
+    other_backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+
+    // new
+    client.send();
+    let opened = backend.accept(0);
+    backend.receive(0);
+    backend.send(0);
+    let first_response = client.receive();
+
+    // reused: no other connection to accept
+    client.send();
+    let reopened = backend.accept(1);
+    let reused_request = backend.receive(0);
+    backend.send(0);
+    let second_response = client.receive();
+
+    // replaced: the connection to the first backend is closed
+    client.set_request(http_request("GET", "/api", "ping", "example.com"));
+    client.send();
+    let replaced = other_backend.accept(0);
+    other_backend.receive(0);
+    other_backend.send(0);
+    let third_response = client.receive();
+    let first_still_connected = backend.is_connected(0);
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    println!(
+        "opened: {opened}, reopened: {reopened}, replaced: {replaced}, first still connected: {first_still_connected}"
+    );
+    let answered = [first_response, second_response, third_response]
+        .iter()
+        .all(|response| matches!(response, Some(response) if response.starts_with("HTTP/1.1 200")));
+
+    if answered
+        && opened
+        && !reopened
+        && reused_request.is_some()
+        && replaced
+        && !first_still_connected
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// A client receives the same simple response twice, the histogram of the slices
+/// each response was written from records their known number twice
+pub fn try_response_slices_metric() -> State {
+    use sozu_command_lib::proto::command::{
+        filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+    };
+
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "SLICES-METRIC",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+
+    let mut responses = Vec::new();
+    for i in 0..2 {
+        client.send();
+        if i == 0 {
+            backend.accept(0);
+        }
+        backend.receive(0);
+        backend.send(0);
+        responses.extend(client.receive());
+    }
+
+    // the status line is written from 6 slices (version, space, status, space, reason
+    // and line ending), each header from 4 (name, separator, value and line ending),
+    // then 1 for the end of the head and 1 for the body, read at once
+    let expected_slices = responses.first().map(|response| {
+        let header_count = response
+            .split("\r\n")
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .count();
+        6 + 4 * header_count as u64 + 2
+    });
+    println!("expected slices: {expected_slices:?}");
+
+    worker.send_proxy_request_type(RequestType::QueryMetrics(QueryMetricsOptions {
+        list: false,
+        cluster_ids: vec![String::from("cluster_0")],
+        backend_ids: vec![],
+        metric_names: vec![String::from("http.response.slices")],
+    }));
+    let metrics = worker.read_proxy_response();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let slices = metrics
+        .and_then(|response| response.content)
+        .and_then(|content| content.content_type)
+        .and_then(|content_type| match content_type {
+            ContentType::WorkerMetrics(worker_metrics) => worker_metrics
+                .clusters
+                .get("cluster_0")
+                .and_then(|cluster| cluster.cluster.get("http.response.slices"))
+                .and_then(|metric| metric.inner.clone()),
+            _ => None,
+        });
+    println!("http.response.slices: {slices:?}");
+
+    if responses.len() == 2
+        && matches!(
+            (slices, expected_slices),
+            (Some(Inner::Percentiles(percentiles)), Some(expected_slices))
+                if percentiles.samples == 2
+                    && percentiles.p_50 == expected_slices
+                    && percentiles.p_100 == expected_slices
+        )
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// The backend answers with non-standard reason phrases: with normalize_reason_phrase,
+/// the reason of a 200 is replaced by "OK" but the one of an unknown status is kept
+pub fn try_normalize_reason_phrase(normalize_reason_phrase: bool) -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("REASON-PHRASE", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_normalize_reason_phrase(normalize_reason_phrase)
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0".to_string(),
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new(
+        "backend",
+        back_address,
+        "HTTP/1.1 200 Everything Is Fine\r\nContent-Length: 4\r\n\r\npong",
+    );
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    backend.receive(0);
+    backend.send(0);
+    let known = client.receive();
+    println!("known status: {known:?}");
+
+    backend.set_response("HTTP/1.1 299 Custom Status\r\nContent-Length: 4\r\n\r\npong");
+    client.send();
+    backend.receive(0);
+    backend.send(0);
+    let unknown = client.receive();
+    println!("unknown status: {unknown:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let expected_known = if normalize_reason_phrase {
+        "HTTP/1.1 200 OK\r\n"
+    } else {
+        "HTTP/1.1 200 Everything Is Fine\r\n"
+    };
+    let known_ok = matches!(&known, Some(response) if response.starts_with(expected_known));
+    let unknown_ok = matches!(&unknown, Some(response) if response.starts_with("HTTP/1.1 299 Custom Status\r\n"));
+    if known_ok && unknown_ok {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// The frontend only allows the "chat" websocket subprotocol: an upgrade offering "mqtt"
+/// and "chat" reaches the backend with only "chat" and is switched, one offering only
+/// "mqtt" is answered with a 400
+pub fn try_websocket_subprotocols() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("WEBSOCKET-SUBPROTOCOLS", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(RequestHttpFrontend {
+        websocket_subprotocols: vec![String::from("chat")],
+        ..Worker::default_http_frontend("cluster_0", front_address)
+    }));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0".to_string(),
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new(
+        "backend",
+        back_address,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\nSec-WebSocket-Protocol: chat\r\n\r\n",
+    );
+    backend.connect();
+
+    let upgrade = |protocol: &str| {
+        format!(
+            "GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: {protocol}\r\n\r\n"
+        )
+    };
+
+    // the disallowed subprotocol is dropped from the offer
+    let mut allowed_client = Client::new("allowed_client", front_address, upgrade("mqtt, chat"));
+    allowed_client.connect();
+    allowed_client.send();
+    backend.accept(0);
+    let forwarded = backend.receive(0);
+    println!("forwarded upgrade: {forwarded:?}");
+    backend.send(0);
+    let switched = allowed_client.receive();
+    println!("allowed subprotocol: {switched:?}");
+
+    let mut disallowed_client = Client::new("disallowed_client", front_address, upgrade("mqtt"));
+    disallowed_client.connect();
+    disallowed_client.send();
+    let refused = disallowed_client.receive();
+    println!("disallowed subprotocol: {refused:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let forwarded_ok = matches!(&forwarded, Some(request) if request.contains("Sec-WebSocket-Protocol: chat\r\n") && !request.contains("mqtt"));
+    let switched_ok = matches!(&switched, Some(response) if response.starts_with("HTTP/1.1 101 "));
+    let refused_ok = matches!(&refused, Some(response) if response.starts_with("HTTP/1.1 400 "));
+    if forwarded_ok && switched_ok && refused_ok {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// With a max_uri_length of 1024 bytes, a request whose URI is 1000 bytes long
+/// is forwarded and one whose URI is 2000 bytes long is answered with a 414
+pub fn try_max_uri_length() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("MAX-URI-LENGTH", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_max_uri_length(Some(1024))
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0".to_string(),
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    let uri = |length: usize| format!("/{}", "a".repeat(length - 1));
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", uri(1000), "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let forwarded = backend.receive(0);
+    backend.send(0);
+    let accepted = client.receive();
+    println!("1000 bytes URI: {accepted:?}");
+
+    let mut long_client = Client::new(
+        "long_client",
+        front_address,
+        http_request("GET", uri(2000), "ping", "localhost"),
+    );
+    long_client.connect();
+    long_client.send();
+    let refused = long_client.receive();
+    println!("2000 bytes URI: {refused:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let expected_refused =
+        "HTTP/1.1 414 URI Too Long\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if forwarded.is_some()
+        && is_http_ok_response(accepted.as_deref(), "pong")
+        && refused.as_deref() == Some(expected_refused)
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// A client opening a TLS connection without sending its ClientHello is closed after
+/// the handshake_timeout of the listener, well before its request_timeout
+pub fn try_tls_handshake_timeout() -> State {
+    use sozu_command_lib::proto::command::{
+        filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+    };
+
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("HANDSHAKE-TIMEOUT", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpsListener(
+        ListenerBuilder::new_https(front_address)
+            .with_handshake_timeout(Some(1))
+            .with_request_timeout(Some(10))
+            .to_tls(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Https.into(),
+        from_scm: false,
+    }));
+    worker.read_to_last();
+
+    let start = Instant::now();
+    let mut client = TcpStream::connect(front_address).expect("could not connect to sozu");
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("could not set read timeout");
+    let mut buffer = [0; 16];
+    let closed = matches!(client.read(&mut buffer), Ok(0));
+    let elapsed = start.elapsed();
+    println!("closed: {closed}, after {elapsed:?}");
+
+    worker.send_proxy_request_type(RequestType::QueryMetrics(QueryMetricsOptions {
+        list: false,
+        cluster_ids: vec![],
+        backend_ids: vec![],
+        metric_names: vec![String::from("tls.handshake.timeout")],
+    }));
+    let metrics = worker.read_proxy_response();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let timeouts = metrics
+        .and_then(|response| response.content)
+        .and_then(|content| content.content_type)
+        .and_then(|content_type| match content_type {
+            ContentType::WorkerMetrics(worker_metrics) => worker_metrics
+                .proxy
+                .get("tls.handshake.timeout")
+                .and_then(|metric| metric.inner.clone()),
+            _ => None,
+        });
+    println!("tls.handshake.timeout: {timeouts:?}");
+
+    if closed
+        && elapsed >= Duration::from_millis(900)
+        && elapsed < Duration::from_secs(5)
+        && timeouts == Some(Inner::Count(1))
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// An HTTP/1.0 request without a Host header is routed with the http10_default_host
+/// of the listener, or answered with a 400 if it has none
+pub fn try_http10_default_host(http10_default_host: Option<&str>) -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test_with_listener(
+        "HTTP10-DEFAULT-HOST",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address).with_http10_default_host(http10_default_host),
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new("client", front_address, "GET /api HTTP/1.0\r\n\r\n");
+    client.connect();
+    client.send();
+    let forwarded = if http10_default_host.is_some() {
+        backend.accept(0);
+        let request = backend.receive(0);
+        backend.send(0);
+        request
+    } else {
+        None
+    };
+    let response = client.receive();
+    println!("forwarded: {forwarded:?}");
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let success = match http10_default_host {
+        Some(_) => forwarded.is_some() && is_http_ok_response(response.as_deref(), "pong"),
+        None => matches!(&response, Some(response) if response.starts_with("HTTP/1.1 400 ")),
+    };
+    if success {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_require_sni(require_sni: bool) -> State {
+    let front_address = create_local_address();
+    let back_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("REQUIRE-SNI", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpsListener(
+        ListenerBuilder::new_https(front_address)
+            .with_require_sni(Some(require_sni))
+            .to_tls(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Https.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpsFrontend(
+        Worker::default_http_frontend("cluster_0", front_address),
+    ));
+    worker.send_proxy_request_type(RequestType::AddCertificate(AddCertificate {
+        address: front_address.to_string(),
+        certificate: CertificateAndKey {
+            certificate: String::from(include_str!("../../../lib/assets/local-certificate.pem")),
+            key: String::from(include_str!("../../../lib/assets/local-key.pem")),
+            certificate_chain: vec![],
+            versions: vec![],
+            names: vec![],
+        },
+        expired_at: None,
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = AsyncBackend::spawn_detached_backend(
+        "BACKEND",
+        back_address,
+        SimpleAggregator::default(),
+        AsyncBackend::http_handler("pong"),
+    );
+
+    let request = format!(
+        "GET /api HTTP/1.1\r\nHost: localhost:{}\r\n\r\n",
+        front_address.port()
+    );
+    let without_sni = send_tls_request(build_no_sni_client_config(), front_address, &request);
+    println!("response without SNI: {without_sni:?}");
+    // clients sending a server name are not affected
+    let with_sni = send_tls_request(build_early_data_client_config(), front_address, &request);
+    println!("response with SNI: {with_sni:?}");
+
+    worker.soft_stop();
+    let success = worker.wait_for_server_stop();
+
+    let aggregator = backend
+        .stop_and_get_aggregator()
+        .expect("Could not get aggregator");
+
+    let without_sni_answered =
+        matches!(without_sni, Some((_, ref response)) if response.starts_with("HTTP/1.1 200"));
+    let expected_requests = if require_sni { 1 } else { 2 };
+    if success
+        && aggregator.requests_received == expected_requests
+        && without_sni_answered != require_sni
+        && matches!(with_sni, Some((_, ref response)) if response.starts_with("HTTP/1.1 200"))
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// Each request smuggling vector is answered with a 400, closes the connection
+/// and is counted in the `http.smuggling.detected` metric
+pub fn try_request_smuggling(request: &str) -> State {
+    use sozu_command_lib::proto::command::{
+        filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+    };
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "REQUEST-SMUGGLING",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new("client", front_address, request);
+    client.connect();
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    thread::sleep(Duration::from_millis(100));
+    let closed = !client.is_connected();
+    let forwarded = backend.accept(0);
+
+    worker.send_proxy_request_type(RequestType::QueryMetrics(QueryMetricsOptions {
+        list: false,
+        cluster_ids: vec![],
+        backend_ids: vec![],
+        metric_names: vec![String::from("http.smuggling.detected")],
+    }));
+    let metrics = worker.read_proxy_response();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let detected = metrics
+        .and_then(|response| response.content)
+        .and_then(|content| content.content_type)
+        .and_then(|content_type| match content_type {
+            ContentType::WorkerMetrics(worker_metrics) => worker_metrics
+                .proxy
+                .get("http.smuggling.detected")
+                .and_then(|metric| metric.inner.clone()),
+            _ => None,
+        });
+    println!("http.smuggling.detected: {detected:?}");
+
+    if response
+        .map(|response| response.starts_with("HTTP/1.1 400"))
+        .unwrap_or(false)
+        && closed
+        && !forwarded
+        && detected == Some(Inner::Count(1))
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// A backend erroneously sends a body with a 304, in the same write or after a delay:
+/// the client must only get the head, and the next request of the connection must not
+/// get the stray body
+pub fn try_stray_304_body(delayed_body: bool) -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "STRAY-304-BODY",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"v1\"\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    backend.receive(0);
+    if delayed_body {
+        backend.set_response("HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n");
+        backend.send(0);
+    } else {
+        backend.set_response("HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\nSTRAY_BODY");
+        backend.send(0);
+    }
+    let not_modified = client.receive();
+    println!("304 response: {not_modified:?}");
+    if delayed_body {
+        thread::sleep(Duration::from_millis(100));
+        backend.set_response("STRAY_BODY");
+        backend.send(0);
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // the connection to the backend is not reused
+    client.set_request("GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    client.send();
+    let reconnected = backend.accept(1);
+    let request = backend.receive(1);
+    println!("second request: {request:?}");
+    backend.set_response("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\npong");
+    backend.send(1);
+    let ok = client.receive();
+    println!("200 response: {ok:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let clean_304 = matches!(&not_modified, Some(response)
+        if response.starts_with("HTTP/1.1 304") && !response.contains("STRAY_BODY"));
+    let clean_200 = matches!(&ok, Some(response)
+        if response.starts_with("HTTP/1.1 200") && response.ends_with("pong"));
+    if clean_304 && reconnected && request.is_some() && clean_200 {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// A backend answers as soon as it accepts the connection and closes it: when sozu
+/// gets the connection event, the socket is hung up with the response readable. The
+/// connection did not fail, the client gets the response and it is not retried
+pub fn try_backend_answers_and_closes() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "ANSWER-AND-CLOSE",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+    backend.set_response("HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\npong");
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    backend.send(0);
+    backend.close(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    let retried = backend.accept(1);
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    if is_http_ok_response(response.as_deref(), "pong") && !retried {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// With a max_response_header_size of 1024 bytes, a response head of about 4 KB is
+/// answered with a 502, whether it arrives in one write with its body or split in two
+/// writes, the first one below the limit
+pub fn try_max_response_header_size(split_head: bool) -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test_with_listener(
+        "MAX-RESPONSE-HEADER-SIZE",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address).with_max_response_header_size(Some(1024)),
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    backend.receive(0);
+    backend.send(0);
+    let accepted = client.receive();
+    println!("small response head: {accepted:?}");
+
+    let filler = (0..40)
+        .map(|i| format!("X-Filler-{i}: {}\r\n", "a".repeat(80)))
+        .collect::<String>();
+    let large_response = format!("HTTP/1.1 200 OK\r\n{filler}Content-Length: 4\r\n\r\npong");
+    client.send();
+    backend.receive(0);
+    if split_head {
+        let (start, end) = large_response.split_at(512);
+        backend.set_response(start);
+        backend.send(0);
+        thread::sleep(Duration::from_millis(100));
+        backend.set_response(end);
+        backend.send(0);
+    } else {
+        backend.set_response(large_response);
+        backend.send(0);
+    }
+    let refused = client.receive();
+    println!("large response head: {refused:?}");
+    thread::sleep(Duration::from_millis(100));
+    let backend_closed = !backend.is_connected(0);
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let expected_refused =
+        "HTTP/1.1 502 Bad Gateway\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if is_http_ok_response(accepted.as_deref(), "pong")
+        && refused.as_deref() == Some(expected_refused)
+        && backend_closed
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_grpc_trailers() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "GRPC-TRAILERS",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    // a unary gRPC call: a 5 bytes message prefix for an empty message
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "POST /helloworld.Greeter/SayHello HTTP/1.1\r\nHost: localhost\r\nConnection: TE\r\nTE: trailers\r\nContent-Type: application/grpc\r\nContent-Length: 5\r\n\r\n\0\0\0\0\0",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let grpc_request = backend.receive(0);
+    println!("gRPC request: {grpc_request:?}");
+    backend.set_response(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/grpc\r\nTrailer: grpc-status\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n\0\0\0\0\0\r\n0\r\ngrpc-status: 0\r\n\r\n",
+    );
+    backend.send(0);
+    let grpc_response = client.receive();
+    println!("gRPC response: {grpc_response:?}");
+
+    // TE is still a hop-by-hop header for the other transfer codings
+    client.set_request("GET /api HTTP/1.1\r\nHost: localhost\r\nTE: gzip\r\n\r\n");
+    client.send();
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend
+        .set_response("HTTP/1.1 200 OK\r\nTrailer: grpc-status\r\nContent-Length: 4\r\n\r\npong");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let te_forwarded = matches!(&grpc_request, Some(request)
+        if request.contains("TE: trailers\r\n") && request.contains("Connection: TE\r\n"));
+    let trailers_delivered = matches!(&grpc_response, Some(response)
+        if response.starts_with("HTTP/1.1 200")
+            && response.contains("Trailer: grpc-status\r\n")
+            && response.ends_with("0\r\ngrpc-status: 0\r\n\r\n"));
+    let te_elided = matches!(&request, Some(request) if !request.contains("TE:"));
+    let trailer_elided = matches!(&response, Some(response)
+        if response.starts_with("HTTP/1.1 200") && !response.contains("Trailer:"));
+    if te_forwarded && trailers_delivered && te_elided && trailer_elided {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// With a max_loop_iterations of 1, the first session going through its loop is closed
+/// as stuck in an infinite loop, in the state it was handling
+pub fn try_max_loop_iterations() -> State {
+    use sozu_command_lib::proto::command::{
+        filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+    };
+
+    let front_address = create_local_address();
+
+    let config = Worker::into_config(FileConfig {
+        max_loop_iterations: Some(1),
+        ..Worker::empty_file_config()
+    });
+    let listeners = Worker::empty_listeners();
+    let state = ConfigState::new();
+    let (mut worker, mut backends) = setup_sync_test(
+        "MAX-LOOP-ITERATIONS",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    let closed = !client.is_connected();
+
+    worker.send_proxy_request_type(RequestType::QueryMetrics(QueryMetricsOptions {
+        list: false,
+        cluster_ids: vec![],
+        backend_ids: vec![],
+        metric_names: vec![
+            String::from("http.infinite_loop.error"),
+            String::from("http.infinite_loop.request"),
+        ],
+    }));
+    let metrics = worker.read_proxy_response();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let (errors, request_state) = metrics
+        .and_then(|response| response.content)
+        .and_then(|content| content.content_type)
+        .map(|content_type| match content_type {
+            ContentType::WorkerMetrics(worker_metrics) => {
+                let count = |name: &str| {
+                    worker_metrics
+                        .proxy
+                        .get(name)
+                        .and_then(|metric| metric.inner.clone())
+                };
+                (
+                    count("http.infinite_loop.error"),
+                    count("http.infinite_loop.request"),
+                )
+            }
+            _ => (None, None),
+        })
+        .unwrap_or_default();
+    println!("http.infinite_loop.error: {errors:?}, http.infinite_loop.request: {request_state:?}");
+
+    if response.is_none()
+        && closed
+        && errors == Some(Inner::Count(1))
+        && request_state == Some(Inner::Count(1))
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// With rewrite_absolute_form, a request with an absolute URI is routed with its host
+/// and forwarded in origin-form, with a Host header set to that host
+pub fn try_absolute_form_rewrite() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test_with_listener(
+        "ABSOLUTE-FORM",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address).with_rewrite_absolute_form(true),
+        1,
+        false,
+    );
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(RequestHttpFrontend {
+        hostname: "example.com".to_owned(),
+        ..Worker::default_http_frontend("cluster_0", front_address)
+    }));
+    worker.read_to_last();
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET http://example.com/x HTTP/1.1\r\nHost: example.com\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let rewritten = backend.receive(0);
+    backend.send(0);
+    let first_response = client.receive();
+
+    // the Host header of the client does not decide the routing
+    client.set_request("GET http://example.com/api?page=2 HTTP/1.1\r\nHost: other.com\r\n\r\n");
+    client.send();
+    let with_query = backend.receive(0);
+    backend.send(0);
+    let second_response = client.receive();
+
+    // origin-form is forwarded as is
+    client.set_request("GET /y HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    client.send();
+    let origin = backend.receive(0);
+    backend.send(0);
+    let third_response = client.receive();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    println!("requests: {rewritten:?} {with_query:?} {origin:?}");
+    let (Some(rewritten), Some(with_query), Some(origin)) = (rewritten, with_query, origin) else {
+        return State::Fail;
+    };
+    let answered = [first_response, second_response, third_response]
+        .iter()
+        .all(|response| matches!(response, Some(response) if response.starts_with("HTTP/1.1 200")));
+
+    if answered
+        && rewritten.starts_with("GET /x HTTP/1.1\r\n")
+        && rewritten.contains("\r\nHost: example.com\r\n")
+        && with_query.starts_with("GET /api?page=2 HTTP/1.1\r\n")
+        && with_query.contains("\r\nHost: example.com\r\n")
+        && !with_query.contains("other.com")
+        && origin.starts_with("GET /y HTTP/1.1\r\n")
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_chunked_body_limit() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test_with_listener(
+        "CHUNKED",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address).with_max_chunked_body_size(Some(16)),
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    // the limit only applies to chunked bodies, not to a larger Content-Length one
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Length: 32\r\n\r\n0123456789abcdef0123456789abcdef",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !request
+        .map(|request| request.ends_with("0123456789abcdef0123456789abcdef"))
+        .unwrap_or(false)
+        || !response
+            .map(|response| response.starts_with("HTTP/1.1 200"))
+            .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(1);
+    let request = backend.receive(1);
+    println!("request: {request:?}");
+
+    // this chunk goes over the limit of 16 bytes
+    client.set_request("4\r\nmore\r\n0\r\n\r\n");
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 413"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+    // the session is terminated
+    if client.receive().is_some() {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+/// With a max_chunk_header_length of 16 bytes, a chunk header with a long extension,
+/// or a chunk size line that never ends, is answered with a 400. A chunk larger
+/// than the max_chunk_size is answered with a 400 too
+pub fn try_chunk_header_limits() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test_with_listener(
+        "CHUNK-HEADER",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address)
+            .with_max_chunk_header_length(Some(16))
+            .with_max_chunk_size(Some(0xff)),
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let head = "POST /api HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+    let within_bounds = format!("{head}4;ext=val\r\nping\r\n0\r\n\r\n");
+    let long_extension = format!("{head}4;ext=a-much-longer-value\r\nping\r\n0\r\n\r\n");
+    let endless_size = format!("{head}{}", "0".repeat(64));
+    let large_chunk = format!("{head}100\r\n");
+
+    let mut responses = Vec::new();
+    for (client_id, request) in [within_bounds, long_extension, endless_size, large_chunk]
+        .into_iter()
+        .enumerate()
+    {
+        let mut client = Client::new(format!("client_{client_id}"), front_address, request);
+        client.connect();
+        client.send();
+        if client_id == 0 {
+            backend.accept(0);
+            backend.receive(0);
+            backend.send(0);
+        }
+        let response = client.receive();
+        println!("response {client_id}: {response:?}");
+        responses.push(response);
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let status = |response: &Option<String>, status: &str| matches!(response, Some(response) if response.starts_with(status));
+    if status(&responses[0], "HTTP/1.1 200")
+        && responses[1..]
+            .iter()
+            .all(|response| status(response, "HTTP/1.1 400"))
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_close_delimited_response_limit() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test_with_listener(
+        "CLOSE-DELIMITED",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address).with_max_close_delimited_response_size(Some(4096)),
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    // no Content-Length nor chunked encoding: the body ends when the backend closes
+    backend.set_response(format!(
+        "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n{}",
+        "a".repeat(1024)
+    ));
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping".to_string(), "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 200"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    // the backend keeps streaming beyond the limit of 4096 bytes
+    backend.set_response("b".repeat(8192));
+    backend.send(0);
+    // the session is terminated without forwarding it
+    if client.receive().is_some() {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_close_delimited_response_connection_close() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test_with_listener(
+        "CLOSE-DELIMITED-CONNECTION",
+        config,
+        listeners,
+        state,
+        ListenerBuilder::new_http(front_address),
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    // no Content-Length, no chunked encoding and no "Connection: close" either
+    backend.set_response("HTTP/1.1 200 OK\r\n\r\nclose-delimited body");
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping".to_string(), "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    match response {
+        Some(response)
+            if response.starts_with("HTTP/1.1 200")
+                && response.to_lowercase().contains("connection: close\r\n") => {}
+        _ => return State::Fail,
+    }
+
+    // the body ends with the backend connection, and the client connection with it
+    backend.close(0);
+    if client.receive().is_some() {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_chunked_request_split() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "SPLIT-CHUNKS",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+
+    backend.connect();
+
+    // chunks of 0x10, 0x100 and 0x7 bytes, cut in the middle of chunk sizes,
+    // chunk data and chunk delimiters
+    let body = format!(
+        "10\r\n{}\r\n100\r\n{}\r\n7\r\n{}\r\n0\r\n\r\n",
+        "q".repeat(0x10),
+        "q".repeat(0x100),
+        "q".repeat(0x7)
+    );
+    let expected = 0x10 + 0x100 + 0x7;
+    let cuts = [5, 21, 22, 150, 280, 290, body.len()];
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    let mut start = 0;
+    for cut in cuts {
+        client.set_request(&body[start..cut]);
+        client.send();
+        start = cut;
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    backend.accept(0);
+    let mut request = String::new();
+    while !request.ends_with("0\r\n\r\n") {
+        match backend.receive(0) {
+            Some(data) => request.push_str(&data),
+            None => break,
+        }
+    }
+    // "q" only appears in the chunk data
+    let received = request.matches('q').count();
+    println!("received {received} bytes of chunk data: {request:?}");
+    if received != expected || !request.ends_with("0\r\n\r\n") {
+        return State::Fail;
+    }
+
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !is_http_ok_response(response.as_deref(), "pong0") {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_tls_early_data() -> State {
+    let front_address = create_local_address();
+    let back_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("EARLY-DATA", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpsListener(
+        ListenerBuilder::new_https(front_address)
+            .with_max_early_data_size(Some(16384))
+            .to_tls(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Https.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpsFrontend(RequestHttpFrontend {
+        hostname: "localhost".to_owned(),
+        ..Worker::default_http_frontend("cluster_0", front_address)
+    }));
+    worker.send_proxy_request_type(RequestType::AddCertificate(AddCertificate {
+        address: front_address.to_string(),
+        certificate: CertificateAndKey {
+            certificate: String::from(include_str!("../../../lib/assets/local-certificate.pem")),
+            key: String::from(include_str!("../../../lib/assets/local-key.pem")),
+            certificate_chain: vec![],
+            versions: vec![],
+            names: vec![],
+        },
+        expired_at: None,
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = AsyncBackend::spawn_detached_backend(
+        "BACKEND",
+        back_address,
+        SimpleAggregator::default(),
+        AsyncBackend::http_handler("pong"),
+    );
+
+    let client_config = build_early_data_client_config();
+    let get = "GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let post = "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody";
+
+    // full handshake, the client receives a session ticket
+    let response = send_tls_request(client_config.clone(), front_address, get);
+    println!("response: {response:?}");
+    if !matches!(response, Some((false, ref response)) if response.starts_with("HTTP/1.1 200")) {
+        return State::Fail;
+    }
+
+    // a GET is safe to replay, it is forwarded from the early data
+    let response = send_tls_request(client_config.clone(), front_address, get);
+    println!("response: {response:?}");
+    if !matches!(response, Some((true, ref response)) if response.starts_with("HTTP/1.1 200")) {
+        return State::Fail;
+    }
+
+    // a POST in early data is not forwarded
+    let response = send_tls_request(client_config, front_address, post);
+    println!("response: {response:?}");
+    if !matches!(response, Some((true, ref response)) if response.starts_with("HTTP/1.1 425")) {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    let success = worker.wait_for_server_stop();
+
+    let aggregator = backend
+        .stop_and_get_aggregator()
+        .expect("Could not get aggregator");
+    println!(
+        "{} sent: {}, received: {}",
+        backend.name, aggregator.responses_sent, aggregator.requests_received
+    );
+
+    if success && aggregator.requests_received == 2 {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_tls_upgrade_without_buffers() -> State {
+    let front_address = create_local_address();
+    let back_address = create_local_address();
+
+    let (mut config, listeners, state) = Worker::empty_config();
+    // enough for a single HTTP session
+    config.min_buffers = 1;
+    config.max_buffers = 2;
+    let mut worker = Worker::start_new_worker("TLS-NO-BUFFER", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpsListener(
+        ListenerBuilder::new_https(front_address)
+            .to_tls(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Https.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpsFrontend(RequestHttpFrontend {
+        hostname: "localhost".to_owned(),
+        ..Worker::default_http_frontend("cluster_0", front_address)
+    }));
+    worker.send_proxy_request_type(RequestType::AddCertificate(AddCertificate {
+        address: front_address.to_string(),
+        certificate: CertificateAndKey {
+            certificate: String::from(include_str!("../../../lib/assets/local-certificate.pem")),
+            key: String::from(include_str!("../../../lib/assets/local-key.pem")),
+            certificate_chain: vec![],
+            versions: vec![],
+            names: vec![],
+        },
+        expired_at: None,
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = AsyncBackend::spawn_detached_backend(
+        "BACKEND",
+        back_address,
+        SimpleAggregator::default(),
+        AsyncBackend::http_handler("pong"),
+    );
+
+    // this session takes the two buffers of the pool
+    let first_connection = open_tls_connection(front_address);
+    if first_connection.is_none() {
+        return State::Fail;
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    // the handshake of the second one succeeds, but it can't be upgraded
+    let Some((mut connection, mut socket)) = open_tls_connection(front_address) else {
+        return State::Fail;
+    };
+    let response = read_tls_until_close(&mut connection, &mut socket);
+    println!("response: {response:?}");
+    if !matches!(response, Some(ref response) if response.starts_with("HTTP/1.1 503")) {
+        return State::Fail;
+    }
+
+    // the buffers are back in the pool once the first session is closed
+    drop(first_connection);
+    thread::sleep(Duration::from_millis(100));
+    let response = send_tls_request(
+        build_early_data_client_config(),
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    println!("response: {response:?}");
+    if !matches!(response, Some((_, ref response)) if response.starts_with("HTTP/1.1 200")) {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    let success = worker.wait_for_server_stop();
+
+    let aggregator = backend
+        .stop_and_get_aggregator()
+        .expect("Could not get aggregator");
+    println!(
+        "{} sent: {}, received: {}",
+        backend.name, aggregator.responses_sent, aggregator.requests_received
+    );
+
+    if success && aggregator.requests_received == 1 {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+/// On a listener with strict SNI binding, a request for a host the certificate served for
+/// the server name of its TLS connection does not cover is answered a 421, even if the
+/// host has a frontend. The other names of the certificate share the connection
+pub fn try_strict_sni_binding() -> State {
+    let front_address = create_local_address();
+    let back_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("STRICT-SNI", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpsListener(
+        ListenerBuilder::new_https(front_address)
+            .with_strict_sni_binding(Some(true))
+            .to_tls(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Https.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    for hostname in ["localhost", "example.com", "api.example.com"] {
+        worker.send_proxy_request_type(RequestType::AddHttpsFrontend(RequestHttpFrontend {
+            hostname: hostname.to_owned(),
+            ..Worker::default_http_frontend("cluster_0", front_address)
+        }));
+    }
+    worker.send_proxy_request_type(RequestType::AddCertificate(AddCertificate {
+        address: front_address.to_string(),
+        certificate: CertificateAndKey {
+            certificate: String::from(include_str!("../../../lib/assets/local-certificate.pem")),
+            key: String::from(include_str!("../../../lib/assets/local-key.pem")),
+            certificate_chain: vec![],
+            versions: vec![],
+            names: vec!["localhost".to_owned(), "*.example.com".to_owned()],
+        },
+        expired_at: None,
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0",
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = AsyncBackend::spawn_detached_backend(
+        "BACKEND",
+        back_address,
+        SimpleAggregator::default(),
+        AsyncBackend::http_handler("pong"),
+    );
+
+    // the client sends "localhost" as server name
+    let misdirected = send_tls_request(
+        build_early_data_client_config(),
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: example.com\r\n\r\n",
+    );
+    println!("misdirected response: {misdirected:?}");
+    let matching = send_tls_request(
+        build_early_data_client_config(),
+        front_address,
+        &format!(
+            "GET /api HTTP/1.1\r\nHost: localhost:{}\r\n\r\n",
+            front_address.port()
+        ),
+    );
+    println!("matching response: {matching:?}");
+    let other_name = send_tls_request(
+        build_early_data_client_config(),
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: api.example.com\r\n\r\n",
+    );
+    println!("other name response: {other_name:?}");
+
+    worker.soft_stop();
+    let success = worker.wait_for_server_stop();
+
+    let aggregator = backend
+        .stop_and_get_aggregator()
+        .expect("Could not get aggregator");
+
+    if success
+        && aggregator.requests_received == 2
+        && matches!(misdirected, Some((_, ref response)) if response.starts_with("HTTP/1.1 421 Misdirected Request\r\n"))
+        && matches!(matching, Some((_, ref response)) if response.starts_with("HTTP/1.1 200"))
+        && matches!(other_name, Some((_, ref response)) if response.starts_with("HTTP/1.1 200"))
+    {
+        State::Success
+    } else {
+        State::Fail
+    }
+}
+
+pub fn try_added_headers_keep_alive() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "ADDED-HEADERS",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+    );
+    client.connect();
+
+    // the headers added by Sozu, split between the request id and the rest
+    let mut added_headers = Vec::new();
+    let mut request_ids = Vec::new();
+    for _ in 0..3 {
+        client.send();
+        if added_headers.is_empty() {
+            backend.accept(0);
+        }
+        let request = match backend.receive(0) {
+            Some(request) => request,
+            None => return State::Fail,
+        };
+        println!("request: {request:?}");
+        let (ids, others): (Vec<&str>, Vec<&str>) = request
+            .split("\r\n")
+            .filter(|line| {
+                line.starts_with("Sozu-Id:")
+                    || line.starts_with("Forwarded:")
+                    || line.starts_with("X-Forwarded-")
+            })
+            .partition(|line| line.starts_with("Sozu-Id:"));
+        request_ids.push(ids.concat());
+        added_headers.push(others.concat());
+
+        backend.send(0);
+        if !is_http_ok_response(client.receive().as_deref(), "pong0") {
+            return State::Fail;
+        }
+    }
+    println!("added headers: {added_headers:?}, request ids: {request_ids:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    // only the request id changes from one request to the next
+    if added_headers[0].is_empty()
+        || added_headers
+            .iter()
+            .any(|headers| headers != &added_headers[0])
+        || request_ids[0] == request_ids[1]
+        || request_ids[1] == request_ids[2]
+    {
+        return State::Fail;
+    }
+    State::Success
+}
+
+pub fn try_transfer_codings() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) =
+        setup_sync_test("TE", config, listeners, state, front_address, 1, false);
+    let mut backend = backends.pop().unwrap();
+
+    backend.connect();
+
+    // chunked is applied last, the gzip coding is forwarded to the backend
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    if !request
+        .map(|request| request.contains("gzip, chunked") && request.ends_with("0\r\n\r\n"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !is_http_ok_response(response.as_deref(), "pong0") {
+        return State::Fail;
+    }
+
+    // the framing of the body can not be determined
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked, gzip\r\n\r\n4\r\nbody\r\n0\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 400"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    // the backend could frame the body with Content-Length
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 400"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_request_error_event() -> State {
+    use sozu_command_lib::proto::command::{
+        response_content::ContentType, EventKind, QueryClustersHashes,
+    };
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, _backends) =
+        setup_sync_test("EVENTS", config, listeners, state, front_address, 1, false);
+
+    let mut client = Client::new("client", front_address, "HELLO\r\n\r\n");
+    client.connect();
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 400"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+
+    // the event is queued before the response to this request
+    worker.send_proxy_request_type(RequestType::QueryClustersHashes(QueryClustersHashes {}));
+    let mut parse_error_event = false;
+    loop {
+        let response = worker.read_proxy_response().unwrap();
+        if let Some(ContentType::Event(event)) = response
+            .content
+            .as_ref()
+            .and_then(|content| content.content_type.as_ref())
+        {
+            parse_error_event |=
+                event.kind() == EventKind::RequestParseError && event.address.is_some();
+        }
+        if response.id == worker.command_id.last {
+            break;
+        }
+    }
+    if !parse_error_event {
+        println!("no REQUEST_PARSE_ERROR event was sent");
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_max_front_read_size() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("READ-SIZE", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_max_front_read_size(Some(512))
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0".to_string(),
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    // the upload is read 512 bytes at a time, the session gives way to the
+    // other ones after each read
+    let body = "a".repeat(32768);
+    let upload = format!(
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    let mut upload_client = Client::new("upload", front_address, upload.clone());
+    upload_client.connect();
+    upload_client.send();
+    backend.accept(0);
+
+    // a small request is handled while the upload goes on
+    let mut small_client = Client::new(
+        "small",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    small_client.connect();
+    small_client.send();
+    backend.accept(1);
+    let request = backend.receive(1);
+    println!("small request: {request:?}");
+    backend.send(1);
+    let response = small_client.receive();
+    println!("small response: {response:?}");
+    if !is_http_ok_response(response.as_deref(), "pong") {
+        return State::Fail;
+    }
+
+    // the whole upload reaches the backend, Sōzu only adds headers
+    let mut received = 0;
+    while received < upload.len() {
+        match backend.receive(0) {
+            Some(request) => received += request.len(),
+            None => break,
+        }
+    }
+    println!("received {received} bytes of the upload");
+    if received < upload.len() {
+        return State::Fail;
+    }
+    backend.send(0);
+    let response = upload_client.receive();
+    println!("upload response: {response:?}");
+    if !is_http_ok_response(response.as_deref(), "pong") {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_http_authentication() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) =
+        setup_sync_test("AUTH", config, listeners, state, front_address, 1, false);
+    let mut backend = backends.pop().unwrap();
+
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        // admin:password
+        basic_auth_credentials: vec![
+            "admin:5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8".to_string(),
+        ],
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.read_to_last();
+
+    backend.connect();
+
+    let request = |authorization: &str| {
+        format!("GET /api HTTP/1.1\r\nHost: localhost\r\nAuthorization: {authorization}\r\n\r\n")
+    };
+
+    // invalid credentials (admin:guess)
+    let mut client = Client::new("client", front_address, request("Basic YWRtaW46Z3Vlc3M="));
+    client.connect();
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    match response {
+        Some(response)
+            if response.starts_with("HTTP/1.1 401 Unauthorized")
+                && response.contains("WWW-Authenticate: Basic realm=\"cluster_0\"") => {}
+        _ => return State::Fail,
+    }
+    if backend.accept(0) {
+        println!("the backend should not receive an unauthenticated request");
+        return State::Fail;
+    }
+
+    // valid credentials (admin:password)
+    let mut client = Client::new(
+        "client",
+        front_address,
+        request("Basic YWRtaW46cGFzc3dvcmQ="),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !is_http_ok_response(response.as_deref(), "pong0") {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_ip_filter() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "IP-FILTER",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+
+    // the e2e clients connect from 127.0.0.1, only X-Forwarded-For can be allowed
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        allowed_ips: vec!["203.0.113.0/24".to_string()],
+        denied_ips: vec!["203.0.113.13".to_string()],
+        ip_filter_trust_forwarded_for: Some(true),
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.read_to_last();
+
+    backend.connect();
+
+    let request = |forwarded_for: Option<&str>| match forwarded_for {
+        Some(ip) => {
+            format!("GET /api HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: {ip}\r\n\r\n")
+        }
+        None => "GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n".to_string(),
+    };
+
+    for forwarded_for in [
+        None,
+        Some("203.0.113.13"),
+        Some("203.0.113.5, 198.51.100.7"),
+    ] {
+        let mut client = Client::new("client", front_address, request(forwarded_for));
+        client.connect();
+        client.send();
+        let response = client.receive();
+        println!("response: {response:?}");
+        match response {
+            Some(response) if response.starts_with("HTTP/1.1 403 Forbidden") => {}
+            _ => return State::Fail,
+        }
+        if backend.accept(0) {
+            println!("the backend should not receive a request from a denied client");
+            return State::Fail;
+        }
+    }
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        request(Some("198.51.100.7, 203.0.113.5")),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !is_http_ok_response(response.as_deref(), "pong0") {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_allowed_methods() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "ALLOWED-METHODS",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(RequestHttpFrontend {
+        path: PathRule::prefix(String::from("/api")),
+        allowed_methods: vec![String::from("GET"), String::from("POST")],
+        ..Worker::default_http_frontend("cluster_0", front_address)
+    }));
+    worker.read_to_last();
+
+    backend.connect();
+
+    for method in ["DELETE", "PURGE"] {
+        let mut client = Client::new(
+            "client",
+            front_address,
+            format!("{method} /api/users HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        );
+        client.connect();
+        client.send();
+        let response = client.receive();
+        println!("response: {response:?}");
+        match response {
+            Some(response)
+                if response.starts_with("HTTP/1.1 405 Method Not Allowed")
+                    && response.contains("\r\nAllow: GET, POST\r\n") => {}
+            _ => return State::Fail,
+        }
+        if backend.accept(0) {
+            println!("the backend should not receive a request with a disallowed method");
+            return State::Fail;
+        }
+    }
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api/users", String::new(), "localhost"),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !is_http_ok_response(response.as_deref(), "pong0") {
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+/// A frontend forwarding HEAD as GET: the backend receives a GET, the client gets
+/// the headers of its response without the body, and the connection stays usable
+pub fn try_head_as_get() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "HEAD-AS-GET",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(RequestHttpFrontend {
+        head_as_get: Some(true),
+        ..Worker::default_http_frontend("cluster_0", front_address)
+    }));
+    worker.read_to_last();
+
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "HEAD /api HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+    );
+    client.connect();
+    backend.accept(0);
+    for _ in 0..2 {
+        client.send();
+        let request = backend.receive(0);
+        println!("request: {request:?}");
+        match request {
+            Some(request) if request.starts_with("GET /api HTTP/1.1\r\n") => {}
+            _ => return State::Fail,
+        }
+        backend.send(0);
+        let response = client.receive();
+        println!("response: {response:?}");
+        match response {
+            Some(response)
+                if response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n")
+                    && response.ends_with("\r\n\r\n") => {}
+            _ => return State::Fail,
+        }
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+/// Deactivating a listener with drain_sessions unbinds it, closes its idle sessions
+/// and makes the busy ones answer with "Connection: close" before closing
+pub fn try_deactivate_listener_drain() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "DEACTIVATE-DRAIN",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let request = "GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n";
+    let mut idle_client = Client::new("idle_client", front_address, request);
+    idle_client.connect();
+    idle_client.send();
+    backend.accept(0);
+    backend.receive(0);
+    backend.send(0);
+    if !is_http_ok_response(idle_client.receive().as_deref(), "pong0") {
+        return State::Fail;
+    }
+
+    let mut busy_client = Client::new("busy_client", front_address, request);
+    busy_client.connect();
+    busy_client.send();
+    backend.accept(1);
+    backend.receive(1);
+
+    worker.send_proxy_request_type(RequestType::DeactivateListener(DeactivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        to_scm: false,
+        drain_sessions: Some(true),
+    }));
+    worker.read_to_last();
+
+    if TcpStream::connect(front_address).is_ok() {
+        println!("the deactivated listener should be unbound");
+        return State::Fail;
+    }
+
+    backend.send(1);
+    let response = busy_client.receive();
+    println!("response: {response:?}");
+    match response {
+        Some(response)
+            if response.starts_with("HTTP/1.1 200 OK\r\n")
+                && response.contains("\r\nConnection: close\r\n") => {}
+        _ => return State::Fail,
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    if idle_client.is_connected() || busy_client.is_connected() {
+        println!("the sessions of the deactivated listener should be closed");
+        return State::Fail;
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+    State::Success
+}
+
+pub fn try_query_sessions() -> State {
+    use sozu_command_lib::proto::command::{response_content::ContentType, QuerySessions};
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "SESSIONS",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    // two keep-alive sessions that stay open after their first request
+    let mut clients = Vec::new();
+    for index in 0..2 {
+        let mut client = Client::new(
+            format!("client{index}"),
+            front_address,
+            "GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+        );
+        client.connect();
+        client.send();
+        backend.accept(index);
+        backend.receive(index);
+        backend.send(index);
+        if !is_http_ok_response(client.receive().as_deref(), "pong0") {
+            return State::Fail;
+        }
+        clients.push(client);
+    }
+
+    worker.send_proxy_request_type(RequestType::QuerySessions(QuerySessions {}));
+    let mut sessions = None;
+    loop {
+        let response = worker.read_proxy_response().unwrap();
+        if let Some(ContentType::Sessions(infos)) =
+            response.content.and_then(|content| content.content_type)
+        {
+            sessions = Some(infos.vec);
+        }
+        if response.id == worker.command_id.last {
+            break;
+        }
+    }
+    println!("sessions: {sessions:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let sessions = match sessions {
+        Some(sessions) => sessions,
+        None => return State::Fail,
+    };
+    if sessions.len() != clients.len()
+        || sessions.iter().any(|session| {
+            session.protocol != "HTTP(Http)"
+                || session.cluster_id.as_deref() != Some("cluster_0")
+                || session.frontend_readiness.is_none()
+        })
+    {
+        return State::Fail;
+    }
+    State::Success
+}
+
+pub fn try_suppressed_headers() -> State {
+    use sozu_command_lib::proto::command::AddedHeader;
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "SUPPRESSED-HEADERS",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+
+    worker.send_proxy_request_type(RequestType::AddCluster(Cluster {
+        suppressed_headers: vec![AddedHeader::Forwarded as i32, AddedHeader::SozuId as i32],
+        ..Worker::default_cluster("cluster_0", false)
+    }));
+    worker.read_to_last();
+
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\nForwarded: for=203.0.113.5\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let (request, response) = match (request, response) {
+        (Some(request), Some(response)) => (request, response),
+        _ => return State::Fail,
+    };
+    let has_header = |message: &str, name: &str| {
+        message
+            .split("\r\n")
+            .any(|line| line.starts_with(&format!("{name}:")))
+    };
+    // the client value of a suppressed header is not forwarded either
+    if has_header(&request, "Forwarded")
+        || has_header(&request, "Sozu-Id")
+        || has_header(&response, "Sozu-Id")
+    {
+        return State::Fail;
+    }
+    if !has_header(&request, "X-Forwarded-For")
+        || !has_header(&request, "X-Forwarded-Proto")
+        || !has_header(&request, "X-Forwarded-Port")
+        || !response.starts_with("HTTP/1.1 200 OK")
+    {
+        return State::Fail;
+    }
+    State::Success
+}
+
+/// A listener omitting the Forwarded header neither adds it nor forwards the client's one,
+/// the X-Forwarded-* headers are still added
+pub fn try_omit_forwarded_header() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("OMIT-FORWARDED", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_omit_forwarded_header(Some(true))
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0".to_string(),
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\nForwarded: for=203.0.113.5\r\nX-Forwarded-For: 203.0.113.5\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let request = backend.receive(0);
+    println!("request: {request:?}");
+    backend.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let (request, response) = match (request, response) {
+        (Some(request), Some(response)) => (request, response),
+        _ => return State::Fail,
+    };
+    let header_value = |name: &str| {
+        request
+            .split("\r\n")
+            .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+            .map(ToOwned::to_owned)
+    };
+    if header_value("Forwarded").is_some()
+        || request.to_lowercase().contains("\r\nforwarded:")
+        || header_value("X-Forwarded-For").as_deref() != Some("203.0.113.5, 127.0.0.1")
+        || header_value("X-Forwarded-Proto").as_deref() != Some("http")
+        || header_value("X-Forwarded-Port") != Some(front_address.port().to_string())
+        || !response.starts_with("HTTP/1.1 200 OK")
+    {
+        return State::Fail;
+    }
+    State::Success
+}
+
+/// With trace context enabled, a valid traceparent is forwarded as is, an invalid or
+/// missing one is replaced by a new trace
+pub fn try_trace_context() -> State {
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("TRACE-CONTEXT", config, &listeners, state);
+
+    worker.send_proxy_request_type(RequestType::AddHttpListener(
+        ListenerBuilder::new_http(front_address)
+            .with_trace_context(true)
+            .to_http(None)
+            .unwrap(),
+    ));
+    worker.send_proxy_request_type(RequestType::ActivateListener(ActivateListener {
+        address: front_address.to_string(),
+        proxy: ListenerType::Http.into(),
+        from_scm: false,
+    }));
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_0",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(Worker::default_http_frontend(
+        "cluster_0",
+        front_address,
+    )));
+    let back_address = create_local_address();
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_0",
+        "cluster_0-0".to_string(),
+        back_address.to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut backend = SyncBackend::new("backend", back_address, http_ok_response("pong"));
+    backend.connect();
+
+    let existing = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    let generated = backend.receive(0);
+    backend.send(0);
+    client.receive();
+
+    client.set_request(format!(
+        "GET /api HTTP/1.1\r\nHost: localhost\r\ntraceparent: {existing}\r\ntracestate: vendor=value\r\n\r\n"
+    ));
+    client.send();
+    let propagated = backend.receive(0);
+    backend.send(0);
+    client.receive();
+
+    client.set_request(
+        "GET /api HTTP/1.1\r\nHost: localhost\r\ntraceparent: 00-invalid\r\ntracestate: vendor=value\r\n\r\n",
+    );
+    client.send();
+    let replaced = backend.receive(0);
+    backend.send(0);
+    client.receive();
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    println!("requests: {generated:?} {propagated:?} {replaced:?}");
+    let (Some(generated), Some(propagated), Some(replaced)) = (generated, propagated, replaced)
+    else {
+        return State::Fail;
+    };
+    let header_values = |request: &str, name: &str| {
+        request
+            .split("\r\n")
+            .filter_map(|line| line.strip_prefix(&format!("{name}: ")))
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>()
+    };
+    // "00-{32 hex digits}-{16 hex digits}-01"
+    let is_well_formed = |traceparent: &str| {
+        let fields = traceparent.split('-').collect::<Vec<_>>();
+        let is_hex = |field: &str| {
+            field
+                .bytes()
+                .all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        fields.len() == 4
+            && fields[0] == "00"
+            && fields[1].len() == 32
+            && fields[2].len() == 16
+            && fields[3] == "01"
+            && fields.iter().all(|field| is_hex(field))
+    };
+
+    let generated_traceparent = header_values(&generated, "traceparent");
+    if generated_traceparent.len() != 1 || !is_well_formed(&generated_traceparent[0]) {
+        return State::Fail;
+    }
+    if header_values(&propagated, "traceparent") != [existing]
+        || header_values(&propagated, "tracestate") != ["vendor=value"]
+    {
+        return State::Fail;
+    }
+    let replaced_traceparent = header_values(&replaced, "traceparent");
+    if replaced_traceparent.len() != 1
+        || !is_well_formed(&replaced_traceparent[0])
+        || !header_values(&replaced, "tracestate").is_empty()
+    {
+        return State::Fail;
+    }
+    State::Success
+}
+
+pub fn try_duplicate_host() -> State {
+    use sozu_command_lib::proto::command::{
+        response_content::ContentType, EventKind, QueryClustersHashes,
+    };
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "DUPLICATE-HOST",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        "GET /api HTTP/1.1\r\nHost: localhost\r\nHost: example.com\r\n\r\n",
+    );
+    client.connect();
+    client.send();
+    let response = client.receive();
+    println!("response: {response:?}");
+    if !response
+        .map(|response| response.starts_with("HTTP/1.1 400"))
+        .unwrap_or(false)
+    {
+        return State::Fail;
+    }
+    if backend.accept(0) {
+        println!("the backend should not receive a request with two Host headers");
+        return State::Fail;
+    }
+
+    // the request is counted as a front parsing error
+    worker.send_proxy_request_type(RequestType::QueryClustersHashes(QueryClustersHashes {}));
+    let mut parse_error_event = false;
+    loop {
+        let response = worker.read_proxy_response().unwrap();
+        if let Some(ContentType::Event(event)) = response
+            .content
+            .as_ref()
+            .and_then(|content| content.content_type.as_ref())
+        {
+            parse_error_event |= event.kind() == EventKind::RequestParseError;
+        }
+        if response.id == worker.command_id.last {
+            break;
+        }
+    }
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    if !parse_error_event {
+        println!("no REQUEST_PARSE_ERROR event was sent");
+        return State::Fail;
+    }
+    State::Success
+}
+
+/// Moves the default frontend from `localhost` to `example.com` with a configuration diff.
+/// With an invalid entry at the end of the diff, the whole diff must be rolled back.
+pub fn try_apply_config(with_invalid_entry: bool) -> State {
+    use sozu_command_lib::proto::command::{ConfigDiff, ResponseStatus};
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, mut backends) = setup_sync_test(
+        "APPLY-CONFIG",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut requests = vec![
+        RequestType::RemoveHttpFrontend(Worker::default_http_frontend("cluster_0", front_address))
+            .into(),
+        RequestType::AddHttpFrontend(RequestHttpFrontend {
+            hostname: String::from("example.com"),
+            ..Worker::default_http_frontend("cluster_0", front_address)
+        })
+        .into(),
+    ];
+    if with_invalid_entry {
+        requests.push(
+            RequestType::RemoveBackend(RemoveBackend {
+                cluster_id: String::from("cluster_0"),
+                backend_id: String::from("cluster_0-missing"),
+                address: String::from("127.0.0.1:1"),
+            })
+            .into(),
+        );
+    }
+    worker.send_proxy_request_type(RequestType::ApplyConfig(ConfigDiff { requests }));
+    let status = loop {
+        let response = worker.read_proxy_response().unwrap();
+        if response.id == worker.command_id.last {
+            println!("apply config response: {response:?}");
+            break response.status;
+        }
+    };
+
+    let (routed_host, unknown_host) = if with_invalid_entry {
+        ("localhost", "example.com")
+    } else {
+        ("example.com", "localhost")
+    };
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", unknown_host),
+    );
+    client.connect();
+    client.send();
+    let not_found = client.receive();
+    println!("response for {unknown_host}: {not_found:?}");
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("GET", "/api", "ping", routed_host),
+    );
+    client.connect();
+    client.send();
+    backend.accept(0);
+    backend.receive(0);
+    backend.send(0);
+    let response = client.receive();
+    println!("response for {routed_host}: {response:?}");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let expected_status = if with_invalid_entry {
+        ResponseStatus::Failure
+    } else {
+        ResponseStatus::Ok
+    };
+    if status != expected_status
+        || not_found != Some(default_404_answer())
+        || !is_http_ok_response(response.as_deref(), "pong0")
+    {
+        return State::Fail;
+    }
+    State::Success
+}
+
+/// Once the sessions are at capacity, a new connection must wait in the listen backlog
+/// until a session is freed, instead of being accepted then closed right away.
+pub fn try_accept_backpressure() -> State {
+    let front_address = create_local_address();
+
+    let (mut config, listeners, state) = Worker::empty_config();
+    config.max_connections = 10;
+    let (mut worker, mut backends) = setup_sync_test(
+        "ACCEPT-BACKPRESSURE",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+    let mut backend = backends.pop().unwrap();
+    backend.connect();
+
+    let mut clients = Vec::new();
+    for i in 0..10 {
+        let mut client = Client::new(
+            format!("client{i}"),
+            front_address,
+            http_request("GET", "/api", format!("ping{i}"), "localhost"),
+        );
+        client.connect();
+        client.send();
+        backend.accept(i);
+        backend.receive(i);
+        backend.send(i);
+        if !is_http_ok_response(client.receive().as_deref(), "pong0") {
+            return State::Fail;
+        }
+        clients.push(client);
+    }
+
+    let mut waiting_client = Client::new(
+        "waiting_client",
+        front_address,
+        http_request("GET", "/api", "ping", "localhost"),
+    );
+    waiting_client.connect();
+    waiting_client.send();
+    thread::sleep(Duration::from_millis(100));
+    if backend.accept(10) {
+        println!("a session was created above max_connections");
+        return State::Fail;
+    }
+
+    // free enough sessions to go back under 90% of max_connections
+    for (i, client) in clients.iter_mut().enumerate().take(2) {
+        client.set_request("GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        client.send();
+        backend.receive(i);
+        backend.send(i);
+        client.receive();
+    }
+
+    thread::sleep(Duration::from_millis(100));
+    let accepted = backend.accept(10);
+    backend.receive(10);
+    backend.send(10);
+    let response = waiting_client.receive();
+    println!("waiting client response: {response:?}");
+
+    worker.hard_stop();
+    worker.wait_for_server_stop();
+
+    if !accepted || !is_http_ok_response(response.as_deref(), "pong0") {
+        return State::Fail;
+    }
+    State::Success
+}
+
+pub fn try_query_route_match() -> State {
+    use sozu_command_lib::proto::command::{
+        response_content::ContentType, PathRule, QueryRouteMatch, RouteMatch,
+    };
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let (mut worker, _backends) = setup_sync_test(
+        "ROUTE-MATCH",
+        config,
+        listeners,
+        state,
+        front_address,
+        1,
+        false,
+    );
+
+    // overlaps the "localhost /" frontend of cluster_0
+    worker.send_proxy_request_type(RequestType::AddCluster(Worker::default_cluster(
+        "cluster_1",
+        false,
+    )));
+    worker.send_proxy_request_type(RequestType::AddHttpFrontend(RequestHttpFrontend {
+        path: PathRule::prefix(String::from("/api")),
+        ..Worker::default_http_frontend("cluster_1", front_address)
+    }));
+    worker.send_proxy_request_type(RequestType::AddBackend(Worker::default_backend(
+        "cluster_1",
+        "cluster_1-0",
+        create_local_address().to_string(),
+        None,
+    )));
+    worker.read_to_last();
+
+    let mut query_route_match = |path: &str| -> Vec<RouteMatch> {
+        worker.send_proxy_request_type(RequestType::QueryRouteMatch(QueryRouteMatch {
+            host: String::from("localhost"),
+            path: path.to_owned(),
+            method: String::from("GET"),
+            address: None,
+        }));
+        let mut route_matches = Vec::new();
+        loop {
+            let response = worker.read_proxy_response().unwrap();
+            if let Some(ContentType::RouteMatches(matches)) =
+                response.content.and_then(|content| content.content_type)
+            {
+                route_matches = matches.vec;
+            }
+            if response.id == worker.command_id.last {
+                break;
+            }
+        }
+        println!("route matches for {path}: {route_matches:?}");
+        route_matches
+    };
+
+    let api = query_route_match("/api/users");
+    let index = query_route_match("/index.html");
+
+    worker.soft_stop();
+    worker.wait_for_server_stop();
+
+    let matches = |route_matches: &[RouteMatch], cluster_id: &str, path: &str| {
+        route_matches.len() == 1
+            && route_matches[0].cluster_id.as_deref() == Some(cluster_id)
+            && route_matches[0]
+                .frontend
+                .as_ref()
+                .map(|front| front.path.value.as_str())
+                == Some(path)
+            && route_matches[0].backends.len() == 1
+            && route_matches[0].backends[0].cluster_id == cluster_id
+    };
+    if !matches(&api, "cluster_1", "/api") || !matches(&index, "cluster_0", "/") {
+        return State::Fail;
+    }
+    State::Success
+}
+
+fn try_wildcard() -> State {
+    use sozu_command_lib::proto::command::{PathRule, RulePosition};
+    let front_address = create_local_address();
+
+    let (config, listeners, state) = Worker::empty_config();
+    let mut worker = Worker::start_new_worker("WLD_CRD", config, &listeners, state);
+    worker.send_proxy_request(
+        RequestType::AddHttpListener(
+            ListenerBuilder::new_http(front_address)
+                .to_http(None)
+                .unwrap(),
+        )
+        .into(),
+    );
+    worker.send_proxy_request(
+        RequestType::ActivateListener(ActivateListener {
+            address: front_address.to_string(),
+            proxy: ListenerType::Http.into(),
+            from_scm: false,
+        })
+        .into(),
+    );
+
+    worker.send_proxy_request(
+        RequestType::AddCluster(Worker::default_cluster("cluster_0", false)).into(),
+    );
+    worker.send_proxy_request(
+        RequestType::AddCluster(Worker::default_cluster("cluster_1", false)).into(),
+    );
+
+    worker.send_proxy_request(
+        RequestType::AddHttpFrontend(RequestHttpFrontend {
+            cluster_id: Some("cluster_0".to_string()),
+            address: front_address.to_string(),
+            hostname: String::from("*.sozu.io"),
+            path: PathRule::prefix(String::from("")),
+            position: RulePosition::Tree.into(),
+            ..Default::default()
+        })
+        .into(),
+    );
+
+    let back_address: SocketAddr = create_local_address();
+    worker.send_proxy_request(
+        RequestType::AddBackend(Worker::default_backend(
+            "cluster_0",
+            "cluster_0-0",
+            back_address.to_string(),
+            None,
+        ))
+        .into(),
+    );
+    worker.read_to_last();
+
+    let mut backend0 = SyncBackend::new(
+        "BACKEND_0",
+        back_address,
+        http_ok_response(format!("pong0")),
+    );
+
+    let mut client = Client::new(
+        "client",
+        front_address,
+        http_request("POST", "/api", format!("ping"), "www.sozu.io"),
+    );
+
+    backend0.connect();
+    client.connect();
+    client.send();
+    let accepted = backend0.accept(0);
+    assert!(accepted);
+    let request = backend0.receive(0);
+    println!("request: {request:?}");
+    backend0.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    worker.send_proxy_request(
+        RequestType::AddHttpFrontend(RequestHttpFrontend {
+            cluster_id: Some("cluster_1".to_string()),
+            address: front_address.to_string(),
+            hostname: String::from("*.sozu.io"),
+            path: PathRule::prefix(String::from("/api")),
+            position: RulePosition::Tree.into(),
+            ..Default::default()
+        })
+        .into(),
+    );
+    let back_address: SocketAddr = create_local_address();
+    worker.send_proxy_request(
+        RequestType::AddBackend(Worker::default_backend(
+            "cluster_1",
+            "cluster_1-0",
+            back_address.to_string(),
+            None,
+        ))
+        .into(),
+    );
+
+    let mut backend1 = SyncBackend::new(
+        "BACKEND_1",
+        back_address,
+        http_ok_response(format!("pong1")),
+    );
+
+    worker.read_to_last();
+
+    backend1.connect();
+
+    client.send();
+    let accepted = backend1.accept(0);
+    assert!(accepted);
+    let request = backend1.receive(0);
+    println!("request: {request:?}");
+    backend1.send(0);
+    let response = client.receive();
+    println!("response: {response:?}");
+
+    State::Success
+}
+
+#[test]
+fn test_sync() {
+    assert_eq!(try_sync(10, 100), State::Success);
+}
+
+#[test]
+fn test_async() {
+    assert_eq!(try_async(3, 10, 100), State::Success);
+}
+
+#[test]
+fn test_hard_stop() {
+    assert_eq!(
+        repeat_until_error_or(
+            10,
+            "Hard Stop: Test that the worker shuts down even if backends are not done",
+            || try_hard_or_soft_stop(false)
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_soft_stop() {
+    assert_eq!(
+            repeat_until_error_or(
+                10,
+                "Hard Stop: Test that the worker waits for all backends to process requests before shutting down",
+                || try_hard_or_soft_stop(true)
+            ),
+            State::Success
+        );
+}
+
+// https://github.com/sozu-proxy/sozu/issues/806
+// This should actually be a success
+
+#[test]
+fn test_issue_806() {
+    assert!(
+        repeat_until_error_or(
+            100,
+            "issue 806: timeout with invalid back token\n(not fixed)",
+            || try_backend_stop(2, None)
+        ) != State::Fail
+    );
+}
+
+// https://github.com/sozu-proxy/sozu/issues/808
+
+#[test]
+fn test_issue_808() {
+    // this test is not relevant anymore, at least not like this
+    // assert_eq!(
+    //     repeat_until_error_or(
+    //         100,
+    //         "issue 808: panic on successful zombie check\n(fixed)",
+    //         || try_backend_stop(2, Some(1))
+    //     ),
+    //     // if Success, it means the session was never a zombie
+    //     // if Fail, it means the zombie checker probably crashed
+    //     State::Undecided
+    // );
+}
+
+// https://github.com/sozu-proxy/sozu/issues/810
+
+#[test]
+fn test_tcp_backend_failover() {
+    assert_eq!(
+        repeat_until_error_or(2, "TCP connection retried on the next backend", || {
+            try_tcp_backend_failover(true)
+        }),
+        State::Success
+    );
+}
+
+#[test]
+fn test_failure_status_codes() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Backend answering with a failure status taken out of the rotation",
+            try_failure_status_codes
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_rewrite_location() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Location header pointing to the backend rewritten to the frontend",
+            try_rewrite_location
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_rewrite_set_cookie() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Domain and Path of the cookies set by the backend rewritten",
+            try_rewrite_set_cookie
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_tcp_expect_proxy_protocol() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "TCP proxy protocol header stripped before the backend",
+            try_tcp_expect_proxy_protocol
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_tcp_sni_routing() {
+    assert_eq!(
+        repeat_until_error_or(2, "TCP connection routed by its SNI", || {
+            try_tcp_sni_routing("lolcatho.st", 1)
+        }),
+        State::Success
+    );
+    assert_eq!(
+        repeat_until_error_or(2, "TCP connection routed by its SNI", || {
+            try_tcp_sni_routing("EXAMPLE.com", 2)
+        }),
+        State::Success
+    );
+}
+
+#[test]
+fn test_tcp_sni_routing_send_proxy() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "TCP connection routed by its SNI to a cluster sending the proxy protocol",
+            try_tcp_sni_routing_send_proxy
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_tcp_sni_routing_default_cluster() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "TCP connection with an unknown SNI routed to the default frontend",
+            || try_tcp_sni_routing("unknown.com", 0)
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_tcp_all_backends_down() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "TCP connection closed when no backend can be reached",
+            || try_tcp_backend_failover(false)
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_issue_810_timeout() {
+    assert_eq!(
+        repeat_until_error_or(
+            100,
+            "issue 810: shutdown struggles until session timeout\n(fixed)",
+            try_issue_810_timeout
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_issue_810_panic_on_session_close() {
+    assert_eq!(
+        repeat_until_error_or(
+            100,
+            "issue 810: shutdown panics on session close\n(fixed)",
+            || try_issue_810_panic(false)
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_issue_810_panic_on_missing_listener() {
+    assert_eq!(
+            repeat_until_error_or(
+                100,
+                "issue 810: shutdown panics on tcp connection after proxy cleared its listeners\n(opinionated fix)",
+                || try_issue_810_panic(true)
+            ),
+            State::Success
+        );
+}
+
+#[test]
+fn test_tls_endpoint() {
+    assert_eq!(
+        repeat_until_error_or(
+            100,
+            "TLS endpoint: Sōzu should decrypt an HTTPS request",
+            try_tls_endpoint
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_http_behaviors() {
+    assert_eq!(
+        repeat_until_error_or(10, "HTTP stack", try_http_behaviors),
+        State::Success
+    );
+}
+
+#[test]
+fn test_msg_close() {
+    assert_eq!(
+        repeat_until_error_or(100, "HTTP error on close", try_msg_close),
+        State::Success
+    );
+}
+
+#[test]
+fn test_blue_green() {
+    assert_eq!(
+        repeat_until_error_or(10, "Blue green switch", try_blue_geen),
+        State::Success
+    );
+}
+
+#[test]
+fn test_keep_alive() {
+    assert_eq!(
+        repeat_until_error_or(10, "Keep alive combinations", try_keep_alive),
+        State::Success
+    );
+}
+
+#[test]
+fn test_client_connection_close() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Connection: close from the client ends the session after one response",
+            try_client_connection_close
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_backend_empty_response() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "a backend closing without answering is reported as a 502",
+            try_backend_empty_response
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_cluster_connection_retries() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "the default connection retries give up before the working backend",
+            || try_cluster_connection_retries(None)
+        ),
+        State::Success
+    );
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "a cluster allowing more connection retries reaches the working backend",
+            || try_cluster_connection_retries(Some(4))
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_listener_max_sessions() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "a listener at its max sessions does not prevent another one from accepting",
+            try_listener_max_sessions
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_stick() {
+    assert_eq!(
+        repeat_until_error_or(10, "Sticky session", try_stick),
+        State::Success
+    );
+}
+
+#[test]
+fn test_stick_fallback() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Sticky session to a backend that is down",
+            try_stick_fallback
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_max_connections() {
+    assert_eq!(
+        repeat_until_error_or(2, "Max connections reached", try_max_connections),
+        State::Success
+    );
+}
+
+#[test]
+fn test_head() {
+    assert_eq!(
+        repeat_until_error_or(10, "Head request", try_head),
+        State::Success
+    );
+}
+
+#[test]
+fn test_wildcard() {
+    assert_eq!(
+        repeat_until_error_or(2, "Hostname with wildcard", try_wildcard),
+        State::Success
+    );
+}
+
+#[test]
+fn test_status_header_split() {
+    assert_eq!(
+        repeat_until_error_or(2, "Status line and Headers split", try_status_header_split),
+        State::Success
+    );
+}
+
+#[test]
+fn test_expect_continue_authorization() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Authorization check on Expect: 100-continue",
+            try_expect_continue_authorization
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_pipelined_requests_limit() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "a client pipelining beyond max_pipelined_requests is read once the responses drain",
+            try_pipelined_requests_limit
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_lame_duck() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "in lame duck mode, the request in flight completes and new connections are closed",
+            try_lame_duck
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_listener_health_check() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "the health check path of the listener is answered without a cluster",
+            try_listener_health_check
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_response_slices_metric() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "slices of the responses recorded in a histogram",
+            try_response_slices_metric
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_keepalive_requests_metric() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Requests per connection histogram recorded on session close",
+            try_keepalive_requests_metric
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_backend_connection_reuse() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "New, reused and replaced backend connections on a keep-alive session",
+            try_backend_connection_reuse
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_normalize_reason_phrase() {
+    assert_eq!(
+        repeat_until_error_or(2, "Reason phrases of known status codes normalized", || {
+            try_normalize_reason_phrase(true)
+        }),
+        State::Success
+    );
+    assert_eq!(
+        repeat_until_error_or(2, "Reason phrases forwarded as sent by the backend", || {
+            try_normalize_reason_phrase(false)
+        }),
+        State::Success
+    );
+}
+
+#[test]
+fn test_websocket_subprotocols() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Websocket upgrades restricted to the subprotocols of the frontend",
+            try_websocket_subprotocols
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_max_uri_length() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Request URIs longer than the limit get a 414",
+            try_max_uri_length
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_tls_handshake_timeout() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Stalled TLS handshakes closed after the handshake timeout",
+            try_tls_handshake_timeout
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_http10_default_host() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "HTTP/1.0 request without Host routed with the default host",
+            || try_http10_default_host(Some("localhost"))
+        ),
+        State::Success
+    );
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "HTTP/1.0 request without Host refused without a default host",
+            || try_http10_default_host(None)
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_require_sni() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Clients without SNI closed on a listener requiring it",
+            || try_require_sni(true)
+        ),
+        State::Success
+    );
+    assert_eq!(
+        repeat_until_error_or(2, "Clients without SNI routed by their Host header", || {
+            try_require_sni(false)
+        }),
+        State::Success
+    );
+}
+
+#[test]
+fn test_request_smuggling() {
+    let vectors = [
+        (
+            "duplicate Host",
+            "GET /api HTTP/1.1\r\nHost: localhost\r\nHost: example.com\r\n\r\n",
+        ),
+        (
+            "conflicting Content-Length",
+            "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nContent-Length: 5\r\n\r\nbody",
+        ),
+        (
+            "Content-Length with Transfer-Encoding",
+            "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\n",
+        ),
+        (
+            "chunked not last in Transfer-Encoding",
+            "POST /api HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked, gzip\r\n\r\n4\r\nbody\r\n0\r\n\r\n",
+        ),
+        (
+            "bare LF",
+            "GET /api HTTP/1.1\r\nHost: localhost\nX-Smuggled: 1\r\n\r\n",
+        ),
+    ];
+    for (vector, request) in vectors {
+        assert_eq!(
+            repeat_until_error_or(
+                2,
+                &format!("Request smuggling with {vector} detected"),
+                || try_request_smuggling(request)
+            ),
+            State::Success
+        );
+    }
+}
+
+#[test]
+fn test_stray_304_body() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Stray body of a 304 response not forwarded nor reused",
+            || try_stray_304_body(false)
+        ),
+        State::Success
     );
-
-    let mut client = Client::new(
-        "client",
-        front_address,
-        http_request("POST", "/api", format!("ping"), "www.sozu.io"),
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Stray body sent late after a 304 response, the connection is not reused",
+            || try_stray_304_body(true)
+        ),
+        State::Success
     );
+}
 
-    backend0.connect();
-    client.connect();
-    client.send();
-    let accepted = backend0.accept(0);
-    assert!(accepted);
-    let request = backend0.receive(0);
-    println!("request: {request:?}");
-    backend0.send(0);
-    let response = client.receive();
-    println!("response: {response:?}");
+#[test]
+fn test_backend_answers_and_closes() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Backend answering and closing right after the connection is not retried",
+            try_backend_answers_and_closes
+        ),
+        State::Success
+    );
+}
 
-    worker.send_proxy_request(
-        RequestType::AddHttpFrontend(RequestHttpFrontend {
-            cluster_id: Some("cluster_1".to_string()),
-            address: front_address.to_string(),
-            hostname: String::from("*.sozu.io"),
-            path: PathRule::prefix(String::from("/api")),
-            position: RulePosition::Tree.into(),
-            ..Default::default()
-        })
-        .into(),
+#[test]
+fn test_max_response_header_size() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Response head larger than the listener limit answered with a 502",
+            || try_max_response_header_size(false)
+        ),
+        State::Success
     );
-    let back_address: SocketAddr = create_local_address();
-    worker.send_proxy_request(
-        RequestType::AddBackend(Worker::default_backend(
-            "cluster_1",
-            "cluster_1-0",
-            back_address.to_string(),
-            None,
-        ))
-        .into(),
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Response head split across writes and larger than the listener limit answered with a 502",
+            || try_max_response_header_size(true)
+        ),
+        State::Success
     );
+}
 
-    let mut backend1 = SyncBackend::new(
-        "BACKEND_1",
-        back_address,
-        http_ok_response(format!("pong1")),
+#[test]
+fn test_grpc_trailers() {
+    assert_eq!(
+        repeat_until_error_or(2, "TE: trailers and trailers for gRPC", try_grpc_trailers),
+        State::Success
     );
+}
 
-    worker.read_to_last();
-
-    backend1.connect();
+#[test]
+fn test_max_loop_iterations() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Sessions going through too many loop iterations",
+            try_max_loop_iterations
+        ),
+        State::Success
+    );
+}
 
-    client.send();
-    let accepted = backend1.accept(0);
-    assert!(accepted);
-    let request = backend1.receive(0);
-    println!("request: {request:?}");
-    backend1.send(0);
-    let response = client.receive();
-    println!("response: {response:?}");
+#[test]
+fn test_absolute_form_rewrite() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "absolute URI forwarded in origin-form",
+            try_absolute_form_rewrite
+        ),
+        State::Success
+    );
+}
 
-    State::Success
+#[test]
+fn test_slow_request_headers() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "request headers sent below the minimum rate are answered with a 408",
+            try_slow_request_headers
+        ),
+        State::Success
+    );
 }
 
 #[test]
-fn test_sync() {
-    assert_eq!(try_sync(10, 100), State::Success);
+fn test_chunked_body_limit() {
+    assert_eq!(
+        repeat_until_error_or(2, "Chunked body size limit", try_chunked_body_limit),
+        State::Success
+    );
 }
 
 #[test]
-fn test_async() {
-    assert_eq!(try_async(3, 10, 100), State::Success);
+fn test_chunk_header_limits() {
+    assert_eq!(
+        repeat_until_error_or(2, "Chunk header limits", try_chunk_header_limits),
+        State::Success
+    );
 }
 
 #[test]
-fn test_hard_stop() {
+fn test_close_delimited_response_limit() {
     assert_eq!(
         repeat_until_error_or(
-            10,
-            "Hard Stop: Test that the worker shuts down even if backends are not done",
-            || try_hard_or_soft_stop(false)
+            2,
+            "Close delimited response size limit",
+            try_close_delimited_response_limit
         ),
         State::Success
     );
 }
 
 #[test]
-fn test_soft_stop() {
+fn test_close_delimited_response_connection_close() {
     assert_eq!(
-            repeat_until_error_or(
-                10,
-                "Hard Stop: Test that the worker waits for all backends to process requests before shutting down",
-                || try_hard_or_soft_stop(true)
-            ),
-            State::Success
-        );
+        repeat_until_error_or(
+            2,
+            "Close-delimited responses are sent with Connection: close",
+            try_close_delimited_response_connection_close
+        ),
+        State::Success
+    );
 }
 
-// https://github.com/sozu-proxy/sozu/issues/806
-// This should actually be a success
+#[test]
+fn test_max_front_read_size() {
+    assert_eq!(
+        repeat_until_error_or(2, "Front reads limited in size", try_max_front_read_size),
+        State::Success
+    );
+}
 
 #[test]
-fn test_issue_806() {
-    assert!(
+fn test_added_headers_keep_alive() {
+    assert_eq!(
         repeat_until_error_or(
-            100,
-            "issue 806: timeout with invalid back token\n(not fixed)",
-            || try_backend_stop(2, None)
-        ) != State::Fail
+            2,
+            "Added request headers across keep-alive requests",
+            try_added_headers_keep_alive
+        ),
+        State::Success
     );
 }
 
-// https://github.com/sozu-proxy/sozu/issues/808
+#[test]
+fn test_transfer_codings() {
+    assert_eq!(
+        repeat_until_error_or(2, "Transfer codings before chunked", try_transfer_codings),
+        State::Success
+    );
+}
 
 #[test]
-fn test_issue_808() {
-    // this test is not relevant anymore, at least not like this
-    // assert_eq!(
-    //     repeat_until_error_or(
-    //         100,
-    //         "issue 808: panic on successful zombie check\n(fixed)",
-    //         || try_backend_stop(2, Some(1))
-    //     ),
-    //     // if Success, it means the session was never a zombie
-    //     // if Fail, it means the zombie checker probably crashed
-    //     State::Undecided
-    // );
+fn test_chunked_request_split() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Chunked request split across reads",
+            try_chunked_request_split
+        ),
+        State::Success
+    );
 }
 
-// https://github.com/sozu-proxy/sozu/issues/810
+#[test]
+fn test_tls_early_data() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "TLS 1.3 early data: only replay safe requests are forwarded",
+            try_tls_early_data
+        ),
+        State::Success
+    );
+}
 
 #[test]
-fn test_issue_810_timeout() {
+fn test_tls_upgrade_without_buffers() {
     assert_eq!(
         repeat_until_error_or(
-            100,
-            "issue 810: shutdown struggles until session timeout\n(fixed)",
-            try_issue_810_timeout
+            2,
+            "TLS: a session without buffer for HTTP is answered a 503",
+            try_tls_upgrade_without_buffers
         ),
         State::Success
     );
 }
 
 #[test]
-fn test_issue_810_panic_on_session_close() {
+fn test_strict_sni_binding() {
     assert_eq!(
         repeat_until_error_or(
-            100,
-            "issue 810: shutdown panics on session close\n(fixed)",
-            || try_issue_810_panic(false)
+            2,
+            "TLS: a host not matching the SNI is answered a 421",
+            try_strict_sni_binding
         ),
         State::Success
     );
 }
 
 #[test]
-fn test_issue_810_panic_on_missing_listener() {
+fn test_http_authentication() {
     assert_eq!(
-            repeat_until_error_or(
-                100,
-                "issue 810: shutdown panics on tcp connection after proxy cleared its listeners\n(opinionated fix)",
-                || try_issue_810_panic(true)
-            ),
-            State::Success
-        );
+        repeat_until_error_or(2, "HTTP Basic authentication", try_http_authentication),
+        State::Success
+    );
 }
 
 #[test]
-fn test_tls_endpoint() {
+fn test_request_error_event() {
+    assert_eq!(
+        repeat_until_error_or(2, "Request parse error event", try_request_error_event),
+        State::Success
+    );
+}
+
+#[test]
+fn test_ip_filter() {
+    assert_eq!(
+        repeat_until_error_or(2, "CIDR allow and deny lists", try_ip_filter),
+        State::Success
+    );
+}
+
+#[test]
+fn test_allowed_methods() {
     assert_eq!(
         repeat_until_error_or(
-            100,
-            "TLS endpoint: Sōzu should decrypt an HTTPS request",
-            try_tls_endpoint
+            2,
+            "405 answer to a method missing from the allowlist",
+            try_allowed_methods
         ),
         State::Success
     );
 }
 
 #[test]
-fn test_http_behaviors() {
+fn test_head_as_get() {
     assert_eq!(
-        repeat_until_error_or(10, "HTTP stack", try_http_behaviors),
+        repeat_until_error_or(2, "HEAD forwarded to the backend as GET", try_head_as_get),
         State::Success
     );
 }
 
 #[test]
-fn test_msg_close() {
+fn test_deactivate_listener_drain() {
     assert_eq!(
-        repeat_until_error_or(100, "HTTP error on close", try_msg_close),
+        repeat_until_error_or(
+            2,
+            "Drain the sessions of a deactivated listener",
+            try_deactivate_listener_drain
+        ),
         State::Success
     );
 }
 
 #[test]
-fn test_blue_green() {
+fn test_query_sessions() {
     assert_eq!(
-        repeat_until_error_or(10, "Blue green switch", try_blue_geen),
+        repeat_until_error_or(2, "Query the active sessions", try_query_sessions),
         State::Success
     );
 }
 
 #[test]
-fn test_keep_alive() {
+fn test_omit_forwarded_header() {
     assert_eq!(
-        repeat_until_error_or(10, "Keep alive combinations", try_keep_alive),
+        repeat_until_error_or(
+            2,
+            "Forwarded header omitted by the listener, X-Forwarded-* kept",
+            try_omit_forwarded_header
+        ),
         State::Success
     );
 }
 
 #[test]
-fn test_stick() {
+fn test_trace_context() {
     assert_eq!(
-        repeat_until_error_or(10, "Sticky session", try_stick),
+        repeat_until_error_or(
+            2,
+            "W3C trace context generated or propagated",
+            try_trace_context
+        ),
         State::Success
     );
 }
 
 #[test]
-fn test_max_connections() {
+fn test_suppressed_headers() {
     assert_eq!(
-        repeat_until_error_or(2, "Max connections reached", try_max_connections),
+        repeat_until_error_or(2, "Headers suppressed per cluster", try_suppressed_headers),
         State::Success
     );
 }
 
 #[test]
-fn test_head() {
+fn test_duplicate_host() {
     assert_eq!(
-        repeat_until_error_or(10, "Head request", try_head),
+        repeat_until_error_or(
+            2,
+            "Requests with two Host headers are refused",
+            try_duplicate_host
+        ),
         State::Success
     );
 }
 
 #[test]
-fn test_wildcard() {
+fn test_apply_config() {
     assert_eq!(
-        repeat_until_error_or(2, "Hostname with wildcard", try_wildcard),
+        repeat_until_error_or(2, "Apply a configuration diff", || try_apply_config(false)),
         State::Success
     );
 }
 
 #[test]
-fn test_status_header_split() {
+fn test_apply_config_rollback() {
     assert_eq!(
-        repeat_until_error_or(2, "Status line and Headers split", try_status_header_split),
+        repeat_until_error_or(
+            2,
+            "Roll back a configuration diff with an invalid entry",
+            || try_apply_config(true)
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_accept_backpressure() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Connections above max_connections wait in the listen backlog",
+            try_accept_backpressure
+        ),
+        State::Success
+    );
+}
+
+#[test]
+fn test_query_route_match() {
+    assert_eq!(
+        repeat_until_error_or(
+            2,
+            "Query the route of a request matching overlapping frontends",
+            try_query_route_match
+        ),
         State::Success
     );
 }
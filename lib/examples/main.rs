@@ -60,6 +60,7 @@ fn main() -> anyhow::Result<()> {
         address: "127.0.0.1:1026".to_string(),
         load_balancing_parameters: Some(LoadBalancingParams::default()),
         backup: None,
+        hostname: None,
     };
 
     command.write_message(&WorkerRequest {
@@ -126,6 +127,7 @@ fn main() -> anyhow::Result<()> {
         address: "127.0.0.1:1026".to_string(),
         load_balancing_parameters: Some(LoadBalancingParams::default()),
         backup: None,
+        hostname: None,
     };
 
     command2.write_message(&WorkerRequest {
@@ -176,6 +178,7 @@ fn main() -> anyhow::Result<()> {
         address: "127.0.0.1:1026".to_string(),
         load_balancing_parameters: Some(LoadBalancingParams::default()),
         backup: None,
+        hostname: None,
     };
 
     command2.write_message(&WorkerRequest {
@@ -49,6 +49,7 @@ fn main() -> anyhow::Result<()> {
         load_balancing_parameters: Some(LoadBalancingParams::default()),
         sticky_id: None,
         backup: None,
+        hostname: None,
     };
 
     command.write_message(&WorkerRequest {
@@ -1,17 +1,20 @@
-use std::{cell::RefCell, collections::HashMap, net::SocketAddr, rc::Rc};
+use std::{
+    cell::RefCell, collections::HashMap, fmt::Debug, io, net::SocketAddr, rc::Rc, time::Instant,
+};
 
 use mio::net::TcpStream;
 use time::Duration;
 
 use sozu_command::{
     proto::command::{Event, EventKind, LoadBalancingAlgorithms, LoadBalancingParams, LoadMetric},
-    state::ClusterId,
+    state::{ClusterId, ConfigState},
 };
 
 use crate::{
     load_balancing::{LeastLoaded, LoadBalancingAlgorithm, PowerOfTwo, Random, RoundRobin},
     retry::{self, RetryPolicy},
     server::{self, push_event},
+    socket::connect_with_buffer_sizes,
     PeakEWMA,
 };
 
@@ -34,6 +37,33 @@ pub enum BackendError {
     },
 }
 
+/// Opens the connections to the backend servers. The proxies use `TcpConnector`,
+/// tests can replace it with `BackendMap::set_connector` to simulate connection
+/// successes and failures without backend servers
+pub trait Connector: Debug {
+    /// `buffer_sizes` are the send and receive buffer sizes of the listener
+    /// the session came from
+    fn connect(
+        &self,
+        address: SocketAddr,
+        buffer_sizes: (Option<u32>, Option<u32>),
+    ) -> io::Result<TcpStream>;
+}
+
+/// starts a non blocking TCP connection
+#[derive(Debug)]
+pub struct TcpConnector;
+
+impl Connector for TcpConnector {
+    fn connect(
+        &self,
+        address: SocketAddr,
+        (send_buffer_size, receive_buffer_size): (Option<u32>, Option<u32>),
+    ) -> io::Result<TcpStream> {
+        connect_with_buffer_sizes(address, send_buffer_size, receive_buffer_size)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum BackendStatus {
     Normal,
@@ -54,6 +84,17 @@ pub struct Backend {
     pub load_balancing_parameters: Option<LoadBalancingParams>,
     pub backup: bool,
     pub connection_time: PeakEWMA,
+    /// 5xx responses received in a row, reset by any other status
+    pub consecutive_5xx: u32,
+    /// set by outlier detection, the backend gets no new connection until then
+    pub ejected_until: Option<Instant>,
+    /// DNS name resolved periodically by the server, the connections go to its
+    /// addresses on the port of `address`
+    pub hostname: Option<String>,
+    /// the last addresses `hostname` resolved to, `address` is used while it is empty
+    pub resolved_addresses: Vec<SocketAddr>,
+    /// index in `resolved_addresses` of the next connection
+    pub next_resolved_address: usize,
 }
 
 impl Backend {
@@ -77,9 +118,19 @@ impl Backend {
             load_balancing_parameters,
             backup: backup.unwrap_or(false),
             connection_time: PeakEWMA::new(),
+            consecutive_5xx: 0,
+            ejected_until: None,
+            hostname: None,
+            resolved_addresses: Vec::new(),
+            next_resolved_address: 0,
         }
     }
 
+    pub fn with_hostname(mut self, hostname: Option<String>) -> Backend {
+        self.hostname = hostname;
+        self
+    }
+
     pub fn set_closing(&mut self) {
         self.status = BackendStatus::Closing;
     }
@@ -89,6 +140,9 @@ impl Backend {
     }
 
     pub fn can_open(&self) -> bool {
+        if self.is_at_max_connections() || self.is_ejected() {
+            return false;
+        }
         if let Some(action) = self.retry_policy.can_try() {
             self.status == BackendStatus::Normal && action == retry::RetryAction::OKAY
         } else {
@@ -96,6 +150,50 @@ impl Backend {
         }
     }
 
+    /// whether outlier detection took the backend out of the rotation
+    pub fn is_ejected(&self) -> bool {
+        match self.ejected_until {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    /// Count a response status for outlier detection: after `threshold` 5xx
+    /// statuses in a row, the backend is ejected for `ejection_time`.
+    /// Returns true if the backend was just ejected
+    pub fn record_response_status(
+        &mut self,
+        status: u16,
+        threshold: u32,
+        ejection_time: std::time::Duration,
+    ) -> bool {
+        if !(500..600).contains(&status) {
+            self.consecutive_5xx = 0;
+            return false;
+        }
+
+        self.consecutive_5xx += 1;
+        if threshold == 0 || self.consecutive_5xx < threshold {
+            return false;
+        }
+
+        self.consecutive_5xx = 0;
+        self.ejected_until = Some(Instant::now() + ejection_time);
+        true
+    }
+
+    /// whether the connection limit of the load balancing parameters is reached
+    pub fn is_at_max_connections(&self) -> bool {
+        match self
+            .load_balancing_parameters
+            .as_ref()
+            .and_then(|params| params.max_connections)
+        {
+            Some(max_connections) => self.active_connections >= max_connections as usize,
+            None => false,
+        }
+    }
+
     pub fn inc_connections(&mut self) -> Option<usize> {
         if self.status == BackendStatus::Normal {
             self.active_connections += 1;
@@ -137,14 +235,44 @@ impl Backend {
         self.connection_time.get(self.active_connections)
     }
 
-    pub fn try_connect(&mut self) -> Result<mio::net::TcpStream, BackendError> {
+    /// Replace the addresses the hostname resolved to.
+    /// Returns true if they changed
+    pub fn set_resolved_addresses(&mut self, mut addresses: Vec<SocketAddr>) -> bool {
+        addresses.sort();
+        addresses.dedup();
+        if addresses == self.resolved_addresses {
+            return false;
+        }
+        self.resolved_addresses = addresses;
+        self.next_resolved_address = 0;
+        true
+    }
+
+    /// the address of the next connection: the resolved addresses in turn,
+    /// or the configured address if the hostname did not resolve yet
+    pub fn connect_address(&mut self) -> SocketAddr {
+        if self.resolved_addresses.is_empty() {
+            return self.address;
+        }
+        let index = self.next_resolved_address % self.resolved_addresses.len();
+        self.next_resolved_address = index + 1;
+        self.resolved_addresses[index]
+    }
+
+    pub fn try_connect(
+        &mut self,
+        connector: &dyn Connector,
+        buffer_sizes: (Option<u32>, Option<u32>),
+    ) -> Result<TcpStream, BackendError> {
         if self.status != BackendStatus::Normal {
             return Err(BackendError::Status(self.status.to_owned()));
         }
 
-        match mio::net::TcpStream::connect(self.address) {
+        match connector.connect(self.connect_address(), buffer_sizes) {
+            // like mio, the connector returns the sockets whose connection is still
+            // in progress (EINPROGRESS), the retry policy succeeds once the connection
+            // is established, when the session gets its first event
             Ok(tcp_stream) => {
-                //self.retry_policy.succeed();
                 self.inc_connections();
                 Ok(tcp_stream)
             }
@@ -180,6 +308,8 @@ pub struct BackendMap {
     pub backends: HashMap<ClusterId, BackendList>,
     pub max_failures: usize,
     pub available: bool,
+    /// opens the connections to the backends
+    pub connector: Box<dyn Connector>,
 }
 
 impl Default for BackendMap {
@@ -194,9 +324,15 @@ impl BackendMap {
             backends: HashMap::new(),
             max_failures: 3,
             available: true,
+            connector: Box::new(TcpConnector),
         }
     }
 
+    /// Replace the connector opening the connections to the backends
+    pub fn set_connector(&mut self, connector: Box<dyn Connector>) {
+        self.connector = connector;
+    }
+
     pub fn import_configuration_state(
         &mut self,
         backends: &HashMap<ClusterId, Vec<sozu_command::response::Backend>>,
@@ -210,6 +346,64 @@ impl BackendMap {
             }));
     }
 
+    /// the inverse of `import_configuration_state`, for one cluster
+    pub fn export_configuration_state(
+        &self,
+        cluster_id: &str,
+    ) -> Vec<sozu_command::response::Backend> {
+        self.backends
+            .get(cluster_id)
+            .map(|backend_list| {
+                backend_list
+                    .backends
+                    .iter()
+                    .map(|backend| {
+                        let backend = backend.borrow();
+                        sozu_command::response::Backend {
+                            cluster_id: cluster_id.to_owned(),
+                            backend_id: backend.backend_id.to_owned(),
+                            address: backend.address,
+                            sticky_id: backend.sticky_id.to_owned(),
+                            load_balancing_parameters: backend.load_balancing_parameters.to_owned(),
+                            backup: Some(backend.backup),
+                            hostname: backend.hostname.to_owned(),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// add the backends of a configuration snapshot, with the load balancing
+    /// policies of their clusters
+    pub fn restore_configuration_state(&mut self, state: &ConfigState) {
+        for cluster in state.clusters.values() {
+            self.set_load_balancing_policy_for_cluster(
+                &cluster.cluster_id,
+                LoadBalancingAlgorithms::try_from(cluster.load_balancing).unwrap_or_default(),
+                cluster
+                    .load_metric
+                    .and_then(|n| LoadMetric::try_from(n).ok()),
+            );
+        }
+
+        for (cluster_id, backends) in &state.backends {
+            for backend in backends {
+                self.add_backend(
+                    cluster_id,
+                    Backend::new(
+                        &backend.backend_id,
+                        backend.address,
+                        backend.sticky_id.clone(),
+                        backend.load_balancing_parameters.clone(),
+                        backend.backup,
+                    )
+                    .with_hostname(backend.hostname.clone()),
+                );
+            }
+        }
+    }
+
     pub fn add_backend(&mut self, cluster_id: &str, backend: Backend) {
         self.backends
             .entry(cluster_id.to_string())
@@ -245,9 +439,67 @@ impl BackendMap {
             .unwrap_or(false)
     }
 
+    /// the addresses of all the backends of a cluster, with the addresses
+    /// their hostnames resolved to
+    pub fn backend_addresses(&self, cluster_id: &str) -> Vec<SocketAddr> {
+        self.backends
+            .get(cluster_id)
+            .map(|backends| {
+                backends
+                    .backends
+                    .iter()
+                    .flat_map(|backend| {
+                        let backend = backend.borrow();
+                        let mut addresses = backend.resolved_addresses.clone();
+                        addresses.push(backend.address);
+                        addresses
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// the hostnames of all the backends to resolve, with the port to connect to
+    pub fn hostnames(&self) -> Vec<(String, u16)> {
+        let mut hostnames: Vec<(String, u16)> = self
+            .backends
+            .values()
+            .flat_map(|backends| backends.backends.iter())
+            .filter_map(|backend| {
+                let backend = backend.borrow();
+                backend
+                    .hostname
+                    .clone()
+                    .map(|hostname| (hostname, backend.address.port()))
+            })
+            .collect();
+        hostnames.sort();
+        hostnames.dedup();
+        hostnames
+    }
+
+    /// update the backends with this hostname and port with the addresses it resolved to
+    pub fn set_resolved_addresses(&mut self, hostname: &str, port: u16, addresses: &[SocketAddr]) {
+        for (cluster_id, backends) in self.backends.iter() {
+            for backend in &backends.backends {
+                let mut backend = backend.borrow_mut();
+                if backend.hostname.as_deref() != Some(hostname) || backend.address.port() != port {
+                    continue;
+                }
+                if backend.set_resolved_addresses(addresses.to_vec()) {
+                    info!(
+                        "backend server {} of cluster {}: {} now resolves to {:?}",
+                        backend.backend_id, cluster_id, hostname, backend.resolved_addresses
+                    );
+                }
+            }
+        }
+    }
+
     pub fn backend_from_cluster_id(
         &mut self,
         cluster_id: &str,
+        buffer_sizes: (Option<u32>, Option<u32>),
     ) -> Result<(Rc<RefCell<Backend>>, TcpStream), BackendError> {
         let cluster_backends = self
             .backends
@@ -259,6 +511,7 @@ impl BackendMap {
             return Err(BackendError::NoBackendForCluster(cluster_id.to_owned()));
         }
 
+        cluster_backends.readmit_ejected_backends(cluster_id);
         let next_backend = match cluster_backends.next_available_backend() {
             Some(nb) => nb,
             None => {
@@ -288,23 +541,27 @@ impl BackendMap {
             )
         );
 
-        let tcp_stream = borrowed_backend.try_connect().map_err(|backend_error| {
-            BackendError::ConnectionFailures {
+        let tcp_stream = borrowed_backend
+            .try_connect(self.connector.as_ref(), buffer_sizes)
+            .map_err(|backend_error| BackendError::ConnectionFailures {
                 cluster_id: cluster_id.to_owned(),
                 backend_address: borrowed_backend.address,
                 failures: borrowed_backend.failures,
                 error: backend_error.to_string(),
-            }
-        })?;
+            })?;
         self.available = true;
 
         Ok((next_backend.clone(), tcp_stream))
     }
 
+    /// Connects to the backend of the sticky session. If it is unknown, unavailable
+    /// or refuses the connection, another backend is selected with the load balancing
+    /// policy of the cluster, and the session will get a new sticky cookie
     pub fn backend_from_sticky_session(
         &mut self,
         cluster_id: &str,
         sticky_session: &str,
+        buffer_sizes: (Option<u32>, Option<u32>),
     ) -> Result<(Rc<RefCell<Backend>>, TcpStream), BackendError> {
         let sticky_conn = self
             .backends
@@ -312,7 +569,7 @@ impl BackendMap {
             .and_then(|cluster_backends| cluster_backends.find_sticky(sticky_session))
             .map(|backend| {
                 let mut borrowed = backend.borrow_mut();
-                let conn = borrowed.try_connect();
+                let conn = borrowed.try_connect(self.connector.as_ref(), buffer_sizes);
 
                 conn.map(|tcp_stream| (backend.clone(), tcp_stream))
                     .map_err(|e| {
@@ -325,13 +582,18 @@ impl BackendMap {
             });
 
         match sticky_conn {
-            Some(backend_and_stream) => backend_and_stream,
+            Some(Ok(backend_and_stream)) => Ok(backend_and_stream),
+            Some(Err(_)) => {
+                incr!("sticky.fallback", Some(cluster_id), None);
+                self.backend_from_cluster_id(cluster_id, buffer_sizes)
+            }
             None => {
                 debug!(
                     "Couldn't find a backend corresponding to sticky_session {} for cluster {}",
                     sticky_session, cluster_id
                 );
-                self.backend_from_cluster_id(cluster_id)
+                incr!("sticky.fallback", Some(cluster_id), None);
+                self.backend_from_cluster_id(cluster_id, buffer_sizes)
             }
         }
     }
@@ -348,6 +610,58 @@ impl BackendMap {
         cluster_backends.set_load_balancing_policy(lb_algo, metric);
     }
 
+    /// Plug a custom load balancing algorithm in for a cluster, for applications
+    /// embedding sozu as a library. It takes precedence over the policy of the
+    /// cluster configuration until it is removed with `None`, the cluster then
+    /// goes back to random selection until its policy is set again
+    pub fn set_custom_load_balancing_for_cluster(
+        &mut self,
+        cluster_id: &str,
+        load_balancing: Option<Box<dyn LoadBalancingAlgorithm>>,
+    ) {
+        let cluster_backends = self.get_or_create_backend_list_for_cluster(cluster_id);
+        cluster_backends.custom_load_balancing = load_balancing.is_some();
+        cluster_backends.load_balancing = load_balancing.unwrap_or_else(|| Box::new(Random));
+    }
+
+    /// to be called when a backend of the cluster goes down: emits a
+    /// ClusterDown event if it was the last healthy one. Backends only go
+    /// down after several failures, so this is the only transition scanning
+    /// the backends of the cluster
+    pub fn backend_down(&mut self, cluster_id: &str) {
+        let cluster_backends = match self.backends.get_mut(cluster_id) {
+            Some(cluster_backends) => cluster_backends,
+            None => return,
+        };
+
+        if cluster_backends.all_down || !cluster_backends.are_all_down() {
+            return;
+        }
+        cluster_backends.all_down = true;
+
+        error!("all backends of cluster {} are down", cluster_id);
+        incr!("cluster.down", Some(cluster_id), None);
+        push_cluster_event(EventKind::ClusterDown, cluster_id);
+    }
+
+    /// to be called when a backend of the cluster comes back up: emits a
+    /// ClusterUp event if the whole cluster was down
+    pub fn backend_up(&mut self, cluster_id: &str) {
+        let cluster_backends = match self.backends.get_mut(cluster_id) {
+            Some(cluster_backends) => cluster_backends,
+            None => return,
+        };
+
+        if !cluster_backends.all_down {
+            return;
+        }
+        cluster_backends.all_down = false;
+
+        info!("cluster {} has a healthy backend again", cluster_id);
+        incr!("cluster.up", Some(cluster_id), None);
+        push_cluster_event(EventKind::ClusterUp, cluster_id);
+    }
+
     pub fn get_or_create_backend_list_for_cluster(&mut self, cluster_id: &str) -> &mut BackendList {
         self.backends
             .entry(cluster_id.to_string())
@@ -355,11 +669,24 @@ impl BackendMap {
     }
 }
 
+fn push_cluster_event(kind: EventKind, cluster_id: &str) {
+    push_event(Event {
+        kind: kind as i32,
+        cluster_id: Some(cluster_id.to_owned()),
+        backend_id: None,
+        address: None,
+    });
+}
+
 #[derive(Debug)]
 pub struct BackendList {
     pub backends: Vec<Rc<RefCell<Backend>>>,
     pub next_id: u32,
     pub load_balancing: Box<dyn LoadBalancingAlgorithm>,
+    /// set if load_balancing is a custom algorithm, the policy of the cluster does not replace it
+    pub custom_load_balancing: bool,
+    /// set when every backend of the cluster is marked down by its retry policy
+    pub all_down: bool,
 }
 
 impl Default for BackendList {
@@ -374,6 +701,8 @@ impl BackendList {
             backends: Vec::new(),
             next_id: 0,
             load_balancing: Box::new(Random),
+            custom_load_balancing: false,
+            all_down: false,
         }
     }
 
@@ -388,7 +717,8 @@ impl BackendList {
                 backend.sticky_id.clone(),
                 backend.load_balancing_parameters.clone(),
                 backend.backup,
-            );
+            )
+            .with_hostname(backend.hostname.clone());
             list.add_backend(backend);
         }
 
@@ -411,6 +741,10 @@ impl BackendList {
                 b.sticky_id = backend.sticky_id.clone();
                 b.load_balancing_parameters = backend.load_balancing_parameters.clone();
                 b.backup = backend.backup;
+                if b.hostname != backend.hostname {
+                    b.hostname = backend.hostname.clone();
+                    b.set_resolved_addresses(Vec::new());
+                }
             }
         }
     }
@@ -426,6 +760,15 @@ impl BackendList {
             .any(|backend| backend.borrow().address == *backend_address)
     }
 
+    /// true if the cluster has backends and all of them are marked down
+    pub fn are_all_down(&self) -> bool {
+        !self.backends.is_empty()
+            && self
+                .backends
+                .iter()
+                .all(|backend| backend.borrow().retry_policy.is_down())
+    }
+
     pub fn find_backend(
         &mut self,
         backend_address: &SocketAddr,
@@ -453,6 +796,26 @@ impl BackendList {
             .collect()
     }
 
+    /// Put back in the rotation the backends whose outlier ejection is over,
+    /// their next requests probe them
+    pub fn readmit_ejected_backends(&mut self, cluster_id: &str) {
+        for backend in &self.backends {
+            let mut backend = backend.borrow_mut();
+            if backend.ejected_until.is_some() && !backend.is_ejected() {
+                info!(
+                    "backend server {} at {} of cluster {} is readmitted after its ejection",
+                    backend.backend_id, backend.address, cluster_id
+                );
+                incr!(
+                    "backend.outlier.readmitted",
+                    Some(cluster_id),
+                    Some(&backend.backend_id)
+                );
+                backend.ejected_until = None;
+            }
+        }
+    }
+
     pub fn next_available_backend(&mut self) -> Option<Rc<RefCell<Backend>>> {
         let mut backends = self.available_backends(false);
 
@@ -472,6 +835,10 @@ impl BackendList {
         load_balancing_policy: LoadBalancingAlgorithms,
         metric: Option<LoadMetric>,
     ) {
+        if self.custom_load_balancing {
+            return;
+        }
+
         match load_balancing_policy {
             LoadBalancingAlgorithms::RoundRobin => {
                 self.load_balancing = Box::new(RoundRobin::new())
@@ -495,7 +862,8 @@ impl BackendList {
 mod backends_test {
 
     use super::*;
-    use std::{net::TcpListener, sync::mpsc::*, thread};
+    use sozu_command::proto::command::response_content::ContentType;
+    use std::{cell::Cell, collections::VecDeque, net::TcpListener, sync::mpsc::*, thread};
 
     fn run_mock_tcp_server(addr: &str, stopper: Receiver<()>) {
         let mut run = true;
@@ -533,7 +901,9 @@ mod backends_test {
             ),
         );
 
-        assert!(backend_map.backend_from_cluster_id(cluster_id).is_ok());
+        assert!(backend_map
+            .backend_from_cluster_id(cluster_id, (None, None))
+            .is_ok());
         sender.send(()).unwrap();
     }
 
@@ -547,7 +917,7 @@ mod backends_test {
         );
 
         assert!(backend_map
-            .backend_from_cluster_id(cluster_not_recorded)
+            .backend_from_cluster_id(cluster_not_recorded, (None, None))
             .is_err());
     }
 
@@ -555,7 +925,9 @@ mod backends_test {
     fn it_should_not_retrieve_a_backend_from_cluster_id_when_backend_list_is_empty() {
         let mut backend_map = BackendMap::new();
 
-        assert!(backend_map.backend_from_cluster_id("dumb").is_err());
+        assert!(backend_map
+            .backend_from_cluster_id("dumb", (None, None))
+            .is_err());
     }
 
     #[test]
@@ -601,7 +973,7 @@ mod backends_test {
         );
 
         assert!(backend_map
-            .backend_from_sticky_session(cluster_id, sticky_session)
+            .backend_from_sticky_session(cluster_id, sticky_session, (None, None))
             .is_ok());
         sender.send(()).unwrap();
     }
@@ -614,7 +986,7 @@ mod backends_test {
         let sticky_session = "test";
 
         assert!(backend_map
-            .backend_from_sticky_session(cluster_id, sticky_session)
+            .backend_from_sticky_session(cluster_id, sticky_session, (None, None))
             .is_err());
     }
 
@@ -625,7 +997,7 @@ mod backends_test {
         let sticky_session = "test";
 
         assert!(backend_map
-            .backend_from_sticky_session(mycluster_not_recorded, sticky_session)
+            .backend_from_sticky_session(mycluster_not_recorded, sticky_session, (None, None))
             .is_err());
     }
 
@@ -667,4 +1039,561 @@ mod backends_test {
 
         assert_eq!(1, backends_list.backends.len());
     }
+
+    #[test]
+    fn it_should_skip_a_backend_at_its_connection_limit() {
+        let mut backends_list = BackendList::new();
+        for index in 0..2 {
+            backends_list.add_backend(Backend::new(
+                &format!("myback-{index}"),
+                format!("127.0.0.1:{}", 8000 + index).parse().unwrap(),
+                None,
+                Some(LoadBalancingParams {
+                    weight: 100,
+                    max_connections: Some(1),
+                }),
+                None,
+            ));
+        }
+
+        let first = backends_list.next_available_backend().unwrap();
+        first.borrow_mut().inc_connections();
+        let second = backends_list.next_available_backend().unwrap();
+        assert_ne!(first.borrow().backend_id, second.borrow().backend_id);
+
+        second.borrow_mut().inc_connections();
+        assert!(backends_list.next_available_backend().is_none());
+
+        first.borrow_mut().dec_connections();
+        assert_eq!(
+            backends_list
+                .next_available_backend()
+                .unwrap()
+                .borrow()
+                .backend_id,
+            first.borrow().backend_id
+        );
+    }
+
+    /// always selects the backend with the highest id
+    #[derive(Debug)]
+    struct LastBackend;
+
+    impl LoadBalancingAlgorithm for LastBackend {
+        fn next_available_backend(
+            &mut self,
+            backends: &mut Vec<Rc<RefCell<Backend>>>,
+        ) -> Option<Rc<RefCell<Backend>>> {
+            backends
+                .iter()
+                .max_by(|a, b| a.borrow().backend_id.cmp(&b.borrow().backend_id))
+                .cloned()
+        }
+    }
+
+    #[test]
+    fn it_should_select_backends_with_a_custom_load_balancing() {
+        let cluster_id = "mycluster";
+        let mut backend_map = BackendMap::new();
+        for (index, address) in ["127.0.0.1:1044", "127.0.0.1:1045", "127.0.0.1:1046"]
+            .iter()
+            .enumerate()
+        {
+            backend_map.add_backend(
+                cluster_id,
+                Backend::new(
+                    &format!("{cluster_id}-{index}"),
+                    address.parse().unwrap(),
+                    None,
+                    None,
+                    None,
+                ),
+            );
+        }
+
+        backend_map.set_custom_load_balancing_for_cluster(cluster_id, Some(Box::new(LastBackend)));
+        // the policy of the cluster configuration does not replace it
+        backend_map.set_load_balancing_policy_for_cluster(
+            cluster_id,
+            LoadBalancingAlgorithms::RoundRobin,
+            None,
+        );
+
+        let backend_list = backend_map.backends.get_mut(cluster_id).unwrap();
+        for _ in 0..3 {
+            let backend = backend_list.next_available_backend().unwrap();
+            assert_eq!(backend.borrow().backend_id, "mycluster-2");
+        }
+
+        backend_map.set_custom_load_balancing_for_cluster(cluster_id, None);
+        backend_map.set_load_balancing_policy_for_cluster(
+            cluster_id,
+            LoadBalancingAlgorithms::RoundRobin,
+            None,
+        );
+        let backend_list = backend_map.backends.get_mut(cluster_id).unwrap();
+        let selected: Vec<String> = (0..3)
+            .map(|_| {
+                backend_list
+                    .next_available_backend()
+                    .map(|backend| backend.borrow().backend_id.clone())
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(selected, vec!["mycluster-0", "mycluster-1", "mycluster-2"]);
+    }
+
+    #[test]
+    fn it_should_eject_a_backend_after_consecutive_5xx() {
+        let mut backends_list = BackendList::new();
+        for index in 0..2 {
+            backends_list.add_backend(Backend::new(
+                &format!("myback-{index}"),
+                format!("127.0.0.1:{}", 8000 + index).parse().unwrap(),
+                None,
+                None,
+                None,
+            ));
+        }
+        let ejection_time = std::time::Duration::from_secs(60);
+
+        {
+            let mut backend = backends_list.backends[0].borrow_mut();
+            assert!(!backend.record_response_status(503, 3, ejection_time));
+            assert!(!backend.record_response_status(502, 3, ejection_time));
+            // any other status resets the count
+            assert!(!backend.record_response_status(404, 3, ejection_time));
+            assert!(!backend.record_response_status(500, 3, ejection_time));
+            assert!(!backend.record_response_status(500, 3, ejection_time));
+            assert!(backend.can_open());
+            assert!(backend.record_response_status(500, 3, ejection_time));
+            assert!(!backend.can_open());
+        }
+
+        for _ in 0..4 {
+            assert_eq!(
+                backends_list
+                    .next_available_backend()
+                    .unwrap()
+                    .borrow()
+                    .backend_id,
+                "myback-1"
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_readmit_an_ejected_backend_after_the_cooldown() {
+        let mut backends_list = BackendList::new();
+        backends_list.add_backend(Backend::new(
+            "myback",
+            "127.0.0.1:8000".parse().unwrap(),
+            None,
+            None,
+            None,
+        ));
+        let backend = backends_list.backends[0].clone();
+
+        let ejection_time = std::time::Duration::from_millis(50);
+        assert!(backend
+            .borrow_mut()
+            .record_response_status(503, 1, ejection_time));
+        assert!(backends_list.next_available_backend().is_none());
+
+        thread::sleep(ejection_time);
+        backends_list.readmit_ejected_backends("mycluster");
+        assert_eq!(backend.borrow().ejected_until, None);
+        assert_eq!(
+            backends_list
+                .next_available_backend()
+                .unwrap()
+                .borrow()
+                .backend_id,
+            "myback"
+        );
+    }
+
+    #[test]
+    fn it_should_fall_back_to_another_backend_when_the_sticky_backend_is_down() {
+        let mut backend_map = BackendMap::new();
+        let cluster_id = "mycluster";
+
+        let backend_addr = "127.0.0.1:3457";
+        let (sender, receiver) = channel();
+        run_mock_tcp_server(backend_addr, receiver);
+
+        backend_map.add_backend(
+            cluster_id,
+            Backend::new(
+                &format!("{cluster_id}-1"),
+                "127.0.0.1:9002".parse().unwrap(),
+                Some("server-1".to_string()),
+                Some(LoadBalancingParams {
+                    weight: 100,
+                    max_connections: None,
+                }),
+                None,
+            ),
+        );
+        backend_map.add_backend(
+            cluster_id,
+            Backend::new(
+                &format!("{cluster_id}-2"),
+                backend_addr.parse().unwrap(),
+                Some("server-2".to_string()),
+                Some(LoadBalancingParams {
+                    weight: 100,
+                    max_connections: None,
+                }),
+                None,
+            ),
+        );
+
+        let pinned = backend_map.backends[cluster_id].backends[0].clone();
+        mark_down(&pinned);
+
+        let (backend, _stream) = backend_map
+            .backend_from_sticky_session(cluster_id, "server-1", (None, None))
+            .expect("should fall back to the other backend");
+        // the new sticky id is sent back to the client in a cookie
+        assert_eq!(backend.borrow().sticky_id.as_deref(), Some("server-2"));
+        sender.send(()).unwrap();
+    }
+
+    fn mark_down(backend: &Rc<RefCell<Backend>>) {
+        let mut backend = backend.borrow_mut();
+        while !backend.retry_policy.is_down() {
+            // skip the backoff delay so that each failure counts
+            backend.retry_policy.retry_after(std::time::Duration::ZERO);
+            backend.retry_policy.fail();
+        }
+    }
+
+    fn drain_events(kind: EventKind) -> usize {
+        server::QUEUE.with(|queue| {
+            queue
+                .borrow_mut()
+                .drain(..)
+                .filter(|response| {
+                    matches!(
+                        response.content.as_ref().and_then(|c| c.content_type.as_ref()),
+                        Some(ContentType::Event(event)) if event.kind == kind as i32
+                    )
+                })
+                .count()
+        })
+    }
+
+    #[test]
+    fn it_should_emit_cluster_down_then_cluster_up() {
+        let cluster_id = "mycluster";
+        let mut backend_map = BackendMap::new();
+        for i in 0..2 {
+            backend_map.add_backend(
+                cluster_id,
+                Backend::new(
+                    &format!("{cluster_id}-{i}"),
+                    format!("127.0.0.1:{}", 1024 + i).parse().unwrap(),
+                    None,
+                    None,
+                    None,
+                ),
+            );
+        }
+        let backends = backend_map
+            .backends
+            .get(cluster_id)
+            .unwrap()
+            .backends
+            .clone();
+        drain_events(EventKind::ClusterDown);
+
+        mark_down(&backends[0]);
+        backend_map.backend_down(cluster_id);
+        assert_eq!(0, drain_events(EventKind::ClusterDown));
+
+        mark_down(&backends[1]);
+        backend_map.backend_down(cluster_id);
+        // the state did not change, no new event
+        backend_map.backend_down(cluster_id);
+        assert!(backend_map.backends.get(cluster_id).unwrap().all_down);
+        server::QUEUE.with(|queue| {
+            let queue = queue.borrow();
+            assert_eq!(1, queue.len());
+        });
+        assert_eq!(1, drain_events(EventKind::ClusterDown));
+
+        backends[1].borrow_mut().retry_policy.succeed();
+        backend_map.backend_up(cluster_id);
+        backend_map.backend_up(cluster_id);
+        assert!(!backend_map.backends.get(cluster_id).unwrap().all_down);
+        assert_eq!(1, drain_events(EventKind::ClusterUp));
+    }
+
+    #[derive(Debug)]
+    enum MockConnection {
+        Success,
+        Refused,
+        InProgress,
+    }
+
+    /// Connects according to a list of outcomes instead of reaching the backends.
+    /// The successful connections go to a local listener that never accepts them,
+    /// the ones in progress are started without waiting for the handshake, like mio
+    #[derive(Debug, Clone)]
+    struct MockConnector {
+        outcomes: Rc<RefCell<VecDeque<MockConnection>>>,
+        attempts: Rc<Cell<usize>>,
+        addresses: Rc<RefCell<Vec<SocketAddr>>>,
+        buffer_sizes: Rc<RefCell<Vec<(Option<u32>, Option<u32>)>>>,
+        listener: Rc<TcpListener>,
+    }
+
+    impl MockConnector {
+        fn new() -> Self {
+            MockConnector {
+                outcomes: Rc::new(RefCell::new(VecDeque::new())),
+                attempts: Rc::new(Cell::new(0)),
+                addresses: Rc::new(RefCell::new(Vec::new())),
+                buffer_sizes: Rc::new(RefCell::new(Vec::new())),
+                listener: Rc::new(TcpListener::bind("127.0.0.1:0").unwrap()),
+            }
+        }
+
+        fn push(&self, outcome: MockConnection) {
+            self.outcomes.borrow_mut().push_back(outcome);
+        }
+
+        fn attempts(&self) -> usize {
+            self.attempts.get()
+        }
+
+        fn addresses(&self) -> Vec<SocketAddr> {
+            self.addresses.borrow().clone()
+        }
+
+        fn buffer_sizes(&self) -> Vec<(Option<u32>, Option<u32>)> {
+            self.buffer_sizes.borrow().clone()
+        }
+    }
+
+    impl Connector for MockConnector {
+        fn connect(
+            &self,
+            address: SocketAddr,
+            buffer_sizes: (Option<u32>, Option<u32>),
+        ) -> io::Result<TcpStream> {
+            self.attempts.set(self.attempts.get() + 1);
+            self.addresses.borrow_mut().push(address);
+            self.buffer_sizes.borrow_mut().push(buffer_sizes);
+            match self.outcomes.borrow_mut().pop_front() {
+                Some(MockConnection::Success) => {
+                    std::net::TcpStream::connect(self.listener.local_addr().unwrap())
+                        .map(TcpStream::from_std)
+                }
+                Some(MockConnection::InProgress) => {
+                    TcpStream::connect(self.listener.local_addr().unwrap())
+                }
+                Some(MockConnection::Refused) | None => {
+                    Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+                }
+            }
+        }
+    }
+
+    fn backend_map_with_connector(cluster_id: &str, connector: &MockConnector) -> BackendMap {
+        let mut backend_map = BackendMap::new();
+        backend_map.set_connector(Box::new(connector.clone()));
+        backend_map.add_backend(
+            cluster_id,
+            Backend::new(
+                &format!("{cluster_id}-1"),
+                "127.0.0.1:9003".parse().unwrap(),
+                None,
+                None,
+                None,
+            ),
+        );
+        backend_map
+    }
+
+    #[test]
+    fn it_should_count_the_connections_of_the_connector() {
+        let cluster_id = "mycluster";
+        let connector = MockConnector::new();
+        let mut backend_map = backend_map_with_connector(cluster_id, &connector);
+
+        connector.push(MockConnection::Success);
+        let (backend, _stream) = backend_map
+            .backend_from_cluster_id(cluster_id, (None, None))
+            .expect("the connection should succeed");
+        assert_eq!(connector.attempts(), 1);
+        assert_eq!(backend.borrow().active_connections, 1);
+        assert_eq!(backend.borrow().failures, 0);
+    }
+
+    #[test]
+    fn it_should_give_the_buffer_sizes_to_the_connector() {
+        let cluster_id = "mycluster";
+        let connector = MockConnector::new();
+        let mut backend_map = backend_map_with_connector(cluster_id, &connector);
+
+        connector.push(MockConnection::Success);
+        assert!(backend_map
+            .backend_from_cluster_id(cluster_id, (Some(65536), None))
+            .is_ok());
+        assert_eq!(connector.buffer_sizes(), vec![(Some(65536), None)]);
+    }
+
+    #[test]
+    fn it_should_not_count_a_connection_in_progress_as_a_failure() {
+        let cluster_id = "mycluster";
+        let connector = MockConnector::new();
+        let mut backend_map = backend_map_with_connector(cluster_id, &connector);
+        let backend = backend_map.backends[cluster_id].backends[0].clone();
+
+        connector.push(MockConnection::InProgress);
+        assert!(backend_map
+            .backend_from_cluster_id(cluster_id, (None, None))
+            .is_ok());
+        assert_eq!(backend.borrow().active_connections, 1);
+        assert_eq!(backend.borrow().failures, 0);
+        assert_eq!(backend.borrow().retry_policy.current_tries(), 0);
+        assert!(backend.borrow().can_open());
+
+        connector.push(MockConnection::Success);
+        assert!(backend_map
+            .backend_from_cluster_id(cluster_id, (None, None))
+            .is_ok());
+        assert_eq!(connector.attempts(), 2);
+    }
+
+    #[test]
+    fn it_should_open_and_close_the_breaker_with_the_connection_results() {
+        let cluster_id = "mycluster";
+        let connector = MockConnector::new();
+        let mut backend_map = backend_map_with_connector(cluster_id, &connector);
+        let backend = backend_map.backends[cluster_id].backends[0].clone();
+        let max_tries = backend.borrow().retry_policy.max_tries();
+
+        for failures in 1..=max_tries {
+            connector.push(MockConnection::Refused);
+            match backend_map.backend_from_cluster_id(cluster_id, (None, None)) {
+                Err(BackendError::ConnectionFailures { failures: f, .. }) => {
+                    assert_eq!(f, failures)
+                }
+                other => panic!("expected a connection failure, got {other:?}"),
+            }
+            // skip the backoff delay so that each failure counts
+            backend
+                .borrow_mut()
+                .retry_policy
+                .retry_after(std::time::Duration::ZERO);
+        }
+        assert!(backend.borrow().retry_policy.is_down());
+        backend_map.backend_down(cluster_id);
+        assert!(backend_map.backends[cluster_id].all_down);
+        assert_eq!(connector.attempts(), max_tries);
+
+        // one more failure starts a backoff, the connector is not called during it
+        connector.push(MockConnection::Refused);
+        assert!(backend_map
+            .backend_from_cluster_id(cluster_id, (None, None))
+            .is_err());
+        assert_eq!(connector.attempts(), max_tries + 1);
+        assert!(matches!(
+            backend_map.backend_from_cluster_id(cluster_id, (None, None)),
+            Err(BackendError::NoBackendForCluster(_))
+        ));
+        assert_eq!(connector.attempts(), max_tries + 1);
+
+        // after the backoff, the backend is tried again and the session
+        // marks it up once the connection is established
+        backend
+            .borrow_mut()
+            .retry_policy
+            .retry_after(std::time::Duration::ZERO);
+        connector.push(MockConnection::Success);
+        let (connected, _stream) = backend_map
+            .backend_from_cluster_id(cluster_id, (None, None))
+            .expect("the connection should succeed after the backoff");
+        connected.borrow_mut().retry_policy.succeed();
+        backend_map.backend_up(cluster_id);
+        assert!(!backend.borrow().retry_policy.is_down());
+        assert!(!backend_map.backends[cluster_id].all_down);
+    }
+
+    #[test]
+    fn it_should_connect_to_the_resolved_addresses_in_turn() {
+        let cluster_id = "mycluster";
+        let connector = MockConnector::new();
+        let mut backend_map = backend_map_with_connector(cluster_id, &connector);
+        let backend = backend_map.backends[cluster_id].backends[0].clone();
+        backend.borrow_mut().hostname = Some(String::from("backend.local"));
+        assert_eq!(
+            backend_map.hostnames(),
+            vec![(String::from("backend.local"), 9003)]
+        );
+
+        // the configured address is used until the hostname resolves
+        connector.push(MockConnection::Success);
+        assert!(backend_map
+            .backend_from_cluster_id(cluster_id, (None, None))
+            .is_ok());
+
+        let first: SocketAddr = "10.0.0.1:9003".parse().unwrap();
+        let second: SocketAddr = "10.0.0.2:9003".parse().unwrap();
+        backend_map.set_resolved_addresses("backend.local", 9003, &[second, first]);
+        for _ in 0..3 {
+            connector.push(MockConnection::Success);
+            assert!(backend_map
+                .backend_from_cluster_id(cluster_id, (None, None))
+                .is_ok());
+        }
+        assert_eq!(
+            connector.addresses(),
+            vec!["127.0.0.1:9003".parse().unwrap(), first, second, first]
+        );
+        assert!(backend_map.backend_addresses(cluster_id).contains(&second));
+
+        // the same hostname on another port is another backend server
+        backend_map.set_resolved_addresses("backend.local", 80, &[]);
+        assert_eq!(backend.borrow().resolved_addresses, vec![first, second]);
+    }
+
+    #[test]
+    fn it_should_fall_back_to_another_backend_when_the_sticky_backend_refuses() {
+        let cluster_id = "mycluster";
+        let connector = MockConnector::new();
+        let mut backend_map = BackendMap::new();
+        backend_map.set_connector(Box::new(connector.clone()));
+        for (index, port) in [(1, 9004), (2, 9005)] {
+            backend_map.add_backend(
+                cluster_id,
+                Backend::new(
+                    &format!("{cluster_id}-{index}"),
+                    format!("127.0.0.1:{port}").parse().unwrap(),
+                    Some(format!("server-{index}")),
+                    None,
+                    None,
+                ),
+            );
+        }
+
+        // the sticky backend is available but refuses the connection,
+        // the other one is selected in the same call
+        connector.push(MockConnection::Refused);
+        connector.push(MockConnection::Success);
+        let (backend, _stream) = backend_map
+            .backend_from_sticky_session(cluster_id, "server-1", (None, None))
+            .expect("should fall back to the other backend");
+        assert_eq!(backend.borrow().sticky_id.as_deref(), Some("server-2"));
+        assert_eq!(
+            connector.addresses(),
+            vec![
+                "127.0.0.1:9004".parse().unwrap(),
+                "127.0.0.1:9005".parse().unwrap()
+            ]
+        );
+    }
 }
@@ -0,0 +1,294 @@
+//! Client address filtering with CIDR allow and deny lists
+
+use std::{collections::HashMap, net::IpAddr, str::FromStr};
+
+use sozu_command::{proto::command::Cluster, state::ClusterId};
+
+use crate::ProxyError;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CidrError {
+    #[error("invalid IP address in {0}")]
+    InvalidAddress(String),
+    #[error("invalid prefix length in {0}")]
+    InvalidPrefix(String),
+}
+
+/// An IPv4 or IPv6 network. The mask is computed once, so that matching an
+/// address is a single mask and compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cidr {
+    V4 { network: u32, mask: u32 },
+    V6 { network: u128, mask: u128 },
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self, ip) {
+            (Cidr::V4 { network, mask }, IpAddr::V4(ip)) => u32::from(*ip) & mask == *network,
+            (Cidr::V6 { network, mask }, IpAddr::V6(ip)) => u128::from(*ip) & mask == *network,
+            // IPv4 clients of a dual stack socket appear as IPv4-mapped IPv6 addresses
+            (Cidr::V4 { network, mask }, IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .map(|ip| u32::from(ip) & mask == *network)
+                .unwrap_or(false),
+            (Cidr::V6 { .. }, IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    /// parses `10.0.0.0/8` or `2001:db8::/32`, an address without prefix
+    /// length matches only itself
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.trim().split_once('/') {
+            Some((address, prefix)) => (
+                address,
+                Some(
+                    prefix
+                        .parse::<u32>()
+                        .map_err(|_| CidrError::InvalidPrefix(s.to_owned()))?,
+                ),
+            ),
+            None => (s.trim(), None),
+        };
+
+        match address
+            .parse::<IpAddr>()
+            .map_err(|_| CidrError::InvalidAddress(s.to_owned()))?
+        {
+            IpAddr::V4(ip) => {
+                let prefix = prefix.unwrap_or(32);
+                if prefix > 32 {
+                    return Err(CidrError::InvalidPrefix(s.to_owned()));
+                }
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                Ok(Cidr::V4 {
+                    network: u32::from(ip) & mask,
+                    mask,
+                })
+            }
+            IpAddr::V6(ip) => {
+                let prefix = prefix.unwrap_or(128);
+                if prefix > 128 {
+                    return Err(CidrError::InvalidPrefix(s.to_owned()));
+                }
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                Ok(Cidr::V6 {
+                    network: u128::from(ip) & mask,
+                    mask,
+                })
+            }
+        }
+    }
+}
+
+/// Allow and deny lists of a cluster
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    allowed: Vec<Cidr>,
+    denied: Vec<Cidr>,
+    /// use the address added by the proxy in front of Sōzu in X-Forwarded-For
+    trust_forwarded_for: bool,
+}
+
+impl IpFilter {
+    pub fn new(
+        allowed: &[String],
+        denied: &[String],
+        trust_forwarded_for: bool,
+    ) -> Result<Self, CidrError> {
+        Ok(IpFilter {
+            allowed: allowed
+                .iter()
+                .map(|cidr| cidr.parse())
+                .collect::<Result<_, _>>()?,
+            denied: denied
+                .iter()
+                .map(|cidr| cidr.parse())
+                .collect::<Result<_, _>>()?,
+            trust_forwarded_for,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty() && self.denied.is_empty()
+    }
+
+    /// the address to check: the peer address of the session, or the last
+    /// address of X-Forwarded-For if the proxy in front of Sōzu is trusted
+    pub fn client_ip(
+        &self,
+        session_ip: Option<IpAddr>,
+        forwarded_for: Option<IpAddr>,
+    ) -> Option<IpAddr> {
+        if self.trust_forwarded_for {
+            forwarded_for.or(session_ip)
+        } else {
+            session_ip
+        }
+    }
+
+    /// denied addresses are refused, then if there is an allow list,
+    /// only the addresses it contains are accepted
+    pub fn is_allowed(&self, ip: &IpAddr) -> bool {
+        if self.denied.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// check the client of a request, an unknown client address is only
+    /// accepted if there is no allow list
+    pub fn check(&self, session_ip: Option<IpAddr>, forwarded_for: Option<IpAddr>) -> bool {
+        match self.client_ip(session_ip, forwarded_for) {
+            Some(ip) => self.is_allowed(&ip),
+            None => self.allowed.is_empty(),
+        }
+    }
+}
+
+/// The IP filters of the clusters of a proxy, only the clusters with
+/// an allow or a deny list have one
+#[derive(Debug, Default)]
+pub struct IpFilters(HashMap<ClusterId, IpFilter>);
+
+impl IpFilters {
+    /// set the filter of a cluster from its configuration
+    pub fn update(&mut self, cluster: &Cluster) -> Result<(), ProxyError> {
+        let ip_filter = IpFilter::new(
+            &cluster.allowed_ips,
+            &cluster.denied_ips,
+            cluster.ip_filter_trust_forwarded_for.unwrap_or(false),
+        )
+        .map_err(|error| ProxyError::IpFilter {
+            cluster_id: cluster.cluster_id.clone(),
+            error,
+        })?;
+
+        if ip_filter.is_empty() {
+            self.0.remove(&cluster.cluster_id);
+        } else {
+            self.0.insert(cluster.cluster_id.clone(), ip_filter);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, cluster_id: &str) {
+        self.0.remove(cluster_id);
+    }
+
+    pub fn get(&self, cluster_id: &str) -> Option<&IpFilter> {
+        self.0.get(cluster_id)
+    }
+}
+
+/// the last address of an X-Forwarded-For header, the one added by the
+/// proxy directly in front of Sōzu
+pub fn last_forwarded_for(value: &str) -> Option<IpAddr> {
+    value
+        .rsplit(',')
+        .next()
+        .map(str::trim)
+        .and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cidr() {
+        assert_eq!(
+            "10.1.2.3/8".parse::<Cidr>(),
+            Ok(Cidr::V4 {
+                network: 0x0a00_0000,
+                mask: 0xff00_0000
+            })
+        );
+        assert_eq!(
+            "0.0.0.0/0".parse::<Cidr>(),
+            Ok(Cidr::V4 {
+                network: 0,
+                mask: 0
+            })
+        );
+        assert!("::/0".parse::<Cidr>().is_ok());
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/a".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let v4: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(v4.contains(&ip("192.168.12.1")));
+        assert!(!v4.contains(&ip("192.169.0.1")));
+        assert!(v4.contains(&ip("::ffff:192.168.1.1")));
+        assert!(!v4.contains(&ip("2001:db8::1")));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(&ip("2001:db8:1::1")));
+        assert!(!v6.contains(&ip("2001:db9::1")));
+        assert!(!v6.contains(&ip("192.168.1.1")));
+
+        let single: Cidr = "127.0.0.1".parse().unwrap();
+        assert!(single.contains(&ip("127.0.0.1")));
+        assert!(!single.contains(&ip("127.0.0.2")));
+    }
+
+    #[test]
+    fn filter_allowed_and_denied() {
+        let filter = IpFilter::new(
+            &["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()],
+            &["10.0.0.13".to_string()],
+            false,
+        )
+        .unwrap();
+
+        assert!(filter.check(Some(ip("10.1.2.3")), None));
+        assert!(filter.check(Some(ip("2001:db8::1")), None));
+        assert!(!filter.check(Some(ip("10.0.0.13")), None));
+        assert!(!filter.check(Some(ip("192.168.1.1")), None));
+        assert!(!filter.check(None, None));
+
+        let deny_only = IpFilter::new(&[], &["192.168.0.0/16".to_string()], false).unwrap();
+        assert!(deny_only.check(Some(ip("10.1.2.3")), None));
+        assert!(!deny_only.check(Some(ip("192.168.1.1")), None));
+        assert!(deny_only.check(None, None));
+    }
+
+    #[test]
+    fn filter_trusting_forwarded_for() {
+        let allowed = vec!["203.0.113.0/24".to_string()];
+        let proxy = Some(ip("10.0.0.1"));
+        let client = last_forwarded_for("198.51.100.7, 203.0.113.5");
+        assert_eq!(client, Some(ip("203.0.113.5")));
+
+        // X-Forwarded-For is ignored unless trusted
+        let untrusted = IpFilter::new(&allowed, &[], false).unwrap();
+        assert!(!untrusted.check(proxy, client));
+
+        let trusted = IpFilter::new(&allowed, &[], true).unwrap();
+        assert!(trusted.check(proxy, client));
+        assert!(!trusted.check(proxy, last_forwarded_for("203.0.113.5, 198.51.100.7")));
+        // without the header, the peer address is used
+        assert!(!trusted.check(proxy, None));
+        assert!(trusted.check(Some(ip("203.0.113.9")), last_forwarded_for("garbage")));
+    }
+
+    #[test]
+    fn invalid_filter() {
+        assert_eq!(
+            IpFilter::new(&["not an ip".to_string()], &[], false),
+            Err(CidrError::InvalidAddress("not an ip".to_string()))
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Resolves the hostnames of the backends out of the event loop.
+//!
+//! The resolutions block, so they run in a thread, the server collects their
+//! results at each turn of its loop and updates the addresses of the backends.
+//!
+//! getaddrinfo does not give the TTL of the records: the hostnames are resolved
+//! again every `dns_refresh_interval`, whatever the TTL set by their DNS zone.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+};
+
+/// resolves a hostname to the socket addresses with this port
+pub type ResolveFn = fn(&str, u16) -> io::Result<Vec<SocketAddr>>;
+
+/// resolves with the system resolver (getaddrinfo)
+pub fn system_resolve(hostname: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    (hostname, port)
+        .to_socket_addrs()
+        .map(|addresses| addresses.collect())
+}
+
+/// the result of the resolution of a hostname
+#[derive(Debug)]
+pub struct Resolution {
+    pub hostname: String,
+    pub port: u16,
+    pub addresses: io::Result<Vec<SocketAddr>>,
+}
+
+#[derive(Debug)]
+pub struct DnsResolver {
+    resolve: ResolveFn,
+    /// sends the hostnames to the resolution thread, which is started by the
+    /// first resolution and stops when the resolver is dropped
+    requests: Option<Sender<Vec<(String, u16)>>>,
+    sender: Sender<Resolution>,
+    receiver: Receiver<Resolution>,
+    /// resolutions started and not collected yet
+    pending: usize,
+}
+
+impl DnsResolver {
+    pub fn new(resolve: ResolveFn) -> Self {
+        let (sender, receiver) = channel();
+        DnsResolver {
+            resolve,
+            requests: None,
+            sender,
+            receiver,
+            pending: 0,
+        }
+    }
+
+    /// whether resolutions are still running
+    pub fn is_busy(&self) -> bool {
+        self.pending > 0
+    }
+
+    /// Resolve these hostnames and ports in the resolution thread.
+    /// Returns false if the thread could not be started or has stopped
+    pub fn resolve(&mut self, names: Vec<(String, u16)>) -> bool {
+        if self.requests.is_none() {
+            match self.start_thread() {
+                Ok(requests) => self.requests = Some(requests),
+                Err(e) => {
+                    error!("could not start the DNS resolution thread: {}", e);
+                    return false;
+                }
+            }
+        }
+
+        let count = names.len();
+        let sent = self
+            .requests
+            .as_ref()
+            .map(|requests| requests.send(names).is_ok())
+            .unwrap_or(false);
+        if !sent {
+            error!("the DNS resolution thread has stopped");
+            // it is started again by the next resolution
+            self.requests = None;
+            return false;
+        }
+        self.pending += count;
+        true
+    }
+
+    fn start_thread(&self) -> io::Result<Sender<Vec<(String, u16)>>> {
+        let (requests, names_receiver) = channel::<Vec<(String, u16)>>();
+        let resolve = self.resolve;
+        let sender = self.sender.clone();
+
+        thread::Builder::new()
+            .name(String::from("sozu-dns"))
+            .spawn(move || {
+                for names in names_receiver {
+                    for (hostname, port) in names {
+                        let addresses = resolve(&hostname, port);
+                        let resolution = Resolution {
+                            hostname,
+                            port,
+                            addresses,
+                        };
+                        if sender.send(resolution).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })?;
+
+        Ok(requests)
+    }
+
+    /// the resolutions finished since the last call, does not block
+    pub fn completed(&mut self) -> Vec<Resolution> {
+        let resolutions: Vec<Resolution> = self.receiver.try_iter().collect();
+        self.pending = self.pending.saturating_sub(resolutions.len());
+        resolutions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::{Backend, BackendMap};
+    use std::{
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            Mutex,
+        },
+        thread::ThreadId,
+        time::{Duration, Instant},
+    };
+
+    static RESOLUTIONS: AtomicU8 = AtomicU8::new(0);
+
+    /// a DNS record whose address changes at each resolution
+    fn changing_resolve(hostname: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if hostname != "backend.local" {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "unknown host"));
+        }
+        let last = RESOLUTIONS.fetch_add(1, Ordering::SeqCst) + 1;
+        Ok(vec![SocketAddr::from(([10, 0, 0, last], port))])
+    }
+
+    fn wait_for_completion(resolver: &mut DnsResolver) -> Vec<Resolution> {
+        let start = Instant::now();
+        let mut resolutions = Vec::new();
+        while resolver.is_busy() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "resolution timeout"
+            );
+            resolutions.extend(resolver.completed());
+            thread::sleep(Duration::from_millis(10));
+        }
+        resolutions
+    }
+
+    #[test]
+    fn backend_addresses_follow_the_resolutions() {
+        let mut backend_map = BackendMap::new();
+        backend_map.add_backend(
+            "cluster_1",
+            Backend::new(
+                "cluster_1-0",
+                "127.0.0.1:8080".parse().unwrap(),
+                None,
+                None,
+                None,
+            )
+            .with_hostname(Some(String::from("backend.local"))),
+        );
+        let backend = backend_map.backends["cluster_1"].backends[0].clone();
+        let mut resolver = DnsResolver::new(changing_resolve);
+
+        for last in 1..=2 {
+            assert!(resolver.resolve(backend_map.hostnames()));
+            for resolution in wait_for_completion(&mut resolver) {
+                let addresses = resolution.addresses.expect("the hostname should resolve");
+                backend_map.set_resolved_addresses(
+                    &resolution.hostname,
+                    resolution.port,
+                    &addresses,
+                );
+            }
+
+            let expected = SocketAddr::from(([10, 0, 0, last], 8080));
+            assert_eq!(backend.borrow().resolved_addresses, vec![expected]);
+            assert_eq!(backend.borrow_mut().connect_address(), expected);
+        }
+    }
+
+    static RESOLUTION_THREADS: Mutex<Vec<ThreadId>> = Mutex::new(Vec::new());
+
+    /// records the thread the resolutions run in
+    fn thread_recording_resolve(_hostname: &str, _port: u16) -> io::Result<Vec<SocketAddr>> {
+        RESOLUTION_THREADS
+            .lock()
+            .unwrap()
+            .push(thread::current().id());
+        Err(io::Error::new(io::ErrorKind::NotFound, "unknown host"))
+    }
+
+    #[test]
+    fn resolutions_share_a_thread() {
+        let mut resolver = DnsResolver::new(thread_recording_resolve);
+        for port in [80, 81] {
+            assert!(resolver.resolve(vec![(String::from("backend.local"), port)]));
+            assert_eq!(wait_for_completion(&mut resolver).len(), 1);
+        }
+
+        let threads = RESOLUTION_THREADS.lock().unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0], threads[1]);
+    }
+
+    #[test]
+    fn resolution_errors_are_reported() {
+        let mut resolver = DnsResolver::new(changing_resolve);
+        assert!(resolver.resolve(vec![(String::from("unknown.local"), 80)]));
+
+        let resolutions = wait_for_completion(&mut resolver);
+        assert_eq!(resolutions.len(), 1);
+        assert_eq!(resolutions[0].hostname, "unknown.local");
+        assert!(resolutions[0].addresses.is_err());
+    }
+}
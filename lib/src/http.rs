@@ -19,30 +19,33 @@ use slab::Slab;
 use time::{Duration, Instant};
 
 use sozu_command::{
+    config::{ConfigError, ListenerBuilder},
     logging,
     proto::command::{
-        request::RequestType, Cluster, HttpListenerConfig, ListenerType, RemoveListener,
-        RequestHttpFrontend,
+        request::RequestType, response_content::ContentType, AccessLogFormat, Cluster,
+        HttpListenerConfig, ListenerType, QueryRouteMatch, RemoveListener, RequestHttpFrontend,
+        RouteMatches, SessionInfo,
     },
     ready::Ready,
     request::WorkerRequest,
     response::{HttpFrontend, WorkerResponse},
     scm_socket::{Listeners, ScmSocket},
-    state::ClusterId,
+    state::{ClusterId, ConfigState},
 };
 
 use crate::{
     backends::BackendMap,
+    cidr::{IpFilter, IpFilters},
     pool::Pool,
     protocol::{
         http::{
-            answers::HttpAnswers,
+            answers::{self, HttpAnswers},
             parser::{hostname_and_port, Method},
         },
         proxy_protocol::expect::ExpectProxyProtocol,
         Http, Pipe, SessionState,
     },
-    router::{Route, Router},
+    router::{parse_route_match_query, Route, RouteOptions, Router},
     server::{ListenSession, ListenToken, ProxyChannel, Server, SessionManager},
     socket::server_bind,
     timer::TimeoutContainer,
@@ -383,6 +386,138 @@ impl ProxySession for HttpSession {
     fn frontend_token(&self) -> Token {
         self.frontend_token
     }
+
+    fn listener_token(&self) -> Option<Token> {
+        Some(self.listener.borrow().token)
+    }
+
+    fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.protocol = format!("HTTP({:?})", self.state.marker());
+        self.state.fill_session_info(info);
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HttpProxyConfigurationError {
+    #[error("the front address of the proxy is missing")]
+    MissingFront,
+    #[error("the {0} timeout can not be zero")]
+    ZeroTimeout(&'static str),
+    #[error("the {0} buffer size can not be zero")]
+    ZeroBufferSize(&'static str),
+    #[error("invalid listener configuration: {0}")]
+    Listener(#[from] ConfigError),
+}
+
+/// Typed builder of the configuration of an HTTP proxy, for the library users:
+/// the front address is mandatory, the options not set take the default values
+/// of the configuration file
+///
+/// ```
+/// use sozu_lib::http::HttpProxyConfigurationBuilder;
+///
+/// let http_listener = HttpProxyConfigurationBuilder::new()
+///     .with_front("127.0.0.1:8080".parse().unwrap())
+///     .with_front_timeout(30)
+///     .build()
+///     .expect("Could not create HTTP listener");
+///
+/// assert_eq!(http_listener.front_timeout, 30);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HttpProxyConfigurationBuilder {
+    front: Option<SocketAddr>,
+    public_address: Option<SocketAddr>,
+    send_buffer_size: Option<u32>,
+    receive_buffer_size: Option<u32>,
+    front_timeout: Option<u32>,
+    back_timeout: Option<u32>,
+    connect_timeout: Option<u32>,
+    request_timeout: Option<u32>,
+}
+
+impl HttpProxyConfigurationBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// address the proxy listens on
+    pub fn with_front(&mut self, front: SocketAddr) -> &mut Self {
+        self.front = Some(front);
+        self
+    }
+
+    /// address written in the Forwarded headers, the front address if not set
+    pub fn with_public_address(&mut self, public_address: SocketAddr) -> &mut Self {
+        self.public_address = Some(public_address);
+        self
+    }
+
+    /// size of the send buffer (SO_SNDBUF) of the frontend and backend sockets
+    pub fn with_send_buffer_size(&mut self, send_buffer_size: u32) -> &mut Self {
+        self.send_buffer_size = Some(send_buffer_size);
+        self
+    }
+
+    /// size of the receive buffer (SO_RCVBUF) of the frontend and backend sockets
+    pub fn with_receive_buffer_size(&mut self, receive_buffer_size: u32) -> &mut Self {
+        self.receive_buffer_size = Some(receive_buffer_size);
+        self
+    }
+
+    pub fn with_front_timeout(&mut self, front_timeout: u32) -> &mut Self {
+        self.front_timeout = Some(front_timeout);
+        self
+    }
+
+    pub fn with_back_timeout(&mut self, back_timeout: u32) -> &mut Self {
+        self.back_timeout = Some(back_timeout);
+        self
+    }
+
+    pub fn with_connect_timeout(&mut self, connect_timeout: u32) -> &mut Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn with_request_timeout(&mut self, request_timeout: u32) -> &mut Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+
+    /// check the options and build the listener configuration
+    pub fn build(&self) -> Result<HttpListenerConfig, HttpProxyConfigurationError> {
+        let front = self
+            .front
+            .ok_or(HttpProxyConfigurationError::MissingFront)?;
+
+        let timeouts = [
+            ("front", self.front_timeout),
+            ("back", self.back_timeout),
+            ("connect", self.connect_timeout),
+            ("request", self.request_timeout),
+        ];
+        if let Some((name, _)) = timeouts.iter().find(|(_, timeout)| *timeout == Some(0)) {
+            return Err(HttpProxyConfigurationError::ZeroTimeout(*name));
+        }
+        let buffer_sizes = [
+            ("send", self.send_buffer_size),
+            ("receive", self.receive_buffer_size),
+        ];
+        if let Some((name, _)) = buffer_sizes.iter().find(|(_, size)| *size == Some(0)) {
+            return Err(HttpProxyConfigurationError::ZeroBufferSize(*name));
+        }
+
+        Ok(ListenerBuilder::new_http(front)
+            .with_public_address(self.public_address)
+            .with_send_buffer_size(self.send_buffer_size)
+            .with_receive_buffer_size(self.receive_buffer_size)
+            .with_front_timeout(self.front_timeout)
+            .with_back_timeout(self.back_timeout)
+            .with_connect_timeout(self.connect_timeout)
+            .with_request_timeout(self.request_timeout)
+            .to_http(None)?)
+    }
 }
 
 pub type Hostname = String;
@@ -413,6 +548,17 @@ impl ListenerHandler for HttpListener {
             None => self.tags.remove(&key),
         };
     }
+
+    fn get_socket_buffer_sizes(&self) -> (Option<u32>, Option<u32>) {
+        (
+            self.config.send_buffer_size,
+            self.config.receive_buffer_size,
+        )
+    }
+
+    fn get_access_logs_format(&self) -> AccessLogFormat {
+        self.config.access_logs_format()
+    }
 }
 
 impl L7ListenerHandler for HttpListener {
@@ -424,13 +570,112 @@ impl L7ListenerHandler for HttpListener {
         self.config.connect_timeout
     }
 
+    fn get_preserved_connection_tokens(&self) -> &[String] {
+        &self.config.preserved_connection_tokens
+    }
+
+    fn get_validate_websocket_handshake(&self) -> bool {
+        self.config.validate_websocket_handshake
+    }
+
+    fn get_hop_by_hop_headers(&self) -> &[String] {
+        &self.config.hop_by_hop_headers
+    }
+
+    fn get_max_chunked_body_size(&self) -> Option<u64> {
+        self.config.max_chunked_body_size
+    }
+
+    fn get_max_chunk_header_length(&self) -> u32 {
+        self.config.max_chunk_header_length
+    }
+
+    fn get_max_chunk_size(&self) -> u64 {
+        self.config.max_chunk_size
+    }
+
+    fn get_max_front_read_size(&self) -> Option<u32> {
+        self.config.max_front_read_size
+    }
+
+    fn get_min_request_header_rate(&self) -> Option<u32> {
+        self.config.min_request_header_rate
+    }
+
+    fn get_max_pipelined_requests(&self) -> Option<u32> {
+        self.config.max_pipelined_requests
+    }
+
+    fn get_health_check_path(&self) -> Option<&str> {
+        self.config.health_check_path.as_deref()
+    }
+
+    fn get_max_uri_length(&self) -> u32 {
+        self.config.max_uri_length
+    }
+
+    fn get_max_response_header_size(&self) -> Option<u32> {
+        self.config.max_response_header_size
+    }
+
+    fn get_http10_default_host(&self) -> Option<&str> {
+        self.config.http10_default_host.as_deref()
+    }
+
+    fn get_back_read_watermarks(&self) -> (Option<u32>, Option<u32>) {
+        (
+            self.config.back_read_low_watermark,
+            self.config.back_read_high_watermark,
+        )
+    }
+
+    fn get_access_logs_sampling(&self) -> Option<u32> {
+        self.config.access_logs_sampling
+    }
+
+    fn get_max_close_delimited_response_size(&self) -> Option<u64> {
+        self.config.max_close_delimited_response_size
+    }
+
+    fn get_access_logs_redacted_headers(&self) -> &[String] {
+        &self.config.access_logs_redacted_headers
+    }
+
+    fn get_access_logs_redact_query(&self) -> bool {
+        self.config.access_logs_redact_query
+    }
+
+    fn is_misdirected(&self, _hostname: &str, _server_name: &str) -> bool {
+        false
+    }
+
+    fn get_omit_forwarded_header(&self) -> bool {
+        self.config.omit_forwarded_header
+    }
+
+    fn get_omit_forwarded_client_port(&self) -> bool {
+        self.config.omit_forwarded_client_port
+    }
+
+    fn get_trace_context(&self) -> bool {
+        self.config.trace_context
+    }
+
+    fn get_rewrite_absolute_form(&self) -> bool {
+        self.config.rewrite_absolute_form
+    }
+
+    fn get_normalize_reason_phrase(&self) -> bool {
+        self.config.normalize_reason_phrase
+    }
+
     // redundant, already called once in extract_route
     fn frontend_from_request(
         &self,
         host: &str,
         uri: &str,
         method: &Method,
-    ) -> Result<Route, FrontendFromRequestError> {
+    ) -> Result<(Route, RouteOptions), FrontendFromRequestError> {
         let start = Instant::now();
         let (remaining_input, (hostname, _)) = match hostname_and_port(host.as_bytes()) {
             Ok(tuple) => tuple,
@@ -459,10 +704,13 @@ impl L7ListenerHandler for HttpListener {
         */
         let host = unsafe { from_utf8_unchecked(hostname) };
 
-        let route = self.fronts.lookup(host, uri, method).map_err(|e| {
-            incr!("http.failed_backend_matching");
-            FrontendFromRequestError::NoClusterFound(e)
-        })?;
+        let (route, options) = self
+            .fronts
+            .lookup_with_options(host, uri, method)
+            .map_err(|e| {
+                incr!("http.failed_backend_matching");
+                FrontendFromRequestError::NoClusterFound(e)
+            })?;
 
         let now = Instant::now();
 
@@ -474,13 +722,14 @@ impl L7ListenerHandler for HttpListener {
             );
         }
 
-        Ok(route)
+        Ok((route, options))
     }
 }
 
 pub struct HttpProxy {
     backends: Rc<RefCell<BackendMap>>,
     clusters: HashMap<ClusterId, Cluster>,
+    ip_filters: IpFilters,
     listeners: HashMap<Token, Rc<RefCell<HttpListener>>>,
     pool: Rc<RefCell<Pool>>,
     registry: Registry,
@@ -497,6 +746,7 @@ impl HttpProxy {
         HttpProxy {
             backends,
             clusters: HashMap::new(),
+            ip_filters: IpFilters::default(),
             listeners: HashMap::new(),
             pool,
             registry,
@@ -593,12 +843,14 @@ impl HttpProxy {
                     .add_custom_answer(&cluster.cluster_id, answer_503);
             }
         }
+        self.ip_filters.update(&cluster)?;
         self.clusters.insert(cluster.cluster_id.clone(), cluster);
         Ok(())
     }
 
     pub fn remove_cluster(&mut self, cluster_id: &str) -> Result<(), ProxyError> {
         self.clusters.remove(cluster_id);
+        self.ip_filters.remove(cluster_id);
 
         for listener in self.listeners.values() {
             listener
@@ -660,6 +912,56 @@ impl HttpProxy {
         Ok(())
     }
 
+    /// Serializable copy of the listeners, clusters, frontends and backends of
+    /// the proxy, to rebuild its routing in another instance with `restore`
+    pub fn snapshot(&self) -> ConfigState {
+        let mut state = ConfigState::new();
+
+        for listener in self.listeners.values() {
+            let listener = listener.borrow();
+            let mut config = listener.config.clone();
+            config.active = listener.active;
+            state
+                .http_listeners
+                .insert(listener.address.to_string(), config);
+
+            for front in listener.fronts.frontends() {
+                let key = RequestHttpFrontend::from(front.clone()).to_string();
+                state.http_fronts.insert(key, front.clone());
+            }
+        }
+
+        let backends = self.backends.borrow();
+        for (cluster_id, cluster) in &self.clusters {
+            state.clusters.insert(cluster_id.clone(), cluster.clone());
+
+            let cluster_backends = backends.export_configuration_state(cluster_id);
+            if !cluster_backends.is_empty() {
+                state.backends.insert(cluster_id.clone(), cluster_backends);
+            }
+        }
+
+        state
+    }
+
+    /// Rebuilds the clusters, frontends and backends of a snapshot. Listeners own
+    /// a socket and a token of the session slab, they are not created here: add
+    /// the `http_listeners` of the snapshot with `add_listener` beforehand.
+    pub fn restore(&mut self, snapshot: &ConfigState) -> Result<(), ProxyError> {
+        for cluster in snapshot.clusters.values() {
+            self.add_cluster(cluster.clone())?;
+        }
+
+        for front in snapshot.http_fronts.values() {
+            self.add_http_frontend(front.clone().into())?;
+        }
+
+        self.backends
+            .borrow_mut()
+            .restore_configuration_state(snapshot);
+        Ok(())
+    }
+
     pub fn soft_stop(&mut self) -> Result<(), ProxyError> {
         let listeners: HashMap<_, _> = self.listeners.drain().collect();
         let mut socket_errors = vec![];
@@ -706,6 +1008,41 @@ impl HttpProxy {
         Ok(())
     }
 
+    pub fn reload_answers(&mut self) -> Result<(), ProxyError> {
+        answers::reload_all(
+            self.listeners
+                .values()
+                .map(|listener| listener.borrow().answers.clone()),
+        )
+        .map_err(ProxyError::ReloadAnswers)
+    }
+
+    /// Describes how each listener would route the request,
+    /// or only the listener on the queried address
+    pub fn query_route_match(&self, query: &QueryRouteMatch) -> Result<RouteMatches, ProxyError> {
+        let (address, method) = parse_route_match_query(query)?;
+
+        let backends = self.backends.borrow();
+        let vec = self
+            .listeners
+            .values()
+            .map(|listener| listener.borrow())
+            .filter(|listener| address.is_none() || address == Some(listener.address))
+            .map(|listener| {
+                listener.fronts.route_match(
+                    listener.address,
+                    ListenerType::Http,
+                    &query.host,
+                    &query.path,
+                    &method,
+                    &backends,
+                )
+            })
+            .collect();
+
+        Ok(RouteMatches { vec })
+    }
+
     pub fn logging(&mut self, logging_filter: String) -> Result<(), ProxyError> {
         logging::LOGGER.with(|l| {
             let directives = logging::parse_logging_spec(&logging_filter);
@@ -724,13 +1061,16 @@ impl HttpListener {
                 address: config.address.clone(),
                 error: parse_error.to_string(),
             })?;
+        let mut answers = HttpAnswers::new(&config.answer_404, &config.answer_503);
+        answers.set_paths(
+            config.answer_404_path.clone(),
+            config.answer_503_path.clone(),
+        );
+
         Ok(HttpListener {
             active: false,
             address,
-            answers: Rc::new(RefCell::new(HttpAnswers::new(
-                &config.answer_404,
-                &config.answer_503,
-            ))),
+            answers: Rc::new(RefCell::new(answers)),
             config,
             fronts: Router::new(),
             listener: None,
@@ -750,11 +1090,15 @@ impl HttpListener {
 
         let mut listener = match tcp_listener {
             Some(tcp_listener) => tcp_listener,
-            None => server_bind(self.config.address.clone()).map_err(|server_bind_error| {
-                ListenerError::Activation {
-                    address: self.config.address.clone(),
-                    error: server_bind_error.to_string(),
-                }
+            None => server_bind(
+                self.config.address.clone(),
+                self.config.listen_backlog,
+                self.config.bind_device.as_deref(),
+                self.get_socket_buffer_sizes(),
+            )
+            .map_err(|server_bind_error| ListenerError::Activation {
+                address: self.config.address.clone(),
+                error: server_bind_error.to_string(),
             })?,
         };
 
@@ -854,6 +1198,22 @@ impl ProxyConfiguration for HttpProxy {
                 );
                 self.logging(logging_filter)
             }
+            Some(RequestType::ReloadAnswers(_)) => {
+                debug!("{} reload default answers", request_id);
+                self.reload_answers()
+            }
+            Some(RequestType::QueryRouteMatch(query)) => {
+                debug!("{} query route match {:?}", request_id, query);
+                match self.query_route_match(&query) {
+                    Ok(route_matches) => {
+                        return WorkerResponse::ok_with_content(
+                            request_id,
+                            ContentType::RouteMatches(route_matches).into(),
+                        );
+                    }
+                    Err(e) => Err(e),
+                }
+            }
             other_command => {
                 debug!(
                     "{} unsupported message for HTTP proxy, ignoring: {:?}",
@@ -883,6 +1243,32 @@ impl ProxyConfiguration for HttpProxy {
         }
     }
 
+    fn pause_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error> {
+        if let Some(listener) = self.listeners.get(&Token(token.0)) {
+            if let Some(sock) = listener.borrow_mut().listener.as_mut() {
+                self.registry.deregister(sock)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resume_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error> {
+        if let Some(listener) = self.listeners.get(&Token(token.0)) {
+            if let Some(sock) = listener.borrow_mut().listener.as_mut() {
+                self.registry
+                    .register(sock, Token(token.0), Interest::READABLE)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn max_sessions(&self, token: ListenToken) -> Option<usize> {
+        self.listeners
+            .get(&Token(token.0))
+            .and_then(|listener| listener.borrow().config.max_sessions)
+            .map(|max_sessions| max_sessions as usize)
+    }
+
     fn create_session(
         &mut self,
         mut frontend_sock: TcpStream,
@@ -992,6 +1378,10 @@ impl L7Proxy for HttpProxy {
     fn clusters(&self) -> &HashMap<ClusterId, Cluster> {
         &self.clusters
     }
+
+    fn ip_filter(&self, cluster_id: &str) -> Option<&IpFilter> {
+        self.ip_filters.get(cluster_id)
+    }
 }
 
 /// This is starts an HTTP worker with an HTTP listener config.
@@ -1105,10 +1495,14 @@ mod tests {
     extern crate tiny_http;
 
     use super::*;
+    use crate::protocol::http::DefaultAnswerStatus;
     use crate::sozu_command::{
         channel::Channel,
         config::ListenerBuilder,
-        proto::command::{LoadBalancingAlgorithms, LoadBalancingParams, PathRule, RulePosition},
+        proto::command::{
+            LoadBalancingAlgorithms, LoadBalancingParams, PathRule, ReloadAnswers, ResponseStatus,
+            RulePosition,
+        },
         request::WorkerRequest,
         response::{Backend, HttpFrontend},
     };
@@ -1174,6 +1568,7 @@ mod tests {
             load_balancing_parameters: Some(LoadBalancingParams::default()),
             sticky_id: None,
             backup: None,
+            hostname: None,
         };
         command
             .write_message(&WorkerRequest {
@@ -1254,6 +1649,7 @@ mod tests {
             address: "127.0.0.1:1028".parse().unwrap(),
             backend_id: String::from("cluster_1-0"),
             backup: None,
+            hostname: None,
             cluster_id: String::from("cluster_1"),
             load_balancing_parameters: Some(LoadBalancingParams::default()),
             sticky_id: None,
@@ -1376,6 +1772,7 @@ mod tests {
             address: "127.0.0.1:1040".parse().unwrap(),
             backend_id: String::from("cluster_1-0"),
             backup: None,
+            hostname: None,
             cluster_id: String::from("cluster_1"),
             load_balancing_parameters: Some(LoadBalancingParams::default()),
             sticky_id: None,
@@ -1472,6 +1869,9 @@ mod tests {
                 position: RulePosition::Tree,
                 cluster_id: Some(cluster_id1),
                 tags: None,
+                allowed_methods: Vec::new(),
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
             })
             .expect("Could not add http frontend");
         fronts
@@ -1483,6 +1883,9 @@ mod tests {
                 position: RulePosition::Tree,
                 cluster_id: Some(cluster_id2),
                 tags: None,
+                allowed_methods: Vec::new(),
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
             })
             .expect("Could not add http frontend");
         fronts
@@ -1494,6 +1897,9 @@ mod tests {
                 position: RulePosition::Tree,
                 cluster_id: Some(cluster_id3),
                 tags: None,
+                allowed_methods: Vec::new(),
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
             })
             .expect("Could not add http frontend");
         fronts
@@ -1505,6 +1911,9 @@ mod tests {
                 position: RulePosition::Tree,
                 cluster_id: Some("cluster_1".to_owned()),
                 tags: None,
+                allowed_methods: Vec::new(),
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
             })
             .expect("Could not add http frontend");
 
@@ -1535,21 +1944,256 @@ mod tests {
         let frontend4 = listener.frontend_from_request("lolcatho.st", "/yolo/swag", &Method::Get);
         let frontend5 = listener.frontend_from_request("domain", "/", &Method::Get);
         assert_eq!(
-            frontend1.expect("should find frontend"),
+            frontend1.expect("should find frontend").0,
             Route::ClusterId("cluster_1".to_string())
         );
         assert_eq!(
-            frontend2.expect("should find frontend"),
+            frontend2.expect("should find frontend").0,
             Route::ClusterId("cluster_1".to_string())
         );
         assert_eq!(
-            frontend3.expect("should find frontend"),
+            frontend3.expect("should find frontend").0,
             Route::ClusterId("cluster_2".to_string())
         );
         assert_eq!(
-            frontend4.expect("should find frontend"),
+            frontend4.expect("should find frontend").0,
             Route::ClusterId("cluster_3".to_string())
         );
         assert!(frontend5.is_err());
     }
+
+    /// an address on a free port of the loopback interface
+    fn create_local_address() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("could not find a free port")
+    }
+
+    #[test]
+    fn reload_answers_from_files() {
+        let path = std::env::temp_dir().join(format!("sozu-404-{}.html", std::process::id()));
+        let other_path = std::env::temp_dir().join(format!("sozu-503-{}.html", std::process::id()));
+        std::fs::write(&path, "HTTP/1.1 404 Not Found\r\n\r\nfirst").unwrap();
+        std::fs::write(&other_path, "HTTP/1.1 503 Service Unavailable\r\n\r\n").unwrap();
+
+        let config = ListenerBuilder::new_http(create_local_address())
+            .with_answer_404_path(path.to_str())
+            .to_http(None)
+            .expect("Could not create HTTP listener config");
+        let other_config = ListenerBuilder::new_http(create_local_address())
+            .with_answer_503_path(other_path.to_str())
+            .to_http(None)
+            .expect("Could not create HTTP listener config");
+
+        let poll = Poll::new().expect("could not create event loop");
+        let registry = poll
+            .registry()
+            .try_clone()
+            .expect("could not clone the mio Registry");
+        let sessions = SessionManager::new(Slab::with_capacity(10), 10);
+        let pool = Rc::new(RefCell::new(Pool::with_capacity(1, 10, 16384)));
+        let backends = Rc::new(RefCell::new(BackendMap::new()));
+        let mut proxy = HttpProxy::new(registry, sessions, pool, backends);
+        proxy
+            .add_listener(config, Token(0))
+            .expect("Could not add HTTP listener");
+        proxy
+            .add_listener(other_config, Token(1))
+            .expect("Could not add HTTP listener");
+
+        let answers = proxy
+            .get_listener(&Token(0))
+            .expect("should find the listener")
+            .borrow()
+            .answers
+            .clone();
+        assert_eq!(
+            &answers.borrow().get(DefaultAnswerStatus::Answer404, None)[..],
+            &b"HTTP/1.1 404 Not Found\r\n\r\nfirst"[..]
+        );
+
+        std::fs::write(&path, "HTTP/1.1 404 Not Found\r\n\r\nsecond").unwrap();
+        let response = proxy.notify(WorkerRequest::new(
+            "ID_RELOAD".to_owned(),
+            RequestType::ReloadAnswers(ReloadAnswers {}).into(),
+        ));
+
+        assert_eq!(response.status, ResponseStatus::Ok);
+        assert_eq!(
+            &answers.borrow().get(DefaultAnswerStatus::Answer404, None)[..],
+            &b"HTTP/1.1 404 Not Found\r\n\r\nsecond"[..]
+        );
+
+        // the file of the other listener is gone, the reload fails and
+        // the first listener keeps its current answer
+        std::fs::write(&path, "HTTP/1.1 404 Not Found\r\n\r\nthird").unwrap();
+        std::fs::remove_file(&other_path).unwrap();
+        let response = proxy.notify(WorkerRequest::new(
+            "ID_RELOAD".to_owned(),
+            RequestType::ReloadAnswers(ReloadAnswers {}).into(),
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.status, ResponseStatus::Failure);
+        assert_eq!(
+            &answers.borrow().get(DefaultAnswerStatus::Answer404, None)[..],
+            &b"HTTP/1.1 404 Not Found\r\n\r\nsecond"[..]
+        );
+    }
+
+    fn snapshot_test_proxy() -> HttpProxy {
+        let poll = Poll::new().expect("could not create event loop");
+        HttpProxy::new(
+            poll.registry()
+                .try_clone()
+                .expect("could not clone the mio registry"),
+            SessionManager::new(Slab::with_capacity(10), 10),
+            Rc::new(RefCell::new(Pool::with_capacity(1, 10, 16384))),
+            Rc::new(RefCell::new(BackendMap::new())),
+        )
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let address = create_local_address();
+        let mut proxy = snapshot_test_proxy();
+        proxy
+            .add_listener(
+                ListenerBuilder::new_http(address)
+                    .to_http(None)
+                    .expect("Could not create HTTP listener config"),
+                Token(0),
+            )
+            .expect("Could not add HTTP listener");
+
+        for cluster_id in ["cluster_1", "cluster_2"] {
+            proxy
+                .add_cluster(Cluster {
+                    cluster_id: cluster_id.to_owned(),
+                    ..Default::default()
+                })
+                .expect("Could not add cluster");
+        }
+        for (path, cluster_id) in [
+            ("/", "cluster_1"),
+            ("/api", "cluster_2"),
+            ("/old", "cluster_2"),
+        ] {
+            proxy
+                .add_http_frontend(RequestHttpFrontend {
+                    cluster_id: Some(cluster_id.to_owned()),
+                    address: address.to_string(),
+                    hostname: "lolcatho.st".to_owned(),
+                    path: PathRule::prefix(path.to_owned()),
+                    ..Default::default()
+                })
+                .expect("Could not add http frontend");
+        }
+        proxy
+            .remove_http_frontend(RequestHttpFrontend {
+                cluster_id: Some("cluster_2".to_owned()),
+                address: address.to_string(),
+                hostname: "lolcatho.st".to_owned(),
+                path: PathRule::prefix("/old".to_owned()),
+                ..Default::default()
+            })
+            .expect("Could not remove http frontend");
+        proxy.backends.borrow_mut().add_backend(
+            "cluster_1",
+            crate::backends::Backend::new("cluster_1-0", create_local_address(), None, None, None),
+        );
+
+        let snapshot = proxy.snapshot();
+        assert_eq!(snapshot.http_listeners.len(), 1);
+        assert_eq!(snapshot.clusters.len(), 2);
+        // the removed frontend is not part of the snapshot
+        assert_eq!(snapshot.http_fronts.len(), 2);
+        assert_eq!(snapshot.backends["cluster_1"].len(), 1);
+
+        let mut restored = snapshot_test_proxy();
+        for (token, config) in snapshot.http_listeners.values().enumerate() {
+            restored
+                .add_listener(config.clone(), Token(token))
+                .expect("Could not add HTTP listener");
+        }
+        restored
+            .restore(&snapshot)
+            .expect("Could not restore the snapshot");
+        assert_eq!(restored.snapshot(), snapshot);
+
+        let listener = proxy.listeners[&Token(0)].borrow();
+        let restored_listener = restored.listeners[&Token(0)].borrow();
+        for (host, path) in [
+            ("lolcatho.st", "/"),
+            ("lolcatho.st", "/api/users"),
+            ("lolcatho.st", "/old/page"),
+            ("other.domain", "/"),
+        ] {
+            assert_eq!(
+                listener
+                    .frontend_from_request(host, path, &Method::Get)
+                    .ok(),
+                restored_listener
+                    .frontend_from_request(host, path, &Method::Get)
+                    .ok(),
+            );
+        }
+    }
+
+    #[test]
+    fn http_proxy_configuration_defaults() {
+        let front: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let built = HttpProxyConfigurationBuilder::new()
+            .with_front(front)
+            .build()
+            .expect("could not build the HTTP proxy configuration");
+
+        let expected = ListenerBuilder::new_http(front)
+            .to_http(None)
+            .expect("could not build the HTTP listener");
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    fn http_proxy_configuration_options() {
+        let built = HttpProxyConfigurationBuilder::new()
+            .with_front("127.0.0.1:8080".parse().unwrap())
+            .with_public_address("1.2.3.4:80".parse().unwrap())
+            .with_send_buffer_size(65536)
+            .with_receive_buffer_size(32768)
+            .with_front_timeout(12)
+            .with_back_timeout(13)
+            .with_connect_timeout(14)
+            .with_request_timeout(15)
+            .build()
+            .expect("could not build the HTTP proxy configuration");
+
+        assert_eq!(built.address, "127.0.0.1:8080");
+        assert_eq!(built.public_address, Some("1.2.3.4:80".to_owned()));
+        assert_eq!(built.send_buffer_size, Some(65536));
+        assert_eq!(built.receive_buffer_size, Some(32768));
+        assert_eq!(built.front_timeout, 12);
+        assert_eq!(built.back_timeout, 13);
+        assert_eq!(built.connect_timeout, 14);
+        assert_eq!(built.request_timeout, 15);
+    }
+
+    #[test]
+    fn http_proxy_configuration_errors() {
+        assert!(matches!(
+            HttpProxyConfigurationBuilder::new().build(),
+            Err(HttpProxyConfigurationError::MissingFront)
+        ));
+
+        let mut builder = HttpProxyConfigurationBuilder::new();
+        builder.with_front("127.0.0.1:8080".parse().unwrap());
+        assert!(matches!(
+            builder.clone().with_connect_timeout(0).build(),
+            Err(HttpProxyConfigurationError::ZeroTimeout("connect"))
+        ));
+        assert!(matches!(
+            builder.clone().with_receive_buffer_size(0).build(),
+            Err(HttpProxyConfigurationError::ZeroBufferSize("receive"))
+        ));
+    }
 }
@@ -1,7 +1,7 @@
 use std::{
     cell::RefCell,
     collections::{hash_map::Entry, BTreeMap, HashMap},
-    io::ErrorKind,
+    io::{ErrorKind, Write},
     net::{Shutdown, SocketAddr as StdSocketAddr},
     os::unix::{io::AsRawFd, net::UnixStream},
     rc::{Rc, Weak},
@@ -26,8 +26,9 @@ use rustls::{
                 TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256, TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
                 TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
             },
+            kx_group::{SECP256R1, SECP384R1, X25519},
         },
-        CryptoProvider,
+        CryptoProvider, SupportedKxGroup,
     },
     CipherSuite, ProtocolVersion, ServerConfig, ServerConnection, SupportedCipherSuite,
 };
@@ -37,35 +38,38 @@ use time::{Duration, Instant};
 
 use sozu_command::{
     certificate::Fingerprint,
-    config::DEFAULT_CIPHER_SUITES,
+    config::{apply_tls_versions_floor, DEFAULT_CIPHER_SUITES},
     logging,
     proto::command::{
-        request::RequestType, response_content::ContentType, AddCertificate, CertificateSummary,
-        CertificatesByAddress, Cluster, HttpsListenerConfig, ListOfCertificatesByAddress,
-        ListenerType, RemoveCertificate, RemoveListener, ReplaceCertificate, RequestHttpFrontend,
-        ResponseContent, TlsVersion,
+        request::RequestType, response_content::ContentType, AccessLogFormat, AddCertificate,
+        CertificateSummary, CertificatesByAddress, Cluster, HttpsListenerConfig,
+        ListOfCertificatesByAddress, ListenerType, QueryRouteMatch, RemoveCertificate,
+        RemoveListener, ReplaceCertificate, ReplaceCertificateByName, RequestHttpFrontend,
+        ResponseContent, RouteMatches, SessionInfo, TlsVersion,
     },
     ready::Ready,
     request::WorkerRequest,
     response::{HttpFrontend, WorkerResponse},
     scm_socket::ScmSocket,
-    state::ClusterId,
+    state::{ClusterId, ConfigState},
 };
 
 use crate::{
     backends::BackendMap,
+    cidr::{IpFilter, IpFilters},
     pool::Pool,
     protocol::{
         h2::Http2,
         http::{
-            answers::HttpAnswers,
+            answers::{self, HttpAnswers},
             parser::{hostname_and_port, Method},
+            DefaultAnswerStatus,
         },
         proxy_protocol::expect::ExpectProxyProtocol,
         rustls::TlsHandshake,
         Http, Pipe, SessionState,
     },
-    router::{Route, Router},
+    router::{parse_route_match_query, Route, RouteOptions, Router},
     server::{ListenSession, ListenToken, ProxyChannel, Server, SessionManager, SessionToken},
     socket::{server_bind, FrontRustls},
     timer::TimeoutContainer,
@@ -102,16 +106,38 @@ StateMachineBuilder! {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AlpnProtocols {
     H2,
     Http11,
 }
 
+impl AlpnProtocols {
+    /// The protocol negotiated with ALPN during the TLS handshake, `None` if it is not supported.
+    /// Some clients don't fill in the ALPN protocol, in this case we default to HTTP/1.1
+    pub fn negotiated(alpn: Option<&[u8]>) -> Option<AlpnProtocols> {
+        match alpn {
+            Some(b"http/1.1") | None => Some(AlpnProtocols::Http11),
+            Some(b"h2") => Some(AlpnProtocols::H2),
+            Some(other) => {
+                error!(
+                    "Unsupported ALPN protocol: {}",
+                    String::from_utf8_lossy(other)
+                );
+                None
+            }
+        }
+    }
+}
+
 pub struct HttpsSession {
     answers: Rc<RefCell<HttpAnswers>>,
     configured_backend_timeout: Duration,
     configured_connect_timeout: Duration,
     configured_frontend_timeout: Duration,
+    /// the request timeout restarts after the handshake if it had its own timeout
+    configured_handshake_timeout: Option<Duration>,
+    configured_request_timeout: Duration,
     frontend_token: Token,
     has_been_closed: bool,
     last_event: Instant,
@@ -132,6 +158,7 @@ impl HttpsSession {
         configured_backend_timeout: Duration,
         configured_connect_timeout: Duration,
         configured_frontend_timeout: Duration,
+        configured_handshake_timeout: Option<Duration>,
         configured_request_timeout: Duration,
         expect_proxy: bool,
         listener: Rc<RefCell<HttpsListener>>,
@@ -152,7 +179,10 @@ impl HttpsSession {
         };
 
         let request_id = Ulid::generate();
-        let container_frontend_timeout = TimeoutContainer::new(configured_request_timeout, token);
+        let container_frontend_timeout = TimeoutContainer::new(
+            configured_handshake_timeout.unwrap_or(configured_request_timeout),
+            token,
+        );
 
         let state = if expect_proxy {
             trace!("starting in expect proxy state");
@@ -179,6 +209,8 @@ impl HttpsSession {
             configured_backend_timeout,
             configured_connect_timeout,
             configured_frontend_timeout,
+            configured_handshake_timeout,
+            configured_request_timeout,
             frontend_token: token,
             has_been_closed: false,
             last_event: Instant::now(),
@@ -276,16 +308,7 @@ impl HttpsSession {
             sni, alpn
         );
 
-        let alpn = match alpn {
-            Some("http/1.1") => AlpnProtocols::Http11,
-            Some("h2") => AlpnProtocols::H2,
-            Some(other) => {
-                error!("Unsupported ALPN protocol: {}", other);
-                return None;
-            }
-            // Some client don't fill in the ALPN protocol, in this case we default to Http/1.1
-            None => AlpnProtocols::Http11,
-        };
+        let alpn = AlpnProtocols::negotiated(handshake.session.alpn_protocol())?;
 
         if let Some(version) = handshake.session.protocol_version() {
             incr!(rustls_version_str(version));
@@ -293,12 +316,26 @@ impl HttpsSession {
         if let Some(cipher) = handshake.session.negotiated_cipher_suite() {
             incr!(rustls_ciphersuite_str(cipher));
         };
+        incr!(rustls_alpn_str(alpn));
+
+        // the HTTP session needs a front and a back buffer
+        if alpn == AlpnProtocols::Http11 && !self.can_checkout_buffers(2) {
+            incr!("https.upgrade.buffer_capacity_reached");
+            self.answer_unavailable(handshake);
+            return None;
+        }
 
         let front_stream = FrontRustls {
             stream: handshake.stream,
             session: handshake.session,
         };
 
+        let mut container_frontend_timeout = handshake.container_frontend_timeout;
+        if self.configured_handshake_timeout.is_some() {
+            // the client gets the whole request timeout for its first request
+            container_frontend_timeout.set_duration(self.configured_request_timeout);
+        }
+
         gauge_add!("protocol.tls.handshake", -1);
         match alpn {
             AlpnProtocols::Http11 => {
@@ -307,7 +344,7 @@ impl HttpsSession {
                     self.configured_backend_timeout,
                     self.configured_connect_timeout,
                     self.configured_frontend_timeout,
-                    handshake.container_frontend_timeout,
+                    container_frontend_timeout,
                     front_stream,
                     self.frontend_token,
                     self.listener.clone(),
@@ -322,10 +359,25 @@ impl HttpsSession {
 
                 http.frontend_readiness.event = handshake.frontend_readiness.event;
 
+                if !handshake.early_data.is_empty() {
+                    incr!("tls.early_data");
+                    if !http.add_early_data(&handshake.early_data) {
+                        error!(
+                            "could not fit {} bytes of early data in the request buffer",
+                            handshake.early_data.len()
+                        );
+                        return None;
+                    }
+                }
+
                 gauge_add!("protocol.https", 1);
                 Some(HttpsStateMachine::Http(http))
             }
             AlpnProtocols::H2 => {
+                if !handshake.early_data.is_empty() {
+                    error!("early data is not supported for HTTP/2");
+                    return None;
+                }
                 let mut http = Http2::new(
                     front_stream,
                     self.frontend_token,
@@ -343,6 +395,38 @@ impl HttpsSession {
         }
     }
 
+    fn can_checkout_buffers(&self, count: usize) -> bool {
+        match self.pool.upgrade() {
+            Some(pool) => pool.borrow().can_checkout(count),
+            None => false,
+        }
+    }
+
+    /// Answer a 503 on the TLS session and close it when there is no buffer left for
+    /// the HTTP session, rather than dropping a connection that completed its handshake
+    fn answer_unavailable(&self, mut handshake: TlsHandshake) {
+        error!(
+            "{} no buffer available for the HTTP session of {:?}, answering 503",
+            handshake.log_context(),
+            self.peer_address
+        );
+        let answer = self
+            .answers
+            .borrow()
+            .get(DefaultAnswerStatus::Answer503, None);
+        if let Err(e) = handshake.session.writer().write_all(&answer) {
+            error!("could not write the 503 answer: {:?}", e);
+            return;
+        }
+        handshake.session.send_close_notify();
+        while handshake.session.wants_write() {
+            if let Err(e) = handshake.session.write_tls(&mut handshake.stream) {
+                error!("could not send the 503 answer: {:?}", e);
+                return;
+            }
+        }
+    }
+
     fn upgrade_http(&self, http: Http<FrontRustls, HttpsListener>) -> Option<HttpsStateMachine> {
         debug!("https switching to wss");
         let front_token = self.frontend_token;
@@ -465,7 +549,14 @@ impl ProxySession for HttpsSession {
     }
 
     fn timeout(&mut self, token: Token) -> SessionIsToBeClosed {
+        let handshaking = matches!(
+            self.state,
+            HttpsStateMachine::Expect(..) | HttpsStateMachine::Handshake(_)
+        );
         let session_result = self.state.timeout(token, &mut self.metrics);
+        if handshaking && session_result == StateResult::CloseSession {
+            incr!("tls.handshake.timeout");
+        }
         session_result == StateResult::CloseSession
     }
 
@@ -520,6 +611,15 @@ impl ProxySession for HttpsSession {
     fn frontend_token(&self) -> Token {
         self.frontend_token
     }
+
+    fn listener_token(&self) -> Option<Token> {
+        Some(self.listener.borrow().token)
+    }
+
+    fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.protocol = format!("HTTPS({:?})", self.state.marker());
+        self.state.fill_session_info(info);
+    }
 }
 
 pub type HostName = String;
@@ -553,6 +653,17 @@ impl ListenerHandler for HttpsListener {
             None => self.tags.remove(&key),
         };
     }
+
+    fn get_socket_buffer_sizes(&self) -> (Option<u32>, Option<u32>) {
+        (
+            self.config.send_buffer_size,
+            self.config.receive_buffer_size,
+        )
+    }
+
+    fn get_access_logs_format(&self) -> AccessLogFormat {
+        self.config.access_logs_format()
+    }
 }
 
 impl L7ListenerHandler for HttpsListener {
@@ -564,12 +675,118 @@ impl L7ListenerHandler for HttpsListener {
         self.config.connect_timeout
     }
 
+    fn get_preserved_connection_tokens(&self) -> &[String] {
+        &self.config.preserved_connection_tokens
+    }
+
+    fn get_validate_websocket_handshake(&self) -> bool {
+        self.config.validate_websocket_handshake
+    }
+
+    fn get_hop_by_hop_headers(&self) -> &[String] {
+        &self.config.hop_by_hop_headers
+    }
+
+    fn get_max_chunked_body_size(&self) -> Option<u64> {
+        self.config.max_chunked_body_size
+    }
+
+    fn get_max_chunk_header_length(&self) -> u32 {
+        self.config.max_chunk_header_length
+    }
+
+    fn get_max_chunk_size(&self) -> u64 {
+        self.config.max_chunk_size
+    }
+
+    fn get_max_front_read_size(&self) -> Option<u32> {
+        self.config.max_front_read_size
+    }
+
+    fn get_min_request_header_rate(&self) -> Option<u32> {
+        self.config.min_request_header_rate
+    }
+
+    fn get_max_pipelined_requests(&self) -> Option<u32> {
+        self.config.max_pipelined_requests
+    }
+
+    fn get_health_check_path(&self) -> Option<&str> {
+        self.config.health_check_path.as_deref()
+    }
+
+    fn get_max_uri_length(&self) -> u32 {
+        self.config.max_uri_length
+    }
+
+    fn get_max_response_header_size(&self) -> Option<u32> {
+        self.config.max_response_header_size
+    }
+
+    fn get_http10_default_host(&self) -> Option<&str> {
+        self.config.http10_default_host.as_deref()
+    }
+
+    fn get_back_read_watermarks(&self) -> (Option<u32>, Option<u32>) {
+        (
+            self.config.back_read_low_watermark,
+            self.config.back_read_high_watermark,
+        )
+    }
+
+    fn get_access_logs_sampling(&self) -> Option<u32> {
+        self.config.access_logs_sampling
+    }
+
+    fn get_max_close_delimited_response_size(&self) -> Option<u64> {
+        self.config.max_close_delimited_response_size
+    }
+
+    fn get_access_logs_redacted_headers(&self) -> &[String] {
+        &self.config.access_logs_redacted_headers
+    }
+
+    fn get_access_logs_redact_query(&self) -> bool {
+        self.config.access_logs_redact_query
+    }
+
+    fn is_misdirected(&self, hostname: &str, server_name: &str) -> bool {
+        if !self.config.strict_sni_binding {
+            return false;
+        }
+        self.resolver
+            .0
+            .lock()
+            .map(|resolver| !resolver.certificate_covers(server_name, hostname))
+            .unwrap_or(false)
+    }
+
+    fn get_omit_forwarded_header(&self) -> bool {
+        self.config.omit_forwarded_header
+    }
+
+    fn get_omit_forwarded_client_port(&self) -> bool {
+        self.config.omit_forwarded_client_port
+    }
+
+    fn get_trace_context(&self) -> bool {
+        self.config.trace_context
+    }
+
+    fn get_rewrite_absolute_form(&self) -> bool {
+        self.config.rewrite_absolute_form
+    }
+
+    fn get_normalize_reason_phrase(&self) -> bool {
+        self.config.normalize_reason_phrase
+    }
+
     fn frontend_from_request(
         &self,
         host: &str,
         uri: &str,
         method: &Method,
-    ) -> Result<Route, FrontendFromRequestError> {
+    ) -> Result<(Route, RouteOptions), FrontendFromRequestError> {
         let start = Instant::now();
         let (remaining_input, (hostname, _)) = match hostname_and_port(host.as_bytes()) {
             Ok(tuple) => tuple,
@@ -593,10 +810,13 @@ impl L7ListenerHandler for HttpsListener {
         // chars in there
         let host = unsafe { from_utf8_unchecked(hostname) };
 
-        let route = self.fronts.lookup(host, uri, method).map_err(|e| {
-            incr!("http.failed_backend_matching");
-            FrontendFromRequestError::NoClusterFound(e)
-        })?;
+        let (route, options) = self
+            .fronts
+            .lookup_with_options(host, uri, method)
+            .map_err(|e| {
+                incr!("http.failed_backend_matching");
+                FrontendFromRequestError::NoClusterFound(e)
+            })?;
 
         let now = Instant::now();
 
@@ -608,7 +828,7 @@ impl L7ListenerHandler for HttpsListener {
             );
         }
 
-        Ok(route)
+        Ok((route, options))
     }
 }
 
@@ -652,11 +872,26 @@ impl ResolveCertificate for HttpsListener {
 }
 
 impl HttpsListener {
+    pub fn replace_certificate_by_name(
+        &mut self,
+        opts: &ReplaceCertificateByName,
+    ) -> Result<Fingerprint, ListenerError> {
+        let mut resolver = self
+            .resolver
+            .0
+            .lock()
+            .map_err(|err| ListenerError::Lock(err.to_string()))?;
+
+        resolver
+            .replace_certificate_by_name(opts)
+            .map_err(ListenerError::Resolver)
+    }
+
     pub fn try_new(
         config: HttpsListenerConfig,
         token: Token,
     ) -> Result<HttpsListener, ListenerError> {
-        let resolver = Arc::new(MutexWrappedCertificateResolver::default());
+        let resolver = Arc::new(MutexWrappedCertificateResolver::new(config.require_sni));
 
         let server_config = Arc::new(Self::create_rustls_context(&config, resolver.to_owned())?);
 
@@ -668,6 +903,12 @@ impl HttpsListener {
                 error: parse_error.to_string(),
             })?;
 
+        let mut answers = HttpAnswers::new(&config.answer_404, &config.answer_503);
+        answers.set_paths(
+            config.answer_404_path.clone(),
+            config.answer_503_path.clone(),
+        );
+
         Ok(HttpsListener {
             listener: None,
             address,
@@ -675,10 +916,7 @@ impl HttpsListener {
             rustls_details: server_config,
             active: false,
             fronts: Router::new(),
-            answers: Rc::new(RefCell::new(HttpAnswers::new(
-                &config.answer_404,
-                &config.answer_503,
-            ))),
+            answers: Rc::new(RefCell::new(answers)),
             config,
             token,
             tags: BTreeMap::new(),
@@ -696,11 +934,15 @@ impl HttpsListener {
 
         let mut listener = match tcp_listener {
             Some(tcp_listener) => tcp_listener,
-            None => server_bind(self.config.address.clone()).map_err(|server_bind_error| {
-                ListenerError::Activation {
-                    address: self.config.address.clone(),
-                    error: server_bind_error.to_string(),
-                }
+            None => server_bind(
+                self.config.address.clone(),
+                self.config.listen_backlog,
+                self.config.bind_device.as_deref(),
+                self.get_socket_buffer_sizes(),
+            )
+            .map_err(|server_bind_error| ListenerError::Activation {
+                address: self.config.address.clone(),
+                error: server_bind_error.to_string(),
             })?,
         };
 
@@ -717,6 +959,56 @@ impl HttpsListener {
         config: &HttpsListenerConfig,
         resolver: Arc<MutexWrappedCertificateResolver>,
     ) -> Result<ServerConfig, ListenerError> {
+        let configured_versions = config
+            .versions
+            .iter()
+            .filter_map(|version| match TlsVersion::try_from(*version) {
+                Ok(version) => Some(version),
+                Err(_) => {
+                    error!("unsupported TLS version");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        // the listeners may not come from a configuration file
+        let configured_versions =
+            apply_tls_versions_floor(configured_versions, config.allow_insecure_tls_versions)
+                .map_err(ListenerError::TlsVersions)?;
+
+        let versions = configured_versions
+            .into_iter()
+            .filter_map(|version| match version {
+                TlsVersion::TlsV12 => Some(&rustls::version::TLS12),
+                TlsVersion::TlsV13 => Some(&rustls::version::TLS13),
+                other_version => {
+                    error!("unsupported TLS version {:?}", other_version);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let provider = Self::create_crypto_provider(config);
+
+        let mut server_config = ServerConfig::builder_with_provider(provider.into())
+            .with_protocol_versions(&versions[..])
+            .map_err(|err| ListenerError::BuildRustls(err.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+        server_config.send_tls13_tickets = config.send_tls13_tickets as usize;
+        server_config.max_early_data_size = config.max_early_data_size.unwrap_or(0);
+
+        let mut protocols = SERVER_PROTOS
+            .iter()
+            .map(|proto| proto.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        server_config.alpn_protocols.append(&mut protocols);
+
+        Ok(server_config)
+    }
+
+    /// the cipher suites and key exchange groups of the listener,
+    /// unknown names are logged and ignored
+    fn create_crypto_provider(config: &HttpsListenerConfig) -> CryptoProvider {
         let cipher_names = if config.cipher_list.is_empty() {
             DEFAULT_CIPHER_SUITES.to_vec()
         } else {
@@ -747,42 +1039,33 @@ impl HttpsListener {
             })
             .collect::<Vec<_>>();
 
-        let versions = config
-            .versions
-            .iter()
-            .filter_map(|version| match TlsVersion::try_from(*version) {
-                Ok(TlsVersion::TlsV12) => Some(&rustls::version::TLS12),
-                Ok(TlsVersion::TlsV13) => Some(&rustls::version::TLS13),
-                Ok(other_version) => {
-                    error!("unsupported TLS version {:?}", other_version);
-                    None
-                }
-                Err(_) => {
-                    error!("unsupported TLS version");
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
+        let default_provider = ring::default_provider();
 
-        let provider = CryptoProvider {
-            cipher_suites: ciphers,
-            ..ring::default_provider()
+        let kx_groups = if config.groups_list.is_empty() {
+            default_provider.kx_groups.clone()
+        } else {
+            config
+                .groups_list
+                .iter()
+                .filter_map(|group| -> Option<&'static dyn SupportedKxGroup> {
+                    match group.as_str() {
+                        "x25519" | "X25519" => Some(X25519),
+                        "P-256" | "secp256r1" => Some(SECP256R1),
+                        "P-384" | "secp384r1" => Some(SECP384R1),
+                        other_group => {
+                            error!("unknown key exchange group: {:?}", other_group);
+                            None
+                        }
+                    }
+                })
+                .collect::<Vec<_>>()
         };
 
-        let mut server_config = ServerConfig::builder_with_provider(provider.into())
-            .with_protocol_versions(&versions[..])
-            .map_err(|err| ListenerError::BuildRustls(err.to_string()))?
-            .with_no_client_auth()
-            .with_cert_resolver(resolver);
-        server_config.send_tls13_tickets = config.send_tls13_tickets as usize;
-
-        let mut protocols = SERVER_PROTOS
-            .iter()
-            .map(|proto| proto.as_bytes().to_vec())
-            .collect::<Vec<_>>();
-        server_config.alpn_protocols.append(&mut protocols);
-
-        Ok(server_config)
+        CryptoProvider {
+            cipher_suites: ciphers,
+            kx_groups,
+            ..default_provider
+        }
     }
 
     pub fn add_https_front(&mut self, tls_front: HttpFrontend) -> Result<(), ListenerError> {
@@ -819,6 +1102,7 @@ impl HttpsListener {
 pub struct HttpsProxy {
     listeners: HashMap<Token, Rc<RefCell<HttpsListener>>>,
     clusters: HashMap<ClusterId, Cluster>,
+    ip_filters: IpFilters,
     backends: Rc<RefCell<BackendMap>>,
     pool: Rc<RefCell<Pool>>,
     registry: Registry,
@@ -835,6 +1119,7 @@ impl HttpsProxy {
         HttpsProxy {
             listeners: HashMap::new(),
             clusters: HashMap::new(),
+            ip_filters: IpFilters::default(),
             backends,
             pool,
             registry,
@@ -929,6 +1214,42 @@ impl HttpsProxy {
         Ok(None)
     }
 
+    pub fn reload_answers(&mut self) -> Result<Option<ResponseContent>, ProxyError> {
+        answers::reload_all(
+            self.listeners
+                .values()
+                .map(|listener| listener.borrow().answers.clone()),
+        )
+        .map_err(ProxyError::ReloadAnswers)?;
+        Ok(None)
+    }
+
+    /// Describes how each listener would route the request,
+    /// or only the listener on the queried address
+    pub fn query_route_match(&self, query: &QueryRouteMatch) -> Result<RouteMatches, ProxyError> {
+        let (address, method) = parse_route_match_query(query)?;
+
+        let backends = self.backends.borrow();
+        let vec = self
+            .listeners
+            .values()
+            .map(|listener| listener.borrow())
+            .filter(|listener| address.is_none() || address == Some(listener.address))
+            .map(|listener| {
+                listener.fronts.route_match(
+                    listener.address,
+                    ListenerType::Https,
+                    &query.host,
+                    &query.path,
+                    &method,
+                    &backends,
+                )
+            })
+            .collect();
+
+        Ok(RouteMatches { vec })
+    }
+
     pub fn query_all_certificates(&mut self) -> Result<Option<ResponseContent>, ProxyError> {
         let certificates = self
             .listeners
@@ -942,6 +1263,7 @@ impl HttpsProxy {
                     .drain()
                     .map(|(k, fingerprint)| CertificateSummary {
                         domain: String::from_utf8(k).unwrap(),
+                        expired_at: resolver.expiration(&fingerprint),
                         fingerprint: fingerprint.to_string(),
                     })
                     .collect();
@@ -979,6 +1301,7 @@ impl HttpsProxy {
                     certificate_summaries.push(CertificateSummary {
                         domain: String::from_utf8(k.to_vec()).unwrap(),
                         fingerprint: fingerprint.to_string(),
+                        expired_at: resolver.expiration(fingerprint),
                     });
                 }
                 CertificatesByAddress {
@@ -1063,6 +1386,7 @@ impl HttpsProxy {
                     .add_custom_answer(&cluster.cluster_id, &answer_503);
             }
         }
+        self.ip_filters.update(&cluster)?;
         self.clusters.insert(cluster.cluster_id.clone(), cluster);
         Ok(None)
     }
@@ -1072,6 +1396,7 @@ impl HttpsProxy {
         cluster_id: &str,
     ) -> Result<Option<ResponseContent>, ProxyError> {
         self.clusters.remove(cluster_id);
+        self.ip_filters.remove(cluster_id);
         for listener in self.listeners.values() {
             listener
                 .borrow()
@@ -1133,6 +1458,74 @@ impl HttpsProxy {
         Ok(None)
     }
 
+    /// Serializable copy of the listeners, clusters, certificates, frontends and
+    /// backends of the proxy, to rebuild its routing in another instance with `restore`.
+    /// The private keys are not kept by the proxy, the certificates come without them.
+    pub fn snapshot(&self) -> ConfigState {
+        let mut state = ConfigState::new();
+
+        for listener in self.listeners.values() {
+            let listener = listener.borrow();
+            let mut config = listener.config.clone();
+            config.active = listener.active;
+            state
+                .https_listeners
+                .insert(listener.address.to_string(), config);
+
+            for front in listener.fronts.frontends() {
+                let key = RequestHttpFrontend::from(front.clone()).to_string();
+                state.https_fronts.insert(key, front.clone());
+            }
+
+            let certificates = unwrap_msg!(listener.resolver.0.lock()).export_certificates();
+            if !certificates.is_empty() {
+                state.certificates.insert(listener.address, certificates);
+            }
+        }
+
+        let backends = self.backends.borrow();
+        for (cluster_id, cluster) in &self.clusters {
+            state.clusters.insert(cluster_id.clone(), cluster.clone());
+
+            let cluster_backends = backends.export_configuration_state(cluster_id);
+            if !cluster_backends.is_empty() {
+                state.backends.insert(cluster_id.clone(), cluster_backends);
+            }
+        }
+
+        state
+    }
+
+    /// Rebuilds the clusters, certificates, frontends and backends of a snapshot.
+    /// Listeners own a socket and a token of the session slab, they are not created
+    /// here: add the `https_listeners` of the snapshot with `add_listener` beforehand.
+    /// The certificates of a snapshot have no private key, take the `certificates`
+    /// of the configuration state of the main process instead.
+    pub fn restore(&mut self, snapshot: &ConfigState) -> Result<(), ProxyError> {
+        for cluster in snapshot.clusters.values() {
+            self.add_cluster(cluster.clone())?;
+        }
+
+        for (address, certificates) in &snapshot.certificates {
+            for certificate in certificates.values() {
+                self.add_certificate(AddCertificate {
+                    address: address.to_string(),
+                    certificate: certificate.clone(),
+                    expired_at: None,
+                })?;
+            }
+        }
+
+        for front in snapshot.https_fronts.values() {
+            self.add_https_frontend(front.clone().into())?;
+        }
+
+        self.backends
+            .borrow_mut()
+            .restore_configuration_state(snapshot);
+        Ok(())
+    }
+
     pub fn add_certificate(
         &mut self,
         add_certificate: AddCertificate,
@@ -1217,6 +1610,36 @@ impl HttpsProxy {
 
         Ok(None)
     }
+
+    pub fn replace_certificate_by_name(
+        &mut self,
+        replace: ReplaceCertificateByName,
+    ) -> Result<Option<ResponseContent>, ProxyError> {
+        let address = replace
+            .address
+            .parse::<StdSocketAddr>()
+            .map_err(|parse_error| ProxyError::SocketParse {
+                address: replace.address.clone(),
+                error: parse_error.to_string(),
+            })?;
+
+        let listener = self
+            .listeners
+            .values()
+            .find(|l| l.borrow().address == address)
+            .ok_or(ProxyError::NoListenerFound(address))?;
+
+        let fingerprint = listener
+            .borrow_mut()
+            .replace_certificate_by_name(&replace)
+            .map_err(ProxyError::ReplaceCertificate)?;
+        debug!(
+            "certificate {} now serves {} on {}",
+            fingerprint, replace.name, address
+        );
+
+        Ok(None)
+    }
 }
 
 impl ProxyConfiguration for HttpsProxy {
@@ -1227,6 +1650,32 @@ impl ProxyConfiguration for HttpsProxy {
         }
     }
 
+    fn pause_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error> {
+        if let Some(listener) = self.listeners.get(&Token(token.0)) {
+            if let Some(sock) = listener.borrow_mut().listener.as_mut() {
+                self.registry.deregister(sock)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resume_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error> {
+        if let Some(listener) = self.listeners.get(&Token(token.0)) {
+            if let Some(sock) = listener.borrow_mut().listener.as_mut() {
+                self.registry
+                    .register(sock, Token(token.0), Interest::READABLE)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn max_sessions(&self, token: ListenToken) -> Option<usize> {
+        self.listeners
+            .get(&Token(token.0))
+            .and_then(|listener| listener.borrow().config.max_sessions)
+            .map(|max_sessions| max_sessions as usize)
+    }
+
     fn create_session(
         &mut self,
         mut frontend_sock: MioTcpStream,
@@ -1282,6 +1731,10 @@ impl ProxyConfiguration for HttpsProxy {
             Duration::seconds(owned.config.back_timeout as i64),
             Duration::seconds(owned.config.connect_timeout as i64),
             Duration::seconds(owned.config.front_timeout as i64),
+            owned
+                .config
+                .handshake_timeout
+                .map(|timeout| Duration::seconds(timeout as i64)),
             Duration::seconds(owned.config.request_timeout as i64),
             owned.config.expect_proxy,
             listener.clone(),
@@ -1342,6 +1795,10 @@ impl ProxyConfiguration for HttpsProxy {
                 );
                 self.replace_certificate(replace_certificate)
             }
+            RequestType::ReplaceCertificateByName(replace) => {
+                debug!("{} replace certificate by name: {:?}", request_id, replace);
+                self.replace_certificate_by_name(replace)
+            }
             RequestType::RemoveListener(remove) => {
                 debug!("removing HTTPS listener at address {:?}", remove.address);
                 self.remove_listener(remove)
@@ -1377,6 +1834,10 @@ impl ProxyConfiguration for HttpsProxy {
                 );
                 self.logging(logging_filter)
             }
+            RequestType::ReloadAnswers(_) => {
+                debug!("{} reload default answers", request_id);
+                self.reload_answers()
+            }
             RequestType::QueryCertificatesFromWorkers(filters) => {
                 if let Some(domain) = filters.domain {
                     debug!("{} query certificate for domain {}", request_id, domain);
@@ -1386,6 +1847,11 @@ impl ProxyConfiguration for HttpsProxy {
                     self.query_all_certificates()
                 }
             }
+            RequestType::QueryRouteMatch(query) => {
+                debug!("{} query route match {:?}", request_id, query);
+                self.query_route_match(&query)
+                    .map(|route_matches| Some(ContentType::RouteMatches(route_matches).into()))
+            }
             other_request => {
                 debug!(
                     "{} unsupported message for HTTPS proxy, ignoring {:?}",
@@ -1451,9 +1917,20 @@ impl L7Proxy for HttpsProxy {
     fn clusters(&self) -> &HashMap<ClusterId, Cluster> {
         &self.clusters
     }
+
+    fn ip_filter(&self, cluster_id: &str) -> Option<&IpFilter> {
+        self.ip_filters.get(cluster_id)
+    }
 }
 
 /// Used for metrics keeping
+fn rustls_alpn_str(alpn: AlpnProtocols) -> &'static str {
+    match alpn {
+        AlpnProtocols::H2 => "tls.alpn.h2",
+        AlpnProtocols::Http11 => "tls.alpn.http11",
+    }
+}
+
 fn rustls_version_str(version: ProtocolVersion) -> &'static str {
     match version {
         ProtocolVersion::SSLv2 => "tls.version.SSLv2",
@@ -1600,7 +2077,7 @@ mod tests {
 
     use sozu_command::config::ListenerBuilder;
 
-    use crate::router::{trie::TrieNode, MethodRule, PathRule, Route, Router};
+    use crate::router::{trie::TrieNode, MethodRule, PathRule, Route, RouteOptions, Router};
 
     use super::*;
 
@@ -1635,25 +2112,29 @@ mod tests {
             "lolcatho.st".as_bytes(),
             &PathRule::Prefix(uri1),
             &MethodRule::new(None),
-            &Route::ClusterId(cluster_id1.clone())
+            &Route::ClusterId(cluster_id1.clone()),
+            &RouteOptions::default()
         ));
         assert!(fronts.add_tree_rule(
             "lolcatho.st".as_bytes(),
             &PathRule::Prefix(uri2),
             &MethodRule::new(None),
-            &Route::ClusterId(cluster_id2)
+            &Route::ClusterId(cluster_id2),
+            &RouteOptions::default()
         ));
         assert!(fronts.add_tree_rule(
             "lolcatho.st".as_bytes(),
             &PathRule::Prefix(uri3),
             &MethodRule::new(None),
-            &Route::ClusterId(cluster_id3)
+            &Route::ClusterId(cluster_id3),
+            &RouteOptions::default()
         ));
         assert!(fronts.add_tree_rule(
             "other.domain".as_bytes(),
             &PathRule::Prefix("test".to_string()),
             &MethodRule::new(None),
-            &Route::ClusterId(cluster_id1)
+            &Route::ClusterId(cluster_id1),
+            &RouteOptions::default()
         ));
 
         let address: StdSocketAddr = FromStr::from_str("127.0.0.1:1032")
@@ -1692,25 +2173,25 @@ mod tests {
         println!("TEST {}", line!());
         let frontend1 = listener.frontend_from_request("lolcatho.st", "/", &Method::Get);
         assert_eq!(
-            frontend1.expect("should find a frontend"),
+            frontend1.expect("should find a frontend").0,
             Route::ClusterId("cluster_1".to_string())
         );
         println!("TEST {}", line!());
         let frontend2 = listener.frontend_from_request("lolcatho.st", "/test", &Method::Get);
         assert_eq!(
-            frontend2.expect("should find a frontend"),
+            frontend2.expect("should find a frontend").0,
             Route::ClusterId("cluster_1".to_string())
         );
         println!("TEST {}", line!());
         let frontend3 = listener.frontend_from_request("lolcatho.st", "/yolo/test", &Method::Get);
         assert_eq!(
-            frontend3.expect("should find a frontend"),
+            frontend3.expect("should find a frontend").0,
             Route::ClusterId("cluster_2".to_string())
         );
         println!("TEST {}", line!());
         let frontend4 = listener.frontend_from_request("lolcatho.st", "/yolo/swag", &Method::Get);
         assert_eq!(
-            frontend4.expect("should find a frontend"),
+            frontend4.expect("should find a frontend").0,
             Route::ClusterId("cluster_3".to_string())
         );
         println!("TEST {}", line!());
@@ -1719,6 +2200,223 @@ mod tests {
         // assert!(false);
     }
 
+    fn snapshot_test_proxy() -> HttpsProxy {
+        let poll = Poll::new().expect("could not create event loop");
+        HttpsProxy::new(
+            poll.registry()
+                .try_clone()
+                .expect("could not clone the mio registry"),
+            SessionManager::new(Slab::with_capacity(10), 10),
+            Rc::new(RefCell::new(Pool::with_capacity(1, 10, 16384))),
+            Rc::new(RefCell::new(BackendMap::new())),
+        )
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        use sozu_command::proto::command::{CertificateAndKey, PathRule};
+
+        use crate::backends::Backend;
+
+        let address: StdSocketAddr = "127.0.0.1:1031".parse().unwrap();
+        let mut proxy = snapshot_test_proxy();
+        proxy.add_listener(
+            ListenerBuilder::new_https(address)
+                .to_tls(None)
+                .expect("Could not create default HTTPS listener config"),
+            Token(0),
+        );
+
+        for cluster_id in ["cluster_1", "cluster_2"] {
+            proxy
+                .add_cluster(Cluster {
+                    cluster_id: cluster_id.to_owned(),
+                    ..Default::default()
+                })
+                .expect("Could not add cluster");
+        }
+        proxy
+            .add_certificate(AddCertificate {
+                address: address.to_string(),
+                certificate: CertificateAndKey {
+                    certificate: String::from(include_str!("../assets/certificate.pem")),
+                    key: String::from(include_str!("../assets/key.pem")),
+                    names: vec!["lolcatho.st".to_owned(), "snapshot.lolcatho.st".to_owned()],
+                    ..Default::default()
+                },
+                expired_at: None,
+            })
+            .expect("Could not add certificate");
+        for (path, cluster_id) in [("/", "cluster_1"), ("/api", "cluster_2")] {
+            proxy
+                .add_https_frontend(RequestHttpFrontend {
+                    cluster_id: Some(cluster_id.to_owned()),
+                    address: address.to_string(),
+                    hostname: "lolcatho.st".to_owned(),
+                    path: PathRule::prefix(path.to_owned()),
+                    ..Default::default()
+                })
+                .expect("Could not add https frontend");
+        }
+        proxy.backends.borrow_mut().add_backend(
+            "cluster_1",
+            Backend::new(
+                "cluster_1-0",
+                "127.0.0.1:1032".parse().unwrap(),
+                None,
+                None,
+                None,
+            ),
+        );
+
+        let snapshot = proxy.snapshot();
+        assert_eq!(snapshot.https_fronts.len(), 2);
+        assert_eq!(snapshot.backends["cluster_1"].len(), 1);
+        // the private key is not exported, nor kept by the proxy
+        assert!(snapshot.certificates[&address]
+            .values()
+            .all(|certificate| certificate.key.is_empty()));
+
+        let mut restored = snapshot_test_proxy();
+        for (token, config) in snapshot.https_listeners.values().enumerate() {
+            restored.add_listener(config.clone(), Token(token));
+        }
+        // without the private keys, the certificates cannot be restored
+        assert!(restored.restore(&snapshot).is_err());
+
+        let mut restored = snapshot_test_proxy();
+        for (token, config) in snapshot.https_listeners.values().enumerate() {
+            restored.add_listener(config.clone(), Token(token));
+        }
+        let mut snapshot_with_keys = snapshot.clone();
+        for certificate in snapshot_with_keys
+            .certificates
+            .values_mut()
+            .flat_map(|certificates| certificates.values_mut())
+        {
+            certificate.key = String::from(include_str!("../assets/key.pem"));
+        }
+        restored
+            .restore(&snapshot_with_keys)
+            .expect("Could not restore the snapshot");
+        assert_eq!(restored.snapshot(), snapshot);
+
+        let listener = proxy.listeners[&Token(0)].borrow();
+        let restored_listener = restored.listeners[&Token(0)].borrow();
+        for (host, path) in [
+            ("lolcatho.st", "/"),
+            ("lolcatho.st", "/api/users"),
+            ("other.domain", "/"),
+        ] {
+            assert_eq!(
+                listener
+                    .frontend_from_request(host, path, &Method::Get)
+                    .ok(),
+                restored_listener
+                    .frontend_from_request(host, path, &Method::Get)
+                    .ok(),
+            );
+        }
+
+        // the names of the certificate are restored with it
+        let resolver = unwrap_msg!(restored_listener.resolver.0.lock());
+        assert!(resolver
+            .domain_lookup(b"snapshot.lolcatho.st", false)
+            .is_some());
+
+        let mut backends = restored.backends.borrow_mut();
+        let backend = backends
+            .get_or_create_backend_list_for_cluster("cluster_1")
+            .next_available_backend()
+            .expect("the backend should be restored");
+        assert_eq!(backend.borrow().backend_id, "cluster_1-0");
+    }
+
+    #[test]
+    fn add_mismatched_certificate_and_key() {
+        use sozu_command::proto::command::{CertificateAndKey, ResponseStatus};
+
+        let address: StdSocketAddr = "127.0.0.1:1033".parse().unwrap();
+        let mut proxy = snapshot_test_proxy();
+        proxy.add_listener(
+            ListenerBuilder::new_https(address)
+                .to_tls(None)
+                .expect("Could not create default HTTPS listener config"),
+            Token(0),
+        );
+
+        let response = proxy.notify(WorkerRequest::new(
+            "ID_MISMATCH".to_owned(),
+            RequestType::AddCertificate(AddCertificate {
+                address: address.to_string(),
+                certificate: CertificateAndKey {
+                    certificate: String::from(include_str!("../assets/certificate.pem")),
+                    key: String::from(include_str!("../assets/key_test.pem")),
+                    ..Default::default()
+                },
+                expired_at: None,
+            })
+            .into(),
+        ));
+        assert_eq!(response.status, ResponseStatus::Failure);
+        assert!(response.message.contains("does not match"));
+
+        let listener = proxy.listeners[&Token(0)].borrow();
+        let resolver = unwrap_msg!(listener.resolver.0.lock());
+        assert!(resolver.export_certificates().is_empty());
+    }
+
+    #[test]
+    fn restricted_key_exchange_groups() {
+        use rustls::NamedGroup;
+
+        let mut config = ListenerBuilder::new_https("127.0.0.1:1034")
+            .with_groups_list(Some(vec![
+                "X25519".to_owned(),
+                "secp256r1".to_owned(),
+                "P-521".to_owned(),
+            ]))
+            .to_tls(None)
+            .expect("Could not create HTTPS listener config");
+
+        let provider = HttpsListener::create_crypto_provider(&config);
+        let groups: Vec<NamedGroup> = provider
+            .kx_groups
+            .iter()
+            .map(|group| group.name())
+            .collect();
+        assert_eq!(groups, vec![NamedGroup::X25519, NamedGroup::secp256r1]);
+
+        let resolver = Arc::new(MutexWrappedCertificateResolver::default());
+        assert!(HttpsListener::create_rustls_context(&config, resolver.clone()).is_ok());
+
+        // no known group, no client could complete a handshake
+        config.groups_list = vec!["P-521".to_owned()];
+        assert!(HttpsListener::create_crypto_provider(&config)
+            .kx_groups
+            .is_empty());
+        assert!(HttpsListener::create_rustls_context(&config, resolver).is_err());
+    }
+
+    #[test]
+    fn tls_versions_floor_on_sent_listeners() {
+        let mut config = ListenerBuilder::new_https("127.0.0.1:1035")
+            .to_tls(None)
+            .expect("Could not create HTTPS listener config");
+        let resolver = Arc::new(MutexWrappedCertificateResolver::default());
+
+        // a listener sent over the command channel skips the configuration checks
+        config.versions = vec![TlsVersion::SslV3 as i32, TlsVersion::TlsV12 as i32];
+        assert!(HttpsListener::create_rustls_context(&config, resolver.clone()).is_err());
+
+        config.versions = vec![TlsVersion::TlsV11 as i32, TlsVersion::TlsV12 as i32];
+        assert!(HttpsListener::create_rustls_context(&config, resolver.clone()).is_ok());
+
+        config.versions = vec![TlsVersion::SslV3 as i32, TlsVersion::TlsV12 as i32];
+        config.allow_insecure_tls_versions = true;
+        assert!(HttpsListener::create_rustls_context(&config, resolver).is_ok());
+    }
+
     #[test]
     fn wildcard_certificate_names() {
         let mut trie = TrieNode::root();
@@ -1785,4 +2483,172 @@ mod tests {
             Some(&("hello.sub.test.example.com".as_bytes().to_vec(), 2u8))
         );
     }
+
+    /// accepts any certificate, the test only looks at the negotiated protocol
+    #[derive(Debug)]
+    struct NoCertificateVerification;
+
+    impl rustls::client::danger::ServerCertVerifier for NoCertificateVerification {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &rustls::pki_types::CertificateDer<'_>,
+            _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+            _server_name: &rustls::pki_types::ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: rustls::pki_types::UnixTime,
+        ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &rustls::pki_types::CertificateDer<'_>,
+            _dss: &rustls::DigitallySignedStruct,
+        ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &rustls::pki_types::CertificateDer<'_>,
+            _dss: &rustls::DigitallySignedStruct,
+        ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+            ring::default_provider()
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    /// move the TLS records written by `from` to `to`
+    fn transfer_tls(from: &mut rustls::Connection, to: &mut rustls::Connection) {
+        let mut records = Vec::new();
+        from.write_tls(&mut records)
+            .expect("could not write TLS records");
+        let mut records = &records[..];
+        while !records.is_empty() {
+            to.read_tls(&mut records)
+                .expect("could not read TLS records");
+            to.process_new_packets()
+                .expect("could not process TLS records");
+        }
+    }
+
+    /// complete a TLS handshake in memory, the client offering `alpn_protocols`
+    fn server_handshake(
+        server_config: Arc<ServerConfig>,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> ServerConnection {
+        let mut client_config = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification))
+            .with_no_client_auth();
+        client_config.alpn_protocols = alpn_protocols;
+
+        let mut client = rustls::Connection::Client(
+            rustls::ClientConnection::new(
+                Arc::new(client_config),
+                "lolcatho.st".try_into().unwrap(),
+            )
+            .expect("could not create the client connection"),
+        );
+        let mut server = rustls::Connection::Server(
+            ServerConnection::new(server_config).expect("could not create the server connection"),
+        );
+        while client.is_handshaking() || server.is_handshaking() {
+            transfer_tls(&mut client, &mut server);
+            transfer_tls(&mut server, &mut client);
+        }
+        match server {
+            rustls::Connection::Server(server) => server,
+            rustls::Connection::Client(_) => unreachable!(),
+        }
+    }
+
+    /// a socket connected to a local listener, the session never uses it here
+    fn local_stream() -> MioTcpStream {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("could not bind");
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap())
+            .expect("could not connect to the listener");
+        stream.set_nonblocking(true).unwrap();
+        MioTcpStream::from_std(stream)
+    }
+
+    #[test]
+    fn upgrade_to_the_negotiated_protocol() {
+        let address: StdSocketAddr = "127.0.0.1:1038".parse().unwrap();
+        let mut proxy = snapshot_test_proxy();
+        proxy.add_listener(
+            ListenerBuilder::new_https(address)
+                .to_tls(None)
+                .expect("Could not create default HTTPS listener config"),
+            Token(0),
+        );
+        proxy
+            .add_certificate(AddCertificate {
+                address: address.to_string(),
+                certificate: sozu_command::proto::command::CertificateAndKey {
+                    certificate: String::from(include_str!("../assets/certificate.pem")),
+                    key: String::from(include_str!("../assets/key.pem")),
+                    names: vec!["lolcatho.st".to_owned()],
+                    ..Default::default()
+                },
+                expired_at: None,
+            })
+            .expect("Could not add certificate");
+        let listener = proxy.listeners[&Token(0)].clone();
+        let server_config = listener.borrow().rustls_details.clone();
+        let pool = Rc::downgrade(&proxy.pool);
+        let proxy = Rc::new(RefCell::new(proxy));
+
+        let mut session = HttpsSession::new(
+            listener.borrow().answers.clone(),
+            Duration::seconds(10),
+            Duration::seconds(3),
+            Duration::seconds(10),
+            None,
+            Duration::seconds(10),
+            false,
+            listener.clone(),
+            pool,
+            proxy,
+            address,
+            ServerConnection::new(server_config.clone())
+                .expect("could not create the server connection"),
+            local_stream(),
+            "SOZUBALANCEID".to_owned(),
+            Token(1),
+            Duration::ZERO,
+        );
+
+        // an unknown protocol is skipped, the clients without ALPN get HTTP/1.1 too
+        for (alpn_protocols, negotiated) in [
+            (vec![b"h3".to_vec(), b"http/1.1".to_vec()], Some("http/1.1")),
+            (Vec::new(), None),
+        ] {
+            let server = server_handshake(server_config.clone(), alpn_protocols);
+            let handshake = TlsHandshake::new(
+                TimeoutContainer::new(Duration::seconds(10), Token(1)),
+                server,
+                local_stream(),
+                Token(1),
+                Ulid::generate(),
+                None,
+            );
+            match session.upgrade_handshake(handshake) {
+                Some(HttpsStateMachine::Http(http)) => {
+                    assert_eq!(http.frontend_socket.alpn_protocol(), negotiated);
+                }
+                _ => panic!("the session should upgrade to HTTP/1.1"),
+            }
+        }
+
+        assert_eq!(AlpnProtocols::negotiated(None), Some(AlpnProtocols::Http11));
+        assert_eq!(AlpnProtocols::negotiated(Some(b"h3")), None);
+    }
 }
@@ -46,6 +46,37 @@
 //! The `http_listener` is of the type `HttpListenerConfig`, that we can be sent to the worker
 //! to start the proxy.
 //!
+//! The builder has setters for the public address, the timeouts and other options,
+//! and checks the addresses when building the listener:
+//!
+//! ```
+//! use sozu_command_lib::config::ListenerBuilder;
+//!
+//! let http_listener = ListenerBuilder::new_http("127.0.0.1:8080")
+//!     .with_public_address(Some("1.2.3.4:80"))
+//!     .with_front_timeout(Some(30))
+//!     .with_connect_timeout(Some(5))
+//!     .to_http(None)
+//!     .expect("Could not create HTTP listener");
+//!
+//! assert!(ListenerBuilder::new_http("not-an-address").to_http(None).is_err());
+//! ```
+//!
+//! Library users can also build it with the typed setters of `HttpProxyConfigurationBuilder`,
+//! that refuses a missing front address, and zero timeouts or buffer sizes:
+//!
+//! ```
+//! use sozu_lib::http::HttpProxyConfigurationBuilder;
+//!
+//! let http_listener = HttpProxyConfigurationBuilder::new()
+//!     .with_front("127.0.0.1:8080".parse().unwrap())
+//!     .with_connect_timeout(5)
+//!     .build()
+//!     .expect("Could not create HTTP listener");
+//!
+//! assert!(HttpProxyConfigurationBuilder::new().build().is_err());
+//! ```
+//!
 //! Then create a pair of channels to communicate with the proxy.
 //! The channel is a wrapper around a unix socket.
 //!
@@ -323,6 +354,8 @@ pub mod metrics;
 mod logs;
 
 pub mod backends;
+pub mod cidr;
+pub mod dns;
 pub mod features;
 pub mod http;
 pub mod load_balancing;
@@ -360,7 +393,8 @@ use time::{Duration, Instant};
 use tls::CertificateResolverError;
 
 use sozu_command::{
-    proto::command::{Cluster, ListenerType, RequestHttpFrontend},
+    config::ConfigError,
+    proto::command::{AccessLogFormat, Cluster, ListenerType, RequestHttpFrontend, SessionInfo},
     ready::Ready,
     request::WorkerRequest,
     response::WorkerResponse,
@@ -368,7 +402,11 @@ use sozu_command::{
     ObjectKind,
 };
 
-use crate::{backends::BackendMap, router::Route};
+use crate::{
+    backends::BackendMap,
+    cidr::{CidrError, IpFilter},
+    router::{Route, RouteOptions},
+};
 
 /// Anything that can be registered in mio (subscribe to kernel events)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -410,6 +448,14 @@ pub trait ProxySession {
     fn print_session(&self);
     /// get the token associated with the frontend
     fn frontend_token(&self) -> Token;
+    /// get the token of the listener that accepted the session, if it drains
+    /// its sessions when it is deactivated
+    fn listener_token(&self) -> Option<Token> {
+        None
+    }
+    /// complete the snapshot of the session returned by the sessions query,
+    /// the token, protocol and age of the last event are already set
+    fn fill_session_info(&self, _info: &mut SessionInfo) {}
     /// tell the session it has to shut down if possible
     ///
     /// if the session handles HTTP requests, it will not close until the response
@@ -516,6 +562,7 @@ macro_rules! StateMachineBuilder {
                     _fn_impl!{timeout(&mut, self, token: Token, metrics: &mut SessionMetrics) -> StateResult}
                     _fn_impl!{cancel_timeouts(&mut, self)}
                     _fn_impl!{print_state(&, self, context: &str) | marker => error!("{} Session(FailedUpgrade({:?}))", context, marker)}
+                    _fn_impl!{fill_session_info(&, self, info: &mut sozu_command::proto::command::SessionInfo) | _ => {}}
                     _fn_impl!{close(&mut, self, proxy: Rc<RefCell<dyn L7Proxy>>, metrics: &mut SessionMetrics) | _ => {}}
                     _fn_impl!{shutting_down(&mut, self) -> SessionIsToBeClosed | _ => true}
                 }
@@ -552,6 +599,14 @@ pub trait ListenerHandler {
     }
 
     fn set_tags(&mut self, key: String, tags: Option<BTreeMap<String, String>>);
+
+    /// sizes of the send and receive buffers of the frontend and backend sockets
+    fn get_socket_buffer_sizes(&self) -> (Option<u32>, Option<u32>);
+
+    /// format of the access logs of the sessions of this listener
+    fn get_access_logs_format(&self) -> AccessLogFormat {
+        AccessLogFormat::Default
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -569,13 +624,86 @@ pub trait L7ListenerHandler {
 
     fn get_connect_timeout(&self) -> u32;
 
+    /// tokens of the Connection header whose named headers must be forwarded
+    fn get_preserved_connection_tokens(&self) -> &[String];
+
+    /// wether to check the handshake headers of websocket upgrade responses
+    fn get_validate_websocket_handshake(&self) -> bool;
+
+    /// headers to remove in addition to the standard hop-by-hop headers
+    fn get_hop_by_hop_headers(&self) -> &[String];
+
+    /// maximum size of a decoded chunked request body
+    fn get_max_chunked_body_size(&self) -> Option<u64>;
+
+    /// maximum length of the chunk header lines of the requests
+    fn get_max_chunk_header_length(&self) -> u32;
+
+    /// maximum size declared by a chunk header of the requests
+    fn get_max_chunk_size(&self) -> u64;
+
+    /// maximum number of bytes read from a client socket at once
+    fn get_max_front_read_size(&self) -> Option<u32>;
+
+    /// minimum rate, in bytes per second, of the request headers sent by the clients
+    fn get_min_request_header_rate(&self) -> Option<u32>;
+
+    /// number of buffered pipelined requests at which reading from the clients stops
+    fn get_max_pipelined_requests(&self) -> Option<u32>;
+
+    /// path answered with a 200 by the listener, without going to a cluster
+    fn get_health_check_path(&self) -> Option<&str>;
+
+    /// maximum length of the request URIs, answered with a 414 beyond it
+    fn get_max_uri_length(&self) -> u32;
+
+    /// maximum size of the response heads, answered with a 502 beyond it
+    fn get_max_response_header_size(&self) -> Option<u32>;
+
+    /// host routing the HTTP/1.0 requests without a Host header
+    fn get_http10_default_host(&self) -> Option<&str>;
+
+    /// buffered response sizes at which reading from the backend stops and resumes
+    fn get_back_read_watermarks(&self) -> (Option<u32>, Option<u32>);
+
+    /// write the access log of one successful request out of this many
+    fn get_access_logs_sampling(&self) -> Option<u32>;
+
+    /// maximum size of a response body delimited by the closing of the backend connection
+    fn get_max_close_delimited_response_size(&self) -> Option<u64>;
+
+    /// headers whose value is written as REDACTED in the access logs
+    fn get_access_logs_redacted_headers(&self) -> &[String];
+
+    /// wether to write the query string of the paths as REDACTED in the access logs
+    fn get_access_logs_redact_query(&self) -> bool;
+
+    /// wether to answer a 421 to a request for this hostname, because the certificate
+    /// served for the server name of its TLS connection does not cover it
+    fn is_misdirected(&self, hostname: &str, server_name: &str) -> bool;
+
+    /// wether to neither add nor forward the Forwarded header
+    fn get_omit_forwarded_header(&self) -> bool;
+
+    /// wether to write the client address without its port in the Forwarded header
+    fn get_omit_forwarded_client_port(&self) -> bool;
+
+    /// wether to add a W3C Trace Context "traceparent" header to the requests without a valid one
+    fn get_trace_context(&self) -> bool;
+
+    /// wether to forward the requests with an absolute URI in origin-form
+    fn get_rewrite_absolute_form(&self) -> bool;
+
+    /// wether to forward the canonical reason phrase of the known status codes
+    fn get_normalize_reason_phrase(&self) -> bool;
+
     /// retrieve a frontend by parsing a request's hostname, uri and method
     fn frontend_from_request(
         &self,
         host: &str,
         uri: &str,
         method: &Method,
-    ) -> Result<Route, FrontendFromRequestError>;
+    ) -> Result<(Route, RouteOptions), FrontendFromRequestError>;
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -591,7 +719,7 @@ impl BackendConnectionStatus {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BackendConnectAction {
     New,
     Reuse,
@@ -623,6 +751,10 @@ pub enum RetrieveClusterError {
     NoPath,
     #[error("unauthorized route")]
     UnauthorizedRoute,
+    #[error("the host does not match the server name of the TLS connection")]
+    MisdirectedRequest,
+    #[error("none of the websocket subprotocols {0} is allowed by the frontend")]
+    DisallowedWebSocketProtocol(String),
     #[error("{0}")]
     RetrieveFrontend(FrontendFromRequestError),
 }
@@ -649,6 +781,8 @@ pub enum ListenerError {
     PemParse(String),
     #[error("failed to build rustls context, {0}")]
     BuildRustls(String),
+    #[error("invalid TLS versions, {0}")]
+    TlsVersions(ConfigError),
     #[error("Wrong socket address")]
     SocketParse { address: String, error: String },
     #[error("could not activate listener with address {address}: {error}")]
@@ -704,6 +838,20 @@ pub enum ProxyError {
     SocketParse { address: String, error: String },
     #[error("wrong certificate fingerprint: {0}")]
     WrongCertificateFingerprint(String),
+    #[error("could not reload the default answers: {0}")]
+    ReloadAnswers(std::io::Error),
+    #[error("invalid IP filter for cluster {cluster_id}: {error}")]
+    IpFilter {
+        cluster_id: String,
+        error: CidrError,
+    },
+    #[error("invalid HTTP method {0:?}")]
+    InvalidMethod(String),
+    #[error("the server name {hostname} is already routed to the cluster {cluster_id}")]
+    DuplicateServerName {
+        hostname: String,
+        cluster_id: ClusterId,
+    },
     #[error("this request is not supported by the proxy")]
     UnsupportedMessage,
 }
@@ -712,6 +860,12 @@ use self::server::ListenToken;
 pub trait ProxyConfiguration {
     fn notify(&mut self, message: WorkerRequest) -> WorkerResponse;
     fn accept(&mut self, token: ListenToken) -> Result<TcpStream, AcceptError>;
+    /// Stop polling the listen socket for new connections, they wait in the kernel backlog
+    fn pause_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error>;
+    /// Poll the listen socket for new connections again
+    fn resume_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error>;
+    /// Maximum number of sessions open at once on this listener, if it has one
+    fn max_sessions(&self, token: ListenToken) -> Option<usize>;
     fn create_session(
         &mut self,
         socket: TcpStream,
@@ -743,6 +897,9 @@ pub trait L7Proxy {
     fn backends(&self) -> Rc<RefCell<BackendMap>>;
 
     fn clusters(&self) -> &HashMap<ClusterId, Cluster>;
+
+    /// CIDR allow and deny lists of a cluster, if it has any
+    fn ip_filter(&self, cluster_id: &str) -> Option<&IpFilter>;
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -951,6 +1108,8 @@ pub struct SessionMetrics {
     pub backend_id: Option<String>,
     pub backend_start: Option<Instant>,
     pub backend_connected: Option<Instant>,
+    /// date at which the first bytes of the response were read from the backend
+    pub backend_first_byte: Option<Instant>,
     pub backend_stop: Option<Instant>,
     pub backend_bin: usize,
     pub backend_bout: usize,
@@ -969,6 +1128,7 @@ impl SessionMetrics {
             backend_id: None,
             backend_start: None,
             backend_connected: None,
+            backend_first_byte: None,
             backend_stop: None,
             backend_bin: 0,
             backend_bout: 0,
@@ -984,6 +1144,7 @@ impl SessionMetrics {
         self.service_start = None;
         self.backend_start = None;
         self.backend_connected = None;
+        self.backend_first_byte = None;
         self.backend_stop = None;
         self.backend_bin = 0;
         self.backend_bout = 0;
@@ -1036,6 +1197,13 @@ impl SessionMetrics {
         self.backend_connected = Some(Instant::now());
     }
 
+    /// only the first call after a reset is recorded
+    pub fn backend_first_byte(&mut self) {
+        if self.backend_first_byte.is_none() {
+            self.backend_first_byte = Some(Instant::now());
+        }
+    }
+
     pub fn backend_stop(&mut self) {
         self.backend_stop = Some(Instant::now());
     }
@@ -1054,6 +1222,14 @@ impl SessionMetrics {
             _ => None,
         }
     }
+
+    /// time between the start of the request and the first bytes of the response
+    pub fn time_to_first_byte(&self) -> Option<Duration> {
+        match (self.start, self.backend_first_byte) {
+            (Some(start), Some(end)) => Some(end - start),
+            _ => None,
+        }
+    }
 }
 
 /// exponentially weighted moving average with high sensibility to latency bursts
@@ -8,6 +8,10 @@ use rand::{
 
 use crate::{backends::Backend, sozu_command::proto::command::LoadMetric};
 
+/// Selects the backend of a cluster that gets the next connection, among the ones
+/// that can accept it. The built-in policies implement it, applications embedding
+/// sozu as a library can plug their own in with
+/// `BackendMap::set_custom_load_balancing_for_cluster`
 pub trait LoadBalancingAlgorithm: Debug {
     fn next_available_backend(
         &mut self,
@@ -193,6 +197,11 @@ mod test {
             load_balancing_parameters: None,
             backup: false,
             connection_time: PeakEWMA::new(),
+            consecutive_5xx: 0,
+            ejected_until: None,
+            hostname: None,
+            resolved_addresses: Vec::new(),
+            next_resolved_address: 0,
         }
     }
 
@@ -1,9 +1,11 @@
-use std::{fmt, net::SocketAddr};
+use std::{borrow::Cow, fmt, net::SocketAddr};
 
+use rand::Rng;
 use rusty_ulid::Ulid;
+use sozu_command::proto::command::AccessLogFormat;
 use time::Duration;
 
-use crate::{protocol::http::parser::Method, SessionMetrics};
+use crate::{protocol::http::parser::Method, BackendConnectAction, SessionMetrics};
 
 pub struct LogContext<'a> {
     pub request_id: Ulid,
@@ -92,12 +94,61 @@ impl fmt::Display for LogDuration {
     }
 }
 
+/// Breakdown of the time spent on a request: until the first bytes of the
+/// response, connecting to the backend, and in total
+pub struct LogTimings {
+    pub time_to_first_byte: Option<Duration>,
+    pub backend_connection_time: Option<Duration>,
+    pub total: Duration,
+}
+
+impl LogTimings {
+    pub fn from_metrics(metrics: &SessionMetrics) -> Self {
+        LogTimings {
+            time_to_first_byte: metrics.time_to_first_byte(),
+            backend_connection_time: metrics.backend_connection_time(),
+            total: metrics.response_time(),
+        }
+    }
+}
+
+impl fmt::Display for LogTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ttfb={} connect={} total={}",
+            LogDuration(self.time_to_first_byte),
+            LogDuration(self.backend_connection_time),
+            LogDuration(Some(self.total))
+        )
+    }
+}
+
+/// How the backend connection of a request was obtained: "new" if it was
+/// opened for it, "reused" if it was kept alive from a previous request,
+/// "replaced" if it replaced the connection to another backend
+pub struct LogBackendConnection(pub Option<BackendConnectAction>);
+
+impl fmt::Display for LogBackendConnection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let connection = match self.0 {
+            None => "-",
+            Some(BackendConnectAction::New) => "new",
+            Some(BackendConnectAction::Reuse) => "reused",
+            Some(BackendConnectAction::Replace) => "replaced",
+        };
+        write!(f, "backend={connection}")
+    }
+}
+
 pub enum Endpoint<'a> {
     Http {
         method: Option<&'a Method>,
         authority: Option<&'a str>,
         path: Option<&'a str>,
         status: Option<u16>,
+        /// written after the status: the reason phrase of a default answer,
+        /// or a non-standard one sent by the backend
         reason: Option<&'a str>,
     },
     Tcp {
@@ -113,20 +164,45 @@ impl fmt::Display for Endpoint<'_> {
                 method,
                 path,
                 status,
-                ..
-            } => write!(
-                f,
-                "{} {} {} -> {}",
-                authority.as_str_or("-"),
-                method.as_str_or("-"),
-                path.as_str_or("-"),
-                status.as_str_or("-"),
-            ),
+                reason,
+            } => {
+                write!(
+                    f,
+                    "{} {} {} -> {}",
+                    authority.as_str_or("-"),
+                    method.as_str_or("-"),
+                    path.as_str_or("-"),
+                    status.as_str_or("-"),
+                )?;
+                match reason {
+                    Some(reason) => write!(f, " {reason}"),
+                    None => Ok(()),
+                }
+            }
             Endpoint::Tcp { context } => write!(f, "{}", context.as_str_or("-")),
         }
     }
 }
 
+/// The tags of the frontend followed by the user agent of the request,
+/// or "-" if there are neither
+pub struct LogTags<'a> {
+    pub tags: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+impl fmt::Display for LogTags<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.tags, self.user_agent) {
+            (Some(tags), Some(user_agent)) if !tags.is_empty() => {
+                write!(f, "{tags}, user-agent={user_agent}")
+            }
+            (_, Some(user_agent)) => write!(f, "user-agent={user_agent}"),
+            (tags, None) => write!(f, "{}", tags.as_str_or("-")),
+        }
+    }
+}
+
 pub struct RequestRecord<'a> {
     pub error: Option<&'a str>,
     pub context: LogContext<'a>,
@@ -137,32 +213,33 @@ pub struct RequestRecord<'a> {
     pub tags: Option<&'a str>,
     pub client_rtt: Option<Duration>,
     pub server_rtt: Option<Duration>,
+    /// whether the backend connection was kept alive from a previous request
+    pub backend_connection: Option<BackendConnectAction>,
     pub metrics: &'a SessionMetrics,
     pub user_agent: Option<&'a str>,
+    pub format: AccessLogFormat,
 }
 
 impl RequestRecord<'_> {
-    pub fn log(&self) {
-        let context = &self.context;
-        let cluster_id = context.cluster_id;
-        let tags = self.tags;
-
-        let protocol = self.protocol;
-        let session_address = self.session_address;
-        let backend_address = self.backend_address;
-        let endpoint = &self.endpoint;
-        let user_agent = &self.user_agent;
+    /// Write the access log only for the requests picked by the sampling
+    /// of the listener, but always record the metrics
+    pub fn log_sampled(&self, sampling: Option<u32>) {
+        let status = match self.endpoint {
+            Endpoint::Http { status, .. } => status,
+            Endpoint::Tcp { .. } => None,
+        };
+        if is_sampled(sampling, self.error.is_some(), status) {
+            self.log();
+        } else {
+            self.record_metrics();
+        }
+    }
 
+    fn record_metrics(&self) {
+        let cluster_id = self.context.cluster_id;
         let metrics = self.metrics;
-        // let backend_response_time = metrics.backend_response_time();
-        // let backend_connection_time = metrics.backend_connection_time();
-        // let backend_bin = metrics.backend_bin;
-        // let backend_bout = metrics.backend_bout;
         let response_time = metrics.response_time();
         let service_time = metrics.service_time();
-        // let wait_time = metrics.wait_time;
-        let client_rtt = self.client_rtt;
-        let server_rtt = self.server_rtt;
 
         if let Some(cluster_id) = cluster_id {
             time!(
@@ -191,11 +268,52 @@ impl RequestRecord<'_> {
                 );
             }
         }
+    }
+
+    pub fn log(&self) {
+        let context = &self.context;
+        let tags = self.tags;
+
+        let protocol = self.protocol;
+        let session_address = self.session_address;
+        let backend_address = self.backend_address;
+        let endpoint = &self.endpoint;
+        let user_agent = self.user_agent;
+
+        let metrics = self.metrics;
+        // let backend_response_time = metrics.backend_response_time();
+        // let backend_connection_time = metrics.backend_connection_time();
+        // let backend_bin = metrics.backend_bin;
+        // let backend_bout = metrics.backend_bout;
+        let response_time = metrics.response_time();
+        let service_time = metrics.service_time();
+        // let wait_time = metrics.wait_time;
+        let client_rtt = self.client_rtt;
+        let server_rtt = self.server_rtt;
+        let timings = LogTimings::from_metrics(metrics);
+        let backend_connection = LogBackendConnection(self.backend_connection);
+
+        self.record_metrics();
+
+        if self.format == AccessLogFormat::Rfc5424 {
+            match self.error {
+                None => {
+                    info_access!("{}", StructuredData(self));
+                    incr!(
+                        "access_logs.count",
+                        self.context.cluster_id,
+                        self.context.backend_id
+                    );
+                }
+                Some(_) => error_access!("{}", StructuredData(self)),
+            }
+            return;
+        }
 
         match self.error {
             None => {
                 info_access!(
-                    "{}{} -> {} \t{}/{}/{}/{} \t{} -> {} \t {}{} {} {}",
+                    "{}{} -> {} \t{}/{}/{}/{} \t{} {} \t{} -> {} \t {} {} {}",
                     context,
                     session_address.as_str_or("X"),
                     backend_address.as_str_or("X"),
@@ -203,20 +321,11 @@ impl RequestRecord<'_> {
                     LogDuration(Some(service_time)),
                     LogDuration(client_rtt),
                     LogDuration(server_rtt),
+                    timings,
+                    backend_connection,
                     metrics.bin,
                     metrics.bout,
-                    match user_agent {
-                        Some(_) => tags.as_str_or(""),
-                        None => tags.as_str_or("-"),
-                    },
-                    match tags {
-                        Some(tags) if !tags.is_empty() => user_agent
-                            .map(|ua| format!(", user-agent={ua}"))
-                            .unwrap_or_default(),
-                        Some(_) | None => user_agent
-                            .map(|ua| format!("user-agent={ua}"))
-                            .unwrap_or_default(),
-                    },
+                    LogTags { tags, user_agent },
                     protocol,
                     endpoint
                 );
@@ -227,7 +336,7 @@ impl RequestRecord<'_> {
                 );
             }
             Some(message) => error_access!(
-                "{}{} -> {} \t{}/{}/{}/{} \t{} -> {} \t {}{} {} {} | {}",
+                "{}{} -> {} \t{}/{}/{}/{} \t{} {} \t{} -> {} \t {} {} {} | {}",
                 context,
                 session_address.as_str_or("X"),
                 backend_address.as_str_or("X"),
@@ -235,20 +344,11 @@ impl RequestRecord<'_> {
                 LogDuration(Some(service_time)),
                 LogDuration(client_rtt),
                 LogDuration(server_rtt),
+                timings,
+                backend_connection,
                 metrics.bin,
                 metrics.bout,
-                match user_agent {
-                    Some(_) => tags.as_str_or(""),
-                    None => tags.as_str_or("-"),
-                },
-                match tags {
-                    Some(tags) if !tags.is_empty() => user_agent
-                        .map(|ua| format!(", user-agent={ua}"))
-                        .unwrap_or_default(),
-                    Some(_) | None => user_agent
-                        .map(|ua| format!("user-agent={ua}"))
-                        .unwrap_or_default(),
-                },
+                LogTags { tags, user_agent },
                 protocol,
                 endpoint,
                 message
@@ -256,3 +356,363 @@ impl RequestRecord<'_> {
         }
     }
 }
+
+/// SD-ID of the structured data of the access logs. 32473 is the private
+/// enterprise number reserved for documentation by RFC 5612
+pub const STRUCTURED_DATA_ID: &str = "sozu@32473";
+
+/// An access log as a RFC 5424 structured data element:
+/// `[sozu@32473 request_id="..." cluster_id="..." status="..." ...]`.
+/// The parameters without a value are left out
+pub struct StructuredData<'a>(pub &'a RequestRecord<'a>);
+
+impl fmt::Display for StructuredData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let record = self.0;
+        let metrics = record.metrics;
+
+        write!(f, "[{STRUCTURED_DATA_ID}")?;
+        write_param(f, "request_id", Some(record.context.request_id))?;
+        write_param(f, "cluster_id", record.context.cluster_id)?;
+        write_param(f, "backend_id", record.context.backend_id)?;
+        write_param(f, "session_address", record.session_address)?;
+        write_param(f, "backend_address", record.backend_address)?;
+        write_param(f, "protocol", Some(record.protocol))?;
+        match &record.endpoint {
+            Endpoint::Http {
+                method,
+                authority,
+                path,
+                status,
+                reason,
+            } => {
+                write_param(f, "method", *method)?;
+                write_param(f, "authority", *authority)?;
+                write_param(f, "path", *path)?;
+                write_param(f, "status", *status)?;
+                write_param(f, "reason", *reason)?;
+            }
+            Endpoint::Tcp { context } => write_param(f, "context", *context)?,
+        }
+        write_param(
+            f,
+            "response_time",
+            Some(LogDuration(Some(metrics.response_time()))),
+        )?;
+        write_param(
+            f,
+            "service_time",
+            Some(LogDuration(Some(metrics.service_time()))),
+        )?;
+        write_param(
+            f,
+            "client_rtt",
+            record.client_rtt.map(|rtt| LogDuration(Some(rtt))),
+        )?;
+        write_param(
+            f,
+            "server_rtt",
+            record.server_rtt.map(|rtt| LogDuration(Some(rtt))),
+        )?;
+        write_param(f, "bytes_in", Some(metrics.bin))?;
+        write_param(f, "bytes_out", Some(metrics.bout))?;
+        write_param(f, "tags", record.tags)?;
+        write_param(f, "user_agent", record.user_agent)?;
+        write_param(f, "error", record.error)?;
+        write!(f, "]")
+    }
+}
+
+/// Write ` name="value"`, with the characters RFC 5424 reserves in
+/// parameter values (`"`, `\` and `]`) escaped with a backslash
+fn write_param<T: fmt::Display>(
+    f: &mut fmt::Formatter,
+    name: &str,
+    value: Option<T>,
+) -> fmt::Result {
+    let Some(value) = value else {
+        return Ok(());
+    };
+    write!(f, " {name}=\"")?;
+    for c in value.to_string().chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            write!(f, "\\")?;
+        }
+        write!(f, "{c}")?;
+    }
+    write!(f, "\"")
+}
+
+/// Appends the HTTP version negotiated with ALPN to the transport protocol,
+/// like "HTTPS-TLS1.3-HTTP/1.1", to tell HTTP/1.1 and HTTP/2 apart in the logs
+pub fn protocol_with_alpn(transport: &'static str, alpn: Option<&str>) -> Cow<'static, str> {
+    match alpn {
+        None => Cow::Borrowed(transport),
+        Some(alpn) => {
+            let version = match alpn {
+                "http/1.1" => "HTTP/1.1",
+                "h2" => "HTTP/2",
+                other => other,
+            };
+            Cow::Owned(format!("{transport}-{version}"))
+        }
+    }
+}
+
+/// written in the access logs in place of the values that must not appear in them
+pub const REDACTED: &str = "REDACTED";
+
+/// The value of the header `name` as it should appear in the access logs:
+/// `REDACTED` if the name is in the `redacted` list, the value itself otherwise
+pub fn redact_header<'a>(
+    name: &str,
+    value: Option<&'a str>,
+    redacted: &[String],
+) -> Option<&'a str> {
+    match value {
+        Some(_)
+            if redacted
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case(name)) =>
+        {
+            Some(REDACTED)
+        }
+        value => value,
+    }
+}
+
+/// Replace the query string of a path with `REDACTED`, "/login?token=secret"
+/// is logged as "/login?REDACTED"
+pub fn redact_query(path: &str) -> Cow<str> {
+    match path.split_once('?') {
+        Some((path, _query)) => Cow::Owned(format!("{path}?{REDACTED}")),
+        None => Cow::Borrowed(path),
+    }
+}
+
+/// Successful requests are logged once out of `rate`,
+/// errors and answers with a status other than 2xx always are
+fn is_sampled(rate: Option<u32>, error: bool, status: Option<u16>) -> bool {
+    match (rate, status) {
+        (Some(rate), Some(200..=299)) if rate > 1 && !error => {
+            rand::thread_rng().gen_ratio(1, rate)
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Instant;
+
+    use super::*;
+    use crate::protocol::http::DefaultAnswerStatus;
+
+    #[test]
+    fn protocol_reflects_alpn() {
+        assert_eq!(protocol_with_alpn("HTTP", None), "HTTP");
+        assert_eq!(protocol_with_alpn("HTTPS-TLS1.3", None), "HTTPS-TLS1.3");
+        assert_eq!(
+            protocol_with_alpn("HTTPS-TLS1.3", Some("http/1.1")),
+            "HTTPS-TLS1.3-HTTP/1.1"
+        );
+        assert_eq!(
+            protocol_with_alpn("HTTPS-TLS1.2", Some("h2")),
+            "HTTPS-TLS1.2-HTTP/2"
+        );
+        assert_eq!(
+            protocol_with_alpn("WSS-TLS1.3", Some("http/1.1")),
+            "WSS-TLS1.3-HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn default_answer_endpoint() {
+        let answer = DefaultAnswerStatus::Answer425;
+        let endpoint = Endpoint::Http {
+            method: Some(&Method::Post),
+            authority: Some("localhost"),
+            path: Some("/api"),
+            status: Some(answer.into()),
+            reason: Some(answer.reason()),
+        };
+        assert_eq!(endpoint.to_string(), "localhost POST /api -> 425 Too Early");
+
+        let endpoint = Endpoint::Http {
+            method: None,
+            authority: None,
+            path: None,
+            status: None,
+            reason: None,
+        };
+        assert_eq!(endpoint.to_string(), "- - - -> -");
+    }
+
+    #[test]
+    fn redacted_headers_and_query() {
+        let redacted = vec!["authorization".to_owned(), "user-agent".to_owned()];
+        let user_agent = Some("curl/8.0.1");
+
+        assert_eq!(
+            LogTags {
+                tags: Some("owner=admin"),
+                user_agent: redact_header("User-Agent", user_agent, &redacted),
+            }
+            .to_string(),
+            "owner=admin, user-agent=REDACTED"
+        );
+        assert_eq!(
+            LogTags {
+                tags: None,
+                user_agent: redact_header("User-Agent", user_agent, &[]),
+            }
+            .to_string(),
+            "user-agent=curl/8.0.1"
+        );
+        assert_eq!(
+            redact_header("Authorization", Some("Basic c296dTpzb3p1"), &redacted),
+            Some(REDACTED)
+        );
+        assert_eq!(redact_header("Authorization", None, &redacted), None);
+
+        let path = redact_query("/login?user=admin&token=secret");
+        let endpoint = Endpoint::Http {
+            method: Some(&Method::Get),
+            authority: Some("localhost"),
+            path: Some(&path),
+            status: Some(200),
+            reason: Some("OK"),
+        };
+        assert_eq!(
+            endpoint.to_string(),
+            "localhost GET /login?REDACTED -> 200 OK"
+        );
+        assert_eq!(redact_query("/login"), "/login");
+    }
+
+    #[test]
+    fn request_timings() {
+        let now = Instant::now();
+        let mut metrics = SessionMetrics::new(None);
+        metrics.start = Some(now - Duration::milliseconds(300));
+        metrics.backend_start = Some(now - Duration::milliseconds(250));
+        metrics.backend_connected = Some(now - Duration::milliseconds(200));
+        metrics.backend_first_byte = Some(now - Duration::milliseconds(50));
+
+        let timings = LogTimings::from_metrics(&metrics);
+        let time_to_first_byte = timings.time_to_first_byte.expect("no time to first byte");
+        let backend_connection_time = timings
+            .backend_connection_time
+            .expect("no backend connection time");
+        assert_eq!(backend_connection_time, Duration::milliseconds(50));
+        assert_eq!(time_to_first_byte, Duration::milliseconds(250));
+        assert!(timings.total >= Duration::milliseconds(300));
+        assert!(backend_connection_time < time_to_first_byte);
+        assert!(time_to_first_byte < timings.total);
+
+        let logged = timings.to_string();
+        assert!(logged.starts_with("ttfb=250ms connect=50ms total="));
+
+        // a default answer never reaches a backend
+        let timings = LogTimings::from_metrics(&SessionMetrics::new(None));
+        assert!(timings.to_string().starts_with("ttfb=- connect=- total="));
+    }
+
+    #[test]
+    fn reused_backend_connection() {
+        assert_eq!(
+            LogBackendConnection(Some(BackendConnectAction::New)).to_string(),
+            "backend=new"
+        );
+        // the second request of a kept alive session goes through the same connection
+        assert_eq!(
+            LogBackendConnection(Some(BackendConnectAction::Reuse)).to_string(),
+            "backend=reused"
+        );
+        assert_eq!(
+            LogBackendConnection(Some(BackendConnectAction::Replace)).to_string(),
+            "backend=replaced"
+        );
+        // a default answer never reaches a backend
+        assert_eq!(LogBackendConnection(None).to_string(), "backend=-");
+    }
+
+    #[test]
+    fn structured_data_access_log() {
+        let request_id = Ulid::generate();
+        let metrics = SessionMetrics::new(None);
+        let record = RequestRecord {
+            error: None,
+            context: LogContext {
+                request_id,
+                cluster_id: Some("cluster_1"),
+                backend_id: None,
+            },
+            session_address: Some("127.0.0.1:54321".parse().unwrap()),
+            backend_address: None,
+            protocol: "HTTP",
+            endpoint: Endpoint::Http {
+                method: Some(&Method::Get),
+                authority: Some("localhost"),
+                path: Some("/search?q=\"a]\\b\""),
+                status: Some(200),
+                reason: Some("OK"),
+            },
+            tags: None,
+            client_rtt: None,
+            server_rtt: None,
+            backend_connection: None,
+            metrics: &metrics,
+            user_agent: Some("curl/8.0.1"),
+            format: AccessLogFormat::Rfc5424,
+        };
+        let logged = StructuredData(&record).to_string();
+
+        assert!(logged.starts_with(&format!(
+            "[sozu@32473 request_id=\"{request_id}\" cluster_id=\"cluster_1\" \
+             session_address=\"127.0.0.1:54321\" protocol=\"HTTP\" method=\"GET\" \
+             authority=\"localhost\" path=\"/search?q=\\\"a\\]\\\\b\\\"\" status=\"200\" reason=\"OK\" "
+        )));
+        assert!(logged.ends_with(" bytes_in=\"0\" bytes_out=\"0\" user_agent=\"curl/8.0.1\"]"));
+        // absent values are left out
+        assert!(!logged.contains("backend_id="));
+        assert!(!logged.contains("error="));
+
+        // the element ends at the only unescaped ']', and every value is quoted
+        let mut escaped = false;
+        let mut quoted = false;
+        let mut end = None;
+        for (i, c) in logged.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = !quoted,
+                ']' => {
+                    assert!(end.is_none(), "unescaped ']' in {logged}");
+                    end = Some(i);
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(end, Some(logged.len() - 1));
+        assert!(!quoted);
+    }
+
+    #[test]
+    fn access_logs_sampling() {
+        let requests = 100_000;
+        let logged = (0..requests)
+            .filter(|_| is_sampled(Some(10), false, Some(200)))
+            .count();
+        assert!(
+            (9_000..11_000).contains(&logged),
+            "{logged} successful requests out of {requests} were logged"
+        );
+
+        assert!((0..1000).all(|_| is_sampled(Some(10), true, Some(200))));
+        assert!((0..1000).all(|_| is_sampled(Some(10), false, Some(503))));
+        assert!((0..1000).all(|_| is_sampled(Some(10), false, None)));
+        assert!((0..1000).all(|_| is_sampled(None, false, Some(200))));
+        assert!((0..1000).all(|_| is_sampled(Some(1), false, Some(200))));
+    }
+}
@@ -49,13 +49,11 @@ pub enum MetricValue {
     GaugeAdd(i64),
     Count(i64),
     Time(usize),
+    /// distribution of values that are not durations, like counts per connection
+    Histogram(usize),
 }
 
 impl MetricValue {
-    fn is_time(&self) -> bool {
-        matches!(self, &MetricValue::Time(_))
-    }
-
     fn update(&mut self, key: &'static str, m: MetricValue) -> bool {
         match (self, m) {
             (&mut MetricValue::Gauge(ref mut v1), MetricValue::Gauge(v2)) => {
@@ -314,7 +312,17 @@ macro_rules! count (
     $crate::metrics::METRICS.with(|metrics| {
       (*metrics.borrow_mut()).count_add($key, v);
     });
-  })
+  });
+  ($key:expr, $value:expr, $cluster_id:expr, $backend_id:expr) => {
+    {
+        use $crate::metrics::Subscriber;
+        let v = $value;
+
+        $crate::metrics::METRICS.with(|metrics| {
+          (*metrics.borrow_mut()).receive_metric($key, $cluster_id, $backend_id, $crate::metrics::MetricValue::Count(v));
+        });
+    }
+  }
 );
 
 /// adds 1 to a counter
@@ -390,6 +398,30 @@ macro_rules! time (
   })
 );
 
+/// records a value that is not a duration in a histogram
+#[macro_export]
+macro_rules! histogram (
+  ($key:expr, $value: expr) => ({
+    use $crate::metrics::{MetricValue,Subscriber};
+    let v = $value;
+    $crate::metrics::METRICS.with(|metrics| {
+      let m = &mut *metrics.borrow_mut();
+
+      m.receive_metric($key, None, None, MetricValue::Histogram(v as usize));
+    });
+  });
+  ($key:expr, $cluster_id:expr, $value: expr) => ({
+    use $crate::metrics::{MetricValue,Subscriber};
+    let v = $value;
+    $crate::metrics::METRICS.with(|metrics| {
+      let m = &mut *metrics.borrow_mut();
+      let cluster: &str = $cluster_id;
+
+      m.receive_metric($key, Some(cluster), None, MetricValue::Histogram(v as usize));
+    });
+  })
+);
+
 #[macro_export]
 macro_rules! record_backend_metrics (
   ($cluster_id:expr, $backend_id:expr, $response_time: expr, $backend_connection_time: expr, $bin: expr, $bout: expr) => {
@@ -20,8 +20,10 @@ pub struct MetricLine {
     label: &'static str,
     cluster_id: Option<String>,
     backend_id: Option<String>,
-    /// in milliseconds
-    duration: usize,
+    /// in milliseconds for the durations
+    value: usize,
+    /// statsd type of the metric: "ms" for the durations, "h" for the other histograms
+    metric_type: &'static str,
 }
 
 /// gathers metrics and send them on a UDP socket
@@ -338,49 +340,66 @@ impl NetworkDrain {
                     (Some(cluster_id), Some(backend_id)) => {
                         if self.use_tagged_metrics {
                             self.remote.write_fmt(format_args!(
-                                "{}.backend.{},origin={},version={},cluster_id={},backend_id={}:{}|ms\n",
-                                self.prefix, metric.label, self.origin, VERSION, cluster_id, backend_id, metric.duration
+                                "{}.backend.{},origin={},version={},cluster_id={},backend_id={}:{}|{}\n",
+                                self.prefix, metric.label, self.origin, VERSION, cluster_id, backend_id, metric.value,
+                                metric.metric_type
                             ))
                         } else {
                             self.remote.write_fmt(format_args!(
-                                "{}.{}.cluster.{}.backend.{}.{}:{}|ms\n",
+                                "{}.{}.cluster.{}.backend.{}.{}:{}|{}\n",
                                 self.prefix,
                                 self.origin,
                                 cluster_id,
                                 backend_id,
                                 metric.label,
-                                metric.duration
+                                metric.value,
+                                metric.metric_type
                             ))
                         }
                     }
                     (Some(cluster_id), None) => {
                         if self.use_tagged_metrics {
                             self.remote.write_fmt(format_args!(
-                                "{}.cluster.{},origin={},version={},cluster_id={}:{}|ms\n",
+                                "{}.cluster.{},origin={},version={},cluster_id={}:{}|{}\n",
                                 self.prefix,
                                 metric.label,
                                 self.origin,
                                 VERSION,
                                 cluster_id,
-                                metric.duration
+                                metric.value,
+                                metric.metric_type
                             ))
                         } else {
                             self.remote.write_fmt(format_args!(
-                                "{}.{}.cluster.{}.{}:{}|ms\n",
-                                self.prefix, self.origin, cluster_id, metric.label, metric.duration
+                                "{}.{}.cluster.{}.{}:{}|{}\n",
+                                self.prefix,
+                                self.origin,
+                                cluster_id,
+                                metric.label,
+                                metric.value,
+                                metric.metric_type
                             ))
                         }
                     }
                     (None, None) => {
                         if self.use_tagged_metrics {
                             self.remote.write_fmt(format_args!(
-                                "{}.{},origin={},version={}:{}|ms\n",
-                                self.prefix, metric.label, self.origin, VERSION, metric.duration
+                                "{}.{},origin={},version={}:{}|{}\n",
+                                self.prefix,
+                                metric.label,
+                                self.origin,
+                                VERSION,
+                                metric.value,
+                                metric.metric_type
                             ))
                         } else {
                             self.remote.write_fmt(format_args!(
-                                "{}.{}.{}:{}|ms\n",
-                                self.prefix, self.origin, metric.label, metric.duration
+                                "{}.{}.{}:{}|{}\n",
+                                self.prefix,
+                                self.origin,
+                                metric.label,
+                                metric.value,
+                                metric.metric_type
                             ))
                         }
                     }
@@ -431,15 +450,19 @@ impl Subscriber for NetworkDrain {
         backend_id: Option<&str>,
         metric: MetricValue,
     ) {
-        if metric.is_time() {
-            if let MetricValue::Time(millis) = metric {
-                self.queue.push_back(MetricLine {
-                    label: key,
-                    cluster_id: cluster_id.map(|s| s.to_string()),
-                    backend_id: backend_id.map(|s| s.to_string()),
-                    duration: millis,
-                });
-            }
+        let histogram = match metric {
+            MetricValue::Time(millis) => Some((millis, "ms")),
+            MetricValue::Histogram(value) => Some((value, "h")),
+            _ => None,
+        };
+        if let Some((value, metric_type)) = histogram {
+            self.queue.push_back(MetricLine {
+                label: key,
+                cluster_id: cluster_id.map(|s| s.to_string()),
+                backend_id: backend_id.map(|s| s.to_string()),
+                value,
+                metric_type,
+            });
             return;
         }
 
@@ -29,6 +29,11 @@ impl Pool {
         Pool { inner, buffer_size }
     }
 
+    /// whether `count` more buffers can be checked out without exceeding the maximum capacity
+    pub fn can_checkout(&self, count: usize) -> bool {
+        self.inner.used() + count <= self.inner.maximum_capacity()
+    }
+
     pub fn checkout(&mut self) -> Option<Checkout> {
         if self.inner.used() == self.inner.capacity()
             && self.inner.capacity() < self.inner.maximum_capacity()
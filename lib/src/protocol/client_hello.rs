@@ -0,0 +1,212 @@
+//! Reads the server name (SNI) of a TLS ClientHello without terminating TLS,
+//! so that the TCP proxy can route the TLS connections it passes through
+
+use std::str::from_utf8;
+
+use nom::{
+    bytes::streaming::take,
+    combinator::verify,
+    multi::length_data,
+    number::streaming::{be_u16, be_u24, be_u8},
+    Err, IResult,
+};
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const NAME_TYPE_HOST_NAME: u8 = 0;
+
+/// Size of the largest TLS record, a ClientHello spanning more than this is not routed
+pub const MAX_TLS_RECORD_SIZE: usize = 5 + 16384;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientHelloSni {
+    /// more data is needed to read the whole ClientHello
+    Incomplete,
+    /// the ClientHello has no server name extension
+    Missing,
+    /// the data is not a TLS ClientHello
+    Invalid,
+    /// the host name of the server name extension, in lowercase
+    Found(String),
+}
+
+/// one TLS record of the handshake protocol, gives back its fragment
+fn handshake_record(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (i, _) = verify(be_u8, |content_type: &u8| {
+        *content_type == CONTENT_TYPE_HANDSHAKE
+    })(i)?;
+    let (i, _legacy_version) = be_u16(i)?;
+    length_data(be_u16)(i)
+}
+
+/// the ClientHello handshake message, gives back its body
+fn client_hello(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (i, _) = verify(be_u8, |handshake_type: &u8| {
+        *handshake_type == HANDSHAKE_TYPE_CLIENT_HELLO
+    })(i)?;
+    let (i, length) = be_u24(i)?;
+    take(length)(i)
+}
+
+/// the host name of the server name extension of a ClientHello body, if any
+fn server_name(i: &[u8]) -> IResult<&[u8], Option<&[u8]>> {
+    let (i, _legacy_version) = be_u16(i)?;
+    let (i, _random) = take(32usize)(i)?;
+    let (i, _legacy_session_id) = length_data(be_u8)(i)?;
+    let (i, _cipher_suites) = length_data(be_u16)(i)?;
+    let (i, _legacy_compression_methods) = length_data(be_u8)(i)?;
+    // a ClientHello without extensions stops here
+    if i.is_empty() {
+        return Ok((i, None));
+    }
+
+    let (i, mut extensions) = length_data(be_u16)(i)?;
+    while !extensions.is_empty() {
+        let (rest, extension_type) = be_u16(extensions)?;
+        let (rest, extension_data) = length_data(be_u16)(rest)?;
+        extensions = rest;
+        if extension_type != EXTENSION_SERVER_NAME {
+            continue;
+        }
+
+        let (_, mut server_names) = length_data(be_u16)(extension_data)?;
+        while !server_names.is_empty() {
+            let (rest, name_type) = be_u8(server_names)?;
+            let (rest, name) = length_data(be_u16)(rest)?;
+            server_names = rest;
+            if name_type == NAME_TYPE_HOST_NAME {
+                return Ok((i, Some(name)));
+            }
+        }
+    }
+    Ok((i, None))
+}
+
+/// Find the server name of the ClientHello at the beginning of `data`.
+///
+/// The ClientHello may be fragmented over several TLS records: they are reassembled
+/// until the handshake message is complete.
+pub fn parse_sni(data: &[u8]) -> ClientHelloSni {
+    let mut handshake = Vec::new();
+    let mut input = data;
+    loop {
+        match handshake_record(input) {
+            Ok((rest, fragment)) => {
+                handshake.extend_from_slice(fragment);
+                input = rest;
+            }
+            Err(Err::Incomplete(_)) => return ClientHelloSni::Incomplete,
+            Err(_) => return ClientHelloSni::Invalid,
+        }
+
+        let body = match client_hello(&handshake) {
+            Ok((_, body)) => body,
+            Err(Err::Incomplete(_)) => continue,
+            Err(_) => return ClientHelloSni::Invalid,
+        };
+
+        // the body is complete, running out of data inside of it means it is malformed
+        return match server_name(body) {
+            Ok((_, Some(name))) => match from_utf8(name) {
+                Ok(name) => ClientHelloSni::Found(name.to_ascii_lowercase()),
+                Err(_) => ClientHelloSni::Invalid,
+            },
+            Ok((_, None)) => ClientHelloSni::Missing,
+            Err(_) => ClientHelloSni::Invalid,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_length_u16(data: &[u8]) -> Vec<u8> {
+        let mut v = (data.len() as u16).to_be_bytes().to_vec();
+        v.extend_from_slice(data);
+        v
+    }
+
+    /// a ClientHello handshake message, with a server name extension if `server_name` is set
+    fn client_hello_message(server_name: Option<&str>) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x42; 32]);
+        body.push(0);
+        body.extend(with_length_u16(&[0x13, 0x01]));
+        body.extend_from_slice(&[1, 0]);
+
+        let mut extensions = Vec::new();
+        // supported_versions, before the server name to check that it is skipped
+        extensions.extend_from_slice(&[0x00, 0x2b]);
+        extensions.extend(with_length_u16(&[0x02, 0x03, 0x04]));
+        if let Some(server_name) = server_name {
+            let mut server_names = vec![NAME_TYPE_HOST_NAME];
+            server_names.extend(with_length_u16(server_name.as_bytes()));
+            extensions.extend_from_slice(&EXTENSION_SERVER_NAME.to_be_bytes());
+            extensions.extend(with_length_u16(&with_length_u16(&server_names)));
+        }
+        body.extend(with_length_u16(&extensions));
+
+        let mut message = vec![HANDSHAKE_TYPE_CLIENT_HELLO];
+        message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        message.extend(body);
+        message
+    }
+
+    fn record(fragment: &[u8]) -> Vec<u8> {
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE, 0x03, 0x01];
+        record.extend(with_length_u16(fragment));
+        record
+    }
+
+    #[test]
+    fn sni_found() {
+        let data = record(&client_hello_message(Some("LolCatHo.st")));
+        assert_eq!(
+            parse_sni(&data),
+            ClientHelloSni::Found(String::from("lolcatho.st"))
+        );
+    }
+
+    #[test]
+    fn sni_missing() {
+        let data = record(&client_hello_message(None));
+        assert_eq!(parse_sni(&data), ClientHelloSni::Missing);
+    }
+
+    #[test]
+    fn sni_incomplete() {
+        let data = record(&client_hello_message(Some("lolcatho.st")));
+        for end in [0, 3, 5, 20, data.len() - 1] {
+            assert_eq!(parse_sni(&data[..end]), ClientHelloSni::Incomplete);
+        }
+    }
+
+    #[test]
+    fn sni_fragmented_over_records() {
+        let message = client_hello_message(Some("lolcatho.st"));
+        let (first, second) = message.split_at(30);
+        let mut data = record(first);
+        assert_eq!(parse_sni(&data), ClientHelloSni::Incomplete);
+
+        data.extend(record(second));
+        assert_eq!(
+            parse_sni(&data),
+            ClientHelloSni::Found(String::from("lolcatho.st"))
+        );
+    }
+
+    #[test]
+    fn sni_invalid() {
+        assert_eq!(
+            parse_sni(b"GET / HTTP/1.1\r\nHost: lolcatho.st\r\n\r\n"),
+            ClientHelloSni::Invalid
+        );
+
+        // a ServerHello is not a ClientHello
+        let mut message = client_hello_message(Some("lolcatho.st"));
+        message[0] = 2;
+        assert_eq!(parse_sni(&record(&message)), ClientHelloSni::Invalid);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, fs, rc::Rc};
 
 use crate::{protocol::http::DefaultAnswerStatus, sozu_command::state::ClusterId};
 
@@ -8,12 +8,22 @@ pub struct DefaultAnswers {
     pub BadRequest: Rc<Vec<u8>>,
     /// 401
     pub Unauthorized: Rc<Vec<u8>>,
+    /// 403
+    pub Forbidden: Rc<Vec<u8>>,
     /// 404
     pub NotFound: Rc<Vec<u8>>,
+    /// 405
+    pub MethodNotAllowed: Rc<Vec<u8>>,
     /// 408
     pub RequestTimeout: Rc<Vec<u8>>,
     /// 413
     pub PayloadTooLarge: Rc<Vec<u8>>,
+    /// 414
+    pub UriTooLong: Rc<Vec<u8>>,
+    /// 421
+    pub MisdirectedRequest: Rc<Vec<u8>>,
+    /// 425
+    pub TooEarly: Rc<Vec<u8>>,
     /// 502
     pub BadGateway: Rc<Vec<u8>>,
     /// 503
@@ -32,6 +42,10 @@ pub struct CustomAnswers {
 pub struct HttpAnswers {
     pub default: DefaultAnswers,
     pub custom: HashMap<ClusterId, CustomAnswers>,
+    /// file the 404 answer is read from, if any
+    pub answer_404_path: Option<String>,
+    /// file the 503 answer is read from, if any
+    pub answer_503_path: Option<String>,
 }
 
 impl HttpAnswers {
@@ -44,13 +58,28 @@ impl HttpAnswers {
                 Unauthorized: Rc::new(Vec::from(
                     &b"HTTP/1.1 401 Unauthorized\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
                 )),
+                Forbidden: Rc::new(Vec::from(
+                    &b"HTTP/1.1 403 Forbidden\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
+                )),
                 NotFound: Rc::new(Vec::from(answer_404.as_bytes())),
+                MethodNotAllowed: Rc::new(Vec::from(
+                    &b"HTTP/1.1 405 Method Not Allowed\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
+                )),
                 RequestTimeout: Rc::new(Vec::from(
                     &b"HTTP/1.1 408 Request Timeout\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
                 )),
                 PayloadTooLarge: Rc::new(Vec::from(
                     &b"HTTP/1.1 413 Payload Too Large\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
                 )),
+                UriTooLong: Rc::new(Vec::from(
+                    &b"HTTP/1.1 414 URI Too Long\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
+                )),
+                MisdirectedRequest: Rc::new(Vec::from(
+                    &b"HTTP/1.1 421 Misdirected Request\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
+                )),
+                TooEarly: Rc::new(Vec::from(
+                    &b"HTTP/1.1 425 Too Early\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
+                )),
                 BadGateway: Rc::new(Vec::from(
                     &b"HTTP/1.1 502 Bad Gateway\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..]
                 )),
@@ -63,6 +92,33 @@ impl HttpAnswers {
                 )),
             },
             custom: HashMap::new(),
+            answer_404_path: None,
+            answer_503_path: None,
+        }
+    }
+
+    /// Remember the files the 404 and 503 answers come from, so that
+    /// `reload` can pick up their changes without restarting
+    pub fn set_paths(&mut self, answer_404_path: Option<String>, answer_503_path: Option<String>) {
+        self.answer_404_path = answer_404_path;
+        self.answer_503_path = answer_503_path;
+    }
+
+    /// Read again the 404 and 503 answers from their files, without replacing
+    /// the current ones. Fails if one of them cannot be read
+    pub fn read_files(&self) -> Result<AnswerFiles, std::io::Error> {
+        Ok(AnswerFiles {
+            answer_404: self.answer_404_path.as_ref().map(fs::read).transpose()?,
+            answer_503: self.answer_503_path.as_ref().map(fs::read).transpose()?,
+        })
+    }
+
+    pub fn set_answers(&mut self, files: AnswerFiles) {
+        if let Some(answer_404) = files.answer_404 {
+            self.default.NotFound = Rc::new(answer_404);
+        }
+        if let Some(answer_503) = files.answer_503 {
+            self.default.ServiceUnavailable = Rc::new(answer_503);
         }
     }
 
@@ -82,12 +138,18 @@ impl HttpAnswers {
 
     pub fn get(&self, answer: DefaultAnswerStatus, cluster_id: Option<&str>) -> Rc<Vec<u8>> {
         match answer {
+            DefaultAnswerStatus::Answer200 => panic!("the 200 answer is generated dynamically"),
             DefaultAnswerStatus::Answer301 => panic!("the 301 answer is generated dynamically"),
             DefaultAnswerStatus::Answer400 => self.default.BadRequest.clone(),
             DefaultAnswerStatus::Answer401 => self.default.Unauthorized.clone(),
+            DefaultAnswerStatus::Answer403 => self.default.Forbidden.clone(),
             DefaultAnswerStatus::Answer404 => self.default.NotFound.clone(),
+            DefaultAnswerStatus::Answer405 => self.default.MethodNotAllowed.clone(),
             DefaultAnswerStatus::Answer408 => self.default.RequestTimeout.clone(),
             DefaultAnswerStatus::Answer413 => self.default.PayloadTooLarge.clone(),
+            DefaultAnswerStatus::Answer414 => self.default.UriTooLong.clone(),
+            DefaultAnswerStatus::Answer421 => self.default.MisdirectedRequest.clone(),
+            DefaultAnswerStatus::Answer425 => self.default.TooEarly.clone(),
             DefaultAnswerStatus::Answer502 => self.default.BadGateway.clone(),
             DefaultAnswerStatus::Answer503 => cluster_id
                 .and_then(|id: &str| self.custom.get(id))
@@ -97,4 +159,73 @@ impl HttpAnswers {
             DefaultAnswerStatus::Answer507 => self.default.InsufficientStorage.clone(),
         }
     }
+
+    /// The default answer with some headers added after its status line,
+    /// like the `Allow` header of a 405 answer. Each header ends with CRLF
+    pub fn get_with_headers(
+        &self,
+        answer: DefaultAnswerStatus,
+        cluster_id: Option<&str>,
+        extra_headers: &str,
+    ) -> Rc<Vec<u8>> {
+        let default_answer = self.get(answer, cluster_id);
+        let status_line_end = default_answer
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .map(|position| position + 2)
+            .unwrap_or(default_answer.len());
+
+        let mut buf = Vec::with_capacity(default_answer.len() + extra_headers.len());
+        buf.extend_from_slice(&default_answer[..status_line_end]);
+        buf.extend_from_slice(extra_headers.as_bytes());
+        buf.extend_from_slice(&default_answer[status_line_end..]);
+        Rc::new(buf)
+    }
+}
+
+/// The 404 and 503 answers read from the files of a listener
+pub struct AnswerFiles {
+    answer_404: Option<Vec<u8>>,
+    answer_503: Option<Vec<u8>>,
+}
+
+/// Reload the answers of all the listeners of a proxy. Every file is read before
+/// any answer is replaced, so if one of them cannot be read, no listener changes
+pub fn reload_all<I>(listeners_answers: I) -> Result<(), std::io::Error>
+where
+    I: IntoIterator<Item = Rc<RefCell<HttpAnswers>>>,
+{
+    let mut read = Vec::new();
+    for answers in listeners_answers {
+        let files = answers.borrow().read_files()?;
+        read.push((answers, files));
+    }
+
+    for (answers, files) in read {
+        answers.borrow_mut().set_answers(files);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_answer_with_headers() {
+        let answers = HttpAnswers::new(
+            "HTTP/1.1 404 Not Found\r\n\r\n",
+            "HTTP/1.1 503 Service Unavailable\r\n\r\n",
+        );
+
+        let answer =
+            answers.get_with_headers(DefaultAnswerStatus::Answer405, None, "Allow: GET, POST\r\n");
+        assert_eq!(
+            std::str::from_utf8(&answer).unwrap(),
+            "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, POST\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+        );
+
+        let answer = answers.get_with_headers(DefaultAnswerStatus::Answer404, None, "");
+        assert_eq!(answer, answers.get(DefaultAnswerStatus::Answer404, None));
+    }
 }
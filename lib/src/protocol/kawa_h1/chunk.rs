@@ -0,0 +1,110 @@
+//! Bounds on the chunk headers of a chunked body. The size of a chunk is parsed
+//! from hexadecimal digits without a limit on their number, so a client could send
+//! an endless chunk-size line, or declare a chunk larger than it will ever send.
+//! The bounds are the `max_chunk_header_length` and `max_chunk_size` of the listener.
+
+/// the bounds of a listener on the chunk headers of the requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLimits {
+    /// maximum length of a chunk header line, extensions included
+    pub max_header_length: usize,
+    /// maximum size declared for a single chunk
+    pub max_size: u64,
+}
+
+impl ChunkLimits {
+    /// Check a chunk header line, with or without its line ending
+    pub fn check_header(&self, line: &[u8]) -> Result<(), &'static str> {
+        let line = line.strip_suffix(b"\r\n").unwrap_or(line);
+        if line.len() > self.max_header_length {
+            return Err("chunk header is too long");
+        }
+
+        let mut size: u64 = 0;
+        for digit in line.iter().map_while(|c| (*c as char).to_digit(16)) {
+            size = match size.checked_mul(16) {
+                Some(size) if size + u64::from(digit) <= self.max_size => size + u64::from(digit),
+                _ => return Err("chunk is too large"),
+            };
+        }
+        Ok(())
+    }
+
+    /// Check the length of the bytes the parser left unparsed while waiting the end
+    /// of a chunk header line. Only their number is known: the line ending of the
+    /// previous chunk may precede the header, it is not counted in its length
+    pub fn check_pending_header(&self, pending: usize) -> Result<(), &'static str> {
+        if pending > self.max_header_length + 2 {
+            return Err("chunk header is too long");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ChunkLimits = ChunkLimits {
+        max_header_length: 1024,
+        max_size: 1 << 32,
+    };
+
+    #[test]
+    fn valid_chunk_headers() {
+        assert_eq!(LIMITS.check_header(b"1a"), Ok(()));
+        assert_eq!(LIMITS.check_header(b"1A;name=value\r\n"), Ok(()));
+        assert_eq!(LIMITS.check_header(b"0\r\n"), Ok(()));
+        assert_eq!(LIMITS.check_header(b"100000000"), Ok(()));
+        // the length of the line is bounded, not its number of digits
+        assert_eq!(LIMITS.check_header(b"00000000000000000000001"), Ok(()));
+        assert_eq!(LIMITS.check_pending_header(1026), Ok(()));
+    }
+
+    #[test]
+    fn absurdly_long_chunk_size() {
+        let line = vec![b'f'; 100_000];
+        assert_eq!(LIMITS.check_header(&line), Err("chunk header is too long"));
+        // the parser never sees the end of the line
+        assert_eq!(
+            LIMITS.check_pending_header(line.len()),
+            Err("chunk header is too long")
+        );
+
+        // digits that would overflow a u64
+        assert_eq!(
+            LIMITS.check_header(b"ffffffffffffffffffffffff\r\n"),
+            Err("chunk is too large")
+        );
+        assert_eq!(
+            LIMITS.check_header(b"100000001\r\n"),
+            Err("chunk is too large")
+        );
+    }
+
+    #[test]
+    fn long_chunk_extensions() {
+        let mut line = b"10;".to_vec();
+        line.extend(vec![b'a'; 1024]);
+        assert_eq!(LIMITS.check_header(&line), Err("chunk header is too long"));
+    }
+
+    #[test]
+    fn listener_limits() {
+        let limits = ChunkLimits {
+            max_header_length: 16,
+            max_size: 0xff,
+        };
+        assert_eq!(limits.check_header(b"ff;a=b\r\n"), Ok(()));
+        assert_eq!(limits.check_header(b"100\r\n"), Err("chunk is too large"));
+        assert_eq!(
+            limits.check_header(b"1;name=long-value\r\n"),
+            Err("chunk header is too long")
+        );
+        assert_eq!(limits.check_pending_header(18), Ok(()));
+        assert_eq!(
+            limits.check_pending_header(19),
+            Err("chunk header is too long")
+        );
+    }
+}
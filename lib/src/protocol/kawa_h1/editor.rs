@@ -1,16 +1,340 @@
 use std::{
+    fmt::{self, Write},
     net::{IpAddr, SocketAddr},
     str::{from_utf8, from_utf8_unchecked},
+    time::Duration,
 };
 
+use rand::Rng;
 use rusty_ulid::Ulid;
+use time::OffsetDateTime;
+
+use sozu_command::proto::command::AddedHeader;
 
 use crate::{
+    cidr::last_forwarded_for,
     pool::Checkout,
-    protocol::http::{parser::compare_no_case, GenericHttpStream, Method},
+    protocol::http::{
+        parser::{
+            accepts_trailers, allowed_websocket_protocols, check_websocket_protocol,
+            compare_no_case, connection_named_headers, is_hop_by_hop_header, is_valid_traceparent,
+            parse_retry_after, parse_transfer_encoding, split_list, trim_ows,
+            validate_websocket_handshake, SmugglingVector, TransferEncoding,
+            WebSocketHandshakeError,
+        },
+        GenericHttpStream, Method,
+    },
+    server::max_retry_after,
     Protocol,
 };
 
+/// Write the element of the "Forwarded" header describing this hop:
+/// "proto=[PROTO];for=[PEER];by=[PUBLIC]", the IPv6 addresses are quoted.
+/// Without its port, the peer IPv6 address is written between brackets as RFC 7239 requires.
+pub fn write_forwarded(
+    out: &mut String,
+    proto: &str,
+    peer_addr: SocketAddr,
+    public_ip: IpAddr,
+    omit_peer_port: bool,
+) -> fmt::Result {
+    let peer_ip = peer_addr.ip();
+    let peer_port = peer_addr.port();
+    write!(out, "proto={proto};for=")?;
+    match (peer_ip, omit_peer_port) {
+        (IpAddr::V4(_), false) => write!(out, "{peer_ip}:{peer_port}")?,
+        (IpAddr::V4(_), true) => write!(out, "{peer_ip}")?,
+        (IpAddr::V6(_), false) => write!(out, "\"{peer_ip}:{peer_port}\"")?,
+        (IpAddr::V6(_), true) => write!(out, "\"[{peer_ip}]\"")?,
+    }
+    match public_ip {
+        IpAddr::V4(_) => write!(out, ";by={public_ip}"),
+        IpAddr::V6(_) => write!(out, ";by=\"{public_ip}\""),
+    }
+}
+
+/// Write a W3C Trace Context "traceparent" header value, for a sampled request
+pub fn write_traceparent(out: &mut String, trace_id: u128, parent_id: u64) -> fmt::Result {
+    write!(out, "00-{trace_id:032x}-{parent_id:016x}-01")
+}
+
+/// Values of the headers added to the requests that only depend on the addresses
+/// of the session. They are rendered once when the session is created, the keep-alive
+/// resets only regenerate the request id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddedRequestHeaders {
+    /// the ip address of the peer, appended to "X-Forwarded-For"
+    pub forwarded_for: Option<String>,
+    /// "proto=[PROTO];for=[PEER];by=[PUBLIC]", appended to "Forwarded"
+    pub forwarded: Option<String>,
+    /// the public port, written in "X-Forwarded-Port"
+    pub forwarded_port: String,
+    /// the listener neither adds nor forwards "Forwarded"
+    pub omit_forwarded: bool,
+}
+
+impl AddedRequestHeaders {
+    pub fn new(
+        protocol: Protocol,
+        session_address: Option<SocketAddr>,
+        public_address: SocketAddr,
+        omit_forwarded: bool,
+        omit_client_port: bool,
+    ) -> Self {
+        let proto = protocol_str(protocol);
+        let mut forwarded = None;
+        if let Some(peer_addr) = session_address.filter(|_| !omit_forwarded) {
+            let mut value = String::new();
+            // writing to a String can not fail
+            let _ = write_forwarded(
+                &mut value,
+                proto,
+                peer_addr,
+                public_address.ip(),
+                omit_client_port,
+            );
+            forwarded = Some(value);
+        }
+        Self {
+            forwarded_for: session_address.map(|peer_addr| peer_addr.ip().to_string()),
+            forwarded,
+            forwarded_port: public_address.port().to_string(),
+            omit_forwarded,
+        }
+    }
+}
+
+/// the name of a header added by Sōzu
+pub fn added_header_name(header: AddedHeader) -> &'static [u8] {
+    match header {
+        AddedHeader::Forwarded => b"Forwarded",
+        AddedHeader::XForwardedFor => b"X-Forwarded-For",
+        AddedHeader::XForwardedProto => b"X-Forwarded-Proto",
+        AddedHeader::XForwardedPort => b"X-Forwarded-Port",
+        AddedHeader::SozuId => b"Sozu-Id",
+        AddedHeader::EarlyData => b"Early-Data",
+    }
+}
+
+/// 1xx, 204 and 304 responses never have a body (RFC 9112 section 6.3)
+fn status_allows_body(status: u16) -> bool {
+    !(100..200).contains(&status) && status != 204 && status != 304
+}
+
+/// The reason phrase registered for a status code (RFC 9110 section 15)
+pub fn canonical_reason(status: u16) -> Option<&'static str> {
+    let reason = match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        103 => "Early Hints",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        305 => "Use Proxy",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        507 => "Insufficient Storage",
+        511 => "Network Authentication Required",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+/// If `location` is an absolute URL pointing to one of the backends, the same URL pointing
+/// to the frontend instead: with its `scheme` and the `authority` of the request
+fn rewrite_location(
+    location: &[u8],
+    backend_addresses: &[SocketAddr],
+    scheme: &str,
+    authority: &str,
+) -> Option<String> {
+    let location = from_utf8(location).ok()?;
+    let (location_scheme, rest) = location.split_once("://")?;
+    let default_port = match location_scheme.to_ascii_lowercase().as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+    let (location_authority, path) = match rest.find(['/', '?', '#']) {
+        Some(index) => rest.split_at(index),
+        None => (rest, ""),
+    };
+
+    let points_to_backend = match location_authority.parse::<SocketAddr>() {
+        Ok(address) => backend_addresses.contains(&address),
+        Err(_) => location_authority
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|ip| backend_addresses.contains(&SocketAddr::new(ip, default_port)))
+            .unwrap_or(false),
+    };
+    if !points_to_backend {
+        return None;
+    }
+    Some(format!("{scheme}://{authority}{path}"))
+}
+
+/// The origin-form (path and query) of an absolute http or https URI
+fn origin_form(uri: &[u8]) -> Option<String> {
+    let uri = from_utf8(uri).ok()?;
+    let (scheme, rest) = uri.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let target = match rest.find(['/', '?', '#']) {
+        Some(index) => rest[index..].split('#').next().unwrap_or_default(),
+        None => "",
+    };
+    if target.starts_with('/') {
+        Some(target.to_owned())
+    } else {
+        // "http://example.com" and "http://example.com?page=2" have an empty path
+        Some(format!("/{target}"))
+    }
+}
+
+/// the host of an authority, without its port
+fn authority_host(authority: &str) -> &str {
+    match authority.rsplit_once(':') {
+        Some((host, port))
+            if !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit()) =>
+        {
+            host
+        }
+        _ => authority,
+    }
+}
+
+/// The value of a Set-Cookie header with its Domain attribute set to `domain`
+/// and `path_prefix` prepended to its Path attribute, if it has any of them
+fn rewrite_set_cookie(
+    set_cookie: &[u8],
+    domain: Option<&str>,
+    path_prefix: Option<&str>,
+) -> Option<String> {
+    let set_cookie = from_utf8(set_cookie).ok()?;
+    let mut rewritten = false;
+    let mut parts = Vec::new();
+    for (index, part) in set_cookie.split(';').enumerate() {
+        let attribute = part.trim();
+        let (name, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        // the first part is the name and value of the cookie, not an attribute
+        if index > 0 && name.trim().eq_ignore_ascii_case("Domain") {
+            if let Some(domain) = domain {
+                parts.push(format!("Domain={domain}"));
+                rewritten = true;
+                continue;
+            }
+        } else if index > 0 && name.trim().eq_ignore_ascii_case("Path") {
+            if let Some(path_prefix) = path_prefix {
+                let path_prefix = path_prefix.trim_end_matches('/');
+                let path = match value.trim() {
+                    "" | "/" if path_prefix.is_empty() => "/".to_owned(),
+                    "" | "/" => path_prefix.to_owned(),
+                    path => format!("{path_prefix}{path}"),
+                };
+                parts.push(format!("Path={path}"));
+                rewritten = true;
+                continue;
+            }
+        }
+        parts.push(attribute.to_owned());
+    }
+    if rewritten {
+        Some(parts.join("; "))
+    } else {
+        None
+    }
+}
+
+/// Elide the W3C Trace Context headers of a request, unless it has exactly one valid
+/// "traceparent". Returns true if they were elided and a new trace should be started
+fn elide_invalid_trace_context(request: &mut GenericHttpStream) -> bool {
+    let buf = &mut request.storage.mut_buffer();
+
+    let mut traceparent_count = 0;
+    let mut valid_traceparent = false;
+    for block in &request.blocks {
+        match block {
+            kawa::Block::Header(header) if !header.is_elided() => {
+                if compare_no_case(header.key.data(buf), b"traceparent") {
+                    traceparent_count += 1;
+                    valid_traceparent = is_valid_traceparent(header.val.data(buf));
+                }
+            }
+            _ => {}
+        }
+    }
+    if traceparent_count == 1 && valid_traceparent {
+        return false;
+    }
+
+    // the "tracestate" is meaningless without the "traceparent" it goes with
+    for block in &mut request.blocks {
+        match block {
+            kawa::Block::Header(header) if !header.is_elided() => {
+                let key = header.key.data(buf);
+                if compare_no_case(key, b"traceparent") || compare_no_case(key, b"tracestate") {
+                    header.elide();
+                }
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+/// the value of the protocol written in the Forwarded headers
+fn protocol_str(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::HTTP => "http",
+        Protocol::HTTPS => "https",
+        _ => unreachable!(),
+    }
+}
+
 /// This is the container used to store and use information about the session from within a Kawa parser callback
 #[derive(Debug)]
 pub struct HttpContext {
@@ -32,6 +356,34 @@ pub struct HttpContext {
     pub status: Option<u16>,
     /// the value of the reason in the response line
     pub reason: Option<String>,
+    /// the delay found in the "Retry-After" header of a 503 response
+    pub retry_after: Option<Duration>,
+    /// the value of the "Sec-WebSocket-Key" header in the request
+    pub websocket_key: Option<String>,
+    /// the subprotocols offered in the "Sec-WebSocket-Protocol" headers of the request
+    pub websocket_protocols: Vec<String>,
+    /// set to true if subprotocols the frontend does not allow were dropped from the request
+    pub websocket_protocols_dropped: bool,
+    /// set if the response accepting a websocket upgrade does not match the request
+    pub websocket_handshake_error: Option<WebSocketHandshakeError>,
+    /// set to true if the request has an "Expect" header with a "100-continue" value
+    pub expect_continue: bool,
+    /// the value of the "Authorization" header in the request
+    pub authorization: Option<String>,
+    /// the last address of the "X-Forwarded-For" header of the request, before Sōzu appends the peer address
+    pub forwarded_for: Option<IpAddr>,
+    /// the codings applied before chunked, listed in the "Transfer-Encoding" headers of the request
+    pub transfer_codings: Vec<String>,
+    /// set to true if the "Transfer-Encoding" headers of the request do not end with chunked
+    pub invalid_transfer_encoding: bool,
+    /// set if the headers of the request could let a client smuggle a request to the backend
+    pub smuggling: Option<SmugglingVector>,
+    /// set to true if the HEAD request is forwarded as a GET: Kawa parses the body of
+    /// the response, that is not sent to the client
+    pub head_as_get: bool,
+    /// set to true if the "TE" header of the request accepts trailers: it is forwarded
+    /// to the backend, and the "Trailer" header of the response to the client
+    pub te_trailers: bool,
     // ---------- Additional optional data
     pub user_agent: Option<String>,
 
@@ -46,11 +398,38 @@ pub struct HttpContext {
     pub public_address: SocketAddr,
     /// the value of the session address Kawa should write in the Forwarded headers of the request
     pub session_address: Option<SocketAddr>,
+    /// the values derived from the addresses above, that Kawa should write in the Forwarded headers of the request
+    pub added_request_headers: AddedRequestHeaders,
     /// the name of the cookie Kawa should read from the request to get the sticky session
     pub sticky_name: String,
     /// the sticky session that should be used
     /// used to create a "Set-Cookie" header in the response in case it differs from sticky_session_found
     pub sticky_session: Option<String>,
+    /// the tokens of the "Connection" header whose named headers Kawa should not remove
+    pub preserved_connection_tokens: Vec<String>,
+    /// headers Kawa should remove in addition to the standard hop-by-hop headers
+    pub hop_by_hop_headers: Vec<String>,
+    /// signals wether Kawa should check the websocket headers of a 101 response
+    pub validate_websocket_handshake: bool,
+    /// signals wether Kawa should start a W3C trace for the requests without a valid "traceparent"
+    pub trace_context: bool,
+    /// signals wether Kawa should write the absolute URI of a request in origin-form
+    pub rewrite_absolute_form: bool,
+    /// signals wether Kawa should replace the reason phrase of the response with the canonical one
+    pub normalize_reason_phrase: bool,
+    /// the authority of the HTTP/1.0 requests without a "Host" header
+    pub http10_default_host: Option<String>,
+    /// set if the request was received in TLS 1.3 early data, Kawa should write an "Early-Data" header (RFC 8470)
+    pub early_data: bool,
+    /// the headers added by Sōzu that the cluster of the request does not want
+    pub suppressed_headers: Vec<AddedHeader>,
+    /// the addresses of the backends of the cluster, if it rewrites the Location header:
+    /// the URLs pointing to any of them are rewritten to the authority of the request
+    pub location_rewrite: Vec<SocketAddr>,
+    /// the Domain attribute of the cookies set by the backend is rewritten to the host of the request
+    pub cookie_domain_rewrite: bool,
+    /// prepended to the Path attribute of the cookies set by the backend
+    pub cookie_path_prefix: Option<String>,
 }
 
 impl kawa::h1::ParserCallbacks<Checkout> for HttpContext {
@@ -73,35 +452,63 @@ impl HttpContext {
     ///   - front keep-alive
     ///   - sticky cookie
     ///   - user-agent
+    /// - write an absolute URI in origin-form, with a matching Host header
     fn on_request_headers(&mut self, request: &mut GenericHttpStream) {
+        self.elide_hop_by_hop_headers(request);
+        let new_trace = self.trace_context && elide_invalid_trace_context(request);
+
         let buf = &mut request.storage.mut_buffer();
 
         // Captures the request line
+        let mut absolute_form = false;
         if let kawa::StatusLine::Request {
+            version,
             method,
+            uri,
             authority,
             path,
             ..
-        } = &request.detached.status_line
+        } = &mut request.detached.status_line
         {
-            self.method = method.data_opt(buf).map(Method::new);
+            self.method = match method.data_opt(buf) {
+                Some(data) => match Method::from_bytes(data) {
+                    Some(method) => Some(method),
+                    None => {
+                        request.parsing_phase = kawa::ParsingPhase::Error {
+                            marker: kawa::ParsingPhaseMarker::StatusLine,
+                            kind: kawa::ParsingErrorKind::Processing {
+                                message: "method is not valid UTF-8",
+                            },
+                        };
+                        return;
+                    }
+                },
+                None => None,
+            };
             self.authority = authority
                 .data_opt(buf)
                 .and_then(|data| from_utf8(data).ok())
                 .map(ToOwned::to_owned);
+            // the Host header is only mandatory since HTTP/1.1
+            if self.authority.is_none() && matches!(version, kawa::Version::V10) {
+                self.authority = self.http10_default_host.clone();
+            }
             self.path = path
                 .data_opt(buf)
                 .and_then(|data| from_utf8(data).ok())
                 .map(ToOwned::to_owned);
+
+            // the request is still routed with the authority of its absolute URI
+            if self.rewrite_absolute_form && self.authority.is_some() {
+                if let Some(target) = uri.data_opt(buf).and_then(origin_form) {
+                    *uri = kawa::Store::from_string(target);
+                    absolute_form = true;
+                }
+            }
         }
 
-        let public_ip = self.public_address.ip();
-        let public_port = self.public_address.port();
-        let proto = match self.protocol {
-            Protocol::HTTP => "http",
-            Protocol::HTTPS => "https",
-            _ => unreachable!(),
-        };
+        let proto = protocol_str(self.protocol);
+        let added = &self.added_request_headers;
 
         // Find and remove the sticky_name cookie
         // if found its value is stored in sticky_session_found
@@ -122,7 +529,15 @@ impl HttpContext {
         // - store X-Forwarded-For
         // - store Forwarded
         // - store User-Agent
+        // - store Sec-WebSocket-Key and Sec-WebSocket-Protocol
+        // - store Expect: 100-continue and Authorization
+        // - store Transfer-Encoding and Content-Length
+        // - count Host
         let mut x_for = None;
+        let mut host_count = 0;
+        let mut transfer_encoding = Vec::new();
+        let mut content_length: Option<Vec<u8>> = None;
+        let mut conflicting_content_length = false;
         let mut forwarded = None;
         let mut has_x_port = false;
         let mut has_x_proto = false;
@@ -131,7 +546,13 @@ impl HttpContext {
             match block {
                 kawa::Block::Header(header) if !header.is_elided() => {
                     let key = header.key.data(buf);
-                    if compare_no_case(key, b"connection") {
+                    if compare_no_case(key, b"Host") {
+                        host_count += 1;
+                        // the backend routes the rewritten request with its Host header
+                        if let Some(authority) = self.authority.as_ref().filter(|_| absolute_form) {
+                            header.val = kawa::Store::from_string(authority.clone());
+                        }
+                    } else if compare_no_case(key, b"connection") {
                         has_connection = true;
                         if self.closing {
                             header.val = kawa::Store::Static(b"close");
@@ -156,7 +577,7 @@ impl HttpContext {
                         // header.val = kawa::Store::from_string(public_port.to_string());
                         incr!("http.trusting.x_port");
                         let val = header.val.data(buf);
-                        let expected = public_port.to_string();
+                        let expected = &added.forwarded_port;
                         if !compare_no_case(val, expected.as_bytes()) {
                             incr!("http.trusting.x_port.diff");
                             debug!(
@@ -165,91 +586,139 @@ impl HttpContext {
                             );
                         }
                     } else if compare_no_case(key, b"X-Forwarded-For") {
+                        self.forwarded_for = header
+                            .val
+                            .data_opt(buf)
+                            .and_then(|data| from_utf8(data).ok())
+                            .and_then(last_forwarded_for);
                         x_for = Some(header);
                     } else if compare_no_case(key, b"Forwarded") {
-                        forwarded = Some(header);
+                        if added.omit_forwarded {
+                            header.elide();
+                        } else {
+                            forwarded = Some(header);
+                        }
                     } else if compare_no_case(key, b"User-Agent") {
                         self.user_agent = header
                             .val
                             .data_opt(buf)
                             .and_then(|data| from_utf8(data).ok())
                             .map(ToOwned::to_owned);
+                    } else if compare_no_case(key, b"Sec-WebSocket-Key") {
+                        self.websocket_key = header
+                            .val
+                            .data_opt(buf)
+                            .and_then(|data| from_utf8(data).ok())
+                            .map(ToOwned::to_owned);
+                    } else if compare_no_case(key, b"Sec-WebSocket-Protocol") {
+                        let val = header.val.data(buf);
+                        self.websocket_protocols.extend(
+                            split_list(val)
+                                .filter_map(|protocol| from_utf8(protocol).ok())
+                                .map(ToOwned::to_owned),
+                        );
+                    } else if compare_no_case(key, b"Expect") {
+                        let val = header.val.data(buf);
+                        self.expect_continue = compare_no_case(val, b"100-continue");
+                    } else if compare_no_case(key, b"Authorization") {
+                        self.authorization = header
+                            .val
+                            .data_opt(buf)
+                            .and_then(|data| from_utf8(data).ok())
+                            .map(ToOwned::to_owned);
+                    } else if compare_no_case(key, b"Transfer-Encoding") {
+                        // multiple headers form a single list
+                        if !transfer_encoding.is_empty() {
+                            transfer_encoding.push(b',');
+                        }
+                        transfer_encoding.extend_from_slice(header.val.data(buf));
+                    } else if compare_no_case(key, b"Content-Length") {
+                        let val = trim_ows(header.val.data(buf));
+                        match &content_length {
+                            Some(previous) => conflicting_content_length |= previous != val,
+                            None => content_length = Some(val.to_vec()),
+                        }
                     }
                 }
                 _ => {}
             }
         }
 
+        // several Host headers could be routed differently by Sōzu and the backend,
+        // the request must be refused (RFC 9112 section 3.2). The backend could also
+        // frame the body differently with several lengths (RFC 9112 section 6.3)
+        let smuggling = if host_count > 1 {
+            Some(SmugglingVector::DuplicateHost)
+        } else if conflicting_content_length {
+            Some(SmugglingVector::ConflictingContentLength)
+        } else if content_length.is_some() && !transfer_encoding.is_empty() {
+            Some(SmugglingVector::ContentLengthWithTransferEncoding)
+        } else {
+            None
+        };
+        if let Some(vector) = smuggling {
+            self.smuggling = Some(vector);
+            request.parsing_phase = kawa::ParsingPhase::Error {
+                marker: kawa::ParsingPhaseMarker::Headers,
+                kind: kawa::ParsingErrorKind::Processing {
+                    message: vector.message(),
+                },
+            };
+            return;
+        }
+
+        // the framing of a request with transfer codings must be chunked,
+        // the codings applied before are forwarded as is
+        if !transfer_encoding.is_empty() {
+            match parse_transfer_encoding(&transfer_encoding) {
+                Some(TransferEncoding {
+                    codings,
+                    chunked: true,
+                }) => {
+                    request.body_size = kawa::BodySize::Chunked;
+                    self.transfer_codings = codings;
+                }
+                _ => self.invalid_transfer_encoding = true,
+            }
+        }
+
         // If session_address is set:
         // - append its ip address to the list of "X-Forwarded-For" if it was found, creates it if not
-        // - append "proto=[PROTO];for=[PEER];by=[PUBLIC]" to the list of "Forwarded" if it was found, creates it if not
-        if let Some(peer_addr) = self.session_address {
-            let peer_ip = peer_addr.ip();
-            let peer_port = peer_addr.port();
-            let has_x_for = x_for.is_some();
-            let has_forwarded = forwarded.is_some();
-
-            if let Some(header) = x_for {
-                header.val = kawa::Store::from_string(format!("{}, {peer_ip}", unsafe {
-                    from_utf8_unchecked(header.val.data(buf))
-                }));
-            }
-            if let Some(header) = &mut forwarded {
-                let value = unsafe { from_utf8_unchecked(header.val.data(buf)) };
-                let new_value = match (peer_ip, public_ip) {
-                    (IpAddr::V4(_), IpAddr::V4(_)) => {
-                        format!("{value}, proto={proto};for={peer_ip}:{peer_port};by={public_ip}")
-                    }
-                    (IpAddr::V4(_), IpAddr::V6(_)) => {
-                        format!(
-                            "{value}, proto={proto};for={peer_ip}:{peer_port};by=\"{public_ip}\""
-                        )
-                    }
-                    (IpAddr::V6(_), IpAddr::V4(_)) => {
-                        format!(
-                            "{value}, proto={proto};for=\"{peer_ip}:{peer_port}\";by={public_ip}"
-                        )
-                    }
-                    (IpAddr::V6(_), IpAddr::V6(_)) => {
-                        format!(
-                        "{value}, proto={proto};for=\"{peer_ip}:{peer_port}\";by=\"{public_ip}\""
-                    )
-                    }
-                };
-                header.val = kawa::Store::from_string(new_value);
-            }
+        // - unless the listener omits it, append "proto=[PROTO];for=[PEER];by=[PUBLIC]" to the list
+        //   of "Forwarded" if it was found, creates it if not
+        let has_x_for = x_for.is_some();
+        let has_forwarded = forwarded.is_some();
+        if let (Some(peer_ip), Some(header)) = (&added.forwarded_for, x_for) {
+            header.val = kawa::Store::from_string(format!("{}, {peer_ip}", unsafe {
+                from_utf8_unchecked(header.val.data(buf))
+            }));
+        }
+        if let (Some(forwarded_value), Some(header)) = (&added.forwarded, forwarded) {
+            header.val = kawa::Store::from_string(format!("{}, {forwarded_value}", unsafe {
+                from_utf8_unchecked(header.val.data(buf))
+            }));
+        }
 
-            if !has_x_for {
+        if !has_x_for {
+            if let Some(peer_ip) = &added.forwarded_for {
                 request.push_block(kawa::Block::Header(kawa::Pair {
                     key: kawa::Store::Static(b"X-Forwarded-For"),
-                    val: kawa::Store::from_string(peer_ip.to_string()),
+                    val: kawa::Store::from_string(peer_ip.clone()),
                 }));
             }
-            if !has_forwarded {
-                let value = match (peer_ip, public_ip) {
-                    (IpAddr::V4(_), IpAddr::V4(_)) => {
-                        format!("proto={proto};for={peer_ip}:{peer_port};by={public_ip}")
-                    }
-                    (IpAddr::V4(_), IpAddr::V6(_)) => {
-                        format!("proto={proto};for={peer_ip}:{peer_port};by=\"{public_ip}\"")
-                    }
-                    (IpAddr::V6(_), IpAddr::V4(_)) => {
-                        format!("proto={proto};for=\"{peer_ip}:{peer_port}\";by={public_ip}")
-                    }
-                    (IpAddr::V6(_), IpAddr::V6(_)) => {
-                        format!("proto={proto};for=\"{peer_ip}:{peer_port}\";by=\"{public_ip}\"")
-                    }
-                };
+        }
+        if !has_forwarded {
+            if let Some(forwarded_value) = &added.forwarded {
                 request.push_block(kawa::Block::Header(kawa::Pair {
                     key: kawa::Store::Static(b"Forwarded"),
-                    val: kawa::Store::from_string(value),
+                    val: kawa::Store::from_string(forwarded_value.clone()),
                 }));
             }
         }
         if !has_x_port {
             request.push_block(kawa::Block::Header(kawa::Pair {
                 key: kawa::Store::Static(b"X-Forwarded-Port"),
-                val: kawa::Store::from_string(public_port.to_string()),
+                val: kawa::Store::from_string(added.forwarded_port.clone()),
             }));
         }
         if !has_x_proto {
@@ -259,6 +728,16 @@ impl HttpContext {
             }));
         }
 
+        // An absolute URI made the Host header optional
+        if absolute_form && host_count == 0 {
+            if let Some(authority) = &self.authority {
+                request.push_block(kawa::Block::Header(kawa::Pair {
+                    key: kawa::Store::Static(b"Host"),
+                    val: kawa::Store::from_string(authority.clone()),
+                }));
+            }
+        }
+
         // Create a "Connection" header in case it was not found and closing it set
         if !has_connection && self.closing {
             request.push_block(kawa::Block::Header(kawa::Pair {
@@ -267,6 +746,26 @@ impl HttpContext {
             }));
         }
 
+        // Tell the backend the request could be replayed
+        if self.early_data {
+            request.push_block(kawa::Block::Header(kawa::Pair {
+                key: kawa::Store::Static(b"Early-Data"),
+                val: kawa::Store::Static(b"1"),
+            }));
+        }
+
+        // Start a trace identified by the request id, Sōzu being its root span
+        if new_trace {
+            let parent_id = rand::thread_rng().gen_range(1..=u64::MAX);
+            let mut traceparent = String::with_capacity(55);
+            // writing to a String can not fail
+            let _ = write_traceparent(&mut traceparent, u128::from(self.id), parent_id);
+            request.push_block(kawa::Block::Header(kawa::Pair {
+                key: kawa::Store::Static(b"traceparent"),
+                val: kawa::Store::from_string(traceparent),
+            }));
+        }
+
         // Create a custom "Sozu-Id" header
         request.push_block(kawa::Block::Header(kawa::Pair {
             key: kawa::Store::Static(b"Sozu-Id"),
@@ -282,41 +781,134 @@ impl HttpContext {
     ///   - reason
     ///   - back keep-alive
     fn on_response_headers(&mut self, response: &mut GenericHttpStream) {
+        self.elide_hop_by_hop_headers(response);
+
         let buf = &mut response.storage.mut_buffer();
 
-        // Captures the response line
-        if let kawa::StatusLine::Response { code, reason, .. } = &response.detached.status_line {
+        // Captures the response line, the reason sent by the backend is logged
+        // even if it is normalized
+        if let kawa::StatusLine::Response { code, reason, .. } = &mut response.detached.status_line
+        {
             self.status = Some(*code);
             self.reason = reason
                 .data_opt(buf)
                 .and_then(|data| from_utf8(data).ok())
                 .map(ToOwned::to_owned);
+            if self.normalize_reason_phrase {
+                if let Some(canonical) = canonical_reason(*code) {
+                    *reason = kawa::Store::Static(canonical.as_bytes());
+                }
+            }
         }
 
-        if self.method == Some(Method::Head) {
+        if self.method == Some(Method::Head) && !self.head_as_get {
             response.parsing_phase = kawa::ParsingPhase::Terminated;
         }
 
+        // A response with a body but neither Content-Length nor chunked encoding ends
+        // when the backend closes the connection, so the client connection can't be
+        // kept alive either: tell the client explicitly
+        let close_delimited = response.body_size == kawa::BodySize::Empty
+            && (self.method != Some(Method::Head) || self.head_as_get)
+            && !matches!(self.status, Some(status) if !status_allows_body(status));
+        if close_delimited {
+            self.keep_alive_backend = false;
+            self.keep_alive_frontend = false;
+        }
+        // The client asked to close its connection after this response, whatever the
+        // backend answered. Interim responses don't end the exchange
+        let client_close =
+            !self.keep_alive_frontend && !matches!(self.status, Some(status) if status < 200);
+
         // If found:
-        // - set Connection to "close" if closing is set
+        // - set Connection to "close" if closing is set, the response is close-delimited
+        //   or the client closes its connection
         // - set keep_alive_backend to false if Connection is "close"
+        // - store Retry-After if the backend is unavailable
+        // - store Sec-WebSocket-Accept and Sec-WebSocket-Protocol
+        let mut websocket_accept = None;
+        let mut websocket_protocol = None;
+        let mut has_connection = false;
         for block in &mut response.blocks {
             match block {
                 kawa::Block::Header(header) if !header.is_elided() => {
                     let key = header.key.data(buf);
                     if compare_no_case(key, b"connection") {
-                        if self.closing {
+                        has_connection = true;
+                        if self.closing || close_delimited || client_close {
                             header.val = kawa::Store::Static(b"close");
                         } else {
                             let val = header.val.data(buf);
                             self.keep_alive_backend &= !compare_no_case(val, b"close");
                         }
+                    } else if self.status == Some(503) && compare_no_case(key, b"Retry-After") {
+                        let val = header.val.data(buf);
+                        self.retry_after =
+                            parse_retry_after(val, OffsetDateTime::now_utc(), max_retry_after());
+                    } else if !self.location_rewrite.is_empty() && compare_no_case(key, b"Location")
+                    {
+                        if let Some(authority) = &self.authority {
+                            let location = rewrite_location(
+                                header.val.data(buf),
+                                &self.location_rewrite,
+                                protocol_str(self.protocol),
+                                authority,
+                            );
+                            if let Some(location) = location {
+                                header.val = kawa::Store::from_string(location);
+                            }
+                        }
+                    } else if compare_no_case(key, b"Set-Cookie") {
+                        let domain = match (self.cookie_domain_rewrite, &self.authority) {
+                            (true, Some(authority)) => Some(authority_host(authority)),
+                            _ => None,
+                        };
+                        let set_cookie = rewrite_set_cookie(
+                            header.val.data(buf),
+                            domain,
+                            self.cookie_path_prefix.as_deref(),
+                        );
+                        if let Some(set_cookie) = set_cookie {
+                            header.val = kawa::Store::from_string(set_cookie);
+                        }
+                    } else if compare_no_case(key, b"Sec-WebSocket-Accept") {
+                        websocket_accept = Some(header.val.data(buf));
+                    } else if compare_no_case(key, b"Sec-WebSocket-Protocol") {
+                        websocket_protocol = Some(header.val.data(buf));
                     }
                 }
                 _ => {}
             }
         }
 
+        // Create a "Connection" header in case it was not found and the response is close-delimited,
+        // the session is closing or the client closes its connection
+        if !has_connection && (close_delimited || self.closing || client_close) {
+            response.push_block(kawa::Block::Header(kawa::Pair {
+                key: kawa::Store::Static(b"Connection"),
+                val: kawa::Store::Static(b"close"),
+            }));
+        }
+
+        // If the backend accepted a websocket upgrade, check it answered to this request.
+        // The subprotocol it selects is always checked if the frontend restricted the offer
+        if self.validate_websocket_handshake && self.status == Some(101) {
+            if let Some(key) = &self.websocket_key {
+                self.websocket_handshake_error = validate_websocket_handshake(
+                    key.as_bytes(),
+                    &self.websocket_protocols,
+                    websocket_accept,
+                    websocket_protocol,
+                )
+                .err();
+            }
+        } else if self.websocket_protocols_dropped && self.status == Some(101) {
+            if let Some(protocol) = websocket_protocol {
+                self.websocket_handshake_error =
+                    check_websocket_protocol(&self.websocket_protocols, protocol).err();
+            }
+        }
+
         // If the sticky_session is set and differs from the one found in the request
         // create a "Set-Cookie" header to update the sticky_name value
         if let Some(sticky_session) = &self.sticky_session {
@@ -332,9 +924,417 @@ impl HttpContext {
         }
 
         // Create a custom "Sozu-Id" header
-        response.push_block(kawa::Block::Header(kawa::Pair {
-            key: kawa::Store::Static(b"Sozu-Id"),
-            val: kawa::Store::from_string(self.id.to_string()),
-        }));
+        if !self.suppressed_headers.contains(&AddedHeader::SozuId) {
+            response.push_block(kawa::Block::Header(kawa::Pair {
+                key: kawa::Store::Static(b"Sozu-Id"),
+                val: kawa::Store::from_string(self.id.to_string()),
+            }));
+        }
+    }
+
+    /// Keep only the websocket subprotocols of the request that its frontend allows,
+    /// all of them if the list is empty, and rewrite the "Sec-WebSocket-Protocol"
+    /// headers accordingly. Returns false if none of the offered subprotocols is allowed.
+    pub fn restrict_websocket_protocols(
+        &mut self,
+        request: &mut GenericHttpStream,
+        allowed: &[String],
+    ) -> bool {
+        let protocols = allowed_websocket_protocols(&self.websocket_protocols, allowed);
+        if protocols.is_empty() {
+            return false;
+        }
+        if protocols.len() == self.websocket_protocols.len() {
+            return true;
+        }
+
+        let buf = &mut request.storage.mut_buffer();
+        let mut value = Some(protocols.join(", "));
+        for block in &mut request.blocks {
+            match block {
+                kawa::Block::Header(header) if !header.is_elided() => {
+                    let key = header.key.data(buf);
+                    if compare_no_case(key, b"Sec-WebSocket-Protocol") {
+                        // the offer may be split over several headers, keep it in the first one
+                        match value.take() {
+                            Some(value) => header.val = kawa::Store::from_string(value),
+                            None => header.elide(),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.websocket_protocols = protocols;
+        self.websocket_protocols_dropped = true;
+        true
+    }
+
+    /// The request headers are added while parsing, before the request is routed.
+    /// Once the cluster is known, elide the ones it suppressed, along with the values
+    /// the client sent for them.
+    pub fn elide_suppressed_headers(&self, request: &mut GenericHttpStream) {
+        if self.suppressed_headers.is_empty() {
+            return;
+        }
+
+        let buf = &mut request.storage.mut_buffer();
+        for block in &mut request.blocks {
+            match block {
+                kawa::Block::Header(header) if !header.is_elided() => {
+                    let key = header.key.data(buf);
+                    if self
+                        .suppressed_headers
+                        .iter()
+                        .any(|suppressed| compare_no_case(key, added_header_name(*suppressed)))
+                    {
+                        header.elide();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Elide the hop-by-hop headers, they only apply to the current connection and should
+    /// not be forwarded:
+    ///
+    /// - the standard ones (see `HOP_BY_HOP_HEADERS`) and the configured hop_by_hop_headers
+    /// - the ones named in the "Connection" header, except those named by preserved_connection_tokens
+    ///
+    /// A "TE" header accepting trailers is kept as "TE: trailers", and so is the "Trailer"
+    /// header of the response, for the gRPC clients that need the trailers end to end.
+    fn elide_hop_by_hop_headers(&mut self, stream: &mut GenericHttpStream) {
+        let buf = &mut stream.storage.mut_buffer();
+        let is_request = matches!(stream.kind, kawa::Kind::Request);
+
+        let mut named_headers = Vec::new();
+        for block in &stream.blocks {
+            match block {
+                kawa::Block::Header(header) if !header.is_elided() => {
+                    let key = header.key.data(buf);
+                    if compare_no_case(key, b"connection") {
+                        let val = header.val.data(buf);
+                        named_headers.extend(connection_named_headers(
+                            val,
+                            &self.preserved_connection_tokens,
+                        ));
+                    } else if is_request
+                        && compare_no_case(key, b"TE")
+                        && accepts_trailers(header.val.data(buf))
+                    {
+                        self.te_trailers = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for block in &mut stream.blocks {
+            match block {
+                kawa::Block::Header(header) if !header.is_elided() => {
+                    let key = header.key.data(buf);
+                    if self.te_trailers {
+                        if is_request && compare_no_case(key, b"TE") {
+                            header.val = kawa::Store::Static(b"trailers");
+                            continue;
+                        }
+                        if !is_request && compare_no_case(key, b"Trailer") {
+                            continue;
+                        }
+                    }
+                    if is_hop_by_hop_header(key, &self.hop_by_hop_headers)
+                        || named_headers
+                            .iter()
+                            .any(|name| compare_no_case(key, name.as_bytes()))
+                    {
+                        header.elide();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// the "Forwarded" element with the peer port, spelled out case by case with `format!`
+#[cfg(test)]
+fn forwarded_reference(proto: &str, peer_addr: SocketAddr, public_ip: IpAddr) -> String {
+    let peer_ip = peer_addr.ip();
+    let peer_port = peer_addr.port();
+    match (peer_ip, public_ip) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => {
+            format!("proto={proto};for={peer_ip}:{peer_port};by={public_ip}")
+        }
+        (IpAddr::V4(_), IpAddr::V6(_)) => {
+            format!("proto={proto};for={peer_ip}:{peer_port};by=\"{public_ip}\"")
+        }
+        (IpAddr::V6(_), IpAddr::V4(_)) => {
+            format!("proto={proto};for=\"{peer_ip}:{peer_port}\";by={public_ip}")
+        }
+        (IpAddr::V6(_), IpAddr::V6(_)) => {
+            format!("proto={proto};for=\"{peer_ip}:{peer_port}\";by=\"{public_ip}\"")
+        }
+    }
+}
+
+#[test]
+fn test_write_forwarded() {
+    let peers: [SocketAddr; 2] = [
+        "192.168.0.2:12345".parse().unwrap(),
+        "[2001:db8::2]:12345".parse().unwrap(),
+    ];
+    let publics: [IpAddr; 2] = ["10.0.0.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+
+    for peer_addr in peers {
+        for public_ip in publics {
+            for proto in ["http", "https"] {
+                let mut value = String::new();
+                write_forwarded(&mut value, proto, peer_addr, public_ip, false).unwrap();
+                assert_eq!(value, forwarded_reference(proto, peer_addr, public_ip));
+            }
+        }
     }
 }
+
+#[test]
+fn test_write_traceparent() {
+    let mut value = String::new();
+    write_traceparent(
+        &mut value,
+        0x4bf92f3577b34da6a3ce929d0e0e4736,
+        0xf067aa0ba902b7,
+    )
+    .unwrap();
+    assert_eq!(
+        value,
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    );
+    assert!(is_valid_traceparent(value.as_bytes()));
+
+    let id = Ulid::generate();
+    let mut value = String::new();
+    write_traceparent(&mut value, u128::from(id), 1).unwrap();
+    assert!(is_valid_traceparent(value.as_bytes()), "{value}");
+}
+
+#[test]
+fn test_added_request_headers() {
+    let peer_addr: SocketAddr = "[2001:db8::2]:12345".parse().unwrap();
+    let public_address: SocketAddr = "10.0.0.1:8443".parse().unwrap();
+
+    let added = AddedRequestHeaders::new(
+        Protocol::HTTPS,
+        Some(peer_addr),
+        public_address,
+        false,
+        false,
+    );
+    assert_eq!(added.forwarded_for.as_deref(), Some("2001:db8::2"));
+    assert_eq!(
+        added.forwarded,
+        Some(forwarded_reference("https", peer_addr, public_address.ip()))
+    );
+    assert_eq!(added.forwarded_port, "8443");
+
+    let added = AddedRequestHeaders::new(Protocol::HTTP, None, public_address, false, false);
+    assert_eq!(added.forwarded_for, None);
+    assert_eq!(added.forwarded, None);
+    assert_eq!(added.forwarded_port, "8443");
+
+    // the X-Forwarded-* values are kept when the Forwarded header is omitted
+    let added = AddedRequestHeaders::new(
+        Protocol::HTTPS,
+        Some(peer_addr),
+        public_address,
+        true,
+        false,
+    );
+    assert_eq!(added.forwarded_for.as_deref(), Some("2001:db8::2"));
+    assert_eq!(added.forwarded, None);
+    assert_eq!(added.forwarded_port, "8443");
+}
+
+#[test]
+fn test_forwarded_without_client_port() {
+    let public_address: SocketAddr = "10.0.0.1:8443".parse().unwrap();
+
+    let peer_addr: SocketAddr = "192.168.0.2:12345".parse().unwrap();
+    let added = AddedRequestHeaders::new(
+        Protocol::HTTPS,
+        Some(peer_addr),
+        public_address,
+        false,
+        true,
+    );
+    assert_eq!(
+        added.forwarded.as_deref(),
+        Some("proto=https;for=192.168.0.2;by=10.0.0.1")
+    );
+    assert_eq!(added.forwarded_for.as_deref(), Some("192.168.0.2"));
+
+    let peer_addr: SocketAddr = "[2001:db8::2]:12345".parse().unwrap();
+    let added = AddedRequestHeaders::new(
+        Protocol::HTTPS,
+        Some(peer_addr),
+        public_address,
+        false,
+        true,
+    );
+    assert_eq!(
+        added.forwarded.as_deref(),
+        Some("proto=https;for=\"[2001:db8::2]\";by=10.0.0.1")
+    );
+    assert_eq!(added.forwarded_for.as_deref(), Some("2001:db8::2"));
+
+    let mut value = String::new();
+    write_forwarded(
+        &mut value,
+        "http",
+        peer_addr,
+        "2001:db8::1".parse().unwrap(),
+        true,
+    )
+    .unwrap();
+    assert_eq!(value, "proto=http;for=\"[2001:db8::2]\";by=\"2001:db8::1\"");
+}
+
+#[test]
+fn test_rewrite_location() {
+    let backends: Vec<SocketAddr> = vec![
+        "10.0.0.2:8080".parse().unwrap(),
+        "10.0.0.4:8080".parse().unwrap(),
+    ];
+    let rewrite =
+        |location: &str| rewrite_location(location.as_bytes(), &backends, "https", "example.com");
+
+    assert_eq!(
+        rewrite("http://10.0.0.2:8080/login?next=/#top").as_deref(),
+        Some("https://example.com/login?next=/#top")
+    );
+    assert_eq!(
+        rewrite("HTTP://10.0.0.2:8080").as_deref(),
+        Some("https://example.com")
+    );
+    assert_eq!(
+        rewrite("http://10.0.0.2:8080?page=2").as_deref(),
+        Some("https://example.com?page=2")
+    );
+    // another backend of the cluster
+    assert_eq!(
+        rewrite("http://10.0.0.4:8080/login").as_deref(),
+        Some("https://example.com/login")
+    );
+
+    // another host, port or a relative URL is left as is
+    assert_eq!(rewrite("http://10.0.0.3:8080/login"), None);
+    assert_eq!(rewrite("http://10.0.0.2/login"), None);
+    assert_eq!(rewrite("http://backend.local:8080/login"), None);
+    assert_eq!(rewrite("/login"), None);
+    assert_eq!(rewrite("ftp://10.0.0.2:8080/file"), None);
+
+    // the default port of the scheme of the URL
+    let backends: Vec<SocketAddr> = vec!["[2001:db8::2]:80".parse().unwrap()];
+    assert_eq!(
+        rewrite_location(
+            b"http://[2001:db8::2]/",
+            &backends,
+            "http",
+            "example.com:8080"
+        )
+        .as_deref(),
+        Some("http://example.com:8080/")
+    );
+    assert_eq!(
+        rewrite_location(b"https://[2001:db8::2]/", &backends, "http", "example.com"),
+        None
+    );
+    assert_eq!(
+        rewrite_location(b"http://10.0.0.2:8080/", &[], "http", "example.com"),
+        None
+    );
+}
+
+#[test]
+fn test_rewrite_set_cookie() {
+    assert_eq!(
+        rewrite_set_cookie(
+            b"session=abc; Domain=backend.local; Path=/; HttpOnly",
+            Some("example.com"),
+            None
+        )
+        .as_deref(),
+        Some("session=abc; Domain=example.com; Path=/; HttpOnly")
+    );
+    assert_eq!(
+        rewrite_set_cookie(b"session=abc;path=/;Secure", None, Some("/app/")).as_deref(),
+        Some("session=abc; Path=/app; Secure")
+    );
+    assert_eq!(
+        rewrite_set_cookie(
+            b"session=abc; domain=.backend.local; Path=/admin",
+            Some("example.com"),
+            Some("/app")
+        )
+        .as_deref(),
+        Some("session=abc; Domain=example.com; Path=/app/admin")
+    );
+
+    // a cookie named like an attribute, or without the attributes, is left as is
+    assert_eq!(
+        rewrite_set_cookie(b"Domain=abc; Secure", Some("example.com"), Some("/app")),
+        None
+    );
+    assert_eq!(
+        rewrite_set_cookie(b"session=abc; Path=/", Some("example.com"), None),
+        None
+    );
+
+    assert_eq!(authority_host("example.com:8080"), "example.com");
+    assert_eq!(authority_host("example.com"), "example.com");
+    assert_eq!(authority_host("[2001:db8::2]:8080"), "[2001:db8::2]");
+    assert_eq!(authority_host("[2001:db8::2]"), "[2001:db8::2]");
+}
+
+#[test]
+fn test_origin_form() {
+    assert_eq!(origin_form(b"http://example.com/x").as_deref(), Some("/x"));
+    assert_eq!(
+        origin_form(b"HTTPS://example.com:8443/api/users?page=2").as_deref(),
+        Some("/api/users?page=2")
+    );
+    assert_eq!(origin_form(b"http://example.com").as_deref(), Some("/"));
+    assert_eq!(
+        origin_form(b"http://example.com?page=2").as_deref(),
+        Some("/?page=2")
+    );
+    assert_eq!(
+        origin_form(b"http://example.com/x#top").as_deref(),
+        Some("/x")
+    );
+
+    // origin-form, authority-form and other schemes are left as is
+    assert_eq!(origin_form(b"/x"), None);
+    assert_eq!(origin_form(b"example.com:443"), None);
+    assert_eq!(origin_form(b"ftp://example.com/file"), None);
+}
+
+#[test]
+fn test_status_allows_body() {
+    for status in [100, 101, 103, 204, 304] {
+        assert!(!status_allows_body(status), "{status}");
+    }
+    for status in [200, 206, 301, 404, 503] {
+        assert!(status_allows_body(status), "{status}");
+    }
+}
+
+#[test]
+fn test_canonical_reason() {
+    assert_eq!(canonical_reason(200), Some("OK"));
+    assert_eq!(canonical_reason(404), Some("Not Found"));
+    assert_eq!(canonical_reason(503), Some("Service Unavailable"));
+    // unregistered status codes keep the reason sent by the backend
+    assert_eq!(canonical_reason(299), None);
+    assert_eq!(canonical_reason(599), None);
+}
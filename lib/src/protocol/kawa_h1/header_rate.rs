@@ -0,0 +1,84 @@
+//! Slowloris protection. A client sending the headers of its request a few bytes
+//! at a time resets the frontend timeout at each read, so it can keep its session
+//! open as long as it wants: the rate at which the headers are received is checked.
+
+use time::{Duration, Instant};
+
+/// The rate is only checked after the headers were received for this long,
+/// the first reads of a request are too close to each other to measure it
+pub const GRACE_PERIOD: Duration = Duration::seconds(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderRate {
+    /// minimum number of bytes received per second
+    min_rate: u32,
+    /// when the first bytes of the request were read
+    start: Option<Instant>,
+    /// bytes of the request read since then
+    received: usize,
+}
+
+impl HeaderRate {
+    pub fn new(min_rate: u32) -> Self {
+        HeaderRate {
+            min_rate,
+            start: None,
+            received: 0,
+        }
+    }
+
+    /// forget the current request, the next one starts with its first read
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.received = 0;
+    }
+
+    /// Count the bytes of the request headers read at `now`.
+    /// Returns true if they are received below the minimum rate
+    pub fn is_too_slow(&mut self, size: usize, now: Instant) -> bool {
+        let start = *self.start.get_or_insert(now);
+        self.received += size;
+
+        let elapsed = now - start;
+        elapsed >= GRACE_PERIOD
+            && (self.received as f64) < f64::from(self.min_rate) * elapsed.as_seconds_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_headers() {
+        let mut rate = HeaderRate::new(100);
+        let start = Instant::now();
+        assert!(!rate.is_too_slow(80, start));
+        assert!(!rate.is_too_slow(80, start + Duration::milliseconds(500)));
+        assert!(!rate.is_too_slow(80, start + Duration::milliseconds(1500)));
+    }
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let mut rate = HeaderRate::new(100);
+        let start = Instant::now();
+        // 20 bytes per second, measured once the grace period is over
+        for i in 0..20 {
+            assert!(!rate.is_too_slow(1, start + Duration::milliseconds(50 * i)));
+        }
+        assert!(rate.is_too_slow(1, start + Duration::milliseconds(1000)));
+    }
+
+    #[test]
+    fn reset_between_requests() {
+        let mut rate = HeaderRate::new(100);
+        let start = Instant::now();
+        assert!(!rate.is_too_slow(150, start));
+
+        // a kept alive connection idle for a while before its next request
+        rate.reset();
+        let next = start + Duration::seconds(30);
+        assert!(!rate.is_too_slow(150, next));
+        assert!(!rate.is_too_slow(10, next + Duration::seconds(1)));
+    }
+}
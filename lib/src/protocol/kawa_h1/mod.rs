@@ -1,10 +1,14 @@
 pub mod answers;
+pub mod chunk;
 pub mod editor;
+pub mod header_rate;
 pub mod parser;
+pub mod watermark;
 
 use std::{
+    borrow::Cow,
     cell::RefCell,
-    io::ErrorKind,
+    io::{ErrorKind, IoSlice},
     net::{Shutdown, SocketAddr},
     rc::{Rc, Weak},
 };
@@ -13,28 +17,42 @@ use kawa;
 use mio::{net::TcpStream, Interest, Token};
 use rusty_ulid::Ulid;
 use sozu_command::{
-    config::MAX_LOOP_ITERATIONS,
-    proto::command::{Event, EventKind, ListenerType},
+    config::DEFAULT_OUTLIER_EJECTION_TIME,
+    proto::command::{AddedHeader, Cluster, Event, EventKind, ListenerType, SessionInfo},
 };
 use time::{Duration, Instant};
 
 use crate::{
     backends::{Backend, BackendError},
-    logs::{Endpoint, LogContext, RequestRecord},
+    logs::{protocol_with_alpn, redact_header, redact_query, Endpoint, LogContext, RequestRecord},
     pool::{Checkout, Pool},
     protocol::{
-        http::{editor::HttpContext, parser::Method},
+        http::{
+            chunk::ChunkLimits,
+            editor::{canonical_reason, AddedRequestHeaders, HttpContext},
+            header_rate::HeaderRate,
+            parser::{
+                authority_hostname, authorization_schemes, authorize_expect_continue,
+                check_authorization, has_bare_line_feed, www_authenticate_headers,
+                ExpectContinueDecision, Method, SmugglingVector,
+            },
+            watermark::ReadWatermarks,
+        },
         SessionState,
     },
     retry::RetryPolicy,
-    router::Route,
-    server::{push_event, CONN_RETRIES},
+    router::{Route, RouterError},
+    server::{
+        max_connection_attempts, max_loop_iterations, push_event, push_request_error_event,
+        yield_session,
+    },
     socket::{stats::socket_rtt, SocketHandler, SocketResult, TransportProtocol},
     sozu_command::ready::Ready,
     timer::TimeoutContainer,
     AcceptError, BackendConnectAction, BackendConnectionError, BackendConnectionStatus,
-    L7ListenerHandler, L7Proxy, ListenerHandler, Protocol, ProxySession, Readiness,
-    RetrieveClusterError, SessionIsToBeClosed, SessionMetrics, SessionResult, StateResult,
+    FrontendFromRequestError, L7ListenerHandler, L7Proxy, ListenerHandler, Protocol, ProxySession,
+    Readiness, RetrieveClusterError, SessionIsToBeClosed, SessionMetrics, SessionResult,
+    StateResult,
 };
 
 /// Generic Http representation using the Kawa crate using the Checkout of Sozu as buffer
@@ -58,12 +76,18 @@ pub enum SessionStatus {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DefaultAnswerStatus {
+    Answer200,
     Answer301,
     Answer400,
     Answer401,
+    Answer403,
     Answer404,
+    Answer405,
     Answer408,
     Answer413,
+    Answer414,
+    Answer421,
+    Answer425,
     Answer502,
     Answer503,
     Answer504,
@@ -74,12 +98,18 @@ pub enum DefaultAnswerStatus {
 impl Into<u16> for DefaultAnswerStatus {
     fn into(self) -> u16 {
         match self {
+            Self::Answer200 => 200,
             Self::Answer301 => 301,
             Self::Answer400 => 400,
             Self::Answer401 => 401,
+            Self::Answer403 => 403,
             Self::Answer404 => 404,
+            Self::Answer405 => 405,
             Self::Answer408 => 408,
             Self::Answer413 => 413,
+            Self::Answer414 => 414,
+            Self::Answer421 => 421,
+            Self::Answer425 => 425,
             Self::Answer502 => 502,
             Self::Answer503 => 503,
             Self::Answer504 => 504,
@@ -88,6 +118,40 @@ impl Into<u16> for DefaultAnswerStatus {
     }
 }
 
+impl DefaultAnswerStatus {
+    /// reason phrase of the status line of the default answer
+    pub fn reason(&self) -> &'static str {
+        match self {
+            // the default answer kept the reason it had before RFC 9110 renamed it
+            Self::Answer413 => "Payload Too Large",
+            // all the other statuses of the default answers are in the table
+            _ => canonical_reason((*self).into()).unwrap_or_default(),
+        }
+    }
+
+    /// key of the metric counting the default answers with this status, per cluster
+    pub fn metric_key(&self) -> &'static str {
+        match self {
+            Self::Answer200 => "http.default_answer.200",
+            Self::Answer301 => "http.default_answer.301",
+            Self::Answer400 => "http.default_answer.400",
+            Self::Answer401 => "http.default_answer.401",
+            Self::Answer403 => "http.default_answer.403",
+            Self::Answer404 => "http.default_answer.404",
+            Self::Answer405 => "http.default_answer.405",
+            Self::Answer408 => "http.default_answer.408",
+            Self::Answer413 => "http.default_answer.413",
+            Self::Answer414 => "http.default_answer.414",
+            Self::Answer421 => "http.default_answer.421",
+            Self::Answer425 => "http.default_answer.425",
+            Self::Answer502 => "http.default_answer.502",
+            Self::Answer503 => "http.default_answer.503",
+            Self::Answer504 => "http.default_answer.504",
+            Self::Answer507 => "http.default_answer.507",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimeoutStatus {
     Request,
@@ -99,12 +163,18 @@ pub enum TimeoutStatus {
 /// Http will be contained in State which itself is contained by Session
 pub struct Http<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> {
     answers: Rc<RefCell<answers::HttpAnswers>>,
+    /// set when reading from the backend stopped because the response buffer
+    /// reached its high watermark
+    back_read_paused: bool,
+    back_read_watermarks: ReadWatermarks,
     pub backend: Option<Rc<RefCell<Backend>>>,
+    /// whether the backend connection of the current request was opened for it
+    /// or kept alive from a previous one
+    backend_connect_action: Option<BackendConnectAction>,
     backend_connection_status: BackendConnectionStatus,
     pub backend_id: Option<String>,
     pub backend_readiness: Readiness,
     pub backend_socket: Option<TcpStream>,
-    backend_stop: Option<Instant>,
     pub backend_token: Option<Token>,
     pub container_backend_timeout: TimeoutContainer,
     pub container_frontend_timeout: TimeoutContainer,
@@ -112,15 +182,55 @@ pub struct Http<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> {
     configured_connect_timeout: Duration,
     configured_frontend_timeout: Duration,
     pub cluster_id: Option<String>,
+    /// decoded size of the chunked body of the current request
+    chunked_body_size: u64,
+    /// size of the body of the current response, when it is delimited by the
+    /// closing of the backend connection
+    close_delimited_response_size: u64,
     /// attempts to connect to the backends during the session
     connection_attempts: u8,
+    /// response statuses of the cluster that count as backend failures, if not empty
+    /// the backends are only deemed healthy once they answer with another status
+    failure_status_codes: Vec<u16>,
+    /// set when a read reached max_front_read_size, the session stops reading
+    /// until the other sessions of the worker had their turn
+    front_read_yielded: bool,
+    /// outlier detection of the cluster: number of 5xx responses in a row
+    /// after which a backend is ejected, and for how long
+    outlier_detection: Option<(u32, std::time::Duration)>,
     pub frontend_readiness: Readiness,
     pub frontend_socket: Front,
     frontend_token: Token,
+    /// reception rate of the request headers, if the listener has a minimum
+    header_rate: Option<HeaderRate>,
     keepalive_count: usize,
     listener: Rc<RefCell<L>>,
+    /// maximum decoded size of a chunked request body
+    max_chunked_body_size: Option<u64>,
+    /// bounds on the chunk headers of the requests
+    chunk_limits: ChunkLimits,
+    /// maximum size of a response body delimited by the closing of the backend connection
+    max_close_delimited_response_size: Option<u64>,
+    /// maximum number of bytes read from the frontend socket at once
+    max_front_read_size: Option<usize>,
+    /// number of pipelined requests parsed in a row at which reading from the frontend stops
+    max_pipelined_requests: Option<usize>,
+    /// maximum length of the request URI, answered with a 414 beyond it
+    max_uri_length: usize,
+    /// maximum size of the response head, the client gets a 502 beyond it
+    max_response_header_size: Option<usize>,
+    /// number of request heads parsed since the front buffer was last empty
+    /// between two requests, all but the first were pipelined
+    parsed_requests: usize,
     pub request_stream: GenericHttpStream,
+    /// number of responses entirely forwarded to the frontend on this connection
+    requests_served: usize,
+    /// number of slices of the current response written to the frontend socket,
+    /// many small slices make for inefficient vectored writes
+    response_slices: usize,
     pub response_stream: GenericHttpStream,
+    /// why the current request was rejected as a request smuggling attempt
+    smuggling: Option<SmugglingVector>,
     status: SessionStatus,
     /// The HTTP context was separated from the State for borrowing reasons.
     /// Calling a kawa parser mutably borrows the State through request_stream or response_stream,
@@ -163,15 +273,59 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             }
             None => return Err(AcceptError::BufferCapacityReached),
         };
+        let preserved_connection_tokens =
+            listener.borrow().get_preserved_connection_tokens().to_vec();
+        let validate_websocket_handshake = listener.borrow().get_validate_websocket_handshake();
+        let omit_forwarded_header = listener.borrow().get_omit_forwarded_header();
+        let omit_forwarded_client_port = listener.borrow().get_omit_forwarded_client_port();
+        let trace_context = listener.borrow().get_trace_context();
+        let rewrite_absolute_form = listener.borrow().get_rewrite_absolute_form();
+        let normalize_reason_phrase = listener.borrow().get_normalize_reason_phrase();
+        let http10_default_host = listener
+            .borrow()
+            .get_http10_default_host()
+            .map(ToOwned::to_owned);
+        let hop_by_hop_headers = listener.borrow().get_hop_by_hop_headers().to_vec();
+        let max_chunked_body_size = listener.borrow().get_max_chunked_body_size();
+        let chunk_limits = ChunkLimits {
+            max_header_length: listener.borrow().get_max_chunk_header_length() as usize,
+            max_size: listener.borrow().get_max_chunk_size(),
+        };
+        let max_close_delimited_response_size =
+            listener.borrow().get_max_close_delimited_response_size();
+        let max_front_read_size = listener
+            .borrow()
+            .get_max_front_read_size()
+            .map(|size| size as usize);
+        let max_pipelined_requests = listener
+            .borrow()
+            .get_max_pipelined_requests()
+            .map(|count| count as usize);
+        let max_uri_length = listener.borrow().get_max_uri_length() as usize;
+        let max_response_header_size = listener
+            .borrow()
+            .get_max_response_header_size()
+            .map(|size| size as usize);
+        let header_rate = listener
+            .borrow()
+            .get_min_request_header_rate()
+            .map(HeaderRate::new);
+        let (low_watermark, high_watermark) = listener.borrow().get_back_read_watermarks();
+        let back_read_watermarks =
+            ReadWatermarks::new(back_buffer.capacity(), low_watermark, high_watermark);
         Ok(Http {
             answers,
+            back_read_paused: false,
+            back_read_watermarks,
+            backend_connect_action: None,
             backend_connection_status: BackendConnectionStatus::NotConnected,
             backend_id: None,
             backend_readiness: Readiness::new(),
             backend_socket: None,
-            backend_stop: None,
             backend_token: None,
             backend: None,
+            chunked_body_size: 0,
+            close_delimited_response_size: 0,
             cluster_id: None,
             configured_backend_timeout,
             configured_connect_timeout,
@@ -179,22 +333,37 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             connection_attempts: 0,
             container_backend_timeout: TimeoutContainer::new_empty(configured_connect_timeout),
             container_frontend_timeout,
+            failure_status_codes: Vec::new(),
+            front_read_yielded: false,
+            outlier_detection: None,
             frontend_readiness: Readiness {
                 interest: Ready::READABLE | Ready::HUP | Ready::ERROR,
                 event: Ready::EMPTY,
             },
             frontend_socket,
             frontend_token,
+            header_rate,
             keepalive_count: 0,
             listener,
+            max_chunked_body_size,
+            chunk_limits,
+            max_close_delimited_response_size,
+            max_front_read_size,
+            max_pipelined_requests,
+            max_uri_length,
+            max_response_header_size,
+            parsed_requests: 0,
             request_stream: GenericHttpStream::new(
                 kawa::Kind::Request,
                 kawa::Buffer::new(front_buffer),
             ),
+            requests_served: 0,
+            response_slices: 0,
             response_stream: GenericHttpStream::new(
                 kawa::Kind::Response,
                 kawa::Buffer::new(back_buffer),
             ),
+            smuggling: None,
             status: SessionStatus::Normal,
             context: HttpContext {
                 closing: false,
@@ -204,16 +373,48 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 protocol,
                 public_address,
                 session_address,
+                added_request_headers: AddedRequestHeaders::new(
+                    protocol,
+                    session_address,
+                    public_address,
+                    omit_forwarded_header,
+                    omit_forwarded_client_port,
+                ),
                 sticky_name,
                 sticky_session: None,
                 sticky_session_found: None,
+                preserved_connection_tokens,
+                validate_websocket_handshake,
+                trace_context,
+                rewrite_absolute_form,
+                normalize_reason_phrase,
+                http10_default_host,
+                early_data: false,
+                suppressed_headers: Vec::new(),
+                location_rewrite: Vec::new(),
+                cookie_domain_rewrite: false,
+                cookie_path_prefix: None,
+                hop_by_hop_headers,
 
                 method: None,
                 authority: None,
                 path: None,
                 status: None,
                 reason: None,
+                retry_after: None,
                 user_agent: None,
+                websocket_handshake_error: None,
+                websocket_key: None,
+                websocket_protocols: Vec::new(),
+                websocket_protocols_dropped: false,
+                expect_continue: false,
+                authorization: None,
+                forwarded_for: None,
+                transfer_codings: Vec::new(),
+                invalid_transfer_encoding: false,
+                smuggling: None,
+                head_as_get: false,
+                te_trailers: false,
             },
         })
     }
@@ -224,10 +425,35 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         self.context.keep_alive_frontend = true;
         self.context.keep_alive_backend = true;
         self.context.sticky_session_found = None;
+        self.context.retry_after = None;
+        self.context.websocket_key = None;
+        self.context.websocket_protocols.clear();
+        self.context.websocket_protocols_dropped = false;
+        self.context.expect_continue = false;
+        self.context.authorization = None;
+        self.context.forwarded_for = None;
+        self.context.transfer_codings.clear();
+        self.context.invalid_transfer_encoding = false;
+        self.context.smuggling = None;
+        self.context.head_as_get = false;
+        self.context.te_trailers = false;
+        self.smuggling = None;
+        self.backend_connect_action = None;
+        self.chunked_body_size = 0;
+        self.close_delimited_response_size = 0;
+        self.response_slices = 0;
+        if let Some(header_rate) = &mut self.header_rate {
+            header_rate.reset();
+        }
         self.context.id = Ulid::generate();
 
         self.request_stream.clear();
         self.response_stream.clear();
+        // pipelined requests left in the buffer may still come from the early data
+        if self.request_stream.storage.is_empty() {
+            self.context.early_data = false;
+            self.parsed_requests = 0;
+        }
         self.keepalive_count += 1;
         gauge_add!("http.active_requests", -1);
 
@@ -272,6 +498,21 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         }
     }
 
+    /// Copy the bytes received in TLS 1.3 early data to the request buffer,
+    /// they will be parsed as if they were read from the frontend socket.
+    /// Returns false if they do not fit in the buffer.
+    pub fn add_early_data(&mut self, data: &[u8]) -> bool {
+        let space = self.request_stream.storage.space();
+        if data.len() > space.len() {
+            return false;
+        }
+        space[..data.len()].copy_from_slice(data);
+        self.request_stream.storage.fill(data.len());
+        self.context.early_data = true;
+        self.frontend_readiness.event.insert(Ready::READABLE);
+        true
+    }
+
     pub fn readable(&mut self, metrics: &mut SessionMetrics) -> StateResult {
         trace!("==============readable");
         if !self.container_frontend_timeout.reset() {
@@ -279,7 +520,7 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 "could not reset front timeout {:?}",
                 self.configured_frontend_timeout
             );
-            self.print_state(self.protocol_string());
+            self.print_state(&self.protocol_string());
         }
 
         if let SessionStatus::DefaultAnswer(_, _, _) = self.status {
@@ -303,9 +544,19 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             return StateResult::Continue;
         }
 
-        let (size, socket_state) = self
-            .frontend_socket
-            .socket_read(self.request_stream.storage.space());
+        if self.too_many_pipelined_requests() {
+            // reset() makes the session readable again once a response is sent
+            incr!("http.pipelining_paused");
+            self.frontend_readiness.interest.remove(Ready::READABLE);
+            return self.readable_parse(metrics);
+        }
+
+        let space = self.request_stream.storage.space();
+        let read_size = match self.max_front_read_size {
+            Some(max_front_read_size) => space.len().min(max_front_read_size),
+            None => space.len(),
+        };
+        let (size, socket_state) = self.frontend_socket.socket_read(&mut space[..read_size]);
         debug!(
             "{}\tFRONT [{}->{:?}]: read {} bytes",
             self.log_context(),
@@ -318,9 +569,19 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             self.request_stream.storage.fill(size);
             count!("bytes_in", size as i64);
             metrics.bin += size;
+
+            // there may be more to read, but give way to the other sessions
+            if Some(size) == self.max_front_read_size && socket_state == SocketResult::Continue {
+                self.front_read_yielded = true;
+                yield_session(self.frontend_token);
+            }
             // if self.kawa_request.storage.is_full() {
             //     self.frontend_readiness.interest.remove(Ready::READABLE);
             // }
+
+            if self.request_headers_too_slow(size) {
+                return StateResult::Continue;
+            }
         } else {
             self.frontend_readiness.event.remove(Ready::READABLE);
         }
@@ -357,13 +618,143 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         self.readable_parse(metrics)
     }
 
+    /// Whether the request is for the health_check_path of the listener,
+    /// which it answers itself without going to a cluster
+    fn is_health_check(&self) -> bool {
+        let listener = self.listener.borrow();
+        let (Some(health_check_path), Some(path)) = (
+            listener.get_health_check_path(),
+            self.context.path.as_deref(),
+        ) else {
+            return false;
+        };
+        let path = path.split_once('?').map(|(path, _)| path).unwrap_or(path);
+        path == health_check_path
+    }
+
+    /// count a request rejected for a reason that could let a client smuggle
+    /// a request to the backend, and tag its access log with it
+    fn smuggling_detected(&mut self, vector: SmugglingVector) {
+        incr!("http.smuggling.detected");
+        warn!(
+            "{} request smuggling attempt: {}",
+            self.log_context(),
+            vector.message()
+        );
+        self.smuggling = Some(vector);
+    }
+
+    /// Whether the URI of the request, parsed or still being received,
+    /// is longer than max_uri_length
+    fn is_uri_too_long(&self) -> bool {
+        let storage = &self.request_stream.storage;
+        let buf = storage.used();
+        let length = match &self.request_stream.detached.status_line {
+            kawa::StatusLine::Request { uri, .. } => uri.data(buf).len(),
+            _ if matches!(
+                self.request_stream.parsing_phase,
+                kawa::ParsingPhase::StatusLine
+            ) =>
+            {
+                parser::pending_uri_length(&buf[storage.head..])
+            }
+            _ => 0,
+        };
+        length > self.max_uri_length
+    }
+
+    /// Whether the status line and headers of the response are longer than
+    /// max_response_header_size, received so far or parsed by the last call.
+    /// The head is measured until a call parses it completely
+    fn is_response_head_too_large(&self, was_main_phase: bool) -> bool {
+        let Some(max_response_header_size) = self.max_response_header_size else {
+            return false;
+        };
+        if was_main_phase {
+            return false;
+        }
+        let storage = &self.response_stream.storage;
+        parser::head_length(&storage.used()[storage.start..]) > max_response_header_size
+    }
+
+    /// Whether max_pipelined_requests requests were parsed from the front buffer
+    /// after the first one without it getting empty, what is left in it is
+    /// answered before reading more
+    fn too_many_pipelined_requests(&self) -> bool {
+        match self.max_pipelined_requests {
+            Some(max_pipelined_requests) => {
+                self.parsed_requests.saturating_sub(1) >= max_pipelined_requests
+            }
+            None => false,
+        }
+    }
+
+    /// Count the bytes read while the request headers are received, and answer
+    /// a 408 if they arrive below the min_request_header_rate of the listener.
+    /// Returns true if the request was answered
+    fn request_headers_too_slow(&mut self, size: usize) -> bool {
+        if self.request_stream.is_main_phase() {
+            return false;
+        }
+        let Some(header_rate) = &mut self.header_rate else {
+            return false;
+        };
+        if !header_rate.is_too_slow(size, Instant::now()) {
+            return false;
+        }
+
+        incr!("http.slow_request_headers");
+        warn!(
+            "{} request headers received below the minimum rate, closing",
+            self.log_context()
+        );
+        self.set_answer(DefaultAnswerStatus::Answer408, None);
+        true
+    }
+
+    /// Put the request in error if a chunk header parsed by the last call,
+    /// or the one the parser is waiting the end of, is out of the listener bounds
+    fn check_chunk_headers(&mut self, parsed_blocks: usize) {
+        if !matches!(
+            self.request_stream.parsing_phase,
+            kawa::ParsingPhase::Chunks { .. }
+        ) {
+            return;
+        }
+
+        let storage = &self.request_stream.storage;
+        let buf = storage.used();
+        let limits = self.chunk_limits;
+        let checked = self
+            .request_stream
+            .blocks
+            .iter()
+            .skip(parsed_blocks)
+            .try_for_each(|block| match block {
+                kawa::Block::ChunkHeader(header) => limits.check_header(header.length.data(buf)),
+                _ => Ok(()),
+            })
+            // the chunk data is parsed as it comes, what is left is a header line
+            // whose end did not come yet
+            .and_then(|_| limits.check_pending_header(storage.end - storage.head));
+
+        if let Err(message) = checked {
+            self.request_stream.parsing_phase = kawa::ParsingPhase::Error {
+                marker: kawa::ParsingPhaseMarker::Chunks,
+                kind: kawa::ParsingErrorKind::Processing { message },
+            };
+        }
+    }
+
     pub fn readable_parse(&mut self, _metrics: &mut SessionMetrics) -> StateResult {
         trace!("==============readable_parse");
         let was_initial = self.request_stream.is_initial();
         let was_not_proxying = !self.request_stream.is_main_phase();
+        let parsed_blocks = self.request_stream.blocks.len();
 
         kawa::h1::parse(&mut self.request_stream, &mut self.context);
         // kawa::debug_kawa(&self.request_stream);
+        self.check_chunk_headers(parsed_blocks);
 
         if was_initial && !self.request_stream.is_initial() {
             // if it was the first request, the front timeout duration
@@ -375,8 +766,37 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             incr!("http.requests");
         }
 
+        // checked before the parsing errors: a URI filling the buffer is too long,
+        // not malformed
+        if was_not_proxying && self.is_uri_too_long() {
+            incr!("http.frontend_parse_errors");
+            warn!(
+                "{} request URI is longer than {} bytes",
+                self.log_context(),
+                self.max_uri_length
+            );
+            if self.response_stream.consumed {
+                return StateResult::CloseSession;
+            } else {
+                self.set_answer(DefaultAnswerStatus::Answer414, None);
+                return StateResult::Continue;
+            }
+        }
+
         if let kawa::ParsingPhase::Error { marker, kind } = self.request_stream.parsing_phase {
             incr!("http.frontend_parse_errors");
+            self.request_error_event(EventKind::RequestParseError);
+            let smuggling = match kind {
+                kawa::ParsingErrorKind::Processing { .. } => self.context.smuggling,
+                kawa::ParsingErrorKind::Consuming { .. } => {
+                    let storage = &self.request_stream.storage;
+                    has_bare_line_feed(&storage.used()[storage.start..])
+                        .then_some(SmugglingVector::BareLineFeed)
+                }
+            };
+            if let Some(vector) = smuggling {
+                self.smuggling_detected(vector);
+            }
             warn!(
                 "{} Parsing request error in {:?}: {}",
                 self.log_context(),
@@ -406,9 +826,78 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             }
         }
 
+        if self.context.invalid_transfer_encoding {
+            incr!("http.frontend_parse_errors");
+            self.smuggling_detected(SmugglingVector::InvalidTransferEncoding);
+            warn!(
+                "{} Parsing request error: chunked is not the last coding of Transfer-Encoding",
+                self.log_context()
+            );
+            if self.response_stream.consumed {
+                return StateResult::CloseSession;
+            } else {
+                self.set_answer(DefaultAnswerStatus::Answer400, None);
+                return StateResult::Continue;
+            }
+        }
+
+        if let (Some(max_chunked_body_size), kawa::BodySize::Chunked) =
+            (self.max_chunked_body_size, &self.request_stream.body_size)
+        {
+            // blocks are only removed from the front when writing to the backend,
+            // so the chunks parsed by this call are at the end
+            self.chunked_body_size += self
+                .request_stream
+                .blocks
+                .iter()
+                .skip(parsed_blocks)
+                .map(|block| match block {
+                    kawa::Block::Chunk(chunk) => chunk.data.len() as u64,
+                    _ => 0,
+                })
+                .sum::<u64>();
+
+            if self.chunked_body_size > max_chunked_body_size {
+                warn!(
+                    "{} chunked request body is larger than {} bytes, closing",
+                    self.log_context(),
+                    max_chunked_body_size
+                );
+                if self.response_stream.consumed {
+                    return StateResult::CloseSession;
+                } else {
+                    self.set_answer(DefaultAnswerStatus::Answer413, None);
+                    return StateResult::Continue;
+                }
+            }
+        }
+
         if self.request_stream.is_main_phase() {
             self.backend_readiness.interest.insert(Ready::WRITABLE);
             if was_not_proxying {
+                self.parsed_requests += 1;
+                // a request received in early data can be replayed by an attacker,
+                // only let through the ones that cannot change the state of the backend
+                if self.context.early_data
+                    && !self
+                        .context
+                        .method
+                        .as_ref()
+                        .map(Method::is_replay_safe)
+                        .unwrap_or(false)
+                {
+                    incr!("tls.early_data.rejected");
+                    self.set_answer(DefaultAnswerStatus::Answer425, None);
+                    return StateResult::Continue;
+                }
+                if self.is_health_check() {
+                    let answer = b"HTTP/1.1 200 OK\r\nCache-Control: no-cache\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+                    self.set_answer(
+                        DefaultAnswerStatus::Answer200,
+                        Some(Rc::new(answer.to_vec())),
+                    );
+                    return StateResult::Continue;
+                }
                 // Sozu tries to connect only once all the headers were gathered and edited
                 // this could be improved
                 trace!("============== HANDLE CONNECTION!");
@@ -434,10 +923,14 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         let bufs = self.response_stream.as_io_slice();
         if bufs.is_empty() {
             self.frontend_readiness.interest.remove(Ready::WRITABLE);
+            if self.back_read_paused {
+                self.resume_back_read();
+            }
             return StateResult::Continue;
         }
 
         let (size, socket_state) = self.frontend_socket.socket_write_vectored(&bufs);
+        self.response_slices += written_slices(&bufs, size);
         debug!(
             "{}\tFRONT [{}<-{:?}]: wrote {} bytes",
             self.log_context(),
@@ -450,7 +943,13 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             self.response_stream.consume(size);
             count!("bytes_out", size as i64);
             metrics.bout += size;
-            self.backend_readiness.interest.insert(Ready::READABLE);
+            if !self.back_read_paused
+                || self
+                    .back_read_watermarks
+                    .should_resume(unwritten_data(&self.response_stream.storage))
+            {
+                self.resume_back_read();
+            }
         } else {
             self.frontend_readiness.event.remove(Ready::WRITABLE);
         }
@@ -477,6 +976,13 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         if self.response_stream.is_terminated() && self.response_stream.is_completed() {
             save_http_status_metric(self.context.status, self.log_context());
 
+            // slicing efficiency: how many slices the response was written from
+            histogram!("http.response.slices", self.response_slices);
+            if let Some(cluster_id) = self.cluster_id.as_deref() {
+                histogram!("http.response.slices", cluster_id, self.response_slices);
+            }
+            self.response_slices = 0;
+
             self.log_request_success(metrics);
             metrics.reset();
 
@@ -488,6 +994,9 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             match self.response_stream.detached.status_line {
                 kawa::StatusLine::Response { code: 101, .. } => {
                     trace!("============== HANDLE UPGRADE!");
+                    // the connection serves no more requests once upgraded
+                    self.requests_served += 1;
+                    self.record_keepalive_requests();
                     return StateResult::Upgrade;
                 }
                 kawa::StatusLine::Response { code: 100, .. } => {
@@ -513,6 +1022,18 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 // return StateResult::CloseSession;
             }
 
+            self.requests_served += 1;
+
+            if self.context.keep_alive_backend && self.has_stray_response_bytes() {
+                warn!(
+                    "{} backend sent a body with a {:?} response, closing the backend connection",
+                    self.log_context(),
+                    self.context.status
+                );
+                incr!("http.stray_response_body");
+                self.context.keep_alive_backend = false;
+            }
+
             // FIXME: we could get smarter about this
             // with no keepalive on backend, we could open a new backend ConnectionError
             // with no keepalive on front but keepalive on backend, we could have
@@ -630,7 +1151,7 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 "could not reset back timeout {:?}",
                 self.configured_backend_timeout
             );
-            self.print_state(self.protocol_string());
+            self.print_state(&self.protocol_string());
         }
 
         if let SessionStatus::DefaultAnswer(_, _, _) = self.status {
@@ -650,8 +1171,14 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             return SessionResult::Close;
         };
 
-        if self.response_stream.storage.is_full() {
+        if self.response_stream.storage.is_full()
+            || (self.response_stream.is_main_phase()
+                && self
+                    .back_read_watermarks
+                    .should_pause(unwritten_data(&self.response_stream.storage)))
+        {
             self.backend_readiness.interest.remove(Ready::READABLE);
+            self.back_read_paused = true;
             if self.response_stream.is_main_phase() {
                 self.frontend_readiness.interest.insert(Ready::WRITABLE);
             } else {
@@ -674,6 +1201,7 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             self.response_stream.storage.fill(size);
             count!("back_bytes_in", size as i64);
             metrics.backend_bin += size;
+            metrics.backend_first_byte();
             // if self.kawa_response.storage.is_full() {
             //     self.backend_readiness.interest.remove(Ready::READABLE);
             // }
@@ -695,6 +1223,16 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 );
                 return SessionResult::Close;
             }
+            // the backend got a request and closed without answering anything
+            SocketResult::Closed
+                if self.response_stream.storage.is_empty()
+                    && self.response_stream.is_initial()
+                    && !self.request_stream.is_initial() =>
+            {
+                self.backend_readiness.event.remove(Ready::READABLE);
+                self.set_empty_response_answer();
+                return SessionResult::Continue;
+            }
             SocketResult::WouldBlock | SocketResult::Closed => {
                 self.backend_readiness.event.remove(Ready::READABLE);
             }
@@ -704,8 +1242,33 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         self.backend_readable_parse(metrics)
     }
 
+    /// The backend closed its connection before sending any byte of the response.
+    /// The request is not retried on another backend, even if it is idempotent:
+    /// its bytes were consumed from the buffer while they were written to the
+    /// backend, replaying it would need a copy of every request
+    fn set_empty_response_answer(&mut self) {
+        incr!(
+            "backend.empty_response",
+            self.cluster_id.as_deref(),
+            self.backend_id.as_deref()
+        );
+        warn!(
+            "{} the backend closed the connection without sending a response",
+            self.log_context()
+        );
+        self.set_answer(DefaultAnswerStatus::Answer502, None);
+        self.backend_readiness.interest = Ready::EMPTY;
+    }
+
+    fn resume_back_read(&mut self) {
+        self.back_read_paused = false;
+        self.backend_readiness.interest.insert(Ready::READABLE);
+    }
+
     pub fn backend_readable_parse(&mut self, metrics: &mut SessionMetrics) -> SessionResult {
         trace!("==============backend_readable_parse");
+        let parsed_blocks = self.response_stream.blocks.len();
+        let was_main_phase = self.response_stream.is_main_phase();
         kawa::h1::parse(&mut self.response_stream, &mut self.context);
         // kawa::debug_kawa(&self.response_stream);
 
@@ -740,12 +1303,94 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             }
         }
 
+        if self.is_response_head_too_large(was_main_phase) {
+            incr!(
+                "http.response_headers_too_large",
+                self.cluster_id.as_deref(),
+                self.backend_id.as_deref()
+            );
+            self.context.keep_alive_backend = false;
+            if self.response_stream.consumed {
+                self.log_request_error(metrics, "response head is too large, closing");
+                return SessionResult::Close;
+            }
+            warn!(
+                "{} response head is larger than {:?} bytes, closing the backend connection",
+                self.log_context(),
+                self.max_response_header_size
+            );
+            self.set_answer(DefaultAnswerStatus::Answer502, None);
+            return SessionResult::Continue;
+        }
+
+        if let Some(handshake_error) = self.context.websocket_handshake_error.take() {
+            incr!("http.websocket.handshake_errors");
+            warn!(
+                "{} Invalid websocket handshake from the backend: {}",
+                self.log_context(),
+                handshake_error
+            );
+            self.set_answer(DefaultAnswerStatus::Answer502, None);
+            return SessionResult::Continue;
+        }
+
+        // without Content-Length nor chunked encoding, the response body lasts
+        // until the backend closes the connection, which may never happen
+        if let (Some(max_size), kawa::BodySize::Empty) = (
+            self.max_close_delimited_response_size,
+            &self.response_stream.body_size,
+        ) {
+            // blocks are only removed from the front when writing to the client,
+            // so the chunks parsed by this call are at the end
+            self.close_delimited_response_size += self
+                .response_stream
+                .blocks
+                .iter()
+                .skip(parsed_blocks)
+                .map(|block| match block {
+                    kawa::Block::Chunk(chunk) => chunk.data.len() as u64,
+                    _ => 0,
+                })
+                .sum::<u64>();
+
+            if self.close_delimited_response_size > max_size {
+                incr!("http.close_delimited_response_too_large");
+                if self.response_stream.consumed {
+                    self.log_request_error(
+                        metrics,
+                        &format!(
+                            "close delimited response is larger than {max_size} bytes, closing"
+                        ),
+                    );
+                    return SessionResult::Close;
+                } else {
+                    warn!(
+                        "{} close delimited response is larger than {} bytes",
+                        self.log_context(),
+                        max_size
+                    );
+                    self.set_answer(DefaultAnswerStatus::Answer502, None);
+                    return SessionResult::Continue;
+                }
+            }
+        }
+
+        if self.context.head_as_get {
+            self.drop_response_body(parsed_blocks, was_main_phase);
+        }
+
         if self.response_stream.is_main_phase() {
             self.frontend_readiness.interest.insert(Ready::WRITABLE);
+            if !was_main_phase {
+                self.check_response_status();
+                self.detect_outlier();
+            }
+            if let Some(retry_after) = self.context.retry_after.take() {
+                self.honor_retry_after(retry_after);
+            }
         }
         if self.response_stream.is_terminated() {
             metrics.backend_stop();
-            self.backend_stop = Some(Instant::now());
             self.backend_readiness.interest.remove(Ready::READABLE);
         }
         SessionResult::Continue
@@ -779,8 +1424,8 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
     }
 
     // The protocol name used in the access logs
-    fn protocol_string(&self) -> &'static str {
-        match self.context.protocol {
+    fn protocol_string(&self) -> Cow<'static, str> {
+        let transport = match self.context.protocol {
             Protocol::HTTP => "HTTP",
             Protocol::HTTPS => match self.frontend_socket.protocol() {
                 TransportProtocol::Ssl2 => "HTTPS-SSL2",
@@ -792,7 +1437,8 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 _ => unreachable!(),
             },
             _ => unreachable!(),
-        }
+        };
+        protocol_with_alpn(transport, self.frontend_socket.alpn_protocol())
     }
 
     /// Format the context of the websocket into a loggable String
@@ -802,13 +1448,23 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             Endpoint::Http {
                 method: self.context.method.as_ref(),
                 authority: self.context.authority.as_deref(),
-                path: self.context.path.as_deref(),
+                path: self.logged_path().as_deref(),
                 status: self.context.status,
                 reason: self.context.reason.as_deref(),
             }
         )
     }
 
+    /// the path of the request as written in the access logs
+    fn logged_path(&self) -> Option<Cow<str>> {
+        let path = self.context.path.as_deref()?;
+        if self.listener.borrow().get_access_logs_redact_query() {
+            Some(redact_query(path))
+        } else {
+            Some(Cow::Borrowed(path))
+        }
+    }
+
     pub fn log_request(&self, metrics: &SessionMetrics, message: Option<&str>) {
         let listener = self.listener.borrow();
         let tags = self.context.authority.as_ref().and_then(|host| {
@@ -818,9 +1474,19 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             };
             listener.get_concatenated_tags(hostname)
         });
-        let status = match self.status {
-            SessionStatus::Normal => self.context.status,
-            SessionStatus::DefaultAnswer(answers, ..) => Some(answers.into()),
+        let smuggling_tags = self.smuggling.map(|vector| match tags {
+            Some(tags) if !tags.is_empty() => format!("{tags}, smuggling={}", vector.as_str()),
+            _ => format!("smuggling={}", vector.as_str()),
+        });
+        let tags = smuggling_tags.as_deref().or(tags);
+        let (status, reason) = match self.status {
+            SessionStatus::Normal => (
+                self.context.status,
+                non_standard_reason(self.context.status, self.context.reason.as_deref()),
+            ),
+            SessionStatus::DefaultAnswer(answer, ..) => {
+                (Some(answer.into()), Some(answer.reason()))
+            }
         };
 
         RequestRecord {
@@ -828,21 +1494,27 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             context: self.log_context(),
             session_address: self.get_session_address(),
             backend_address: self.get_backend_address(),
-            protocol: self.protocol_string(),
+            protocol: &self.protocol_string(),
             endpoint: Endpoint::Http {
                 method: self.context.method.as_ref(),
                 authority: self.context.authority.as_deref(),
-                path: self.context.path.as_deref(),
+                path: self.logged_path().as_deref(),
                 status,
-                reason: self.context.reason.as_deref(),
+                reason,
             },
             tags,
             client_rtt: socket_rtt(self.front_socket()),
             server_rtt: self.backend_socket.as_ref().and_then(socket_rtt),
+            backend_connection: self.backend_connect_action,
             metrics,
-            user_agent: self.context.user_agent.as_deref(),
+            user_agent: redact_header(
+                "User-Agent",
+                self.context.user_agent.as_deref(),
+                listener.get_access_logs_redacted_headers(),
+            ),
+            format: listener.get_access_logs_format(),
         }
-        .log();
+        .log_sampled(listener.get_access_logs_sampling());
     }
 
     pub fn log_request_success(&self, metrics: &SessionMetrics) {
@@ -858,7 +1530,7 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             self.log_context(),
             message
         );
-        self.print_state(self.protocol_string());
+        self.print_state(&self.protocol_string());
         self.log_request(metrics, Some(message));
     }
 
@@ -869,50 +1541,11 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 status, answer
             );
         } else {
-            match answer {
-                DefaultAnswerStatus::Answer301 => incr!(
-                    "http.301.redirection",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-                DefaultAnswerStatus::Answer400 => incr!("http.400.errors"),
-                DefaultAnswerStatus::Answer401 => incr!(
-                    "http.401.errors",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-                DefaultAnswerStatus::Answer404 => incr!("http.404.errors"),
-                DefaultAnswerStatus::Answer408 => incr!(
-                    "http.408.errors",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-                DefaultAnswerStatus::Answer413 => incr!(
-                    "http.413.errors",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-                DefaultAnswerStatus::Answer502 => incr!(
-                    "http.502.errors",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-                DefaultAnswerStatus::Answer503 => incr!(
-                    "http.503.errors",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-                DefaultAnswerStatus::Answer504 => incr!(
-                    "http.504.errors",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-                DefaultAnswerStatus::Answer507 => incr!(
-                    "http.507.errors",
-                    self.cluster_id.as_deref(),
-                    self.backend_id.as_deref()
-                ),
-            };
+            count_default_answer(
+                answer,
+                self.cluster_id.as_deref(),
+                self.backend_id.as_deref(),
+            );
         }
 
         let buf = buf.unwrap_or_else(|| {
@@ -925,29 +1558,29 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         self.backend_readiness.interest = Ready::HUP | Ready::ERROR;
     }
 
+    /// Like `set_answer` with the default answer, carrying additional headers.
+    /// Each header ends with CRLF
+    pub fn set_answer_with_headers(&mut self, answer: DefaultAnswerStatus, extra_headers: &str) {
+        let buf = self.answers.borrow().get_with_headers(
+            answer,
+            self.cluster_id.as_deref(),
+            extra_headers,
+        );
+        self.set_answer(answer, Some(buf));
+    }
+
     fn writable_default_answer(&mut self, metrics: &mut SessionMetrics) -> StateResult {
         let res = match self.status {
-            SessionStatus::DefaultAnswer(status, ref buf, mut index) => {
-                let len = buf.len();
-
-                let mut sz = 0usize;
-                let mut res = SocketResult::Continue;
-                while res == SocketResult::Continue && index < len {
-                    let (current_sz, current_res) =
-                        self.frontend_socket.socket_write(&buf[index..]);
-                    res = current_res;
-                    sz += current_sz;
-                    index += current_sz;
-                }
+            // the index is written back in the status, so that a partially
+            // written answer resumes where it stopped
+            SessionStatus::DefaultAnswer(status, ref buf, ref mut index) => {
+                let (sz, res) = write_default_answer(&mut self.frontend_socket, buf, index);
+                let done = *index == buf.len();
 
                 count!("bytes_out", sz as i64);
                 metrics.bout += sz;
 
-                if res != SocketResult::Continue {
-                    self.frontend_readiness.event.remove(Ready::WRITABLE);
-                }
-
-                if index == len {
+                if done {
                     save_http_status_metric(Some(status.into()), self.log_context());
                     self.log_default_answer_success(metrics);
                     self.frontend_readiness.reset();
@@ -960,15 +1593,28 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             _ => return StateResult::CloseSession,
         };
 
-        if res == SocketResult::Error {
-            self.frontend_socket.write_error();
-            self.log_request_error(
-                metrics,
-                "error writing default answer to front socket, closing",
-            );
-            StateResult::CloseSession
-        } else {
-            StateResult::Continue
+        match res {
+            SocketResult::Error => {
+                self.frontend_socket.write_error();
+                self.log_request_error(
+                    metrics,
+                    "error writing default answer to front socket, closing",
+                );
+                StateResult::CloseSession
+            }
+            SocketResult::Closed => {
+                self.log_request_error(
+                    metrics,
+                    "front socket closed before the default answer was written, closing",
+                );
+                StateResult::CloseSession
+            }
+            // wait for the next writable event to send the rest of the answer
+            SocketResult::WouldBlock | SocketResult::Continue => {
+                self.frontend_readiness.event.remove(Ready::WRITABLE);
+                self.frontend_readiness.interest.insert(Ready::WRITABLE);
+                StateResult::Continue
+            }
         }
     }
 
@@ -989,20 +1635,41 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         }
     }
 
-    pub fn is_valid_backend_socket(&self) -> bool {
-        // if socket was last used in the last second, test it
-        match self.backend_stop.as_ref() {
-            Some(stop_instant) => {
-                let now = Instant::now();
-                let dur = now - *stop_instant;
-                if dur > Duration::seconds(1) {
-                    return self.test_backend_socket();
-                }
-            }
-            None => return self.test_backend_socket(),
+    /// Whether a kept alive backend connection is still open and the backend sent
+    /// nothing after its last response. Bytes arriving while it was idle, like a body
+    /// sent late after a 204 or 304, would be parsed as the next response
+    fn is_idle_backend_socket(&self) -> bool {
+        match &self.backend_socket {
+            Some(socket) => match socket.peek(&mut [0u8; 1]) {
+                // half open, or stray bytes from the backend
+                Ok(_) => false,
+                Err(e) => matches!(e.kind(), std::io::ErrorKind::WouldBlock),
+            },
+            None => false,
         }
+    }
 
-        true
+    /// A 204 or 304 response has no body, whatever its headers say. The bytes a
+    /// backend sends after it would be parsed as the beginning of the next response
+    /// on the connection, so it must not be reused. The bytes arriving later are
+    /// found when the connection is tested before its reuse
+    fn has_stray_response_bytes(&self) -> bool {
+        if !matches!(self.context.status, Some(204 | 304)) {
+            return false;
+        }
+        if !self.response_stream.storage.is_empty() {
+            return true;
+        }
+        match &self.backend_socket {
+            Some(socket) => matches!(socket.peek(&mut [0u8; 1]), Ok(size) if size > 0),
+            None => false,
+        }
+    }
+
+    /// A kept alive backend connection is tested before each reuse, bytes sent
+    /// by the backend while it was idle are only visible there
+    pub fn is_valid_backend_socket(&self) -> bool {
+        self.is_idle_backend_socket()
     }
 
     pub fn set_backend_socket(&mut self, socket: TcpStream, backend: Option<Rc<RefCell<Backend>>>) {
@@ -1089,8 +1756,11 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
     }
 
     /// Check the number of connection attempts against authorized connection retries
-    fn check_circuit_breaker(&mut self) -> Result<(), BackendConnectionError> {
-        if self.connection_attempts >= CONN_RETRIES {
+    fn check_circuit_breaker(
+        &mut self,
+        connection_retries: Option<u32>,
+    ) -> Result<(), BackendConnectionError> {
+        if self.connection_attempts >= max_connection_attempts(connection_retries) {
             error!("{} max connection attempt reached", self.log_context());
             self.set_answer(DefaultAnswerStatus::Answer503, None);
             return Err(BackendConnectionError::MaxConnectionRetries(None));
@@ -1148,15 +1818,40 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             }
         };
 
+        // the client should retry on a connection opened for this host
+        if let (Some(server_name), Some(hostname)) =
+            (self.frontend_socket.server_name(), authority_hostname(host))
+        {
+            if self.listener.borrow().is_misdirected(hostname, server_name) {
+                debug!(
+                    "{} host {} is not covered by the certificate of the server name {}",
+                    self.log_context(),
+                    host,
+                    server_name
+                );
+                self.set_answer(DefaultAnswerStatus::Answer421, None);
+                return Err(RetrieveClusterError::MisdirectedRequest);
+            }
+        }
+
         let route_result = self
             .listener
             .borrow()
             .frontend_from_request(host, uri, method);
 
-        let route = match route_result {
-            Ok(route) => route,
+        let (route, options) = match route_result {
+            Ok(route_and_options) => route_and_options,
             Err(frontend_error) => {
-                self.set_answer(DefaultAnswerStatus::Answer404, None);
+                match &frontend_error {
+                    FrontendFromRequestError::NoClusterFound(RouterError::MethodNotAllowed {
+                        allowed,
+                        ..
+                    }) => self.set_answer_with_headers(
+                        DefaultAnswerStatus::Answer405,
+                        &format!("Allow: {}\r\n", allowed.join(", ")),
+                    ),
+                    _ => self.set_answer(DefaultAnswerStatus::Answer404, None),
+                }
                 return Err(RetrieveClusterError::RetrieveFrontend(frontend_error));
             }
         };
@@ -1169,11 +1864,35 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             }
         };
 
-        let frontend_should_redirect_https = matches!(proxy.borrow().kind(), ListenerType::Http)
-            && proxy
-                .borrow()
-                .clusters()
-                .get(&cluster_id)
+        let client_allowed = proxy
+            .borrow()
+            .ip_filter(&cluster_id)
+            .map(|ip_filter| {
+                ip_filter.check(
+                    self.context.session_address.map(|addr| addr.ip()),
+                    self.context.forwarded_for,
+                )
+            })
+            .unwrap_or(true);
+
+        if !client_allowed {
+            debug!(
+                "{} client {:?} is not allowed on cluster {}",
+                self.log_context(),
+                self.context.session_address,
+                cluster_id
+            );
+            incr!("http.ip_filter.denied", Some(cluster_id.as_str()), None);
+            self.set_answer(DefaultAnswerStatus::Answer403, None);
+            return Err(RetrieveClusterError::UnauthorizedRoute);
+        }
+
+        // the cluster is looked up once for all its checks
+        let proxy = proxy.borrow();
+        let cluster = proxy.clusters().get(&cluster_id);
+
+        let frontend_should_redirect_https = matches!(proxy.kind(), ListenerType::Http)
+            && cluster
                 .map(|cluster| cluster.https_redirect)
                 .unwrap_or(false);
 
@@ -1186,9 +1905,168 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             return Err(RetrieveClusterError::UnauthorizedRoute);
         }
 
+        if let Some(challenges) =
+            cluster.and_then(|cluster| self.check_authentication(&cluster_id, cluster))
+        {
+            let answer = format!(
+                "HTTP/1.1 401 Unauthorized\r\nCache-Control: no-cache\r\nConnection: close\r\n{challenges}Content-Length: 0\r\n\r\n"
+            );
+            self.set_answer(
+                DefaultAnswerStatus::Answer401,
+                Some(Rc::new(answer.into_bytes())),
+            );
+            return Err(RetrieveClusterError::UnauthorizedRoute);
+        }
+
+        // the client waits for a "100 Continue" before sending the body,
+        // refuse it early so that the backend never receives the upload
+        if self.context.expect_continue {
+            let decision = cluster
+                .map(|cluster| {
+                    authorize_expect_continue(
+                        self.context.authorization.as_deref().map(str::as_bytes),
+                        &cluster.expect_continue_authorizations,
+                    )
+                })
+                .unwrap_or(ExpectContinueDecision::Continue);
+
+            match decision {
+                ExpectContinueDecision::Continue => {}
+                ExpectContinueDecision::Unauthorized => {
+                    let challenges = cluster
+                        .map(|cluster| {
+                            www_authenticate_headers(
+                                authorization_schemes(&cluster.expect_continue_authorizations),
+                                cluster
+                                    .authentication_realm
+                                    .as_deref()
+                                    .unwrap_or(&cluster_id),
+                            )
+                        })
+                        .unwrap_or_default();
+                    self.set_answer_with_headers(DefaultAnswerStatus::Answer401, &challenges);
+                    return Err(RetrieveClusterError::UnauthorizedRoute);
+                }
+                ExpectContinueDecision::Forbidden => {
+                    self.set_answer(DefaultAnswerStatus::Answer403, None);
+                    return Err(RetrieveClusterError::UnauthorizedRoute);
+                }
+            }
+        }
+
+        // the borrow of the request method ends here, the context is modified below
+        let forward_head_as_get = options.head_as_get && *method == Method::Head;
+
+        // offer the backend only the subprotocols the frontend allows, and refuse the upgrade
+        // before it reaches the backend if none is, the session would become a pipe relaying
+        // a subprotocol the frontend does not want
+        if self.context.websocket_key.is_some()
+            && !self.context.websocket_protocols.is_empty()
+            && !self.context.restrict_websocket_protocols(
+                &mut self.request_stream,
+                &options.websocket_subprotocols,
+            )
+        {
+            let protocols = self.context.websocket_protocols.join(", ");
+            debug!(
+                "{} websocket subprotocols {} are not allowed",
+                self.log_context(),
+                protocols
+            );
+            incr!(
+                "http.websocket.disallowed_subprotocol",
+                Some(cluster_id.as_str()),
+                None
+            );
+            self.set_answer(DefaultAnswerStatus::Answer400, None);
+            return Err(RetrieveClusterError::DisallowedWebSocketProtocol(protocols));
+        }
+
+        if forward_head_as_get {
+            self.forward_head_as_get();
+        }
+
         Ok(cluster_id)
     }
 
+    /// Rewrite the method of the request line to send a HEAD request as a GET,
+    /// the body of the response is dropped while it is parsed
+    fn forward_head_as_get(&mut self) {
+        if let kawa::StatusLine::Request { method, .. } =
+            &mut self.request_stream.detached.status_line
+        {
+            *method = kawa::Store::Static(b"GET");
+        }
+        self.context.head_as_get = true;
+    }
+
+    /// Remove the body of the response parsed by the last call, from the blocks
+    /// following the end of its headers, so that only the headers reach the client
+    fn drop_response_body(&mut self, parsed_blocks: usize, was_main_phase: bool) {
+        let body_start = if was_main_phase {
+            parsed_blocks
+        } else {
+            match self
+                .response_stream
+                .blocks
+                .iter()
+                .skip(parsed_blocks)
+                .position(|block| {
+                    matches!(
+                        block,
+                        kawa::Block::Flags(kawa::Flags {
+                            end_header: true,
+                            ..
+                        })
+                    )
+                }) {
+                Some(index) => parsed_blocks + index + 1,
+                // the headers are not complete yet
+                None => return,
+            }
+        };
+        self.response_stream.blocks.truncate(body_start);
+    }
+
+    /// If the cluster requires HTTP authentication and the request does not
+    /// present valid credentials, returns the schemes to offer to the client
+    fn check_authentication(&self, cluster_id: &str, cluster: &Cluster) -> Option<String> {
+        if cluster.basic_auth_credentials.is_empty() && cluster.bearer_tokens.is_empty() {
+            return None;
+        }
+
+        let authorized = self
+            .context
+            .authorization
+            .as_deref()
+            .map(|authorization| {
+                check_authorization(
+                    authorization.as_bytes(),
+                    &cluster.basic_auth_credentials,
+                    &cluster.bearer_tokens,
+                )
+            })
+            .unwrap_or(false);
+        if authorized {
+            return None;
+        }
+
+        let mut schemes = Vec::new();
+        if !cluster.basic_auth_credentials.is_empty() {
+            schemes.push("Basic");
+        }
+        if !cluster.bearer_tokens.is_empty() {
+            schemes.push("Bearer");
+        }
+        Some(www_authenticate_headers(
+            schemes,
+            cluster
+                .authentication_realm
+                .as_deref()
+                .unwrap_or(cluster_id),
+        ))
+    }
+
     pub fn backend_from_request(
         &mut self,
         cluster_id: &str,
@@ -1236,17 +2114,29 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         cluster_id: &str,
         proxy: Rc<RefCell<dyn L7Proxy>>,
     ) -> Result<(Rc<RefCell<Backend>>, TcpStream), BackendError> {
+        let buffer_sizes = self.listener.borrow().get_socket_buffer_sizes();
         match (frontend_should_stick, sticky_session) {
-            (true, Some(sticky_session)) => proxy
+            // the first connection attempt goes to the backend of the sticky session
+            (true, Some(sticky_session)) if self.connection_attempts == 0 => proxy
                 .borrow()
                 .backends()
                 .borrow_mut()
-                .backend_from_sticky_session(cluster_id, sticky_session),
+                .backend_from_sticky_session(cluster_id, sticky_session, buffer_sizes),
+            // it could not be reached, the load balancing policy selects
+            // another backend and the client will get a new sticky cookie
+            (true, Some(_)) => {
+                incr!("sticky.fallback", Some(cluster_id), None);
+                proxy
+                    .borrow()
+                    .backends()
+                    .borrow_mut()
+                    .backend_from_cluster_id(cluster_id, buffer_sizes)
+            }
             _ => proxy
                 .borrow()
                 .backends()
                 .borrow_mut()
-                .backend_from_cluster_id(cluster_id),
+                .backend_from_cluster_id(cluster_id, buffer_sizes),
         }
     }
 
@@ -1259,12 +2149,72 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
         let old_cluster_id = self.cluster_id.clone();
         let old_backend_token = self.backend_token;
 
-        self.check_circuit_breaker()?;
-
         let cluster_id = self
             .cluster_id_from_request(proxy.clone())
             .map_err(BackendConnectionError::RetrieveClusterError)?;
 
+        // the cluster is looked up once, its settings apply to this request
+        let frontend_should_stick = {
+            let proxy = proxy.borrow();
+            let cluster = proxy.clusters().get(&cluster_id);
+
+            self.check_circuit_breaker(cluster.and_then(|cluster| cluster.connection_retries))?;
+
+            self.context.suppressed_headers = cluster
+                .map(|cluster| {
+                    cluster
+                        .suppressed_headers
+                        .iter()
+                        .filter_map(|header| AddedHeader::try_from(*header).ok())
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.failure_status_codes = cluster
+                .map(|cluster| {
+                    cluster
+                        .failure_status_codes
+                        .iter()
+                        .filter_map(|status| u16::try_from(*status).ok())
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.outlier_detection = cluster.and_then(|cluster| {
+                let threshold = cluster.outlier_consecutive_5xx.filter(|t| *t > 0)?;
+                let ejection_time = cluster
+                    .outlier_ejection_time
+                    .unwrap_or(DEFAULT_OUTLIER_EJECTION_TIME);
+                Some((
+                    threshold,
+                    std::time::Duration::from_secs(ejection_time as u64),
+                ))
+            });
+            let rewrite_location = cluster
+                .and_then(|cluster| cluster.rewrite_location)
+                .unwrap_or(false);
+            self.context.location_rewrite = if rewrite_location {
+                proxy.backends().borrow().backend_addresses(&cluster_id)
+            } else {
+                Vec::new()
+            };
+            (
+                self.context.cookie_domain_rewrite,
+                self.context.cookie_path_prefix,
+            ) = cluster
+                .map(|cluster| {
+                    (
+                        cluster.rewrite_cookie_domain.unwrap_or(false),
+                        cluster.cookie_path_prefix.clone(),
+                    )
+                })
+                .unwrap_or_default();
+
+            cluster
+                .map(|cluster| cluster.sticky_session)
+                .unwrap_or(false)
+        };
+        self.context
+            .elide_suppressed_headers(&mut self.request_stream);
+
         trace!(
             "connect_to_backend: {:?} {:?} {:?}",
             self.cluster_id,
@@ -1289,6 +2239,7 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 .unwrap_or(false);
 
             if has_backend && self.check_backend_connection(metrics) {
+                self.backend_connect_action = Some(BackendConnectAction::Reuse);
                 return Ok(BackendConnectAction::Reuse);
             } else if self.backend_token.take().is_some() {
                 self.close_backend(proxy.clone(), metrics);
@@ -1305,13 +2256,6 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
 
         self.cluster_id = Some(cluster_id.clone());
 
-        let frontend_should_stick = proxy
-            .borrow()
-            .clusters()
-            .get(&cluster_id)
-            .map(|cluster| cluster.sticky_session)
-            .unwrap_or(false);
-
         let mut socket =
             self.backend_from_request(&cluster_id, frontend_should_stick, proxy.clone(), metrics)?;
         if let Err(e) = socket.set_nodelay(true) {
@@ -1338,6 +2282,7 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 self.set_backend_socket(socket, self.backend.clone());
                 self.set_backend_timeout(self.configured_connect_timeout);
 
+                self.backend_connect_action = Some(BackendConnectAction::Replace);
                 Ok(BackendConnectAction::Replace)
             }
             None => {
@@ -1355,16 +2300,18 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 self.set_backend_token(backend_token);
                 self.set_backend_timeout(self.configured_connect_timeout);
 
+                self.backend_connect_action = Some(BackendConnectAction::New);
                 Ok(BackendConnectAction::New)
             }
         }
     }
 
+    /// returns true if the backend was down and the connection brought it back up
     fn set_backend_connected(
         &mut self,
         connected: BackendConnectionStatus,
         metrics: &mut SessionMetrics,
-    ) {
+    ) -> bool {
         let last = self.backend_connection_status;
         self.backend_connection_status = connected;
 
@@ -1389,71 +2336,158 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             if let Some(backend) = &self.backend {
                 let mut backend = backend.borrow_mut();
 
-                if backend.retry_policy.is_down() {
-                    incr!(
-                        "backend.up",
-                        self.cluster_id.as_deref(),
-                        metrics.backend_id.as_deref()
-                    );
-
-                    info!(
-                        "backend server {} at {} is up",
-                        backend.backend_id, backend.address
-                    );
-
-                    push_event(Event {
-                        kind: EventKind::BackendUp as i32,
-                        backend_id: Some(backend.backend_id.to_owned()),
-                        address: Some(backend.address.to_string()),
-                        cluster_id: None,
-                    });
-                }
-
                 if let BackendConnectionStatus::Connecting(start) = last {
                     backend.set_connection_time(Instant::now() - start);
                 }
 
-                //successful connection, reset failure counter
-                backend.failures = 0;
                 backend.active_requests += 1;
-                backend.retry_policy.succeed();
+                // with failure statuses, the backend is healthy once it answers properly
+                if self.failure_status_codes.is_empty() {
+                    return succeed_backend(
+                        &mut backend,
+                        self.cluster_id.as_deref(),
+                        metrics.backend_id.as_deref(),
+                    );
+                }
             }
         }
+        false
     }
 
-    fn fail_backend_connection(&mut self, metrics: &SessionMetrics) {
+    /// returns true if this failure brought the backend down
+    fn fail_backend_connection(&mut self, metrics: &SessionMetrics) -> bool {
+        let mut went_down = false;
         if let Some(backend) = &self.backend {
-            let mut backend = backend.borrow_mut();
-            backend.failures += 1;
-
-            let already_unavailable = backend.retry_policy.is_down();
-            backend.retry_policy.fail();
             incr!(
                 "backend.connections.error",
                 self.cluster_id.as_deref(),
                 metrics.backend_id.as_deref()
             );
-            if !already_unavailable && backend.retry_policy.is_down() {
-                error!(
-                    "backend server {} at {} is down",
-                    backend.backend_id, backend.address
-                );
-                incr!(
-                    "backend.down",
-                    self.cluster_id.as_deref(),
-                    metrics.backend_id.as_deref()
-                );
+            went_down = fail_backend(
+                &mut backend.borrow_mut(),
+                self.cluster_id.as_deref(),
+                metrics.backend_id.as_deref(),
+            );
+        }
+        self.request_error_event(EventKind::BackendConnectionError);
+        went_down
+    }
 
-                push_event(Event {
-                    kind: EventKind::BackendDown as i32,
-                    backend_id: Some(backend.backend_id.to_owned()),
-                    address: Some(backend.address.to_string()),
-                    cluster_id: None,
-                });
+    /// Once the status line of the response is parsed, a status configured as a
+    /// failure for the cluster backs the backend off like a connection error,
+    /// taking it out of the rotation. Another status marks it healthy
+    fn check_response_status(&mut self) {
+        let (Some(backend), Some(status)) = (&self.backend, self.context.status) else {
+            return;
+        };
+        if self.failure_status_codes.is_empty() {
+            return;
+        }
+
+        let mut backend = backend.borrow_mut();
+        if self.failure_status_codes.contains(&status) {
+            warn!(
+                "{} backend server {} at {} answered with the failure status {}",
+                self.log_context(),
+                backend.backend_id,
+                backend.address,
+                status
+            );
+            incr!(
+                "backend.failure_status",
+                self.cluster_id.as_deref(),
+                self.backend_id.as_deref()
+            );
+            fail_backend(
+                &mut backend,
+                self.cluster_id.as_deref(),
+                self.backend_id.as_deref(),
+            );
+        } else {
+            succeed_backend(
+                &mut backend,
+                self.cluster_id.as_deref(),
+                self.backend_id.as_deref(),
+            );
+        }
+    }
+
+    /// Outlier detection: a backend answering with too many 5xx statuses in a row
+    /// is ejected from the rotation of its cluster for a while
+    fn detect_outlier(&mut self) {
+        let (Some(backend), Some(status), Some((threshold, ejection_time))) =
+            (&self.backend, self.context.status, self.outlier_detection)
+        else {
+            return;
+        };
+
+        let mut backend = backend.borrow_mut();
+        if backend.record_response_status(status, threshold, ejection_time) {
+            warn!(
+                "{} backend server {} at {} answered {} 5xx statuses in a row, ejecting it for {:?}",
+                self.log_context(),
+                backend.backend_id,
+                backend.address,
+                threshold,
+                ejection_time
+            );
+            incr!(
+                "backend.outlier.ejected",
+                self.cluster_id.as_deref(),
+                self.backend_id.as_deref()
+            );
+        }
+    }
+
+    /// Send a request level error event, only received by the subscribers
+    /// that asked for them
+    fn request_error_event(&self, kind: EventKind) {
+        push_request_error_event(Event {
+            kind: kind as i32,
+            cluster_id: self.cluster_id.clone(),
+            backend_id: self.backend_id.clone(),
+            address: self
+                .context
+                .session_address
+                .map(|address| address.to_string()),
+        });
+    }
+
+    /// A backend of the cluster went down or up, check if the cluster
+    /// as a whole changed state
+    fn update_cluster_health(&self, proxy: &Rc<RefCell<dyn L7Proxy>>, backend_up: bool) {
+        if let Some(cluster_id) = &self.cluster_id {
+            let proxy = proxy.borrow();
+            let mut backends = proxy.backends().borrow_mut();
+            if backend_up {
+                backends.backend_up(cluster_id);
+            } else {
+                backends.backend_down(cluster_id);
             }
         }
     }
 
+    /// The backend answered 503 with a Retry-After header, do not send it
+    /// new connections before the delay it asked for
+    fn honor_retry_after(&mut self, retry_after: std::time::Duration) {
+        if let Some(backend) = &self.backend {
+            let mut backend = backend.borrow_mut();
+            debug!(
+                "{} backend server {} at {} asked to retry after {:?}",
+                self.log_context(),
+                backend.backend_id,
+                backend.address,
+                retry_after
+            );
+            incr!(
+                "backend.retry_after",
+                self.cluster_id.as_deref(),
+                self.backend_id.as_deref()
+            );
+            backend.retry_policy.retry_after(retry_after);
+        }
+    }
+
     pub fn backend_hup(&mut self) -> StateResult {
         // there might still data we can read on the socket
         if self.backend_readiness.event.is_readable()
@@ -1489,11 +2523,14 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             }
             // the frontend already transmitted data so we can't redirect
             (false, true) => {
-                error!(
-                    "PROXY session {:?}, the front transmitted data but the back closed",
-                    self.frontend_token
-                );
-                self.set_answer(DefaultAnswerStatus::Answer503, None);
+                // the answer may already be set when reading the end of the stream
+                if !matches!(self.status, SessionStatus::DefaultAnswer(..)) {
+                    error!(
+                        "PROXY session {:?}, the front transmitted data but the back closed",
+                        self.frontend_token
+                    );
+                    self.set_empty_response_answer();
+                }
                 self.backend_readiness.interest = Ready::EMPTY;
                 StateResult::Continue
             }
@@ -1531,7 +2568,9 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
                 );
 
                 self.connection_attempts += 1;
-                self.fail_backend_connection(metrics);
+                if self.fail_backend_connection(metrics) {
+                    self.update_cluster_health(&proxy, false);
+                }
 
                 self.backend_connection_status =
                     BackendConnectionStatus::Connecting(Instant::now());
@@ -1552,7 +2591,9 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             } else {
                 metrics.backend_connected();
                 self.connection_attempts = 0;
-                self.set_backend_connected(BackendConnectionStatus::Connected, metrics);
+                if self.set_backend_connected(BackendConnectionStatus::Connected, metrics) {
+                    self.update_cluster_health(&proxy, true);
+                }
                 // we might get an early response from the backend, so we want to look
                 // at readable events
                 self.backend_readiness.interest.insert(Ready::READABLE);
@@ -1563,8 +2604,14 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             return SessionResult::Close;
         }
 
-        while counter < MAX_LOOP_ITERATIONS {
-            let frontend_interest = self.frontend_readiness.filter_interest();
+        self.front_read_yielded = false;
+        let max_loop_iterations = max_loop_iterations();
+        while counter < max_loop_iterations {
+            let mut frontend_interest = self.frontend_readiness.filter_interest();
+            if self.front_read_yielded {
+                // the readable event is kept for when the session runs again
+                frontend_interest.remove(Ready::READABLE);
+            }
             let backend_interest = self.backend_readiness.filter_interest();
 
             trace!(
@@ -1662,14 +2709,21 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             counter += 1;
         }
 
-        if counter >= MAX_LOOP_ITERATIONS {
+        if counter >= max_loop_iterations {
+            let state = if self.response_stream.is_initial() {
+                incr!("http.infinite_loop.request");
+                "request"
+            } else {
+                incr!("http.infinite_loop.response");
+                "response"
+            };
             error!(
-                "PROXY\thandling session {:?} went through {} iterations, there's a probable infinite loop bug, closing the connection",
-                self.frontend_token, MAX_LOOP_ITERATIONS
+                "PROXY\thandling session {:?} went through {} iterations in the {} state, there's a probable infinite loop bug, closing the connection",
+                self.frontend_token, max_loop_iterations, state
             );
             incr!("http.infinite_loop.error");
 
-            self.print_state(self.protocol_string());
+            self.print_state(&self.protocol_string());
 
             return SessionResult::Close;
         }
@@ -1690,6 +2744,17 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> Http<Front, L
             TimeoutStatus::Request
         }
     }
+
+    /// keep-alive efficiency: how many requests the connection served,
+    /// recorded when it closes or is upgraded
+    fn record_keepalive_requests(&self) {
+        if self.requests_served > 0 {
+            histogram!("http.keepalive.requests", self.requests_served);
+            if let Some(cluster_id) = self.cluster_id.as_deref() {
+                histogram!("http.keepalive.requests", cluster_id, self.requests_served);
+            }
+        }
+    }
 }
 
 impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> SessionState for Http<Front, L> {
@@ -1726,6 +2791,8 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> SessionState
     fn close(&mut self, proxy: Rc<RefCell<dyn L7Proxy>>, metrics: &mut SessionMetrics) {
         self.close_backend(proxy, metrics);
 
+        self.record_keepalive_requests();
+
         //if the state was initial, the connection was already reset
         if !self.request_stream.is_initial() {
             gauge_add!("http.active_requests", -1);
@@ -1743,14 +2810,19 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> SessionState
             self.container_frontend_timeout.triggered();
             return match self.timeout_status() {
                 TimeoutStatus::Request => {
+                    self.request_error_event(EventKind::RequestTimeout);
                     self.set_answer(DefaultAnswerStatus::Answer408, None);
                     self.writable(metrics)
                 }
                 TimeoutStatus::WaitingForResponse => {
+                    self.request_error_event(EventKind::RequestTimeout);
                     self.set_answer(DefaultAnswerStatus::Answer504, None);
                     self.writable(metrics)
                 }
-                TimeoutStatus::Response => StateResult::CloseSession,
+                TimeoutStatus::Response => {
+                    self.request_error_event(EventKind::RequestTimeout);
+                    StateResult::CloseSession
+                }
                 TimeoutStatus::WaitingForNewRequest => StateResult::CloseSession,
             };
         }
@@ -1767,10 +2839,12 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> SessionState
                     self.writable(metrics)
                 }
                 TimeoutStatus::WaitingForResponse => {
+                    self.request_error_event(EventKind::RequestTimeout);
                     self.set_answer(DefaultAnswerStatus::Answer504, None);
                     self.writable(metrics)
                 }
                 TimeoutStatus::Response => {
+                    self.request_error_event(EventKind::RequestTimeout);
                     error!(
                         "backend {:?} timeout while receiving response (cluster {:?})",
                         self.backend_id, self.cluster_id
@@ -1808,6 +2882,15 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> SessionState
         );
     }
 
+    fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.cluster_id = self.cluster_id.clone();
+        info.backend_id = self.backend_id.clone();
+        info.frontend_readiness = Some(format!("{:?}", self.frontend_readiness));
+        info.backend_readiness = Some(format!("{:?}", self.backend_readiness));
+        info.request_state = Some(format!("{:?}", self.request_stream.parsing_phase));
+        info.response_state = Some(format!("{:?}", self.response_stream.parsing_phase));
+    }
+
     fn shutting_down(&mut self) -> SessionIsToBeClosed {
         if self.request_stream.is_initial()
             && self.request_stream.storage.is_empty()
@@ -1821,6 +2904,87 @@ impl<Front: SocketHandler, L: ListenerHandler + L7ListenerHandler> SessionState
     }
 }
 
+/// The backend answered, reset its failure counter and bring it back up if it was down.
+/// Returns true if the backend was down
+fn succeed_backend(
+    backend: &mut Backend,
+    cluster_id: Option<&str>,
+    backend_id: Option<&str>,
+) -> bool {
+    let was_down = backend.retry_policy.is_down();
+    if was_down {
+        incr!("backend.up", cluster_id, backend_id);
+
+        info!(
+            "backend server {} at {} is up",
+            backend.backend_id, backend.address
+        );
+
+        push_event(Event {
+            kind: EventKind::BackendUp as i32,
+            backend_id: Some(backend.backend_id.to_owned()),
+            address: Some(backend.address.to_string()),
+            cluster_id: None,
+        });
+    }
+
+    backend.failures = 0;
+    backend.retry_policy.succeed();
+    was_down
+}
+
+/// The backend failed, back it off and bring it down once it failed too many times.
+/// Returns true if this failure brought it down
+fn fail_backend(backend: &mut Backend, cluster_id: Option<&str>, backend_id: Option<&str>) -> bool {
+    backend.failures += 1;
+
+    let already_unavailable = backend.retry_policy.is_down();
+    backend.retry_policy.fail();
+    let went_down = !already_unavailable && backend.retry_policy.is_down();
+    if went_down {
+        error!(
+            "backend server {} at {} is down",
+            backend.backend_id, backend.address
+        );
+        incr!("backend.down", cluster_id, backend_id);
+
+        push_event(Event {
+            kind: EventKind::BackendDown as i32,
+            backend_id: Some(backend.backend_id.to_owned()),
+            address: Some(backend.address.to_string()),
+            cluster_id: None,
+        });
+    }
+    went_down
+}
+
+/// Bytes read from the backend and not yet written to the client. The written bytes stay
+/// at the beginning of the buffer until it is shifted, they are not counted
+fn unwritten_data(storage: &kawa::Buffer<Checkout>) -> usize {
+    storage.end - storage.start
+}
+
+/// Number of slices entirely written by a vectored write of `size` bytes. The rest of
+/// a slice partially written is offered again by the next write, it is counted then
+fn written_slices(bufs: &[IoSlice], size: usize) -> usize {
+    let mut remaining = size;
+    bufs.iter()
+        .take_while(|buf| {
+            if buf.len() > remaining {
+                return false;
+            }
+            remaining -= buf.len();
+            true
+        })
+        .count()
+}
+
+/// The reason phrase of a backend response if it is not the standard one
+/// for its status, only those are worth logging
+fn non_standard_reason(status: Option<u16>, reason: Option<&str>) -> Option<&str> {
+    reason.filter(|reason| status.and_then(canonical_reason) != Some(*reason))
+}
+
 /// Save the HTTP status code of the backend response
 fn save_http_status_metric(status: Option<u16>, context: LogContext) {
     if let Some(status) = status {
@@ -1847,3 +3011,264 @@ fn save_http_status_metric(status: Option<u16>, context: LogContext) {
         }
     }
 }
+
+/// Count a default answer in the metric specific to its status, and in
+/// `http.default_answer.<status>`, kept for each cluster to chart its synthetic responses
+fn count_default_answer(
+    answer: DefaultAnswerStatus,
+    cluster_id: Option<&str>,
+    backend_id: Option<&str>,
+) {
+    match answer {
+        DefaultAnswerStatus::Answer200 => incr!("http.health_check"),
+        DefaultAnswerStatus::Answer301 => incr!("http.301.redirection", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer400 => incr!("http.400.errors"),
+        DefaultAnswerStatus::Answer401 => incr!("http.401.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer403 => incr!("http.403.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer404 => incr!("http.404.errors"),
+        DefaultAnswerStatus::Answer405 => incr!("http.405.errors"),
+        DefaultAnswerStatus::Answer408 => incr!("http.408.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer413 => incr!("http.413.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer414 => incr!("http.414.errors"),
+        DefaultAnswerStatus::Answer421 => incr!("http.421.errors"),
+        DefaultAnswerStatus::Answer425 => incr!("http.425.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer502 => incr!("http.502.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer503 => incr!("http.503.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer504 => incr!("http.504.errors", cluster_id, backend_id),
+        DefaultAnswerStatus::Answer507 => incr!("http.507.errors", cluster_id, backend_id),
+    };
+    incr!(answer.metric_key(), cluster_id, None);
+}
+
+/// Write the default answer from `index` until it is complete or the socket
+/// cannot take more, `index` is advanced by the number of bytes written
+fn write_default_answer<S: SocketHandler>(
+    socket: &mut S,
+    answer: &[u8],
+    index: &mut usize,
+) -> (usize, SocketResult) {
+    let mut size = 0usize;
+    let mut res = SocketResult::Continue;
+    while res == SocketResult::Continue && *index < answer.len() {
+        let (current_size, current_res) = socket.socket_write(&answer[*index..]);
+        res = current_res;
+        size += current_size;
+        *index += current_size;
+    }
+    (size, res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A socket taking at most `chunk` bytes per write,
+    /// then blocking until the next writable event
+    struct SlowSocket {
+        /// only there to answer socket_ref and socket_mut,
+        /// the writes never reach it
+        stream: TcpStream,
+        written: Vec<u8>,
+        chunk: usize,
+        blocked: bool,
+    }
+
+    impl SlowSocket {
+        fn new(chunk: usize) -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("could not bind");
+            let stream = std::net::TcpStream::connect(listener.local_addr().unwrap())
+                .expect("could not connect to the listener");
+            stream.set_nonblocking(true).unwrap();
+            SlowSocket {
+                stream: TcpStream::from_std(stream),
+                written: Vec::new(),
+                chunk,
+                blocked: false,
+            }
+        }
+    }
+
+    impl SocketHandler for SlowSocket {
+        fn socket_read(&mut self, _buf: &mut [u8]) -> (usize, SocketResult) {
+            (0, SocketResult::WouldBlock)
+        }
+
+        fn socket_write(&mut self, buf: &[u8]) -> (usize, SocketResult) {
+            if self.blocked {
+                return (0, SocketResult::WouldBlock);
+            }
+            self.blocked = true;
+            let size = buf.len().min(self.chunk);
+            self.written.extend_from_slice(&buf[..size]);
+            (size, SocketResult::Continue)
+        }
+
+        fn socket_ref(&self) -> &TcpStream {
+            &self.stream
+        }
+
+        fn socket_mut(&mut self) -> &mut TcpStream {
+            &mut self.stream
+        }
+
+        fn protocol(&self) -> TransportProtocol {
+            TransportProtocol::Tcp
+        }
+
+        fn read_error(&self) {}
+
+        fn write_error(&self) {}
+    }
+
+    #[test]
+    fn default_answer_written_in_small_writes() {
+        let answer = format!(
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 1000\r\n\r\n{}",
+            "x".repeat(1000)
+        );
+        let mut socket = SlowSocket::new(100);
+
+        let mut index = 0;
+        let mut writable_events = 0;
+        while index < answer.len() {
+            writable_events += 1;
+            socket.blocked = false;
+            let (size, res) = write_default_answer(&mut socket, answer.as_bytes(), &mut index);
+            assert!(size <= 100);
+            if index < answer.len() {
+                assert_eq!(res, SocketResult::WouldBlock);
+            }
+        }
+
+        assert_eq!(index, answer.len());
+        assert_eq!(socket.written, answer.as_bytes());
+        // 1058 bytes, 100 per writable event
+        assert_eq!(writable_events, 11);
+    }
+
+    #[test]
+    fn slices_counted_once_written() {
+        let head = ["HTTP/1.1", " ", "200", " ", "OK", "\r\n"];
+        let mut offset = 0;
+        let mut slices = 0;
+        let mut writes = 0;
+        // 5 bytes per write, like the SlowSocket, the slices partially written are
+        // offered again from where the previous write stopped
+        while offset < head.concat().len() {
+            let mut skipped = offset;
+            let bufs: Vec<IoSlice> = head
+                .iter()
+                .filter_map(|slice| {
+                    if skipped >= slice.len() {
+                        skipped -= slice.len();
+                        return None;
+                    }
+                    let buf = IoSlice::new(&slice.as_bytes()[skipped..]);
+                    skipped = 0;
+                    Some(buf)
+                })
+                .collect();
+            let size = bufs.iter().map(|buf| buf.len()).sum::<usize>().min(5);
+            slices += written_slices(&bufs, size);
+            offset += size;
+            writes += 1;
+        }
+
+        assert_eq!(writes, 4);
+        assert_eq!(slices, head.len());
+    }
+
+    #[test]
+    fn back_read_resumes_once_drained_below_low_watermark() {
+        let mut pool = Pool::with_capacity(1, 1, 16384);
+        let checkout = pool.checkout().expect("could not checkout a buffer");
+        let watermarks = ReadWatermarks::new(checkout.capacity(), Some(4096), None);
+        let mut storage = kawa::Buffer::new(checkout);
+
+        storage.fill(16384);
+        assert!(watermarks.should_pause(unwritten_data(&storage)));
+
+        // 8kB written to the client, 8kB left: still above the low watermark
+        storage.consume(8192);
+        assert_eq!(unwritten_data(&storage), 8192);
+        assert!(!watermarks.should_resume(unwritten_data(&storage)));
+
+        // 12kB written, 4kB left, even if the written bytes are still in the buffer
+        storage.consume(4096);
+        assert_eq!(storage.used().len(), 16384);
+        assert!(watermarks.should_resume(unwritten_data(&storage)));
+    }
+
+    #[test]
+    fn default_answer_metric_per_cluster() {
+        use sozu_command::proto::command::{
+            filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+        };
+
+        use crate::metrics::METRICS;
+
+        count_default_answer(
+            DefaultAnswerStatus::Answer503,
+            Some("cluster_x"),
+            Some("cluster_x-0"),
+        );
+        count_default_answer(DefaultAnswerStatus::Answer503, Some("cluster_x"), None);
+        count_default_answer(DefaultAnswerStatus::Answer503, Some("cluster_y"), None);
+        count_default_answer(DefaultAnswerStatus::Answer404, Some("cluster_x"), None);
+
+        let query = QueryMetricsOptions {
+            cluster_ids: vec!["cluster_x".to_owned()],
+            metric_names: vec!["http.default_answer.503".to_owned()],
+            ..Default::default()
+        };
+        let content = METRICS.with(|metrics| metrics.borrow_mut().query(&query).unwrap());
+        let Some(ContentType::WorkerMetrics(worker_metrics)) = content.content_type else {
+            panic!("expected worker metrics");
+        };
+        let count = worker_metrics.clusters["cluster_x"].cluster["http.default_answer.503"]
+            .inner
+            .clone();
+        assert_eq!(count, Some(Inner::Count(2)));
+    }
+
+    #[test]
+    fn failure_status_backs_the_backend_off() {
+        let mut backend = Backend::new(
+            "cluster_1-0",
+            "127.0.0.1:1027".parse().unwrap(),
+            None,
+            None,
+            None,
+        );
+        assert!(backend.can_open());
+
+        fail_backend(&mut backend, Some("cluster_1"), Some("cluster_1-0"));
+        assert_eq!(backend.failures, 1);
+        assert!(!backend.can_open());
+
+        succeed_backend(&mut backend, Some("cluster_1"), Some("cluster_1-0"));
+        assert_eq!(backend.failures, 0);
+        assert!(backend.can_open());
+    }
+
+    #[test]
+    fn only_non_standard_reasons_are_logged() {
+        assert_eq!(non_standard_reason(Some(200), Some("OK")), None);
+        assert_eq!(non_standard_reason(Some(200), Some("Fine")), Some("Fine"));
+        assert_eq!(non_standard_reason(Some(299), Some("OK")), Some("OK"));
+        assert_eq!(non_standard_reason(None, Some("OK")), Some("OK"));
+        assert_eq!(non_standard_reason(Some(200), None), None);
+    }
+
+    #[test]
+    fn default_answers_have_a_registered_reason() {
+        use DefaultAnswerStatus::*;
+        for answer in [
+            Answer200, Answer301, Answer400, Answer401, Answer403, Answer404, Answer405, Answer408,
+            Answer413, Answer414, Answer421, Answer425, Answer502, Answer503, Answer504, Answer507,
+        ] {
+            assert!(!answer.reason().is_empty(), "{answer:?}");
+        }
+        assert_eq!(Answer413.reason(), "Payload Too Large");
+    }
+}
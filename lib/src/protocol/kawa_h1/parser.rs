@@ -1,9 +1,11 @@
 use std::{
     cmp::min,
     fmt::{self, Write},
-    str::from_utf8_unchecked,
+    str::from_utf8,
+    time::Duration,
 };
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use nom::{
     bytes::{self, complete::take_while},
     character::{complete::digit1, is_alphanumeric},
@@ -12,19 +14,26 @@ use nom::{
     sequence::preceded,
     Err, IResult,
 };
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use subtle::{Choice, ConstantTimeEq};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
 
+/// ASCII case insensitive comparison, other bytes are compared exactly.
+///
+/// This is called for every header name, so it folds the case with masks
+/// over the whole slice instead of branching on each byte: a letter may only
+/// differ from the other byte by its case bit (0x20), anything else must be equal.
 pub fn compare_no_case(left: &[u8], right: &[u8]) -> bool {
     if left.len() != right.len() {
         return false;
     }
 
-    left.iter().zip(right).all(|(a, b)| match (*a, *b) {
-        (0..=64, 0..=64) | (91..=96, 91..=96) | (123..=255, 123..=255) => a == b,
-        (65..=90, 65..=90) | (97..=122, 97..=122) | (65..=90, 97..=122) | (97..=122, 65..=90) => {
-            *a | 0b00_10_00_00 == *b | 0b00_10_00_00
-        }
-        _ => false,
-    })
+    left.iter().zip(right).fold(0u8, |mismatch, (a, b)| {
+        // 0x20 if `a` is an ASCII letter, 0 otherwise
+        let case_bit = (((*a | 0x20).wrapping_sub(b'a') < 26) as u8) << 5;
+        mismatch | ((a ^ b) & !case_bit)
+    }) == 0
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -41,8 +50,17 @@ pub enum Method {
 }
 
 impl Method {
+    /// The invalid UTF-8 of a custom method is replaced, use `from_bytes`
+    /// to refuse it
     pub fn new(s: &[u8]) -> Method {
-        if compare_no_case(s, b"GET") {
+        Method::from_bytes(s)
+            .unwrap_or_else(|| Method::Custom(String::from_utf8_lossy(s).into_owned()))
+    }
+
+    /// Returns None if the method is not valid UTF-8. The parser only accepts token
+    /// characters, but the bytes of custom methods are still checked
+    pub fn from_bytes(s: &[u8]) -> Option<Method> {
+        let method = if compare_no_case(s, b"GET") {
             Method::Get
         } else if compare_no_case(s, b"POST") {
             Method::Post
@@ -59,8 +77,15 @@ impl Method {
         } else if compare_no_case(s, b"CONNECT") {
             Method::Connect
         } else {
-            Method::Custom(String::from(unsafe { from_utf8_unchecked(s) }))
-        }
+            Method::Custom(from_utf8(s).ok()?.to_owned())
+        };
+        Some(method)
+    }
+
+    /// GET, HEAD and OPTIONS do not change the state of the server,
+    /// a replay of those requests is harmless
+    pub fn is_replay_safe(&self) -> bool {
+        matches!(self, Method::Get | Method::Head | Method::Options)
     }
 }
 
@@ -80,6 +105,13 @@ impl fmt::Display for Method {
     }
 }
 
+/// whether the bytes are an RFC 9110 token, like a method or a header name
+pub fn is_token(s: &[u8]) -> bool {
+    !s.is_empty()
+        && s.iter()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(c))
+}
+
 #[cfg(feature = "tolerant-http1-parser")]
 fn is_hostname_char(i: u8) -> bool {
     is_alphanumeric(i) ||
@@ -118,6 +150,473 @@ pub fn hostname_and_port(i: &[u8]) -> IResult<&[u8], (&[u8], Option<&[u8]>)> {
     Ok((i, (host, port)))
 }
 
+/// The hostname of an authority, without its port and trailing dot.
+/// The authorities that can't be parsed are left to the routing
+pub fn authority_hostname(authority: &str) -> Option<&str> {
+    let (_, (hostname, _)) = hostname_and_port(authority.as_bytes()).ok()?;
+    let hostname = hostname.strip_suffix(b".").unwrap_or(hostname);
+    std::str::from_utf8(hostname).ok()
+}
+
+/// Hop-by-hop headers (RFC 9110 section 7.6.1) that are never forwarded.
+///
+/// "Connection" is rewritten rather than removed, "Transfer-Encoding" is kept since
+/// Sōzu forwards the message framing as is, and "Upgrade" is kept since Sōzu tunnels
+/// upgraded connections to the backend.
+pub const HOP_BY_HOP_HEADERS: [&[u8]; 6] = [
+    b"Keep-Alive",
+    b"Proxy-Authenticate",
+    b"Proxy-Authorization",
+    b"Proxy-Connection",
+    b"TE",
+    b"Trailer",
+];
+
+/// wether a header is a standard hop-by-hop header or one of the `extra` configured ones
+pub fn is_hop_by_hop_header(key: &[u8], extra: &[String]) -> bool {
+    HOP_BY_HOP_HEADERS
+        .iter()
+        .any(|name| compare_no_case(key, name))
+        || extra
+            .iter()
+            .any(|name| compare_no_case(key, name.as_bytes()))
+}
+
+/// wether a "TE" header value accepts trailers in a chunked response, gRPC clients
+/// send it and the backends may refuse the requests without it (RFC 9110 section 10.1.4)
+pub fn accepts_trailers(value: &[u8]) -> bool {
+    split_list(value).any(|element| {
+        let coding = element.split(|c| *c == b';').next().unwrap_or(element);
+        compare_no_case(trim_ows(coding), b"trailers")
+    })
+}
+
+/// List the headers named by the tokens of a "Connection" header value.
+///
+/// Those headers only apply to the current connection and should not be forwarded.
+/// The "close", "keep-alive" and "upgrade" options are not header names, and the
+/// `preserved` tokens name headers that some upgrades (like h2c with "HTTP2-Settings")
+/// need end to end, so they are left out.
+pub fn connection_named_headers(value: &[u8], preserved: &[String]) -> Vec<String> {
+    split_list(value)
+        .filter(|token| {
+            !compare_no_case(token, b"close")
+                && !compare_no_case(token, b"keep-alive")
+                && !compare_no_case(token, b"upgrade")
+                && !preserved
+                    .iter()
+                    .any(|name| compare_no_case(token, name.as_bytes()))
+        })
+        .filter_map(|token| from_utf8(token).ok().map(ToOwned::to_owned))
+        .collect()
+}
+
+/// remove optional whitespace around a header value element
+pub fn trim_ows(i: &[u8]) -> &[u8] {
+    let is_ows = |c: &u8| *c == b' ' || *c == b'\t';
+    let start = i.iter().position(|c| !is_ows(c)).unwrap_or(i.len());
+    let end = i
+        .iter()
+        .rposition(|c| !is_ows(c))
+        .map_or(start, |end| end + 1);
+    &i[start..end]
+}
+
+/// GUID concatenated to the Sec-WebSocket-Key to compute the Sec-WebSocket-Accept (RFC 6455)
+const WEBSOCKET_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum WebSocketHandshakeError {
+    #[error("missing Sec-WebSocket-Accept header")]
+    MissingAccept,
+    #[error("Sec-WebSocket-Accept {found} does not match the key, expected {expected}")]
+    InvalidAccept { expected: String, found: String },
+    #[error("subprotocol {0} was not requested by the client")]
+    UnrequestedProtocol(String),
+}
+
+/// the Sec-WebSocket-Accept value a server must answer to a Sec-WebSocket-Key
+pub fn websocket_accept(key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(trim_ows(key));
+    hasher.update(WEBSOCKET_GUID);
+    STANDARD.encode(hasher.finalize())
+}
+
+/// Check the headers of a response accepting a websocket upgrade:
+///
+/// - the Sec-WebSocket-Accept must be derived from the Sec-WebSocket-Key of the request
+/// - the Sec-WebSocket-Protocol, if any, must be one of the subprotocols offered by the client
+pub fn validate_websocket_handshake(
+    key: &[u8],
+    offered_protocols: &[String],
+    accept: Option<&[u8]>,
+    protocol: Option<&[u8]>,
+) -> Result<(), WebSocketHandshakeError> {
+    let accept = accept.ok_or(WebSocketHandshakeError::MissingAccept)?;
+    let expected = websocket_accept(key);
+    if trim_ows(accept) != expected.as_bytes() {
+        return Err(WebSocketHandshakeError::InvalidAccept {
+            expected,
+            found: String::from_utf8_lossy(accept).into_owned(),
+        });
+    }
+
+    match protocol {
+        Some(protocol) => check_websocket_protocol(offered_protocols, protocol),
+        None => Ok(()),
+    }
+}
+
+/// Check that the Sec-WebSocket-Protocol selected by a response accepting a websocket
+/// upgrade is one of the subprotocols offered to the backend
+pub fn check_websocket_protocol(
+    offered_protocols: &[String],
+    protocol: &[u8],
+) -> Result<(), WebSocketHandshakeError> {
+    let protocol = trim_ows(protocol);
+    if offered_protocols
+        .iter()
+        .any(|offered| offered.as_bytes() == protocol)
+    {
+        Ok(())
+    } else {
+        Err(WebSocketHandshakeError::UnrequestedProtocol(
+            String::from_utf8_lossy(protocol).into_owned(),
+        ))
+    }
+}
+
+/// The subprotocols offered by a websocket upgrade that are in the `allowed` list
+/// of its frontend, all of them if the list is empty
+pub fn allowed_websocket_protocols(
+    offered_protocols: &[String],
+    allowed: &[String],
+) -> Vec<String> {
+    offered_protocols
+        .iter()
+        .filter(|offered| allowed.is_empty() || allowed.contains(offered))
+        .cloned()
+        .collect()
+}
+
+/// split a comma separated header value in its trimmed, non empty elements
+pub fn split_list(value: &[u8]) -> impl Iterator<Item = &[u8]> {
+    value
+        .split(|c| *c == b',')
+        .map(trim_ows)
+        .filter(|element| !element.is_empty())
+}
+
+/// What to do with a request announcing its body with `Expect: 100-continue`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectContinueDecision {
+    /// let the backend ask for the body
+    Continue,
+    /// no credentials were presented, answer 401
+    Unauthorized,
+    /// the credentials are not accepted by the cluster, answer 403
+    Forbidden,
+}
+
+/// Check the `Authorization` header of a request announcing its body with
+/// `Expect: 100-continue` against the values accepted by the cluster.
+///
+/// An empty list of accepted values disables the check.
+pub fn authorize_expect_continue(
+    authorization: Option<&[u8]>,
+    accepted: &[String],
+) -> ExpectContinueDecision {
+    if accepted.is_empty() {
+        return ExpectContinueDecision::Continue;
+    }
+
+    match authorization.map(trim_ows) {
+        None | Some(b"") => ExpectContinueDecision::Unauthorized,
+        Some(value) if matches_any_secret(value, accepted) => ExpectContinueDecision::Continue,
+        Some(_) => ExpectContinueDecision::Forbidden,
+    }
+}
+
+/// Compare a presented credential with every accepted one in constant time,
+/// so that the time taken to refuse it does not tell how much of it matched
+fn matches_any_secret(value: &[u8], accepted: &[String]) -> bool {
+    accepted
+        .iter()
+        .fold(Choice::from(0), |found, secret| {
+            found | secret.as_bytes().ct_eq(value)
+        })
+        .into()
+}
+
+/// The authentication schemes of `Authorization` header values
+pub fn authorization_schemes(values: &[String]) -> impl Iterator<Item = &str> {
+    values.iter().filter_map(|value| value.split(' ').next())
+}
+
+/// The `WWW-Authenticate` headers of a 401 answer, one challenge for each
+/// authentication scheme (RFC 9110 §11.6.1). Each header ends with CRLF
+pub fn www_authenticate_headers<'a, I>(schemes: I, realm: &str) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut unique_schemes: Vec<&str> = Vec::new();
+    for scheme in schemes {
+        if !scheme.is_empty()
+            && !unique_schemes
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(scheme))
+        {
+            unique_schemes.push(scheme);
+        }
+    }
+
+    // the realm is a quoted-string (RFC 9110 §5.6.4)
+    let mut quoted_realm = String::with_capacity(realm.len());
+    for c in realm.chars().filter(|c| !c.is_control()) {
+        if c == '"' || c == '\\' {
+            quoted_realm.push('\\');
+        }
+        quoted_realm.push(c);
+    }
+
+    unique_schemes
+        .iter()
+        .map(|scheme| format!("WWW-Authenticate: {scheme} realm=\"{quoted_realm}\"\r\n"))
+        .collect()
+}
+
+/// Check the value of an `Authorization` header against the credentials
+/// accepted by a cluster. The scheme name is case insensitive, the
+/// credentials are compared in constant time.
+///
+/// `basic_credentials` are written `user:hex(sha256(password))`, or
+/// `user:salt:hex(sha256(salt + password))` with a salt, the `bearer_tokens`
+/// are compared as is.
+///
+/// The hash only keeps the passwords out of the configuration in clear text.
+/// It is checked on the event loop for each request, so it is a single SHA-256
+/// and not a slow KDF: someone reading the configuration can brute force weak
+/// passwords offline, the passwords should be long and random, like tokens.
+pub fn check_authorization(
+    authorization: &[u8],
+    basic_credentials: &[String],
+    bearer_tokens: &[String],
+) -> bool {
+    let authorization = trim_ows(authorization);
+    let (scheme, credentials) = match authorization.iter().position(|c| *c == b' ') {
+        Some(index) => (
+            &authorization[..index],
+            trim_ows(&authorization[index + 1..]),
+        ),
+        None => return false,
+    };
+
+    if compare_no_case(scheme, b"Bearer") {
+        return matches_any_secret(credentials, bearer_tokens);
+    }
+
+    if !compare_no_case(scheme, b"Basic") {
+        return false;
+    }
+
+    let decoded = match STANDARD.decode(credentials) {
+        Ok(decoded) => decoded,
+        Err(_) => return false,
+    };
+    let (user, password) = match decoded.iter().position(|c| *c == b':') {
+        Some(index) => (&decoded[..index], &decoded[index + 1..]),
+        None => return false,
+    };
+
+    basic_credentials
+        .iter()
+        .fold(Choice::from(0), |found, credential| {
+            found | basic_credential_matches(credential, user, password)
+        })
+        .into()
+}
+
+/// `credential` is `user:hash` or `user:salt:hash`
+fn basic_credential_matches(credential: &str, user: &[u8], password: &[u8]) -> Choice {
+    let (expected_user, salt_and_hash) = match credential.split_once(':') {
+        Some(parts) => parts,
+        None => return Choice::from(0),
+    };
+    let (salt, expected_hash) = salt_and_hash.split_once(':').unwrap_or(("", salt_and_hash));
+
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(password);
+    let password_hash = hex::encode(hasher.finalize());
+
+    expected_user.as_bytes().ct_eq(user)
+        & expected_hash
+            .to_ascii_lowercase()
+            .as_bytes()
+            .ct_eq(password_hash.as_bytes())
+}
+
+/// The transfer codings listed in the `Transfer-Encoding` headers of a request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferEncoding {
+    /// the codings applied before chunked (gzip, deflate, compress or
+    /// unknown ones), lowercased and in the order they were applied
+    pub codings: Vec<String>,
+    /// the last coding is chunked, the body is framed with chunks
+    pub chunked: bool,
+}
+
+/// Parse the comma separated list of transfer codings of a request.
+///
+/// Sōzu does not decode the inner codings, it only forwards them, but the
+/// framing must be chunked: RFC 9112 requires `chunked` to be applied last,
+/// exactly once, so `gzip, chunked` is accepted while `chunked, gzip` is not.
+/// Returns None for an invalid list.
+pub fn parse_transfer_encoding(value: &[u8]) -> Option<TransferEncoding> {
+    let mut transfer_encoding = TransferEncoding::default();
+    for coding in split_list(value) {
+        // a coding may have parameters: "name;param=value"
+        let name = trim_ows(coding.split(|c| *c == b';').next().unwrap_or(coding));
+        if transfer_encoding.chunked || name.is_empty() {
+            // nothing may follow chunked
+            return None;
+        }
+        if compare_no_case(name, b"chunked") {
+            if name.len() != coding.len() {
+                return None;
+            }
+            transfer_encoding.chunked = true;
+        } else {
+            let name = from_utf8(name).ok()?;
+            transfer_encoding.codings.push(name.to_ascii_lowercase());
+        }
+    }
+    Some(transfer_encoding)
+}
+
+/// The reasons to reject a request that another parser, like the one of the
+/// backend, could frame differently: a client could smuggle a second request
+/// in what Sōzu considers the body of the first one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmugglingVector {
+    /// several Host headers
+    DuplicateHost,
+    /// several Content-Length headers with different values
+    ConflictingContentLength,
+    /// both Content-Length and Transfer-Encoding headers
+    ContentLengthWithTransferEncoding,
+    /// a Transfer-Encoding that does not end with chunked
+    InvalidTransferEncoding,
+    /// a line of the request head ending with LF instead of CRLF
+    BareLineFeed,
+}
+
+impl SmugglingVector {
+    /// message of the parsing error set on the request
+    pub fn message(&self) -> &'static str {
+        match self {
+            SmugglingVector::DuplicateHost => "duplicate Host header",
+            SmugglingVector::ConflictingContentLength => "conflicting Content-Length headers",
+            SmugglingVector::ContentLengthWithTransferEncoding => {
+                "Content-Length with Transfer-Encoding"
+            }
+            SmugglingVector::InvalidTransferEncoding => {
+                "chunked is not the last coding of Transfer-Encoding"
+            }
+            SmugglingVector::BareLineFeed => "bare LF in the request head",
+        }
+    }
+
+    /// written in the tags of the access log of the request
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SmugglingVector::DuplicateHost => "duplicate_host",
+            SmugglingVector::ConflictingContentLength => "conflicting_content_length",
+            SmugglingVector::ContentLengthWithTransferEncoding => {
+                "content_length_with_transfer_encoding"
+            }
+            SmugglingVector::InvalidTransferEncoding => "invalid_transfer_encoding",
+            SmugglingVector::BareLineFeed => "bare_lf",
+        }
+    }
+}
+
+/// Wether a line of the head at the start of `buf`, up to the first empty line,
+/// ends with a bare LF. The parser rejects them, but other parsers may accept them
+pub fn has_bare_line_feed(buf: &[u8]) -> bool {
+    let head = match buf.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(end) => &buf[..end],
+        None => buf,
+    };
+    head.iter()
+        .enumerate()
+        .any(|(i, c)| *c == b'\n' && (i == 0 || head[i - 1] != b'\r'))
+}
+
+/// Parse the value of a `Retry-After` header into the delay to wait from `now`.
+///
+/// Both forms of RFC 9110 are accepted: delta-seconds (`120`) and an
+/// IMF-fixdate HTTP-date (`Sun, 06 Nov 1994 08:49:37 GMT`). A date in the
+/// past yields a zero delay, a delay longer than `max` is capped to it: the
+/// backend can not take itself out of the rotation for an arbitrary time.
+pub fn parse_retry_after(value: &[u8], now: OffsetDateTime, max: Duration) -> Option<Duration> {
+    let value = from_utf8(value).ok()?.trim();
+
+    let delay = if !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()) {
+        // too many digits for a u64 is still a long delay
+        value.parse::<u64>().map(Duration::from_secs).unwrap_or(max)
+    } else {
+        let delay = parse_http_date(value)? - now;
+        if delay.is_negative() {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(delay.whole_seconds() as u64)
+        }
+    };
+    Some(delay.min(max))
+}
+
+/// parse an IMF-fixdate, like `Sun, 06 Nov 1994 08:49:37 GMT`
+fn parse_http_date(value: &str) -> Option<OffsetDateTime> {
+    let mut parts = value.split_ascii_whitespace();
+
+    let _day_name = parts.next().filter(|day| day.ends_with(','))?;
+    let day: u8 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => Month::January,
+        "Feb" => Month::February,
+        "Mar" => Month::March,
+        "Apr" => Month::April,
+        "May" => Month::May,
+        "Jun" => Month::June,
+        "Jul" => Month::July,
+        "Aug" => Month::August,
+        "Sep" => Month::September,
+        "Oct" => Month::October,
+        "Nov" => Month::November,
+        "Dec" => Month::December,
+        _ => return None,
+    };
+    let year: i32 = parts.next()?.parse().ok()?;
+
+    let mut clock = parts.next()?.split(':');
+    let hour: u8 = clock.next()?.parse().ok()?;
+    let minute: u8 = clock.next()?.parse().ok()?;
+    let second: u8 = clock.next()?.parse().ok()?;
+    if clock.next().is_some() {
+        return None;
+    }
+
+    if parts.next()? != "GMT" || parts.next().is_some() {
+        return None;
+    }
+
+    let date = Date::from_calendar_date(year, month, day).ok()?;
+    let time = Time::from_hms(hour, minute, second).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
 pub fn view(buf: &[u8], size: usize, points: &[usize]) -> String {
     let mut view = format!("{points:?} => ");
     let mut end = 0;
@@ -166,3 +665,559 @@ fn test_view_out_of_bound() {
         )
     );
 }
+
+/// Length of the URI received so far in a request line the parser is still
+/// waiting the end of: the bytes between the method and the next space or line ending
+pub fn pending_uri_length(line: &[u8]) -> usize {
+    let Some(method_end) = line.iter().position(|c| *c == b' ') else {
+        return 0;
+    };
+    line[method_end + 1..]
+        .iter()
+        .take_while(|c| !matches!(c, b' ' | b'\r' | b'\n'))
+        .count()
+}
+
+/// Length of the status line and headers at the start of `buf`, up to the empty line
+/// ending them, or of all of `buf` if it is not received yet
+pub fn head_length(buf: &[u8]) -> usize {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|end| end + 4)
+        .unwrap_or(buf.len())
+}
+
+/// Wether a "traceparent" header value follows the W3C Trace Context format:
+/// "{version}-{trace id}-{parent id}-{flags}" in lowercase hexadecimal, where the ids
+/// are not all zeros. Versions after "00" may append fields, "ff" is forbidden
+pub fn is_valid_traceparent(value: &[u8]) -> bool {
+    fn is_lower_hex(field: &[u8]) -> bool {
+        field.iter().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    if value.len() < 55 || value[2] != b'-' || value[35] != b'-' || value[52] != b'-' {
+        return false;
+    }
+    let version = &value[..2];
+    let trace_id = &value[3..35];
+    let parent_id = &value[36..52];
+    let flags = &value[53..55];
+    let valid_end = match version {
+        b"00" => value.len() == 55,
+        _ => value.len() == 55 || value[55] == b'-',
+    };
+    valid_end
+        && version != b"ff"
+        && is_lower_hex(version)
+        && is_lower_hex(trace_id)
+        && is_lower_hex(parent_id)
+        && is_lower_hex(flags)
+        && trace_id.iter().any(|c| *c != b'0')
+        && parent_id.iter().any(|c| *c != b'0')
+}
+
+#[test]
+fn test_pending_uri_length() {
+    assert_eq!(pending_uri_length(b""), 0);
+    assert_eq!(pending_uri_length(b"GET"), 0);
+    assert_eq!(pending_uri_length(b"GET "), 0);
+    assert_eq!(pending_uri_length(b"GET /api/us"), 7);
+    assert_eq!(pending_uri_length(b"GET /api HTTP/1."), 4);
+    assert_eq!(pending_uri_length(b"GET /api\r\n"), 4);
+}
+
+#[test]
+fn test_head_length() {
+    assert_eq!(head_length(b""), 0);
+    assert_eq!(head_length(b"HTTP/1.1 200 OK\r\nContent-Le"), 27);
+    assert_eq!(
+        head_length(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\npong"),
+        38
+    );
+    // the body is not counted, even if it contains an empty line
+    assert_eq!(
+        head_length(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\n\r\n\r\npong"),
+        38
+    );
+}
+
+#[test]
+fn test_is_valid_traceparent() {
+    assert!(is_valid_traceparent(
+        b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    ));
+    // a later version may add fields
+    assert!(is_valid_traceparent(
+        b"01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"
+    ));
+
+    for invalid in [
+        &b""[..],
+        b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        b"01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00extra",
+        b"ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        b"00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        b"00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        b"00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        b"00_4bf92f3577b34da6a3ce929d0e0e4736_00f067aa0ba902b7_01",
+        b"00-4bf92f3577b34da6a3ce929d0e0e473g-00f067aa0ba902b7-01",
+    ] {
+        assert!(
+            !is_valid_traceparent(invalid),
+            "{}",
+            String::from_utf8_lossy(invalid)
+        );
+    }
+}
+
+#[test]
+fn test_authority_hostname() {
+    assert_eq!(authority_hostname("lolcatho.st"), Some("lolcatho.st"));
+    assert_eq!(authority_hostname("LolCatHo.st:8443"), Some("LolCatHo.st"));
+    assert_eq!(authority_hostname("lolcatho.st."), Some("lolcatho.st"));
+    assert_eq!(
+        authority_hostname("api.lolcatho.st"),
+        Some("api.lolcatho.st")
+    );
+    // left to the routing
+    assert_eq!(authority_hostname("[::1]:8443"), None);
+}
+
+#[test]
+fn test_retry_after_delta_seconds() {
+    let now = OffsetDateTime::from_unix_timestamp(784111777).unwrap();
+    let max = Duration::from_secs(3600);
+
+    assert_eq!(
+        parse_retry_after(b"120", now, max),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(parse_retry_after(b" 0 ", now, max), Some(Duration::ZERO));
+    assert_eq!(parse_retry_after(b"-5", now, max), None);
+    assert_eq!(parse_retry_after(b"", now, max), None);
+}
+
+#[test]
+fn test_retry_after_capped() {
+    let now = OffsetDateTime::from_unix_timestamp(784111777).unwrap();
+    let max = Duration::from_secs(3600);
+
+    assert_eq!(parse_retry_after(b"86400", now, max), Some(max));
+    assert_eq!(
+        parse_retry_after(b"99999999999999999999999", now, max),
+        Some(max)
+    );
+    assert_eq!(
+        parse_retry_after(b"Sun, 06 Nov 2044 08:49:37 GMT", now, max),
+        Some(max)
+    );
+}
+
+#[test]
+fn test_retry_after_http_date() {
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    let now = OffsetDateTime::from_unix_timestamp(784111777).unwrap();
+    let max = Duration::from_secs(3600);
+
+    assert_eq!(
+        parse_retry_after(b"Sun, 06 Nov 1994 08:51:37 GMT", now, max),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(
+        parse_retry_after(b"Sun, 06 Nov 1994 08:00:00 GMT", now, max),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(
+        parse_retry_after(b"Sun, 06 Nov 1994 08:51:37 CET", now, max),
+        None
+    );
+    assert_eq!(parse_retry_after(b"Sunday, 06-Nov-94", now, max), None);
+}
+
+#[test]
+fn test_connection_named_headers() {
+    assert!(connection_named_headers(b"close", &[]).is_empty());
+    assert!(connection_named_headers(b"Keep-Alive, Upgrade", &[]).is_empty());
+    assert_eq!(
+        connection_named_headers(b"Upgrade, HTTP2-Settings", &[]),
+        vec!["HTTP2-Settings".to_string()]
+    );
+}
+
+#[test]
+fn test_connection_named_headers_preserved() {
+    let preserved = vec!["http2-settings".to_string()];
+
+    assert!(connection_named_headers(b"Upgrade, HTTP2-Settings", &preserved).is_empty());
+    assert_eq!(
+        connection_named_headers(b"Upgrade,HTTP2-Settings ,\tX-Secret, , Foo", &preserved),
+        vec!["X-Secret".to_string(), "Foo".to_string()]
+    );
+}
+
+#[test]
+fn test_websocket_accept() {
+    // example from RFC 6455 section 1.3
+    assert_eq!(
+        websocket_accept(b"dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn test_validate_websocket_handshake() {
+    let key = b"dGhlIHNhbXBsZSBub25jZQ==";
+    let offered = vec!["chat".to_string(), "superchat".to_string()];
+
+    assert_eq!(
+        validate_websocket_handshake(
+            key,
+            &offered,
+            Some(&b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="[..]),
+            Some(&b"chat"[..])
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        validate_websocket_handshake(key, &[], Some(&b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="[..]), None),
+        Ok(())
+    );
+    assert_eq!(
+        validate_websocket_handshake(key, &offered, None, None),
+        Err(WebSocketHandshakeError::MissingAccept)
+    );
+    assert_eq!(
+        validate_websocket_handshake(
+            key,
+            &offered,
+            Some(&b"HSmrc0sMlYUkAGmm5OPpG2HaGWk="[..]),
+            None
+        ),
+        Err(WebSocketHandshakeError::InvalidAccept {
+            expected: "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string(),
+            found: "HSmrc0sMlYUkAGmm5OPpG2HaGWk=".to_string(),
+        })
+    );
+    assert_eq!(
+        validate_websocket_handshake(
+            key,
+            &offered,
+            Some(&b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="[..]),
+            Some(&b"megachat"[..])
+        ),
+        Err(WebSocketHandshakeError::UnrequestedProtocol(
+            "megachat".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_allowed_websocket_protocols() {
+    let allowed = vec!["chat".to_string(), "superchat".to_string()];
+    let offered =
+        |protocols: &[&str]| -> Vec<String> { protocols.iter().map(ToString::to_string).collect() };
+
+    assert_eq!(
+        allowed_websocket_protocols(&offered(&["superchat", "chat"]), &allowed),
+        offered(&["superchat", "chat"])
+    );
+    // the disallowed subprotocols are dropped from a valid offer
+    assert_eq!(
+        allowed_websocket_protocols(&offered(&["chat", "mqtt"]), &allowed),
+        offered(&["chat"])
+    );
+    // a frontend without allowed subprotocols does not restrict them
+    assert_eq!(
+        allowed_websocket_protocols(&offered(&["mqtt"]), &[]),
+        offered(&["mqtt"])
+    );
+
+    assert!(allowed_websocket_protocols(&offered(&["mqtt"]), &allowed).is_empty());
+    // subprotocols are compared case sensitively, like in the handshake validation
+    assert!(allowed_websocket_protocols(&offered(&["Chat"]), &allowed).is_empty());
+}
+
+#[test]
+fn test_hop_by_hop_headers() {
+    for header in [
+        &b"Keep-Alive"[..],
+        b"proxy-authenticate",
+        b"Proxy-Authorization",
+        b"PROXY-CONNECTION",
+        b"te",
+        b"Trailer",
+    ] {
+        assert!(is_hop_by_hop_header(header, &[]));
+    }
+
+    assert!(!is_hop_by_hop_header(b"Connection", &[]));
+    assert!(!is_hop_by_hop_header(b"Transfer-Encoding", &[]));
+    assert!(!is_hop_by_hop_header(b"Upgrade", &[]));
+    assert!(!is_hop_by_hop_header(b"X-Internal-Token", &[]));
+
+    let extra = vec!["X-Internal-Token".to_string()];
+    assert!(is_hop_by_hop_header(b"x-internal-token", &extra));
+    assert!(is_hop_by_hop_header(b"TE", &extra));
+}
+
+#[test]
+fn test_accepts_trailers() {
+    assert!(accepts_trailers(b"trailers"));
+    assert!(accepts_trailers(b"Trailers"));
+    assert!(accepts_trailers(b"gzip;q=0.5, trailers"));
+    assert!(accepts_trailers(b"trailers ;q=1"));
+    assert!(!accepts_trailers(b"gzip, deflate"));
+    assert!(!accepts_trailers(b"trailers-only"));
+    assert!(!accepts_trailers(b""));
+}
+
+#[test]
+fn test_authorize_expect_continue() {
+    assert_eq!(
+        authorize_expect_continue(None, &[]),
+        ExpectContinueDecision::Continue
+    );
+
+    let accepted = vec!["Bearer secret".to_string()];
+    assert_eq!(
+        authorize_expect_continue(Some(&b"Bearer secret"[..]), &accepted),
+        ExpectContinueDecision::Continue
+    );
+    assert_eq!(
+        authorize_expect_continue(None, &accepted),
+        ExpectContinueDecision::Unauthorized
+    );
+    assert_eq!(
+        authorize_expect_continue(Some(&b" "[..]), &accepted),
+        ExpectContinueDecision::Unauthorized
+    );
+    assert_eq!(
+        authorize_expect_continue(Some(&b"Bearer guess"[..]), &accepted),
+        ExpectContinueDecision::Forbidden
+    );
+    assert_eq!(
+        authorize_expect_continue(Some(&b"Bearer secret2"[..]), &accepted),
+        ExpectContinueDecision::Forbidden
+    );
+}
+
+#[test]
+fn test_www_authenticate_headers() {
+    let accepted = vec![
+        "Bearer first".to_string(),
+        "bearer second".to_string(),
+        "Basic YWRtaW46cGFzc3dvcmQ=".to_string(),
+    ];
+    assert_eq!(
+        www_authenticate_headers(authorization_schemes(&accepted), "cluster_1"),
+        "WWW-Authenticate: Bearer realm=\"cluster_1\"\r\nWWW-Authenticate: Basic realm=\"cluster_1\"\r\n"
+    );
+    assert_eq!(
+        www_authenticate_headers(["Basic"], "my \"realm\"\r\n"),
+        "WWW-Authenticate: Basic realm=\"my \\\"realm\\\"\"\r\n"
+    );
+    assert_eq!(
+        www_authenticate_headers(authorization_schemes(&[]), "cluster_1"),
+        ""
+    );
+}
+
+#[test]
+fn test_check_authorization() {
+    // "password"
+    let basic =
+        vec!["admin:5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8".to_string()];
+    let bearer = vec!["my-api-token".to_string()];
+
+    // admin:password
+    assert!(check_authorization(
+        b"Basic YWRtaW46cGFzc3dvcmQ=",
+        &basic,
+        &bearer
+    ));
+    assert!(check_authorization(
+        b"basic  YWRtaW46cGFzc3dvcmQ= ",
+        &basic,
+        &bearer
+    ));
+    assert!(check_authorization(b"Bearer my-api-token", &basic, &bearer));
+
+    // admin:guess
+    assert!(!check_authorization(
+        b"Basic YWRtaW46Z3Vlc3M=",
+        &basic,
+        &bearer
+    ));
+    // root:password
+    assert!(!check_authorization(
+        b"Basic cm9vdDpwYXNzd29yZA==",
+        &basic,
+        &bearer
+    ));
+    assert!(!check_authorization(b"Basic not-base64", &basic, &bearer));
+    assert!(!check_authorization(b"Bearer other-token", &basic, &bearer));
+    assert!(!check_authorization(
+        b"Digest my-api-token",
+        &basic,
+        &bearer
+    ));
+    assert!(!check_authorization(b"my-api-token", &basic, &bearer));
+    assert!(!check_authorization(b"Bearer my-api-token", &basic, &[]));
+
+    // sha256("s4lt" + "password")
+    let salted = vec![
+        "admin:s4lt:f59a61fe2f74088c79a10475bbebc0f081edb3abb95965e01444f444d2a8cd1f".to_string(),
+    ];
+    assert!(check_authorization(
+        b"Basic YWRtaW46cGFzc3dvcmQ=",
+        &salted,
+        &bearer
+    ));
+    assert!(!check_authorization(
+        b"Basic YWRtaW46cGFzc3dvcmQ=",
+        &basic[..0],
+        &bearer
+    ));
+    assert!(!check_authorization(
+        b"Basic YWRtaW46Z3Vlc3M=",
+        &salted,
+        &bearer
+    ));
+}
+
+#[test]
+fn test_parse_transfer_encoding() {
+    assert_eq!(
+        parse_transfer_encoding(b"chunked"),
+        Some(TransferEncoding {
+            codings: vec![],
+            chunked: true,
+        })
+    );
+    assert_eq!(
+        parse_transfer_encoding(b"gzip, chunked"),
+        Some(TransferEncoding {
+            codings: vec!["gzip".to_string()],
+            chunked: true,
+        })
+    );
+    assert_eq!(
+        parse_transfer_encoding(b"GZip,deflate ,  Chunked"),
+        Some(TransferEncoding {
+            codings: vec!["gzip".to_string(), "deflate".to_string()],
+            chunked: true,
+        })
+    );
+    assert_eq!(
+        parse_transfer_encoding(b"x-custom;level=2, chunked"),
+        Some(TransferEncoding {
+            codings: vec!["x-custom".to_string()],
+            chunked: true,
+        })
+    );
+    // without chunked, the framing is left to the parser
+    assert_eq!(
+        parse_transfer_encoding(b"gzip"),
+        Some(TransferEncoding {
+            codings: vec!["gzip".to_string()],
+            chunked: false,
+        })
+    );
+
+    assert_eq!(parse_transfer_encoding(b"chunked, gzip"), None);
+    assert_eq!(parse_transfer_encoding(b"chunked, chunked"), None);
+    assert_eq!(parse_transfer_encoding(b"gzip, chunked;ext=1"), None);
+    assert_eq!(parse_transfer_encoding(b";q=1, chunked"), None);
+}
+
+/// the previous, per byte implementation of compare_no_case
+#[cfg(test)]
+fn compare_no_case_reference(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+
+    left.iter().zip(right).all(|(a, b)| match (*a, *b) {
+        (0..=64, 0..=64) | (91..=96, 91..=96) | (123..=255, 123..=255) => a == b,
+        (65..=90, 65..=90) | (97..=122, 97..=122) | (65..=90, 97..=122) | (97..=122, 65..=90) => {
+            *a | 0b00_10_00_00 == *b | 0b00_10_00_00
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+quickcheck! {
+  fn qc_compare_no_case(left: Vec<u8>, right: Vec<u8>) -> bool {
+    compare_no_case(&left, &right) == compare_no_case_reference(&left, &right)
+  }
+
+  // random slices rarely have the same length, flip the case bit of some
+  // bytes to compare slices that are close
+  fn qc_compare_no_case_flipped(left: Vec<u8>, flips: Vec<bool>) -> bool {
+    let right: Vec<u8> = left
+        .iter()
+        .zip(flips.iter().chain(std::iter::repeat(&false)))
+        .map(|(c, flip)| if *flip { c ^ 0x20 } else { *c })
+        .collect();
+    compare_no_case(&left, &right) == compare_no_case_reference(&left, &right)
+  }
+
+  fn qc_compare_no_case_ascii_case(value: String) -> bool {
+    let upper = value.to_ascii_uppercase();
+    compare_no_case(value.as_bytes(), upper.as_bytes())
+        && compare_no_case_reference(value.as_bytes(), upper.as_bytes())
+  }
+}
+
+#[test]
+fn test_bare_line_feed() {
+    assert!(!has_bare_line_feed(
+        b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\nbody\nwith LF"
+    ));
+    assert!(has_bare_line_feed(
+        b"GET / HTTP/1.1\r\nHost: localhost\nContent-Length: 5\r\n\r\n"
+    ));
+    assert!(has_bare_line_feed(b"GET / HTTP/1.1\nHost: localhost\n\n"));
+    assert!(has_bare_line_feed(b"\nGET / HTTP/1.1\r\n"));
+}
+
+#[test]
+fn test_compare_no_case() {
+    assert!(compare_no_case(b"Content-Length", b"content-length"));
+    assert!(compare_no_case(b"X-FORWARDED-FOR", b"x-forwarded-for"));
+    assert!(compare_no_case(b"", b""));
+    assert!(!compare_no_case(b"Host", b"Hos"));
+    assert!(!compare_no_case(b"Host", b"Hosu"));
+    // only letters are case insensitive: '@' ^ '`' and '[' ^ '{' are the case bit
+    assert!(!compare_no_case(b"@", b"`"));
+    assert!(!compare_no_case(b"[", b"{"));
+    assert!(!compare_no_case(b"\xc1", b"\xe1"));
+}
+
+#[test]
+fn test_method_is_replay_safe() {
+    assert!(Method::new(b"GET").is_replay_safe());
+    assert!(Method::new(b"HEAD").is_replay_safe());
+    assert!(Method::new(b"OPTIONS").is_replay_safe());
+    assert!(!Method::new(b"POST").is_replay_safe());
+    assert!(!Method::new(b"PUT").is_replay_safe());
+    assert!(!Method::new(b"DELETE").is_replay_safe());
+    assert!(!Method::new(b"PATCH").is_replay_safe());
+}
+
+#[test]
+fn test_method_new() {
+    assert_eq!(Method::from_bytes(b"get"), Some(Method::Get));
+    assert_eq!(
+        Method::from_bytes(b"PURGE"),
+        Some(Method::Custom("PURGE".to_owned()))
+    );
+    assert_eq!(Method::from_bytes(b"G\xe9T"), None);
+    assert_eq!(Method::from_bytes(b"\xff\xfe"), None);
+    assert_eq!(
+        Method::new(b"G\xe9T"),
+        Method::Custom("G\u{fffd}T".to_owned())
+    );
+}
@@ -0,0 +1,107 @@
+//! Hysteresis on the reads from the backend socket. Without it, a session
+//! with a fast backend and a slow client stops reading as soon as the response
+//! buffer is full, then reads again after each write to the client, which only
+//! freed a few bytes: the session alternates between tiny reads and writes.
+
+/// Reading from the backend stops once `high` bytes are buffered,
+/// and resumes when the buffered data went down to `low` bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadWatermarks {
+    pub low: usize,
+    pub high: usize,
+}
+
+impl ReadWatermarks {
+    /// By default, reading stops when the buffer is full
+    /// and resumes as soon as some of it was written
+    pub fn new(capacity: usize, low: Option<u32>, high: Option<u32>) -> Self {
+        let high = high.map_or(capacity, |high| (high as usize).clamp(1, capacity));
+        let low = low.map_or(high - 1, |low| (low as usize).min(high - 1));
+        ReadWatermarks { low, high }
+    }
+
+    pub fn should_pause(&self, buffered: usize) -> bool {
+        buffered >= self.high
+    }
+
+    pub fn should_resume(&self, buffered: usize) -> bool {
+        buffered <= self.low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulates a backend that always has data to read,
+    /// and a client that drains 1kB at each write.
+    /// Returns the number of times reading from the backend was paused
+    fn count_read_stalls(watermarks: ReadWatermarks, capacity: usize, to_send: usize) -> usize {
+        let mut buffered = 0;
+        let mut read = 0;
+        let mut paused = false;
+        let mut stalls = 0;
+
+        while read < to_send || buffered > 0 {
+            while !paused && read < to_send {
+                if watermarks.should_pause(buffered) {
+                    paused = true;
+                    stalls += 1;
+                } else {
+                    let size = (capacity - buffered).min(to_send - read);
+                    buffered += size;
+                    read += size;
+                }
+            }
+
+            buffered -= buffered.min(1024);
+            if paused && watermarks.should_resume(buffered) {
+                paused = false;
+            }
+        }
+        stalls
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            ReadWatermarks::new(16384, None, None),
+            ReadWatermarks {
+                low: 16383,
+                high: 16384
+            }
+        );
+        assert_eq!(
+            ReadWatermarks::new(16384, Some(20000), Some(100000)),
+            ReadWatermarks {
+                low: 16383,
+                high: 16384
+            }
+        );
+        assert_eq!(
+            ReadWatermarks::new(16384, Some(4096), None),
+            ReadWatermarks {
+                low: 4096,
+                high: 16384
+            }
+        );
+    }
+
+    #[test]
+    fn fewer_read_stalls_with_low_watermark() {
+        let capacity = 16384;
+        let to_send = 1024 * 1024;
+
+        let without =
+            count_read_stalls(ReadWatermarks::new(capacity, None, None), capacity, to_send);
+        let with = count_read_stalls(
+            ReadWatermarks::new(capacity, Some(4096), None),
+            capacity,
+            to_send,
+        );
+        // without it, every 1kB written to the client resumes reading for 1kB,
+        // with it, reading resumes after 12kB were written and reads 12kB at once
+        assert_eq!(without, 1008);
+        assert_eq!(with, 84);
+    }
+}
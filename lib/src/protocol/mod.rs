@@ -1,3 +1,4 @@
+pub mod client_hello;
 pub mod h2;
 pub mod kawa_h1;
 pub mod pipe;
@@ -7,7 +8,7 @@ pub mod rustls;
 use std::{cell::RefCell, rc::Rc};
 
 use mio::Token;
-use sozu_command::ready::Ready;
+use sozu_command::{proto::command::SessionInfo, ready::Ready};
 
 use crate::{
     L7Proxy, ProxySession, SessionIsToBeClosed, SessionMetrics, SessionResult, StateResult,
@@ -49,6 +50,8 @@ pub trait SessionState {
     ///         - Token(...) Readiness(...)
     /// ```
     fn print_state(&self, context: &str);
+    /// describe the state in the snapshot of the session returned by the sessions query
+    fn fill_session_info(&self, _info: &mut SessionInfo) {}
     /// tell the session it has to shut down if possible
     ///
     /// if the session handles HTTP requests, it will not close until the response
@@ -1,14 +1,15 @@
-use std::{cell::RefCell, net::SocketAddr, rc::Rc};
+use std::{borrow::Cow, cell::RefCell, net::SocketAddr, rc::Rc};
 
 use mio::{net::TcpStream, Token};
 use rusty_ulid::Ulid;
-use sozu_command::config::MAX_LOOP_ITERATIONS;
+use sozu_command::proto::command::SessionInfo;
 
 use crate::{
     backends::Backend,
-    logs::{Endpoint, LogContext, RequestRecord},
+    logs::{protocol_with_alpn, Endpoint, LogContext, RequestRecord},
     pool::Checkout,
     protocol::SessionState,
+    server::max_loop_iterations,
     socket::{stats::socket_rtt, SocketHandler, SocketResult, TransportProtocol},
     sozu_command::ready::Ready,
     timer::TimeoutContainer,
@@ -125,6 +126,22 @@ impl<Front: SocketHandler, L: ListenerHandler> Pipe<Front, L> {
         self.frontend.socket_mut()
     }
 
+    /// Copy the pending data of the frontend socket in the free space of the
+    /// frontend buffer, without consuming it from the socket nor filling the buffer.
+    /// Returns the peeked data, and whether it took all the free space
+    pub fn peek_frontend(&mut self) -> std::io::Result<(&[u8], bool)> {
+        let space = self.frontend_buffer.space();
+        let size = self.frontend.socket_ref().peek(space)?;
+        let is_full = size == space.len();
+        Ok((&space[..size], is_full))
+    }
+
+    /// Give back the frontend socket and both buffers, for a pipe that never
+    /// connected to a backend and has to start over in another state
+    pub fn into_parts(self) -> (Front, Checkout, Checkout) {
+        (self.frontend, self.frontend_buffer, self.backend_buffer)
+    }
+
     pub fn back_socket(&self) -> Option<&TcpStream> {
         self.backend_socket.as_ref()
     }
@@ -179,8 +196,8 @@ impl<Front: SocketHandler, L: ListenerHandler> Pipe<Front, L> {
             .and_then(|backend| backend.peer_addr().ok())
     }
 
-    fn protocol_string(&self) -> &'static str {
-        match self.protocol {
+    fn protocol_string(&self) -> Cow<'static, str> {
+        let transport = match self.protocol {
             Protocol::TCP => "TCP",
             Protocol::HTTP => "WS",
             Protocol::HTTPS => match self.frontend.protocol() {
@@ -193,7 +210,8 @@ impl<Front: SocketHandler, L: ListenerHandler> Pipe<Front, L> {
                 _ => unreachable!(),
             },
             _ => unreachable!(),
-        }
+        };
+        protocol_with_alpn(transport, self.frontend.alpn_protocol())
     }
 
     pub fn log_request(&self, metrics: &SessionMetrics, message: Option<&str>) {
@@ -203,15 +221,17 @@ impl<Front: SocketHandler, L: ListenerHandler> Pipe<Front, L> {
             context: self.log_context(),
             session_address: self.get_session_address(),
             backend_address: self.get_backend_address(),
-            protocol: self.protocol_string(),
+            protocol: &self.protocol_string(),
             endpoint: Endpoint::Tcp {
                 context: self.websocket_context.as_deref(),
             },
             tags: listener.get_concatenated_tags(&listener.get_addr().to_string()),
             client_rtt: socket_rtt(self.front_socket()),
             server_rtt: self.backend_socket.as_ref().and_then(socket_rtt),
+            backend_connection: None,
             metrics,
             user_agent: None,
+            format: listener.get_access_logs_format(),
         }
         .log();
     }
@@ -227,7 +247,7 @@ impl<Front: SocketHandler, L: ListenerHandler> Pipe<Front, L> {
             self.log_context(),
             message
         );
-        self.print_state(self.protocol_string());
+        self.print_state(&self.protocol_string());
         self.log_request(metrics, Some(message));
     }
 
@@ -631,7 +651,8 @@ impl<Front: SocketHandler, L: ListenerHandler> SessionState for Pipe<Front, L> {
         }
 
         let token = self.frontend_token;
-        while counter < MAX_LOOP_ITERATIONS {
+        let max_loop_iterations = max_loop_iterations();
+        while counter < max_loop_iterations {
             let frontend_interest = self.frontend_readiness.filter_interest();
             let backend_interest = self.backend_readiness.filter_interest();
 
@@ -709,14 +730,15 @@ impl<Front: SocketHandler, L: ListenerHandler> SessionState for Pipe<Front, L> {
             counter += 1;
         }
 
-        if counter >= MAX_LOOP_ITERATIONS {
+        if counter >= max_loop_iterations {
             error!(
-                "PROXY\thandling session {:?} went through {} iterations, there's a probable infinite loop bug, closing the connection",
-                self.frontend_token, MAX_LOOP_ITERATIONS
+                "PROXY\thandling session {:?} went through {} iterations in the pipe state, there's a probable infinite loop bug, closing the connection",
+                self.frontend_token, max_loop_iterations
             );
             incr!("http.infinite_loop.error");
+            incr!("http.infinite_loop.pipe");
 
-            self.print_state(self.protocol_string());
+            self.print_state(&self.protocol_string());
 
             return SessionResult::Close;
         }
@@ -784,4 +806,13 @@ impl<Front: SocketHandler, L: ListenerHandler> SessionState for Pipe<Front, L> {
             self.backend_readiness
         );
     }
+
+    fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.cluster_id = self.cluster_id.clone();
+        info.backend_id = self.backend_id.clone();
+        info.frontend_readiness = Some(format!("{:?}", self.frontend_readiness));
+        info.backend_readiness = Some(format!("{:?}", self.backend_readiness));
+        info.request_state = Some(format!("{:?}", self.frontend_status));
+        info.response_state = Some(format!("{:?}", self.backend_status));
+    }
 }
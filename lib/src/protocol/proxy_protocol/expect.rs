@@ -1,14 +1,15 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, net::SocketAddr, rc::Rc};
 
 use mio::{net::TcpStream, *};
 use nom::{Err, HexDisplay};
 use rusty_ulid::Ulid;
-use sozu_command::config::MAX_LOOP_ITERATIONS;
+use sozu_command::proto::command::SessionInfo;
 
 use crate::{
     logs::LogContext,
     pool::Checkout,
     protocol::{pipe::Pipe, SessionResult, SessionState},
+    server::max_loop_iterations,
     socket::{SocketHandler, SocketResult},
     sozu_command::ready::Ready,
     tcp::TcpListener,
@@ -33,6 +34,8 @@ pub struct ExpectProxyProtocol<Front: SocketHandler> {
     pub frontend_readiness: Readiness,
     pub frontend_token: Token,
     pub frontend: Front,
+    /// end of the header in frontend_buffer, the bytes read after it belong to the stream
+    header_end: usize,
     header_len: HeaderLen,
     index: usize,
     pub request_id: Ulid,
@@ -58,6 +61,7 @@ impl<Front: SocketHandler> ExpectProxyProtocol<Front> {
             },
             frontend_token,
             frontend,
+            header_end: 0,
             header_len: HeaderLen::V4,
             index: 0,
             request_id,
@@ -117,6 +121,7 @@ impl<Front: SocketHandler> ExpectProxyProtocol<Front> {
                     rest.len()
                 );
                 self.addresses = Some(header.addr);
+                self.header_end = self.index - rest.len();
                 SessionResult::Upgrade
             }
             Err(Err::Incomplete(_)) => {
@@ -158,15 +163,34 @@ impl<Front: SocketHandler> ExpectProxyProtocol<Front> {
         self.frontend.socket_ref()
     }
 
+    /// The client address given by the header, or the peer address of the
+    /// socket for a header without addresses (LOCAL command, AF_UNSPEC)
+    pub fn session_address(&self) -> Option<SocketAddr> {
+        self.addresses
+            .as_ref()
+            .and_then(|addresses| addresses.source())
+            .or_else(|| self.front_socket().peer_addr().ok())
+    }
+
+    /// The bytes read past the header, the beginning of the client stream
+    pub fn stream_start(&self) -> &[u8] {
+        &self.frontend_buffer[self.header_end..self.index]
+    }
+
     pub fn into_pipe(
         self,
-        front_buf: Checkout,
+        mut front_buf: Checkout,
         back_buf: Checkout,
         backend_socket: Option<TcpStream>,
         backend_token: Option<Token>,
         listener: Rc<RefCell<TcpListener>>,
     ) -> Pipe<Front, TcpListener> {
-        let addr = self.front_socket().peer_addr().ok();
+        let addr = self.session_address();
+
+        // the header is stripped, the rest is relayed to the backend
+        let stream_start = self.stream_start();
+        front_buf.space()[..stream_start.len()].copy_from_slice(stream_start);
+        front_buf.fill(stream_start.len());
 
         let mut pipe = Pipe::new(
             back_buf,
@@ -217,7 +241,8 @@ impl<Front: SocketHandler> SessionState for ExpectProxyProtocol<Front> {
             return SessionResult::Close;
         }
 
-        while counter < MAX_LOOP_ITERATIONS {
+        let max_loop_iterations = max_loop_iterations();
+        while counter < max_loop_iterations {
             let frontend_interest = self.frontend_readiness.filter_interest();
 
             trace!(
@@ -251,12 +276,13 @@ impl<Front: SocketHandler> SessionState for ExpectProxyProtocol<Front> {
             counter += 1;
         }
 
-        if counter >= MAX_LOOP_ITERATIONS {
+        if counter >= max_loop_iterations {
             error!(
-                "PROXY\thandling session {:?} went through {} iterations, there's a probable infinite loop bug, closing the connection",
-                self.frontend_token, MAX_LOOP_ITERATIONS
+                "PROXY\thandling session {:?} went through {} iterations in the expect proxy protocol state, there's a probable infinite loop bug, closing the connection",
+                self.frontend_token, max_loop_iterations
             );
             incr!("http.infinite_loop.error");
+            incr!("http.infinite_loop.expect");
 
             self.print_state("");
 
@@ -295,6 +321,10 @@ impl<Front: SocketHandler> SessionState for ExpectProxyProtocol<Front> {
             context, self.frontend_token, self.frontend_readiness
         );
     }
+
+    fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.frontend_readiness = Some(format!("{:?}", self.frontend_readiness));
+    }
 }
 
 #[cfg(test)]
@@ -384,4 +414,58 @@ mod expect_test {
             };
         })
     }
+
+    /// Feed `data` to an ExpectProxyProtocol state until it read a whole header
+    fn read_header(data: &[u8]) -> ExpectProxyProtocol<mio::net::TcpStream> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("could not bind");
+        let mut client = StdTcpStream::connect(listener.local_addr().unwrap())
+            .expect("could not connect to the middleware");
+        client.write_all(data).expect("could not send the header");
+        let (stream, _) = listener.accept().expect("could not accept the connection");
+        stream.set_nonblocking(true).unwrap();
+
+        let mut expect_pp = ExpectProxyProtocol::new(
+            TimeoutContainer::new(Duration::seconds(10), Token(0)),
+            mio::net::TcpStream::from_std(stream),
+            Token(0),
+            Ulid::generate(),
+        );
+        let mut session_metrics = SessionMetrics::new(None);
+        let mut res = SessionResult::Continue;
+        while res == SessionResult::Continue {
+            res = expect_pp.readable(&mut session_metrics);
+        }
+        assert_eq!(res, SessionResult::Upgrade);
+        expect_pp
+    }
+
+    #[test]
+    fn session_address_comes_from_the_header() {
+        let src_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(125, 25, 10, 1)), 8080);
+        let dst_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 4, 5, 8)), 4200);
+        let mut data = HeaderV2::new(Command::Proxy, src_addr, dst_addr).into_bytes();
+        data.extend_from_slice(b"ping");
+
+        let expect_pp = read_header(&data);
+        assert_eq!(expect_pp.session_address(), Some(src_addr));
+        assert_eq!(expect_pp.stream_start(), b"");
+    }
+
+    #[test]
+    fn bytes_read_past_the_header_are_kept() {
+        // mixed address families give a 16 bytes AF_UNSPEC header,
+        // shorter than the first read
+        let src_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(125, 25, 10, 1)), 8080);
+        let dst_addr: SocketAddr = "[::1]:4200".parse().unwrap();
+        let mut data = HeaderV2::new(Command::Local, src_addr, dst_addr).into_bytes();
+        data.extend_from_slice(b"ping");
+
+        let expect_pp = read_header(&data);
+        assert_eq!(expect_pp.stream_start(), b"ping");
+        // without addresses in the header, the peer address is used
+        assert_eq!(
+            expect_pp.session_address(),
+            expect_pp.front_socket().peer_addr().ok()
+        );
+    }
 }
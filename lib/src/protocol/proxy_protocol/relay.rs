@@ -8,7 +8,7 @@ use crate::{
     pool::Checkout,
     protocol::{pipe::Pipe, proxy_protocol::parser::parse_v2_header, SessionResult},
     socket::{SocketHandler, SocketResult},
-    sozu_command::ready::Ready,
+    sozu_command::{proto::command::SessionInfo, ready::Ready},
     tcp::TcpListener,
     Protocol, Readiness, SessionMetrics, StateResult,
 };
@@ -169,6 +169,12 @@ impl<Front: SocketHandler> RelayProxyProtocol<Front> {
         self.backend_token = Some(token);
     }
 
+    /// describe the state in the snapshot of the session returned by the sessions query
+    pub fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.frontend_readiness = Some(format!("{:?}", self.frontend_readiness));
+        info.backend_readiness = Some(format!("{:?}", self.backend_readiness));
+    }
+
     pub fn into_pipe(
         mut self,
         back_buf: Checkout,
@@ -15,7 +15,7 @@ use crate::{
         SessionResult,
     },
     socket::SocketHandler,
-    sozu_command::ready::Ready,
+    sozu_command::{proto::command::SessionInfo, ready::Ready},
     tcp::TcpListener,
     BackendConnectionStatus, Protocol, Readiness, SessionMetrics, StateResult,
 };
@@ -147,6 +147,12 @@ impl<Front: SocketHandler> SendProxyProtocol<Front> {
         self.backend_token = Some(token);
     }
 
+    /// describe the state in the snapshot of the session returned by the sessions query
+    pub fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.frontend_readiness = Some(format!("{:?}", self.frontend_readiness));
+        info.backend_readiness = Some(format!("{:?}", self.backend_readiness));
+    }
+
     pub fn set_back_connected(&mut self, status: BackendConnectionStatus) {
         if status == BackendConnectionStatus::Connected {
             self.backend_readiness.interest.insert(Ready::WRITABLE);
@@ -1,13 +1,18 @@
-use std::{cell::RefCell, io::ErrorKind, net::SocketAddr, rc::Rc};
+use std::{
+    cell::RefCell,
+    io::{ErrorKind, Read},
+    net::SocketAddr,
+    rc::Rc,
+};
 
 use mio::{net::TcpStream, Token};
 use rustls::ServerConnection;
 use rusty_ulid::Ulid;
-use sozu_command::config::MAX_LOOP_ITERATIONS;
+use sozu_command::proto::command::SessionInfo;
 
 use crate::{
-    logs::LogContext, protocol::SessionState, timer::TimeoutContainer, Readiness, Ready,
-    SessionMetrics, SessionResult, StateResult,
+    logs::LogContext, protocol::SessionState, server::max_loop_iterations, timer::TimeoutContainer,
+    Readiness, Ready, SessionMetrics, SessionResult, StateResult,
 };
 
 pub enum TlsState {
@@ -19,6 +24,8 @@ pub enum TlsState {
 
 pub struct TlsHandshake {
     pub container_frontend_timeout: TimeoutContainer,
+    /// application data received in TLS 1.3 early data (0-RTT)
+    pub early_data: Vec<u8>,
     pub frontend_readiness: Readiness,
     frontend_token: Token,
     pub peer_address: Option<SocketAddr>,
@@ -42,6 +49,7 @@ impl TlsHandshake {
     ) -> TlsHandshake {
         TlsHandshake {
             container_frontend_timeout,
+            early_data: Vec::new(),
             frontend_readiness: Readiness {
                 interest: Ready::READABLE | Ready::HUP | Ready::ERROR,
                 event: Ready::EMPTY,
@@ -95,6 +103,19 @@ impl TlsHandshake {
                     );
                     return SessionResult::Close;
                 }
+
+                // only accepted if the listener enabled max_early_data_size
+                if let Some(mut early_data) = self.session.early_data() {
+                    if let Err(e) = early_data.read_to_end(&mut self.early_data) {
+                        error!(
+                            "Session(sni={:?}, source={:?}) could not read early data: {:?}",
+                            self.session.server_name(),
+                            self.peer_address,
+                            e
+                        );
+                        return SessionResult::Close;
+                    }
+                }
             }
 
             if !can_work {
@@ -215,7 +236,8 @@ impl SessionState for TlsHandshake {
             return SessionResult::Close;
         }
 
-        while counter < MAX_LOOP_ITERATIONS {
+        let max_loop_iterations = max_loop_iterations();
+        while counter < max_loop_iterations {
             let frontend_interest = self.frontend_readiness.filter_interest();
 
             trace!(
@@ -256,12 +278,13 @@ impl SessionState for TlsHandshake {
             counter += 1;
         }
 
-        if counter >= MAX_LOOP_ITERATIONS {
+        if counter >= max_loop_iterations {
             error!(
-                "PROXY\thandling session {:?} went through {} iterations, there's a probable infinite loop bug, closing the connection",
-                self.frontend_token, MAX_LOOP_ITERATIONS
+                "PROXY\thandling session {:?} went through {} iterations in the handshake state, there's a probable infinite loop bug, closing the connection",
+                self.frontend_token, max_loop_iterations
             );
             incr!("http.infinite_loop.error");
+            incr!("http.infinite_loop.handshake");
 
             self.print_state("HTTPS");
 
@@ -301,4 +324,8 @@ impl SessionState for TlsHandshake {
             context, self.frontend_token, self.frontend_readiness
         );
     }
+
+    fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.frontend_readiness = Some(format!("{:?}", self.frontend_readiness));
+    }
 }
@@ -14,6 +14,9 @@ pub trait RetryPolicy: Debug + PartialEq + Eq {
 
     fn fail(&mut self);
     fn succeed(&mut self);
+    /// the backend asked to wait before trying again (e.g. `Retry-After`
+    /// on a 503), this overrides the current backoff
+    fn retry_after(&mut self, wait: time::Duration);
 
     fn can_try(&self) -> Option<RetryAction> {
         if self.current_tries() >= self.max_tries() {
@@ -84,6 +87,11 @@ impl RetryPolicy for ExponentialBackoffPolicy {
         self.current_tries = 0;
     }
 
+    fn retry_after(&mut self, wait: time::Duration) {
+        self.wait = wait;
+        self.last_try = time::Instant::now();
+    }
+
     fn can_try(&self) -> Option<RetryAction> {
         let action = if self.last_try.elapsed().ge(&self.wait) {
             RetryAction::OKAY
@@ -134,6 +142,13 @@ impl RetryPolicy for RetryPolicyWrapper {
         .succeed()
     }
 
+    fn retry_after(&mut self, wait: time::Duration) {
+        match *self {
+            RetryPolicyWrapper::ExponentialBackoff(ref mut policy) => policy,
+        }
+        .retry_after(wait)
+    }
+
     fn can_try(&self) -> Option<RetryAction> {
         match *self {
             RetryPolicyWrapper::ExponentialBackoff(ref policy) => policy,
@@ -153,6 +168,7 @@ impl RetryPolicy for RetryPolicyWrapper {
 mod tests {
     use super::{ExponentialBackoffPolicy, RetryAction, RetryPolicy};
     use serial_test::serial;
+    use std::time::Duration;
 
     const MAX_FAILS: usize = 10;
 
@@ -209,4 +225,18 @@ mod tests {
 
         assert_eq!(Some(RetryAction::WAIT), can_try)
     }
+
+    #[serial]
+    #[test]
+    fn retry_after_overrides_backoff() {
+        let mut policy = ExponentialBackoffPolicy::new(MAX_FAILS);
+        policy.retry_after(Duration::from_secs(3600));
+
+        assert_eq!(Some(RetryAction::WAIT), policy.can_try());
+        // a server asking us to wait is not a connection failure
+        assert!(!policy.is_down());
+
+        policy.retry_after(Duration::ZERO);
+        assert_eq!(Some(RetryAction::OKAY), policy.can_try());
+    }
 }
@@ -1,18 +1,26 @@
 pub mod pattern_trie;
 pub mod trie;
 
-use std::str::from_utf8;
+use std::{collections::HashMap, net::SocketAddr, str::from_utf8};
 
 use regex::bytes::Regex;
 use time::Instant;
 
 use sozu_command::{
-    proto::command::{PathRule as CommandPathRule, PathRuleKind, RulePosition},
+    proto::command::{
+        ListenerType, PathRule as CommandPathRule, PathRuleKind, QueryRouteMatch, RouteMatch,
+        RulePosition,
+    },
     response::HttpFrontend,
     state::ClusterId,
 };
 
-use crate::{protocol::http::parser::Method, router::pattern_trie::TrieNode};
+use crate::{
+    backends::BackendMap,
+    protocol::http::parser::{is_token, Method},
+    router::pattern_trie::TrieNode,
+    ProxyError,
+};
 
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum RouterError {
@@ -30,12 +38,91 @@ pub enum RouterError {
         path: String,
         method: Method,
     },
+    #[error("method {method} is not allowed for {host} {path}, only {allowed:?}")]
+    MethodNotAllowed {
+        host: String,
+        path: String,
+        method: Method,
+        allowed: Vec<String>,
+    },
+    #[error("invalid allowed method {0:?}, it must be an HTTP token")]
+    InvalidAllowedMethod(String),
+}
+
+/// Parses the listener address, if any, and the method of a route match query,
+/// shared by the HTTP and HTTPS proxies
+pub fn parse_route_match_query(
+    query: &QueryRouteMatch,
+) -> Result<(Option<SocketAddr>, Method), ProxyError> {
+    let address = query
+        .address
+        .as_ref()
+        .map(|address| {
+            address
+                .parse::<SocketAddr>()
+                .map_err(|parse_error| ProxyError::SocketParse {
+                    address: address.clone(),
+                    error: parse_error.to_string(),
+                })
+        })
+        .transpose()?;
+    let method = Method::from_bytes(query.method.as_bytes())
+        .ok_or_else(|| ProxyError::InvalidMethod(query.method.clone()))?;
+    Ok((address, method))
+}
+
+/// Options of the frontend that added a rule, applied to the requests it routes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteOptions {
+    /// if not empty, requests with another method get a 405 answer
+    pub allowed_methods: Vec<Method>,
+    /// HEAD requests are forwarded to the backends as GET
+    pub head_as_get: bool,
+    /// if not empty, the only websocket subprotocols offered to the backends
+    pub websocket_subprotocols: Vec<String>,
+}
+
+impl RouteOptions {
+    /// The allowed methods are parsed once here. They are sent back in the
+    /// `Allow` header of 405 answers, so they must be HTTP tokens
+    pub fn from_frontend(front: &HttpFrontend) -> Result<Self, RouterError> {
+        let allowed_methods = front
+            .allowed_methods
+            .iter()
+            .map(|allowed| {
+                if is_token(allowed.as_bytes()) {
+                    Ok(Method::new(allowed.as_bytes()))
+                } else {
+                    Err(RouterError::InvalidAllowedMethod(allowed.to_owned()))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RouteOptions {
+            allowed_methods,
+            head_as_get: front.head_as_get,
+            websocket_subprotocols: front.websocket_subprotocols.clone(),
+        })
+    }
 }
 
 pub struct Router {
-    pre: Vec<(DomainRule, PathRule, MethodRule, Route)>,
-    pub tree: TrieNode<Vec<(PathRule, MethodRule, Route)>>,
-    post: Vec<(DomainRule, PathRule, MethodRule, Route)>,
+    pre: Vec<(DomainRule, PathRule, MethodRule, Route, RouteOptions)>,
+    pub tree: TrieNode<Vec<(PathRule, MethodRule, Route, RouteOptions)>>,
+    post: Vec<(DomainRule, PathRule, MethodRule, Route, RouteOptions)>,
+    /// the frontends added with `add_http_front`, the rules do not keep their tags
+    frontends: HashMap<FrontendKey, HttpFrontend>,
+}
+
+/// identifies the rule added by a frontend: position, hostname, path and method
+type FrontendKey = (RulePosition, String, CommandPathRule, Option<String>);
+
+fn frontend_key(front: &HttpFrontend) -> FrontendKey {
+    (
+        front.position,
+        front.hostname.clone(),
+        front.path.clone(),
+        front.method.clone(),
+    )
 }
 
 impl Default for Router {
@@ -50,38 +137,145 @@ impl Router {
             pre: Vec::new(),
             tree: TrieNode::root(),
             post: Vec::new(),
+            frontends: HashMap::new(),
         }
     }
 
+    pub fn frontends(&self) -> impl Iterator<Item = &HttpFrontend> {
+        self.frontends.values()
+    }
+
     pub fn lookup(
         &self,
         hostname: &str,
         path: &str,
         method: &Method,
     ) -> Result<Route, RouterError> {
+        let rule = self.lookup_allowed_rule(hostname, path, method)?;
+        Ok(rule.route.clone())
+    }
+
+    /// Looks up the route of a request like `lookup`,
+    /// along with the options of the frontend that added its rule
+    pub fn lookup_with_options(
+        &self,
+        hostname: &str,
+        path: &str,
+        method: &Method,
+    ) -> Result<(Route, RouteOptions), RouterError> {
+        let rule = self.lookup_allowed_rule(hostname, path, method)?;
+        Ok((rule.route.clone(), rule.options.clone()))
+    }
+
+    /// The rule matched by a request, if its frontend allows the request method
+    fn lookup_allowed_rule(
+        &self,
+        hostname: &str,
+        path: &str,
+        method: &Method,
+    ) -> Result<MatchedRule<'_>, RouterError> {
+        let rule = self.lookup_rule(hostname, path, method)?;
+
+        let allowed_methods = &rule.options.allowed_methods;
+        if !allowed_methods.is_empty() && !allowed_methods.contains(method) {
+            return Err(RouterError::MethodNotAllowed {
+                host: hostname.to_owned(),
+                path: path.to_owned(),
+                method: method.to_owned(),
+                allowed: allowed_methods.iter().map(ToString::to_string).collect(),
+            });
+        }
+        Ok(rule)
+    }
+
+    /// Looks up the route of a request like `lookup`,
+    /// along with the frontend whose rule the request matched
+    pub fn explain(
+        &self,
+        hostname: &str,
+        path: &str,
+        method: &Method,
+    ) -> Result<(Route, Option<&HttpFrontend>), RouterError> {
+        let rule = self.lookup_rule(hostname, path, method)?;
+        let frontend = self
+            .frontends
+            .values()
+            .find(|front| rule.was_added_by(front));
+        Ok((rule.route.clone(), frontend))
+    }
+
+    /// Describes how the listener owning this router would route a request,
+    /// with the backends of the matched cluster
+    pub fn route_match(
+        &self,
+        listener_address: SocketAddr,
+        listener_type: ListenerType,
+        hostname: &str,
+        path: &str,
+        method: &Method,
+        backends: &BackendMap,
+    ) -> RouteMatch {
+        let mut route_match = RouteMatch {
+            listener_address: listener_address.to_string(),
+            listener_type: listener_type.into(),
+            ..Default::default()
+        };
+        if let Ok((route, frontend)) = self.explain(hostname, path, method) {
+            match route {
+                Route::ClusterId(cluster_id) => {
+                    route_match.backends = backends
+                        .export_configuration_state(&cluster_id)
+                        .into_iter()
+                        .map(|backend| backend.to_add_backend())
+                        .collect();
+                    route_match.cluster_id = Some(cluster_id);
+                }
+                Route::Deny => route_match.deny = true,
+            }
+            route_match.frontend = frontend.map(|front| front.to_owned().into());
+        }
+        route_match
+    }
+
+    fn lookup_rule(
+        &self,
+        hostname: &str,
+        path: &str,
+        method: &Method,
+    ) -> Result<MatchedRule<'_>, RouterError> {
         let hostname_b = hostname.as_bytes();
         let path_b = path.as_bytes();
-        for (domain_rule, path_rule, method_rule, cluster_id) in &self.pre {
+        for (domain_rule, path_rule, method_rule, cluster_id, options) in &self.pre {
             if domain_rule.matches(hostname_b)
                 && path_rule.matches(path_b) != PathRuleResult::None
                 && method_rule.matches(method) != MethodRuleResult::None
             {
-                return Ok(cluster_id.clone());
+                return Ok(MatchedRule {
+                    position: RulePosition::Pre,
+                    domain: MatchedDomain::Rule(domain_rule),
+                    path: path_rule,
+                    method: method_rule,
+                    route: cluster_id,
+                    options,
+                });
             }
         }
 
-        if let Some((_, path_rules)) = self.tree.lookup(hostname_b, true) {
+        if let Some((key, path_rules)) = self.tree.lookup(hostname_b, true) {
             let mut prefix_length = 0;
-            let mut route = None;
+            let mut matched = None;
 
-            for (rule, method_rule, cluster_id) in path_rules {
+            for path_rule @ (rule, method_rule, _, _) in path_rules {
                 match rule.matches(path_b) {
                     PathRuleResult::Regex | PathRuleResult::Equals => {
                         match method_rule.matches(method) {
-                            MethodRuleResult::Equals => return Ok(cluster_id.clone()),
+                            MethodRuleResult::Equals => {
+                                matched = Some(path_rule);
+                                break;
+                            }
                             MethodRuleResult::All => {
                                 prefix_length = path_b.len();
-                                route = Some(cluster_id);
+                                matched = Some(path_rule);
                             }
                             MethodRuleResult::None => {}
                         }
@@ -92,11 +286,11 @@ impl Router {
                                 // FIXME: the rule order will be important here
                                 MethodRuleResult::Equals => {
                                     prefix_length = size;
-                                    route = Some(cluster_id);
+                                    matched = Some(path_rule);
                                 }
                                 MethodRuleResult::All => {
                                     prefix_length = size;
-                                    route = Some(cluster_id);
+                                    matched = Some(path_rule);
                                 }
                                 MethodRuleResult::None => {}
                             }
@@ -106,17 +300,31 @@ impl Router {
                 }
             }
 
-            if let Some(cluster_id) = route {
-                return Ok(cluster_id.clone());
+            if let Some((path_rule, method_rule, cluster_id, options)) = matched {
+                return Ok(MatchedRule {
+                    position: RulePosition::Tree,
+                    domain: MatchedDomain::Tree(key),
+                    path: path_rule,
+                    method: method_rule,
+                    route: cluster_id,
+                    options,
+                });
             }
         }
 
-        for (domain_rule, path_rule, method_rule, cluster_id) in self.post.iter() {
+        for (domain_rule, path_rule, method_rule, cluster_id, options) in self.post.iter() {
             if domain_rule.matches(hostname_b)
                 && path_rule.matches(path_b) != PathRuleResult::None
                 && method_rule.matches(method) != MethodRuleResult::None
             {
-                return Ok(cluster_id.clone());
+                return Ok(MatchedRule {
+                    position: RulePosition::Post,
+                    domain: MatchedDomain::Rule(domain_rule),
+                    path: path_rule,
+                    method: method_rule,
+                    route: cluster_id,
+                    options,
+                });
             }
         }
 
@@ -137,6 +345,7 @@ impl Router {
             Some(cluster_id) => Route::ClusterId(cluster_id.clone()),
             None => Route::Deny,
         };
+        let options = RouteOptions::from_frontend(front)?;
 
         let success = match front.position {
             RulePosition::Pre => {
@@ -146,7 +355,7 @@ impl Router {
                     }
                 })?;
 
-                self.add_pre_rule(&domain, &path_rule, &method_rule, &route)
+                self.add_pre_rule(&domain, &path_rule, &method_rule, &route, &options)
             }
             RulePosition::Post => {
                 let domain = front.hostname.parse::<DomainRule>().map_err(|_| {
@@ -155,15 +364,20 @@ impl Router {
                     }
                 })?;
 
-                self.add_post_rule(&domain, &path_rule, &method_rule, &route)
-            }
-            RulePosition::Tree => {
-                self.add_tree_rule(front.hostname.as_bytes(), &path_rule, &method_rule, &route)
+                self.add_post_rule(&domain, &path_rule, &method_rule, &route, &options)
             }
+            RulePosition::Tree => self.add_tree_rule(
+                front.hostname.as_bytes(),
+                &path_rule,
+                &method_rule,
+                &route,
+                &options,
+            ),
         };
         if !success {
             return Err(RouterError::AddRoute(format!("{:?}", front)));
         }
+        self.frontends.insert(frontend_key(front), front.clone());
         Ok(())
     }
 
@@ -199,6 +413,7 @@ impl Router {
         if !remove_success {
             return Err(RouterError::RemoveRoute(format!("{:?}", front)));
         }
+        self.frontends.remove(&frontend_key(front));
         Ok(())
     }
 
@@ -208,6 +423,7 @@ impl Router {
         path: &PathRule,
         method: &MethodRule,
         cluster: &Route,
+        options: &RouteOptions,
     ) -> bool {
         let hostname = match from_utf8(hostname) {
             Err(_) => return false,
@@ -222,8 +438,13 @@ impl Router {
                     self.tree.domain_lookup_mut(hostname.as_bytes(), false)
                 {
                     empty = false;
-                    if !paths.iter().any(|(p, m, _)| p == path && m == method) {
-                        paths.push((path.to_owned(), method.to_owned(), cluster.to_owned()));
+                    if !paths.iter().any(|(p, m, _, _)| p == path && m == method) {
+                        paths.push((
+                            path.to_owned(),
+                            method.to_owned(),
+                            cluster.to_owned(),
+                            options.to_owned(),
+                        ));
                         return true;
                     }
                 }
@@ -231,7 +452,12 @@ impl Router {
                 if empty {
                     self.tree.domain_insert(
                         hostname.into_bytes(),
-                        vec![(path.to_owned(), method.to_owned(), cluster.to_owned())],
+                        vec![(
+                            path.to_owned(),
+                            method.to_owned(),
+                            cluster.to_owned(),
+                            options.to_owned(),
+                        )],
                     );
                     return true;
                 }
@@ -260,7 +486,7 @@ impl Router {
                     let paths_opt = self.tree.domain_lookup_mut(hostname.as_bytes(), false);
 
                     if let Some((_, paths)) = paths_opt {
-                        paths.retain(|(p, m, _)| p != path || m != method);
+                        paths.retain(|(p, m, _, _)| p != path || m != method);
                     }
 
                     paths_opt
@@ -285,17 +511,19 @@ impl Router {
         path: &PathRule,
         method: &MethodRule,
         cluster_id: &Route,
+        options: &RouteOptions,
     ) -> bool {
         if !self
             .pre
             .iter()
-            .any(|(d, p, m, _)| d == domain && p == path && m == method)
+            .any(|(d, p, m, _, _)| d == domain && p == path && m == method)
         {
             self.pre.push((
                 domain.to_owned(),
                 path.to_owned(),
                 method.to_owned(),
                 cluster_id.to_owned(),
+                options.to_owned(),
             ));
             true
         } else {
@@ -309,17 +537,19 @@ impl Router {
         path: &PathRule,
         method: &MethodRule,
         cluster_id: &Route,
+        options: &RouteOptions,
     ) -> bool {
         if !self
             .post
             .iter()
-            .any(|(d, p, m, _)| d == domain && p == path && m == method)
+            .any(|(d, p, m, _, _)| d == domain && p == path && m == method)
         {
             self.post.push((
                 domain.to_owned(),
                 path.to_owned(),
                 method.to_owned(),
                 cluster_id.to_owned(),
+                options.to_owned(),
             ));
             true
         } else {
@@ -336,7 +566,7 @@ impl Router {
         match self
             .pre
             .iter()
-            .position(|(d, p, m, _)| d == domain && p == path && m == method)
+            .position(|(d, p, m, _, _)| d == domain && p == path && m == method)
         {
             None => false,
             Some(index) => {
@@ -355,7 +585,7 @@ impl Router {
         match self
             .post
             .iter()
-            .position(|(d, p, m, _)| d == domain && p == path && m == method)
+            .position(|(d, p, m, _, _)| d == domain && p == path && m == method)
         {
             None => false,
             Some(index) => {
@@ -554,6 +784,7 @@ impl std::cmp::PartialEq for PathRule {
         match (self, other) {
             (PathRule::Prefix(s1), PathRule::Prefix(s2)) => s1 == s2,
             (PathRule::Regex(r1), PathRule::Regex(r2)) => r1.as_str() == r2.as_str(),
+            (PathRule::Equals(s1), PathRule::Equals(s2)) => s1 == s2,
             _ => false,
         }
     }
@@ -592,6 +823,45 @@ impl MethodRule {
     }
 }
 
+/// The domain part of a matched rule
+enum MatchedDomain<'a> {
+    /// domain rule of a pre or post rule
+    Rule(&'a DomainRule),
+    /// hostname under which a tree rule is stored
+    Tree(&'a [u8]),
+}
+
+/// A rule of the router, as matched by a request
+struct MatchedRule<'a> {
+    position: RulePosition,
+    domain: MatchedDomain<'a>,
+    path: &'a PathRule,
+    method: &'a MethodRule,
+    route: &'a Route,
+    options: &'a RouteOptions,
+}
+
+impl MatchedRule<'_> {
+    fn was_added_by(&self, front: &HttpFrontend) -> bool {
+        if front.position != self.position
+            || MethodRule::new(front.method.clone()) != *self.method
+            || PathRule::from_config(front.path.clone()).as_ref() != Some(self.path)
+        {
+            return false;
+        }
+        match self.domain {
+            MatchedDomain::Rule(domain_rule) => match front.hostname.parse::<DomainRule>() {
+                Ok(front_rule) => front_rule == *domain_rule,
+                Err(_) => false,
+            },
+            MatchedDomain::Tree(key) => match ::idna::domain_to_ascii(&front.hostname) {
+                Ok(hostname) => hostname.as_bytes() == key,
+                Err(_) => false,
+            },
+        }
+    }
+}
+
 /// The cluster to which the traffic will be redirected
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Route {
@@ -717,7 +987,8 @@ mod tests {
             b"*.sozu.io",
             &PathRule::Prefix("".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("base".to_string())
+            &Route::ClusterId("base".to_string()),
+            &RouteOptions::default()
         ));
         println!("{:#?}", router.tree);
         assert_eq!(
@@ -728,7 +999,8 @@ mod tests {
             b"*.sozu.io",
             &PathRule::Prefix("/api".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("api".to_string())
+            &Route::ClusterId("api".to_string()),
+            &RouteOptions::default()
         ));
         println!("{:#?}", router.tree);
         assert_eq!(
@@ -756,7 +1028,8 @@ mod tests {
             b"*.sozu.io",
             &PathRule::Prefix("".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("base".to_string())
+            &Route::ClusterId("base".to_string()),
+            &RouteOptions::default()
         ));
         println!("{:#?}", router.tree);
         assert_eq!(
@@ -767,7 +1040,8 @@ mod tests {
             b"api.sozu.io",
             &PathRule::Prefix("".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("api".to_string())
+            &Route::ClusterId("api".to_string()),
+            &RouteOptions::default()
         ));
         println!("{:#?}", router.tree);
         assert_eq!(
@@ -788,14 +1062,16 @@ mod tests {
             b"www./.*/.io",
             &PathRule::Prefix("".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("base".to_string())
+            &Route::ClusterId("base".to_string()),
+            &RouteOptions::default()
         ));
         println!("{:#?}", router.tree);
         assert!(router.add_tree_rule(
             b"www.doc./.*/.io",
             &PathRule::Prefix("".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("doc".to_string())
+            &Route::ClusterId("doc".to_string()),
+            &RouteOptions::default()
         ));
         println!("{:#?}", router.tree);
         assert_eq!(
@@ -827,25 +1103,29 @@ mod tests {
             &"*".parse::<DomainRule>().unwrap(),
             &PathRule::Prefix("/.well-known/acme-challenge".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("acme".to_string())
+            &Route::ClusterId("acme".to_string()),
+            &RouteOptions::default()
         ));
         assert!(router.add_tree_rule(
             "www.example.com".as_bytes(),
             &PathRule::Prefix("/".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("example".to_string())
+            &Route::ClusterId("example".to_string()),
+            &RouteOptions::default()
         ));
         assert!(router.add_tree_rule(
             "*.test.example.com".as_bytes(),
             &PathRule::Regex(Regex::new("/hello[A-Z]+/").unwrap()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("examplewildcard".to_string())
+            &Route::ClusterId("examplewildcard".to_string()),
+            &RouteOptions::default()
         ));
         assert!(router.add_tree_rule(
             "/test[0-9]/.example.com".as_bytes(),
             &PathRule::Prefix("/".to_string()),
             &MethodRule::new(Some("GET".to_string())),
-            &Route::ClusterId("exampleregex".to_string())
+            &Route::ClusterId("exampleregex".to_string()),
+            &RouteOptions::default()
         ));
 
         assert_eq!(
@@ -876,4 +1156,245 @@ mod tests {
             Ok(Route::ClusterId("exampleregex".to_string()))
         );
     }
+
+    #[test]
+    fn remove_equals_rule() {
+        let mut router = Router::new();
+        let path = PathRule::Equals("/health".to_string());
+        let method = MethodRule::new(None);
+
+        assert!(router.add_tree_rule(
+            b"example.com",
+            &path,
+            &method,
+            &Route::ClusterId("health".to_string()),
+            &RouteOptions::default()
+        ));
+        // the same rule is not added twice
+        assert!(!router.add_tree_rule(
+            b"example.com",
+            &path,
+            &method,
+            &Route::ClusterId("health".to_string()),
+            &RouteOptions::default()
+        ));
+        assert_eq!(
+            router.lookup("example.com", "/health", &Method::Get),
+            Ok(Route::ClusterId("health".to_string()))
+        );
+
+        assert!(router.remove_tree_rule(b"example.com", &path, &method));
+        assert!(router
+            .lookup("example.com", "/health", &Method::Get)
+            .is_err());
+    }
+
+    #[test]
+    fn explain_overlapping_rules() {
+        let front = |hostname: &str,
+                     path: CommandPathRule,
+                     method: Option<&str>,
+                     position,
+                     cluster_id: &str| {
+            HttpFrontend {
+                cluster_id: Some(cluster_id.to_owned()),
+                address: "0.0.0.0:80".parse().unwrap(),
+                hostname: hostname.to_owned(),
+                path,
+                method: method.map(str::to_owned),
+                position,
+                tags: None,
+                allowed_methods: Vec::new(),
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
+            }
+        };
+        let acme = front(
+            "*",
+            CommandPathRule::prefix("/.well-known/acme-challenge"),
+            Some("GET"),
+            RulePosition::Pre,
+            "acme",
+        );
+        let root = front(
+            "example.com",
+            CommandPathRule::prefix("/"),
+            None,
+            RulePosition::Tree,
+            "root",
+        );
+        let api = front(
+            "example.com",
+            CommandPathRule::prefix("/api"),
+            None,
+            RulePosition::Tree,
+            "api",
+        );
+        let api_post = front(
+            "example.com",
+            CommandPathRule::prefix("/api/upload"),
+            Some("POST"),
+            RulePosition::Tree,
+            "upload",
+        );
+        let wildcard = front(
+            "*.example.com",
+            CommandPathRule::prefix("/"),
+            None,
+            RulePosition::Tree,
+            "wildcard",
+        );
+
+        let mut router = Router::new();
+        for front in [&acme, &root, &api, &api_post, &wildcard] {
+            router
+                .add_http_front(front)
+                .expect("could not add frontend");
+        }
+
+        let explain = |host: &str, path: &str, method: &Method| {
+            router
+                .explain(host, path, method)
+                .map(|(route, front)| (route, front.cloned()))
+        };
+
+        assert_eq!(
+            explain(
+                "example.com",
+                "/.well-known/acme-challenge/token",
+                &Method::Get
+            ),
+            Ok((Route::ClusterId("acme".to_owned()), Some(acme)))
+        );
+        assert_eq!(
+            explain("example.com", "/index.html", &Method::Get),
+            Ok((Route::ClusterId("root".to_owned()), Some(root)))
+        );
+        assert_eq!(
+            explain("example.com", "/api/upload", &Method::Get),
+            Ok((Route::ClusterId("api".to_owned()), Some(api.clone())))
+        );
+        assert_eq!(
+            explain("example.com", "/api/upload", &Method::Post),
+            Ok((Route::ClusterId("upload".to_owned()), Some(api_post)))
+        );
+        assert_eq!(
+            explain("www.example.com", "/api", &Method::Get),
+            Ok((Route::ClusterId("wildcard".to_owned()), Some(wildcard)))
+        );
+        assert!(explain("example.org", "/", &Method::Get).is_err());
+    }
+
+    #[test]
+    fn allowed_methods() {
+        let mut router = Router::new();
+        router
+            .add_http_front(&HttpFrontend {
+                cluster_id: Some("api".to_owned()),
+                address: "0.0.0.0:80".parse().unwrap(),
+                hostname: "example.com".to_owned(),
+                path: CommandPathRule::prefix("/api"),
+                method: None,
+                position: RulePosition::Tree,
+                tags: None,
+                allowed_methods: vec!["GET".to_owned(), "POST".to_owned()],
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
+            })
+            .expect("could not add frontend");
+        router
+            .add_http_front(&HttpFrontend {
+                cluster_id: Some("root".to_owned()),
+                address: "0.0.0.0:80".parse().unwrap(),
+                hostname: "example.com".to_owned(),
+                path: CommandPathRule::prefix("/"),
+                method: None,
+                position: RulePosition::Tree,
+                tags: None,
+                allowed_methods: Vec::new(),
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
+            })
+            .expect("could not add frontend");
+
+        assert_eq!(
+            router.lookup("example.com", "/api/users", &Method::Post),
+            Ok(Route::ClusterId("api".to_owned()))
+        );
+        assert_eq!(
+            router.lookup("example.com", "/api/users", &Method::Delete),
+            Err(RouterError::MethodNotAllowed {
+                host: "example.com".to_owned(),
+                path: "/api/users".to_owned(),
+                method: Method::Delete,
+                allowed: vec!["GET".to_owned(), "POST".to_owned()],
+            })
+        );
+        assert!(matches!(
+            router.lookup(
+                "example.com",
+                "/api/users",
+                &Method::Custom("PURGE".to_owned())
+            ),
+            Err(RouterError::MethodNotAllowed { .. })
+        ));
+        assert_eq!(
+            router.lookup("example.com", "/index.html", &Method::Delete),
+            Ok(Route::ClusterId("root".to_owned()))
+        );
+    }
+
+    #[test]
+    fn allowed_methods_must_be_tokens() {
+        let mut router = Router::new();
+        for allowed in ["GET\r\nSet-Cookie: injected=1", "GET POST", ""] {
+            let front = HttpFrontend {
+                cluster_id: Some("api".to_owned()),
+                address: "0.0.0.0:80".parse().unwrap(),
+                hostname: "example.com".to_owned(),
+                path: CommandPathRule::prefix("/api"),
+                method: None,
+                position: RulePosition::Tree,
+                tags: None,
+                allowed_methods: vec!["HEAD".to_owned(), allowed.to_owned()],
+                head_as_get: false,
+                websocket_subprotocols: Vec::new(),
+            };
+            assert_eq!(
+                router.add_http_front(&front),
+                Err(RouterError::InvalidAllowedMethod(allowed.to_owned()))
+            );
+        }
+        assert!(router.frontends().next().is_none());
+        assert!(router.lookup("example.com", "/api", &Method::Head).is_err());
+    }
+
+    #[test]
+    fn route_options_follow_the_matched_rule() {
+        let mut router = Router::new();
+        for (path, head_as_get) in [("/", false), ("/static", true)] {
+            router
+                .add_http_front(&HttpFrontend {
+                    cluster_id: Some("static".to_owned()),
+                    address: "0.0.0.0:80".parse().unwrap(),
+                    hostname: "example.com".to_owned(),
+                    path: CommandPathRule::prefix(path),
+                    method: None,
+                    position: RulePosition::Tree,
+                    tags: None,
+                    allowed_methods: Vec::new(),
+                    head_as_get,
+                    websocket_subprotocols: Vec::new(),
+                })
+                .expect("could not add the frontend");
+        }
+
+        let head_as_get = |path: &str| {
+            router
+                .lookup_with_options("example.com", path, &Method::Head)
+                .map(|(_, options)| options.head_as_get)
+        };
+        assert_eq!(head_as_get("/static/logo.png"), Ok(true));
+        assert_eq!(head_as_get("/index.html"), Ok(false));
+    }
 }
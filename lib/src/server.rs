@@ -1,7 +1,7 @@
 //! event loop management
 use std::{
-    cell::RefCell,
-    collections::{HashSet, VecDeque},
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
     convert::TryFrom,
     os::unix::io::{AsRawFd, FromRawFd},
     rc::Rc,
@@ -17,13 +17,17 @@ use time::{Duration, Instant};
 
 use sozu_command::{
     channel::Channel,
-    config::Config,
-    proto::command::{
-        request::RequestType, response_content::ContentType, ActivateListener, AddBackend,
-        CertificatesWithFingerprints, Cluster, ClusterHashes, ClusterInformations,
-        DeactivateListener, Event, HttpListenerConfig, HttpsListenerConfig, ListenerType,
-        LoadBalancingAlgorithms, LoadMetric, MetricsConfiguration, RemoveBackend, ResponseStatus,
-        TcpListenerConfig as CommandTcpListener,
+    config::{Config, DEFAULT_MAX_RETRY_AFTER, MAX_LOOP_ITERATIONS},
+    proto::{
+        command::{
+            request::RequestType, response_content::ContentType, ActivateListener, AddBackend,
+            CertificatesWithFingerprints, Cluster, ClusterHashes, ClusterInformations, ConfigDiff,
+            DeactivateListener, Event, HttpListenerConfig, HttpsListenerConfig, ListenerType,
+            LoadBalancingAlgorithms, LoadMetric, MetricsConfiguration, RemoveBackend, Request,
+            ResponseStatus, RouteMatches, SessionInfo, SessionInfos,
+            TcpListenerConfig as CommandTcpListener,
+        },
+        display::format_request_type,
     },
     ready::Ready,
     request::WorkerRequest,
@@ -34,6 +38,7 @@ use sozu_command::{
 
 use crate::{
     backends::{Backend, BackendMap},
+    dns::{system_resolve, DnsResolver},
     features::FEATURES,
     http, https,
     metrics::METRICS,
@@ -46,6 +51,16 @@ use crate::{
 // Number of retries to perform on a server after a connection failure
 pub const CONN_RETRIES: u8 = 3;
 
+/// the connection attempts to the backends of a cluster before giving up:
+/// its `connection_retries` if set, `CONN_RETRIES` otherwise. The state refuses
+/// the clusters whose `connection_retries` is 0 or does not fit in a u8
+pub fn max_connection_attempts(connection_retries: Option<u32>) -> u8 {
+    match connection_retries {
+        Some(retries) if retries > 0 => u8::try_from(retries).unwrap_or(u8::MAX),
+        _ => CONN_RETRIES,
+    }
+}
+
 pub type ProxyChannel = Channel<WorkerResponse, WorkerRequest>;
 
 thread_local! {
@@ -73,6 +88,83 @@ pub fn push_event(event: Event) {
     });
 }
 
+thread_local! {
+  /// sessions that stopped reading before their socket was drained, they run
+  /// again once the other sessions had their turn in the event loop
+  static YIELDED_SESSIONS: RefCell<HashSet<Token>> = RefCell::new(HashSet::new());
+}
+
+/// Schedule a session to run again at the end of the event loop iteration,
+/// even if it does not get new events
+pub fn yield_session(token: Token) {
+    YIELDED_SESSIONS.with(|yielded| {
+        yielded.borrow_mut().insert(token);
+    });
+}
+
+thread_local! {
+  /// iterations of the loop handling the events of a session before it is closed
+  static LOOP_ITERATIONS_LIMIT: Cell<usize> = Cell::new(MAX_LOOP_ITERATIONS);
+  /// longest delay a backend can ask with a Retry-After header, in seconds
+  static RETRY_AFTER_LIMIT: Cell<u64> = Cell::new(DEFAULT_MAX_RETRY_AFTER);
+}
+
+/// Iterations of the loop handling the events of a session, past which the session
+/// is closed as stuck in an infinite loop (see the `max_loop_iterations` option)
+pub fn max_loop_iterations() -> usize {
+    LOOP_ITERATIONS_LIMIT.with(Cell::get)
+}
+
+/// Longest delay a backend answering 503 can ask with a Retry-After header,
+/// longer ones are capped to it (see the `max_retry_after` option)
+pub fn max_retry_after() -> std::time::Duration {
+    std::time::Duration::from_secs(RETRY_AFTER_LIMIT.with(Cell::get))
+}
+
+/// Maximum number of request error events a worker sends each second
+pub const MAX_REQUEST_ERROR_EVENTS_PER_SECOND: u32 = 100;
+
+/// Slab entries that are not sessions: listeners, the command channel, the metrics…
+const SLAB_RESERVED_ENTRIES: usize = 10;
+
+/// Slab entries taken by a session: its frontend and its backend tokens
+const SLAB_ENTRIES_PER_SESSION: usize = 2;
+
+/// Number of slab entries needed to hold `max_connections` sessions
+fn slab_capacity(max_connections: usize) -> usize {
+    SLAB_RESERVED_ENTRIES + SLAB_ENTRIES_PER_SESSION * max_connections
+}
+
+thread_local! {
+  /// start of the current one second window, and number of request error events sent in it
+  static REQUEST_ERROR_EVENTS: RefCell<(Instant, u32)> = RefCell::new((Instant::now(), 0));
+}
+
+/// Push an event about a failed request. Under an error flood, the events
+/// over MAX_REQUEST_ERROR_EVENTS_PER_SECOND are dropped instead of
+/// overwhelming the main process and the subscribers.
+pub fn push_request_error_event(event: Event) {
+    let under_limit = REQUEST_ERROR_EVENTS.with(|window| {
+        let mut window = window.borrow_mut();
+        let now = Instant::now();
+        if now - window.0 >= Duration::seconds(1) {
+            *window = (now, 0);
+        }
+        if window.1 < MAX_REQUEST_ERROR_EVENTS_PER_SECOND {
+            window.1 += 1;
+            true
+        } else {
+            false
+        }
+    });
+
+    if under_limit {
+        push_event(event);
+    } else {
+        incr!("events.request_errors.dropped");
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ListenToken(pub usize);
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -109,6 +201,9 @@ pub struct ServerConfig {
     pub connect_timeout: u32,
     pub zombie_check_interval: u32,
     pub accept_queue_timeout: u32,
+    pub dns_refresh_interval: u32,
+    pub max_loop_iterations: usize,
+    pub max_retry_after: u64,
 }
 
 impl ServerConfig {
@@ -120,11 +215,14 @@ impl ServerConfig {
             connect_timeout: config.connect_timeout,
             zombie_check_interval: config.zombie_check_interval,
             accept_queue_timeout: config.accept_queue_timeout,
+            dns_refresh_interval: config.dns_refresh_interval,
+            max_loop_iterations: config.max_loop_iterations,
+            max_retry_after: config.max_retry_after,
         }
     }
 
     fn slab_capacity(&self) -> usize {
-        10 + 2 * self.max_connections
+        slab_capacity(self.max_connections)
     }
 }
 
@@ -137,6 +235,9 @@ impl Default for ServerConfig {
             connect_timeout: 3,
             zombie_check_interval: 30 * 60,
             accept_queue_timeout: 60,
+            dns_refresh_interval: 60,
+            max_loop_iterations: MAX_LOOP_ITERATIONS,
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
         }
     }
 }
@@ -146,6 +247,10 @@ pub struct SessionManager {
     pub nb_connections: usize,
     pub can_accept: bool,
     pub slab: Slab<Rc<RefCell<dyn ProxySession>>>,
+    /// number of sessions accepted by each listener, for their max_sessions
+    pub listener_sessions: HashMap<ListenToken, usize>,
+    /// the listener that accepted each session, by frontend token
+    session_listeners: HashMap<Token, ListenToken>,
 }
 
 impl SessionManager {
@@ -158,12 +263,14 @@ impl SessionManager {
             nb_connections: 0,
             can_accept: true,
             slab,
+            listener_sessions: HashMap::new(),
+            session_listeners: HashMap::new(),
         }))
     }
 
     /// The slab is considered at capacity if it contains more sessions than twice max_connections
     pub fn at_capacity(&self) -> bool {
-        self.slab.len() >= 10 + 2 * self.max_connections
+        self.slab.len() >= slab_capacity(self.max_connections)
     }
 
     /// Check the number of connections against max_connections, and the slab capacity.
@@ -191,23 +298,49 @@ impl SessionManager {
         true
     }
 
+    /// Check that one more session would fit in the limits, counting the sockets
+    /// already accepted and waiting in the accept queue
+    pub fn has_room_for(&self, queued_sockets: usize) -> bool {
+        self.nb_connections + queued_sockets < self.max_connections
+            && self.slab.len() + SLAB_ENTRIES_PER_SESSION * (queued_sockets + 1)
+                <= slab_capacity(self.max_connections)
+    }
+
     pub fn to_session(token: Token) -> SessionToken {
         SessionToken(token.0)
     }
 
-    pub fn incr(&mut self) {
+    /// Counts a new session, in total and for the listener that accepted it
+    pub fn incr(&mut self, listener_token: ListenToken, session_token: Token) {
         self.nb_connections += 1;
         assert!(self.nb_connections <= self.max_connections);
         gauge!("client.connections", self.nb_connections);
+
+        *self.listener_sessions.entry(listener_token).or_insert(0) += 1;
+        self.session_listeners.insert(session_token, listener_token);
+    }
+
+    /// number of sessions accepted by this listener that are still open
+    pub fn listener_session_count(&self, listener_token: ListenToken) -> usize {
+        self.listener_sessions
+            .get(&listener_token)
+            .copied()
+            .unwrap_or(0)
     }
 
     /// Decrements the number of sessions, start accepting new connections
     /// if the capacity limit of 90% has not been reached.
-    pub fn decr(&mut self) {
+    pub fn decr(&mut self, session_token: Token) {
         assert!(self.nb_connections != 0);
         self.nb_connections -= 1;
         gauge!("client.connections", self.nb_connections);
 
+        if let Some(listener_token) = self.session_listeners.remove(&session_token) {
+            if let Some(count) = self.listener_sessions.get_mut(&listener_token) {
+                *count = count.saturating_sub(1);
+            }
+        }
+
         // do not be ready to accept right away, wait until we get back to 10% capacity
         if !self.can_accept && self.nb_connections < self.max_connections * 90 / 100 {
             debug!(
@@ -245,11 +378,23 @@ pub struct Server {
     channel: ProxyChannel,
     config_state: ConfigState,
     current_poll_errors: i32,
+    /// resolves the hostnames of the backends
+    dns_resolver: DnsResolver,
+    dns_refresh_interval: Duration,
+    /// listeners that stopped accepting at their max_sessions
+    full_listeners: HashSet<ListenToken>,
     http: Rc<RefCell<http::HttpProxy>>,
     https: Rc<RefCell<https::HttpsProxy>>,
+    /// the new connections are closed once accepted, the sessions close after
+    /// their current request
+    lame_duck: bool,
+    /// None if the backend hostnames must be resolved at the next turn of the loop
+    last_dns_refresh: Option<Instant>,
     last_sessions_len: usize,
     last_shutting_down_message: Option<Instant>,
     last_zombie_check: Instant,
+    /// the listen sockets are deregistered while the sessions are at capacity
+    listeners_paused: bool,
     loop_start: Instant,
     max_poll_errors: i32, // TODO: make this configurable? this defaults to 10000 for now
     pub poll: Poll,
@@ -407,6 +552,9 @@ impl Server {
             }
         }));
 
+        LOOP_ITERATIONS_LIMIT.with(|limit| limit.set(server_config.max_loop_iterations));
+        RETRY_AFTER_LIMIT.with(|limit| limit.set(server_config.max_retry_after));
+
         let mut server = Server {
             accept_queue_timeout: Duration::seconds(i64::from(server_config.accept_queue_timeout)),
             accept_queue: VecDeque::new(),
@@ -416,13 +564,19 @@ impl Server {
             channel,
             config_state: ConfigState::new(),
             current_poll_errors: 0,
+            dns_resolver: DnsResolver::new(system_resolve),
+            dns_refresh_interval: Duration::seconds(i64::from(server_config.dns_refresh_interval)),
+            full_listeners: HashSet::new(),
             http,
             https,
+            last_dns_refresh: None,
             last_sessions_len: 0, // to be reset on server run
+            lame_duck: false,
             last_shutting_down_message: None,
             last_zombie_check: Instant::now(), // to be reset on server run
-            loop_start: Instant::now(),        // to be reset on server run
-            max_poll_errors: 10000,            // TODO: make it configurable?
+            listeners_paused: false,
+            loop_start: Instant::now(), // to be reset on server run
+            max_poll_errors: 10000,     // TODO: make it configurable?
             poll_timeout: Some(Duration::milliseconds(1000)), // TODO: make it configurable?
             poll,
             pool,
@@ -583,12 +737,15 @@ impl Server {
                 }
             }
             self.handle_remaining_readiness();
+            self.handle_yielded_sessions();
             self.create_sessions();
 
             self.should_poll_at = TIMER.with(|timer| timer.borrow().next_poll_date());
 
             self.zombie_check();
 
+            self.refresh_backend_addresses();
+
             let now = time::OffsetDateTime::now_utc();
             // clear the local metrics drain every plain hour (01:00, 02:00, etc.) to prevent memory overuse
             // TODO: have one-hour-lasting metrics instead
@@ -652,6 +809,11 @@ impl Server {
         };
 
         self.loop_start = now;
+
+        // yielded sessions still have data to read, do not wait for events
+        if YIELDED_SESSIONS.with(|yielded| !yielded.borrow().is_empty()) {
+            return Some(std::time::Duration::ZERO);
+        }
         timeout.and_then(|t| std::time::Duration::try_from(t).ok())
     }
 
@@ -706,6 +868,82 @@ impl Server {
         false
     }
 
+    /// Snapshot of the client sessions, to find the ones that are stuck.
+    /// A session owns several tokens of the slab but is listed once.
+    fn session_infos(&self) -> SessionInfos {
+        let now = Instant::now();
+        let mut tokens = HashSet::new();
+        let mut vec = Vec::new();
+
+        for (_index, session) in self.sessions.borrow().slab.iter() {
+            let session = session.borrow();
+            let protocol = session.protocol();
+            if !matches!(protocol, Protocol::HTTP | Protocol::HTTPS | Protocol::TCP) {
+                continue;
+            }
+            let token = session.frontend_token();
+            if !tokens.insert(token) {
+                continue;
+            }
+
+            let mut info = SessionInfo {
+                token: token.0 as u64,
+                protocol: format!("{protocol:?}"),
+                last_event_age: (now - session.last_event()).whole_milliseconds() as u64,
+                ..Default::default()
+            };
+            session.fill_session_info(&mut info);
+            vec.push(info);
+        }
+
+        vec.sort_by_key(|info| info.token);
+        SessionInfos { vec }
+    }
+
+    /// Applies the finished resolutions of the backend hostnames, and starts
+    /// new ones every `dns_refresh_interval`. The resolutions run in a thread,
+    /// a slow DNS server does not block the event loop
+    fn refresh_backend_addresses(&mut self) {
+        for resolution in self.dns_resolver.completed() {
+            match resolution.addresses {
+                Ok(addresses) if !addresses.is_empty() => {
+                    self.backends.borrow_mut().set_resolved_addresses(
+                        &resolution.hostname,
+                        resolution.port,
+                        &addresses,
+                    );
+                }
+                // the backends keep their last addresses
+                Ok(_) => {
+                    error!("backend hostname {} has no address", resolution.hostname);
+                    incr!("dns.resolution.error");
+                }
+                Err(e) => {
+                    error!(
+                        "could not resolve backend hostname {}: {}",
+                        resolution.hostname, e
+                    );
+                    incr!("dns.resolution.error");
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(last_dns_refresh) = self.last_dns_refresh {
+            if now - last_dns_refresh < self.dns_refresh_interval {
+                return;
+            }
+        }
+        if self.dns_resolver.is_busy() {
+            return;
+        }
+
+        let hostnames = self.backends.borrow().hostnames();
+        if hostnames.is_empty() || self.dns_resolver.resolve(hostnames) {
+            self.last_dns_refresh = Some(now);
+        }
+    }
+
     /// Scans all sessions that have been inactive for longer than the configured interval
     fn zombie_check(&mut self) {
         let now = Instant::now();
@@ -754,7 +992,7 @@ impl Server {
             if self.sessions.borrow().slab.contains(token.0) {
                 let session = { self.sessions.borrow_mut().slab.remove(token.0) };
                 session.borrow_mut().close();
-                self.sessions.borrow_mut().decr();
+                self.sessions.borrow_mut().decr(*token);
             }
         }
 
@@ -778,6 +1016,56 @@ impl Server {
         dangling_entries_count
     }
 
+    /// Order the sessions of a deactivated listener to shut down, like on a soft stop:
+    /// the idle ones are closed now, the others answer their current request with
+    /// "Connection: close" and close once it is sent
+    fn drain_listener_sessions(&self, listener_token: Token) {
+        let mut sessions_to_shut_down = HashSet::new();
+        for (_key, session) in &self.sessions.borrow().slab {
+            let mut session = session.borrow_mut();
+            if session.listener_token() == Some(listener_token) && session.shutting_down() {
+                sessions_to_shut_down.insert(session.frontend_token());
+            }
+        }
+
+        info!(
+            "draining the sessions of listener {:?}, closing {} idle sessions",
+            listener_token,
+            sessions_to_shut_down.len()
+        );
+        let _ = self.shut_down_sessions_by_frontend_tokens(sessions_to_shut_down);
+    }
+
+    /// In lame duck mode, for rolling restarts, the new connections are closed
+    /// as soon as they are accepted, so that the clients try elsewhere instead
+    /// of waiting in the listen queues, and the sessions close once their current
+    /// request is answered, the idle ones right away. Unlike a soft stop, the
+    /// worker keeps running and the mode can be turned off
+    fn set_lame_duck(&mut self, enabled: bool) {
+        if self.lame_duck == enabled {
+            return;
+        }
+        self.lame_duck = enabled;
+
+        if enabled {
+            let mut sessions_to_shut_down = HashSet::new();
+            for (_key, session) in &self.sessions.borrow().slab {
+                let mut session = session.borrow_mut();
+                // a TCP session has no request to finish, it is left running
+                if session.protocol() != Protocol::TCP && session.shutting_down() {
+                    sessions_to_shut_down.insert(session.frontend_token());
+                }
+            }
+            info!(
+                "entering lame duck mode, closing {} idle sessions",
+                sessions_to_shut_down.len()
+            );
+            let _ = self.shut_down_sessions_by_frontend_tokens(sessions_to_shut_down);
+        } else {
+            info!("leaving lame duck mode");
+        }
+    }
+
     /// Order sessions to shut down, check that they are all down
     fn shut_down_sessions(&mut self) -> bool {
         let sessions_count = self.sessions.borrow().slab.len();
@@ -894,6 +1182,26 @@ impl Server {
                 ));
                 return;
             }
+            Some(RequestType::QuerySessions(_)) => {
+                push_queue(WorkerResponse::ok_with_content(
+                    message.id.clone(),
+                    ContentType::Sessions(self.session_infos()).into(),
+                ));
+                return;
+            }
+            Some(RequestType::ApplyConfig(diff)) => {
+                push_queue(self.apply_config(&message.id, diff));
+                return;
+            }
+            Some(RequestType::LameDuck(lame_duck)) => {
+                self.set_lame_duck(lame_duck.enabled);
+                push_queue(WorkerResponse::ok(message.id.clone()));
+                return;
+            }
+            Some(RequestType::QueryRouteMatch(_)) => {
+                push_queue(self.query_route_match(message));
+                return;
+            }
             Some(RequestType::QueryClusterById(cluster_id)) => {
                 push_queue(WorkerResponse::ok_with_content(
                     message.id.clone(),
@@ -906,6 +1214,7 @@ impl Server {
                     })
                     .into(),
                 ));
+                return;
             }
             Some(RequestType::QueryClustersByDomain(domain)) => {
                 let cluster_ids = self
@@ -959,6 +1268,17 @@ impl Server {
     }
 
     pub fn notify_proxys(&mut self, request: WorkerRequest) {
+        // the proxies deregister the listen sockets they stop or remove,
+        // which must be registered for that
+        if request.content.is_a_stop()
+            || matches!(
+                request.content.request_type,
+                Some(RequestType::RemoveListener(_)) | Some(RequestType::DeactivateListener(_))
+            )
+        {
+            self.resume_listeners();
+        }
+
         if let Err(e) = self.config_state.dispatch(&request.content) {
             error!("Could not execute order on config state: {}", e);
         }
@@ -1053,11 +1373,17 @@ impl Server {
             add_backend.sticky_id.clone(),
             add_backend.load_balancing_parameters.clone(),
             add_backend.backup,
-        );
+        )
+        .with_hostname(add_backend.hostname.clone());
         self.backends
             .borrow_mut()
             .add_backend(&add_backend.cluster_id, new_backend);
 
+        // resolve the new hostname without waiting for the next refresh
+        if add_backend.hostname.is_some() {
+            self.last_dns_refresh = None;
+        }
+
         WorkerResponse::ok(req_id)
     }
 
@@ -1070,6 +1396,108 @@ impl Server {
         WorkerResponse::ok(req_id)
     }
 
+    /// Both the HTTP and HTTPS proxies answer with the routes of their listeners,
+    /// they are merged in one response
+    fn query_route_match(&mut self, request: WorkerRequest) -> WorkerResponse {
+        let mut route_matches = RouteMatches::default();
+
+        for response in [
+            self.http.borrow_mut().notify(request.clone()),
+            self.https.borrow_mut().notify(request.clone()),
+        ] {
+            if response.is_failure() {
+                return response;
+            }
+            if let Some(ContentType::RouteMatches(RouteMatches { vec })) =
+                response.content.and_then(|content| content.content_type)
+            {
+                route_matches.vec.extend(vec);
+            }
+        }
+
+        WorkerResponse::ok_with_content(request.id, ContentType::RouteMatches(route_matches).into())
+    }
+
+    /// Applies the requests of the diff one after the other. If one of them fails,
+    /// the ones already applied are reverted, so that the proxies end up in the
+    /// configuration they had before the diff.
+    fn apply_config(&mut self, req_id: &str, diff: &ConfigDiff) -> WorkerResponse {
+        let initial_state = self.config_state.clone();
+
+        for (index, request) in diff.requests.iter().enumerate() {
+            if let Err(error) = self.apply_config_entry(req_id, request) {
+                for rollback_request in self.config_state.diff(&initial_state) {
+                    if let Err(rollback_error) = self.apply_config_entry(req_id, &rollback_request)
+                    {
+                        error!(
+                            "{} could not roll back the configuration diff: {}",
+                            req_id, rollback_error
+                        );
+                    }
+                }
+                self.config_state = initial_state;
+
+                return WorkerResponse::error(
+                    req_id,
+                    format!(
+                        "could not apply request {index} of the configuration diff, \
+                        rolled back the whole diff: {error}"
+                    ),
+                );
+            }
+        }
+
+        WorkerResponse::ok(req_id)
+    }
+
+    /// Applies one request of a configuration diff on the state and on the proxies
+    fn apply_config_entry(&mut self, req_id: &str, request: &Request) -> Result<(), String> {
+        if !request.is_a_config_diff_entry() {
+            return Err(format!(
+                "{} can not be part of a configuration diff",
+                request
+                    .request_type
+                    .as_ref()
+                    .map(format_request_type)
+                    .unwrap_or_default()
+            ));
+        }
+
+        self.config_state
+            .dispatch(request)
+            .map_err(|state_error| state_error.to_string())?;
+
+        let response = match &request.request_type {
+            Some(RequestType::AddBackend(add_backend)) => self.add_backend(req_id, add_backend),
+            Some(RequestType::RemoveBackend(remove_backend)) => {
+                self.remove_backend(req_id, remove_backend)
+            }
+            _ => {
+                let worker_request = WorkerRequest::new(req_id.to_owned(), request.clone());
+                let proxy_destinations = request.get_destinations();
+                let mut responses = Vec::new();
+                if proxy_destinations.to_http_proxy {
+                    responses.push(self.http.borrow_mut().notify(worker_request.clone()));
+                }
+                if proxy_destinations.to_https_proxy {
+                    responses.push(self.https.borrow_mut().notify(worker_request.clone()));
+                }
+                if proxy_destinations.to_tcp_proxy {
+                    responses.push(self.tcp.borrow_mut().notify(worker_request));
+                }
+                match responses.into_iter().find(|response| response.is_failure()) {
+                    Some(failure) => failure,
+                    None => WorkerResponse::ok(req_id),
+                }
+            }
+        };
+
+        if response.is_failure() {
+            return Err(response.message);
+        }
+        Ok(())
+    }
+
     fn notify_add_http_listener(
         &mut self,
         req_id: &str,
@@ -1295,6 +1723,9 @@ impl Server {
                         info!("removed listen token {:?}", token);
                     }
                 }
+                if deactivate.drain_sessions.unwrap_or(false) {
+                    self.drain_listener_sessions(token);
+                }
 
                 if deactivate.to_scm {
                     self.unblock_scm_socket();
@@ -1336,6 +1767,9 @@ impl Server {
                     self.sessions.borrow_mut().slab.remove(token.0);
                     info!("removed listen token {:?}", token);
                 }
+                if deactivate.drain_sessions.unwrap_or(false) {
+                    self.drain_listener_sessions(token);
+                }
 
                 if deactivate.to_scm {
                     self.unblock_scm_socket();
@@ -1471,8 +1905,17 @@ impl Server {
     }
 
     pub fn accept(&mut self, token: ListenToken, protocol: Protocol) {
+        let max_sessions = self.listener_max_sessions(token, protocol);
         match protocol {
             Protocol::TCPListen => loop {
+                if !self.has_room_to_accept() {
+                    self.pause_listeners();
+                    break;
+                }
+                if !self.listener_has_room(token, max_sessions) {
+                    self.hold_listener(token);
+                    break;
+                }
                 match self.tcp.borrow_mut().accept(token) {
                     Ok(sock) => self.accept_queue.push_back((
                         sock,
@@ -1492,6 +1935,14 @@ impl Server {
                 }
             },
             Protocol::HTTPListen => loop {
+                if !self.has_room_to_accept() {
+                    self.pause_listeners();
+                    break;
+                }
+                if !self.listener_has_room(token, max_sessions) {
+                    self.hold_listener(token);
+                    break;
+                }
                 match self.http.borrow_mut().accept(token) {
                     Ok(sock) => self.accept_queue.push_back((
                         sock,
@@ -1511,6 +1962,14 @@ impl Server {
                 }
             },
             Protocol::HTTPSListen => loop {
+                if !self.has_room_to_accept() {
+                    self.pause_listeners();
+                    break;
+                }
+                if !self.listener_has_room(token, max_sessions) {
+                    self.hold_listener(token);
+                    break;
+                }
                 match self.https.borrow_mut().accept(token) {
                     Ok(sock) => self.accept_queue.push_back((
                         sock,
@@ -1535,6 +1994,135 @@ impl Server {
         gauge!("accept_queue.connections", self.accept_queue.len());
     }
 
+    fn has_room_to_accept(&self) -> bool {
+        self.sessions.borrow().has_room_for(self.accept_queue.len())
+    }
+
+    fn listener_max_sessions(&self, token: ListenToken, protocol: Protocol) -> Option<usize> {
+        match protocol {
+            Protocol::HTTPListen => self.http.borrow().max_sessions(token),
+            Protocol::HTTPSListen => self.https.borrow().max_sessions(token),
+            Protocol::TCPListen => self.tcp.borrow().max_sessions(token),
+            _ => None,
+        }
+    }
+
+    /// Check that the listener is under its max_sessions, counting its sockets
+    /// already accepted and waiting in the accept queue
+    fn listener_has_room(&self, token: ListenToken, max_sessions: Option<usize>) -> bool {
+        let Some(max_sessions) = max_sessions else {
+            return true;
+        };
+        let queued_sockets = self
+            .accept_queue
+            .iter()
+            .filter(|(_, queued_token, _, _)| *queued_token == token)
+            .count();
+        self.sessions.borrow().listener_session_count(token) + queued_sockets < max_sessions
+    }
+
+    /// The listener reached its max_sessions: its connections wait in the kernel
+    /// backlog until one of its sessions closes, the other listeners still accept
+    fn hold_listener(&mut self, token: ListenToken) {
+        self.accept_ready.remove(&token);
+        if self.full_listeners.insert(token) {
+            debug!("listener {:?} reached its max sessions", token);
+            incr!("accept_queue.listener_full");
+        }
+    }
+
+    /// Accept again on the listeners held at their max_sessions whose sessions closed
+    fn release_full_listeners(&mut self) {
+        let full_listeners: Vec<ListenToken> = self.full_listeners.iter().copied().collect();
+        for token in full_listeners {
+            let protocol = self
+                .sessions
+                .borrow()
+                .slab
+                .get(token.0)
+                .map(|session| session.borrow().protocol());
+            match protocol {
+                Some(
+                    protocol @ (Protocol::HTTPListen | Protocol::HTTPSListen | Protocol::TCPListen),
+                ) => {
+                    let max_sessions = self.listener_max_sessions(token, protocol);
+                    if self.listener_has_room(token, max_sessions) {
+                        self.full_listeners.remove(&token);
+                        self.accept_ready.insert(token);
+                    }
+                }
+                // the listener was removed
+                _ => {
+                    self.full_listeners.remove(&token);
+                }
+            }
+        }
+    }
+
+    /// Deregisters the listen sockets, so that the connections wait in the kernel
+    /// backlog until sessions are freed, instead of being accepted then dropped
+    /// from the accept queue
+    fn pause_listeners(&mut self) {
+        self.sessions.borrow_mut().can_accept = false;
+        if self.listeners_paused {
+            return;
+        }
+        debug!("sessions at capacity, pausing the listeners");
+        gauge!("accept_queue.backpressure", 1);
+
+        for (token, protocol) in self.listen_tokens() {
+            let result = match protocol {
+                Protocol::HTTPListen => self.http.borrow_mut().pause_listener(token),
+                Protocol::HTTPSListen => self.https.borrow_mut().pause_listener(token),
+                Protocol::TCPListen => self.tcp.borrow_mut().pause_listener(token),
+                _ => continue,
+            };
+            if let Err(e) = result {
+                error!("could not pause listener {:?}: {}", token, e);
+            }
+        }
+        self.listeners_paused = true;
+    }
+
+    /// Registers the listen sockets again. The ones with pending connections
+    /// get a readable event right away
+    fn resume_listeners(&mut self) {
+        if !self.listeners_paused {
+            return;
+        }
+        debug!("resuming the listeners");
+
+        for (token, protocol) in self.listen_tokens() {
+            let result = match protocol {
+                Protocol::HTTPListen => self.http.borrow_mut().resume_listener(token),
+                Protocol::HTTPSListen => self.https.borrow_mut().resume_listener(token),
+                Protocol::TCPListen => self.tcp.borrow_mut().resume_listener(token),
+                _ => continue,
+            };
+            if let Err(e) = result {
+                error!("could not resume listener {:?}: {}", token, e);
+            }
+        }
+        self.listeners_paused = false;
+    }
+
+    fn listen_tokens(&self) -> Vec<(ListenToken, Protocol)> {
+        self.sessions
+            .borrow()
+            .slab
+            .iter()
+            .filter_map(|(token, session)| {
+                let protocol = session.borrow().protocol();
+                match protocol {
+                    Protocol::HTTPListen | Protocol::HTTPSListen | Protocol::TCPListen => {
+                        Some((ListenToken(token), protocol))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
     pub fn create_sessions(&mut self) {
         while let Some((sock, token, protocol, timestamp)) = self.accept_queue.pop_back() {
             let wait_time = Instant::now() - timestamp;
@@ -1543,11 +2131,20 @@ impl Server {
                 incr!("accept_queue.timeout");
                 continue;
             }
+            // dropping the socket closes the connection, including the ones
+            // accepted before the lame duck mode was set
+            if self.lame_duck {
+                incr!("accept_queue.lame_duck");
+                continue;
+            }
 
             if !self.sessions.borrow_mut().check_limits() {
                 break;
             }
 
+            // the proxies create the session in the next vacant entry of the slab
+            let session_token = Token(self.sessions.borrow().slab.vacant_key());
+
             //FIXME: check the timestamp
             //TODO: create_session should return the session and
             // the server should insert it in the the SessionManager
@@ -1586,7 +2183,7 @@ impl Server {
                 }
                 _ => panic!("should not call accept() on a HTTP, HTTPS or TCP session"),
             };
-            self.sessions.borrow_mut().incr();
+            self.sessions.borrow_mut().incr(token, session_token);
         }
 
         gauge!("accept_queue.connections", self.accept_queue.len());
@@ -1652,6 +2249,14 @@ impl Server {
     }
 
     pub fn handle_remaining_readiness(&mut self) {
+        if self.listeners_paused && self.sessions.borrow().can_accept {
+            self.resume_listeners();
+        }
+
+        if !self.full_listeners.is_empty() {
+            self.release_full_listeners();
+        }
+
         // try to accept again after handling all session events,
         // since we might have released a few session slots
         if self.sessions.borrow().can_accept && !self.accept_ready.is_empty() {
@@ -1669,6 +2274,16 @@ impl Server {
             }
         }
     }
+    /// run the sessions that gave way to the others, their readiness
+    /// was kept so no new event is needed
+    fn handle_yielded_sessions(&mut self) {
+        let tokens: Vec<Token> =
+            YIELDED_SESSIONS.with(|yielded| yielded.borrow_mut().drain().collect());
+        for token in tokens {
+            self.ready(token, Ready::EMPTY);
+        }
+    }
+
     fn block_channel(&mut self) {
         if let Err(e) = self.channel.blocking() {
             error!("Could not block channel: {}", e);
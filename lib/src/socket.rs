@@ -24,6 +24,13 @@ pub enum ServerBindError {
     SocketCreationError(std::io::Error),
     #[error("Invalid socket address '{address}': {error}")]
     InvalidSocketAddress { address: String, error: String },
+    #[error("could not bind to the network interface '{device}': {error}")]
+    BindToDevice {
+        device: String,
+        error: std::io::Error,
+    },
+    #[error("could not set the socket buffer sizes: {0}")]
+    SetBufferSizes(std::io::Error),
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -54,6 +61,14 @@ pub trait SocketHandler {
     fn socket_ref(&self) -> &TcpStream;
     fn socket_mut(&mut self) -> &mut TcpStream;
     fn protocol(&self) -> TransportProtocol;
+    /// application protocol negotiated with ALPN during the TLS handshake
+    fn alpn_protocol(&self) -> Option<&str> {
+        None
+    }
+    /// server name sent by the client during the TLS handshake (SNI)
+    fn server_name(&self) -> Option<&str> {
+        None
+    }
     fn read_error(&self);
     fn write_error(&self);
 }
@@ -456,6 +471,16 @@ impl SocketHandler for FrontRustls {
             .unwrap_or(TransportProtocol::Tcp)
     }
 
+    fn alpn_protocol(&self) -> Option<&str> {
+        self.session
+            .alpn_protocol()
+            .and_then(|alpn| std::str::from_utf8(alpn).ok())
+    }
+
+    fn server_name(&self) -> Option<&str> {
+        self.session.server_name()
+    }
+
     fn read_error(&self) {
         incr!("rustls.read.error");
     }
@@ -465,7 +490,19 @@ impl SocketHandler for FrontRustls {
     }
 }
 
-pub fn server_bind(addr: String) -> Result<TcpListener, ServerBindError> {
+/// size of the listen queue when the listener does not configure it
+pub const DEFAULT_LISTEN_BACKLOG: u32 = 1024;
+
+/// Create a nonblocking TCP listener on `addr`, with room for `backlog` connections
+/// waiting to be accepted (the kernel may cap it, with `net.core.somaxconn` on Linux).
+/// If `device` is set, the listener only accepts connections from this network interface.
+/// The accepted sockets inherit the buffer sizes set on the listener
+pub fn server_bind(
+    addr: String,
+    backlog: Option<u32>,
+    device: Option<&str>,
+    buffer_sizes: (Option<u32>, Option<u32>),
+) -> Result<TcpListener, ServerBindError> {
     let address = addr.parse::<SocketAddr>().map_err(|parse_error| {
         ServerBindError::InvalidSocketAddress {
             address: addr.clone(),
@@ -489,6 +526,17 @@ pub fn server_bind(addr: String) -> Result<TcpListener, ServerBindError> {
     sock.set_reuse_port(true)
         .map_err(ServerBindError::SetReusePort)?;
 
+    if let Some(device) = device {
+        bind_to_device(&sock, device).map_err(|error| ServerBindError::BindToDevice {
+            device: device.to_owned(),
+            error,
+        })?;
+    }
+
+    // before listening, so that the window scaling of the connections accounts for it
+    set_buffer_sizes(&sock, buffer_sizes.0, buffer_sizes.1)
+        .map_err(ServerBindError::SetBufferSizes)?;
+
     // bind the socket
     let addr = address.into();
     sock.bind(&addr).map_err(ServerBindError::BindError)?;
@@ -497,12 +545,105 @@ pub fn server_bind(addr: String) -> Result<TcpListener, ServerBindError> {
         .map_err(ServerBindError::SetNonBlocking)?;
 
     // listen
-    // FIXME: make the backlog configurable?
-    sock.listen(1024).map_err(ServerBindError::Listen)?;
+    let backlog = backlog
+        .unwrap_or(DEFAULT_LISTEN_BACKLOG)
+        .min(i32::MAX as u32) as i32;
+    sock.listen(backlog).map_err(ServerBindError::Listen)?;
 
     Ok(TcpListener::from_std(sock.into()))
 }
 
+/// Restrict the socket to a network interface with SO_BINDTODEVICE
+#[cfg(target_os = "linux")]
+fn bind_to_device(sock: &Socket, device: &str) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            device.as_ptr() as *const libc::c_void,
+            device.len() as libc::socklen_t,
+        )
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_sock: &Socket, _device: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "binding to a network interface is only supported on Linux",
+    ))
+}
+
+/// Set the size of the send (SO_SNDBUF) and receive (SO_RCVBUF) buffers of a socket.
+/// Linux doubles the requested sizes to make room for its bookkeeping, and caps them
+/// with `net.core.wmem_max` and `net.core.rmem_max`
+pub fn set_buffer_sizes<S: std::os::unix::io::AsRawFd>(
+    socket: &S,
+    send_buffer_size: Option<u32>,
+    receive_buffer_size: Option<u32>,
+) -> std::io::Result<()> {
+    for (option, size) in [
+        (libc::SO_SNDBUF, send_buffer_size),
+        (libc::SO_RCVBUF, receive_buffer_size),
+    ] {
+        let Some(size) = size else {
+            continue;
+        };
+        let size = size.min(i32::MAX as u32) as libc::c_int;
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                option,
+                &size as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Starts a non blocking TCP connection with the given send and receive buffer sizes.
+/// They are set before connecting because the window scale the kernel offers in the
+/// SYN depends on the receive buffer, setting it on an established connection is too late
+pub fn connect_with_buffer_sizes(
+    address: SocketAddr,
+    send_buffer_size: Option<u32>,
+    receive_buffer_size: Option<u32>,
+) -> std::io::Result<TcpStream> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_nonblocking(true)?;
+    if let Err(e) = set_buffer_sizes(&socket, send_buffer_size, receive_buffer_size) {
+        error!(
+            "error setting buffer sizes on back socket to {}: {:?}",
+            address, e
+        );
+    }
+
+    match socket.connect(&address.into()) {
+        Ok(()) => {}
+        // the connection goes on in the background, like mio's TcpStream::connect
+        Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(TcpStream::from_std(socket.into()))
+}
+
 /// Socket statistics
 pub mod stats {
     use std::os::fd::AsRawFd;
@@ -588,6 +729,12 @@ pub mod stats {
             pub fn rtt(&self) -> u32 {
                 self.tcpi_rtt
             }
+
+            /// On a listening socket, Linux reports the backlog in place of the sacked segments
+            #[cfg(target_os = "linux")]
+            pub fn listen_backlog(&self) -> u32 {
+                self.tcpi_sacked
+            }
         }
     }
 
@@ -647,4 +794,133 @@ pub mod stats {
         println!("{:#?}", info);
         println!("rtt: {}", crate::logs::LogDuration(socket_rtt(&sock)));
     }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_listen_backlog() {
+        use crate::socket::{server_bind, DEFAULT_LISTEN_BACKLOG};
+
+        // the kernel silently caps the backlog to net.core.somaxconn
+        let somaxconn = std::fs::read_to_string("/proc/sys/net/core/somaxconn")
+            .ok()
+            .and_then(|value| value.trim().parse::<u32>().ok())
+            .unwrap_or(u32::MAX);
+
+        let listener =
+            server_bind("127.0.0.1:0".to_string(), Some(42), None, (None, None)).unwrap();
+        let info = socket_info(listener.as_raw_fd()).unwrap();
+        assert_eq!(info.listen_backlog(), somaxconn.min(42));
+
+        let listener = server_bind("127.0.0.1:0".to_string(), None, None, (None, None)).unwrap();
+        let info = socket_info(listener.as_raw_fd()).unwrap();
+        assert_eq!(info.listen_backlog(), DEFAULT_LISTEN_BACKLOG.min(somaxconn));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_bind_device() {
+        use crate::socket::{server_bind, ServerBindError};
+
+        fn bound_device(fd: i32) -> String {
+            let mut device = [0u8; libc::IFNAMSIZ];
+            let mut len = device.len() as libc::socklen_t;
+            let result = unsafe {
+                libc::getsockopt(
+                    fd,
+                    libc::SOL_SOCKET,
+                    libc::SO_BINDTODEVICE,
+                    device.as_mut_ptr() as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(result, 0, "could not read SO_BINDTODEVICE");
+            let device = &device[..len as usize];
+            let end = device.iter().position(|c| *c == 0).unwrap_or(device.len());
+            String::from_utf8_lossy(&device[..end]).to_string()
+        }
+
+        let listener =
+            server_bind("127.0.0.1:0".to_string(), None, Some("lo"), (None, None)).unwrap();
+        assert_eq!(bound_device(listener.as_raw_fd()), "lo");
+        let address = listener.local_addr().unwrap();
+        assert!(std::net::TcpStream::connect(address).is_ok());
+
+        let listener = server_bind("127.0.0.1:0".to_string(), None, None, (None, None)).unwrap();
+        assert_eq!(bound_device(listener.as_raw_fd()), "");
+
+        assert!(matches!(
+            server_bind(
+                "127.0.0.1:0".to_string(),
+                None,
+                Some("sozu-missing0"),
+                (None, None)
+            ),
+            Err(ServerBindError::BindToDevice { .. })
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_buffer_sizes() {
+        use crate::socket::{connect_with_buffer_sizes, server_bind};
+
+        fn buffer_size(fd: i32, option: libc::c_int) -> libc::c_int {
+            let mut size: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let result = unsafe {
+                libc::getsockopt(
+                    fd,
+                    libc::SOL_SOCKET,
+                    option,
+                    &mut size as *mut libc::c_int as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(result, 0, "could not read the buffer size");
+            size
+        }
+
+        // small enough to stay under net.core.wmem_max and net.core.rmem_max,
+        // large enough to be over the minimum sizes: Linux doubles them
+        let listener = server_bind(
+            "127.0.0.1:0".to_string(),
+            None,
+            None,
+            (Some(32 * 1024), Some(48 * 1024)),
+        )
+        .unwrap();
+        assert_eq!(
+            buffer_size(listener.as_raw_fd(), libc::SO_SNDBUF),
+            64 * 1024
+        );
+        assert_eq!(
+            buffer_size(listener.as_raw_fd(), libc::SO_RCVBUF),
+            96 * 1024
+        );
+
+        // the accepted sockets inherit them
+        let address = listener.local_addr().unwrap();
+        let _client = std::net::TcpStream::connect(address).unwrap();
+        let (accepted, _) = loop {
+            match listener.accept() {
+                Ok(accepted) => break accepted,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                Err(e) => panic!("could not accept: {e}"),
+            }
+        };
+        assert_eq!(
+            buffer_size(accepted.as_raw_fd(), libc::SO_SNDBUF),
+            64 * 1024
+        );
+        assert_eq!(
+            buffer_size(accepted.as_raw_fd(), libc::SO_RCVBUF),
+            96 * 1024
+        );
+
+        // the backend sockets get them before connecting
+        let stream = connect_with_buffer_sizes(address, None, Some(40 * 1024)).unwrap();
+        assert_eq!(buffer_size(stream.as_raw_fd(), libc::SO_RCVBUF), 80 * 1024);
+    }
 }
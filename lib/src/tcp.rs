@@ -18,28 +18,30 @@ use rusty_ulid::Ulid;
 use slab::Slab;
 use time::{Duration, Instant};
 
-use sozu_command::{config::MAX_LOOP_ITERATIONS, proto::command::request::RequestType, ObjectKind};
+use sozu_command::{proto::command::request::RequestType, ObjectKind};
 
 use crate::{
     backends::{Backend, BackendMap},
     logs::{Endpoint, LogContext, RequestRecord},
     pool::{Checkout, Pool},
     protocol::{
+        client_hello::{parse_sni, ClientHelloSni, MAX_TLS_RECORD_SIZE},
         proxy_protocol::{
             expect::ExpectProxyProtocol, relay::RelayProxyProtocol, send::SendProxyProtocol,
         },
-        Pipe,
+        Pipe, SessionState,
     },
     retry::RetryPolicy,
     server::{
-        push_event, ListenSession, ListenToken, ProxyChannel, Server, SessionManager, CONN_RETRIES,
-        TIMER,
+        max_connection_attempts, max_loop_iterations, push_event, ListenSession, ListenToken,
+        ProxyChannel, Server, SessionManager, TIMER,
     },
     socket::{server_bind, stats::socket_rtt},
     sozu_command::{
         logging,
         proto::command::{
-            Event, EventKind, ProxyProtocolConfig, RequestTcpFrontend, TcpListenerConfig,
+            Event, EventKind, ProxyProtocolConfig, RequestTcpFrontend, SessionInfo,
+            TcpListenerConfig,
         },
         ready::Ready,
         request::WorkerRequest,
@@ -86,6 +88,8 @@ pub struct TcpSession {
     metrics: SessionMetrics,
     proxy: Rc<RefCell<TcpProxy>>,
     request_id: Ulid,
+    /// the cluster is picked from the server name of the ClientHello
+    routing_by_sni: bool,
     state: TcpStateMachine,
 }
 
@@ -102,6 +106,7 @@ impl TcpSession {
         listener: Rc<RefCell<TcpListener>>,
         proxy_protocol: Option<ProxyProtocolConfig>,
         proxy: Rc<RefCell<TcpProxy>>,
+        routing_by_sni: bool,
         socket: MioTcpStream,
         wait_time: Duration,
     ) -> TcpSession {
@@ -176,6 +181,10 @@ impl TcpSession {
         let metrics = SessionMetrics::new(Some(wait_time));
         //FIXME: timeout usage
 
+        if cluster_id.is_some() {
+            gauge_add!("tcp.active_connections", 1, cluster_id.as_deref(), None);
+        }
+
         TcpSession {
             backend_buffer: backend_buffer_session,
             backend_connected: BackendConnectionStatus::NotConnected,
@@ -195,6 +204,7 @@ impl TcpSession {
             metrics,
             proxy,
             request_id,
+            routing_by_sni,
             state,
         }
     }
@@ -211,8 +221,10 @@ impl TcpSession {
             tags: listener.get_concatenated_tags(&listener.get_addr().to_string()),
             client_rtt: socket_rtt(self.state.front_socket()),
             server_rtt: None,
+            backend_connection: None,
             metrics: &self.metrics,
             user_agent: None,
+            format: listener.get_access_logs_format(),
         }
         .log();
     }
@@ -258,7 +270,8 @@ impl TcpSession {
             TcpStateMachine::ExpectProxyProtocol(pp) => {
                 should_upgrade_protocol = pp.readable(&mut self.metrics);
                 match should_upgrade_protocol {
-                    SessionResult::Upgrade => StateResult::Continue,
+                    // the backend connection waited for the header
+                    SessionResult::Upgrade => StateResult::ConnectBackend,
                     SessionResult::Continue => StateResult::Continue,
                     SessionResult::Close => StateResult::CloseSession,
                 }
@@ -269,7 +282,7 @@ impl TcpSession {
 
         if let SessionResult::Upgrade = should_upgrade_protocol {
             match self.upgrade() {
-                false => StateResult::Continue,
+                false => state_result,
                 true => StateResult::CloseSession,
             }
         } else {
@@ -384,6 +397,8 @@ impl TcpSession {
         epp: ExpectProxyProtocol<MioTcpStream>,
     ) -> Option<TcpStateMachine> {
         if self.frontend_buffer.is_some() && self.backend_buffer.is_some() {
+            // the access logs show the client address given by the header
+            self.frontend_address = epp.session_address();
             let mut pipe = epp.into_pipe(
                 self.frontend_buffer.take().unwrap(),
                 self.backend_buffer.take().unwrap(),
@@ -471,10 +486,12 @@ impl TcpSession {
                 spp.set_back_connected(BackendConnectionStatus::Connected);
             }
 
+            let mut came_up = false;
             if let Some(backend) = self.backend.as_ref() {
                 let mut backend = backend.borrow_mut();
 
                 if backend.retry_policy.is_down() {
+                    came_up = true;
                     incr!(
                         "backend.up",
                         self.cluster_id.as_deref(),
@@ -500,6 +517,9 @@ impl TcpSession {
                 backend.failures = 0;
                 backend.retry_policy.succeed();
             }
+            if came_up {
+                self.update_cluster_health(true);
+            }
         }
     }
 
@@ -511,7 +531,22 @@ impl TcpSession {
         self.backend_token = None;
     }
 
+    /// A backend of the cluster went down or up, check if the cluster
+    /// as a whole changed state
+    fn update_cluster_health(&self, backend_up: bool) {
+        if let Some(cluster_id) = &self.cluster_id {
+            let proxy = self.proxy.borrow();
+            let mut backends = proxy.backends.borrow_mut();
+            if backend_up {
+                backends.backend_up(cluster_id);
+            } else {
+                backends.backend_down(cluster_id);
+            }
+        }
+    }
+
     fn fail_backend_connection(&mut self) {
+        let mut went_down = false;
         if let Some(backend) = self.backend.as_ref() {
             let backend = &mut *backend.borrow_mut();
             backend.failures += 1;
@@ -540,8 +575,12 @@ impl TcpSession {
                     address: Some(backend.address.to_string()),
                     cluster_id: None,
                 });
+                went_down = true;
             }
         }
+        if went_down {
+            self.update_cluster_health(false);
+        }
     }
 
     fn reset_connection_attempt(&mut self) {
@@ -576,8 +615,12 @@ impl TcpSession {
         let back_connected = self.back_connected();
         if back_connected.is_connecting() {
             if self.back_readiness().unwrap().event.is_hup() && !self.test_back_socket() {
-                //retry connecting the backend
-                debug!("error connecting to backend, trying again");
+                // the failed backend is now backing off, so the load balancer
+                // picks the next eligible backend of the cluster
+                debug!(
+                    "{} error connecting to backend, trying another one",
+                    self.log_context()
+                );
                 self.connection_attempt += 1;
                 self.fail_backend_connection();
 
@@ -590,9 +633,13 @@ impl TcpSession {
                         // stop here, we must wait for an event
                         return StateResult::Continue;
                     }
-                    // TODO: should we return CloseSession here?
                     Err(connection_error) => {
-                        error!("Error connecting to backend: {}", connection_error)
+                        error!(
+                            "{} could not connect to a backend, closing the session: {}",
+                            self.log_context(),
+                            connection_error
+                        );
+                        return StateResult::CloseSession;
                     }
                 }
             } else if self.back_readiness().unwrap().event != Ready::EMPTY {
@@ -605,7 +652,15 @@ impl TcpSession {
 
                 self.set_back_connected(BackendConnectionStatus::Connected);
             }
-        } else if back_connected == BackendConnectionStatus::NotConnected {
+        } else if back_connected == BackendConnectionStatus::NotConnected
+            // the backend connection waits for the proxy protocol header
+            && !matches!(self.state, TcpStateMachine::ExpectProxyProtocol(_))
+        {
+            if self.routing_by_sni {
+                if let Some(state_result) = self.route_by_sni() {
+                    return state_result;
+                }
+            }
             match self.connect_to_backend(session.clone()) {
                 // reuse connection or send a default answer, we can continue
                 Ok(BackendConnectAction::Reuse) => {}
@@ -614,7 +669,12 @@ impl TcpSession {
                     return StateResult::Continue;
                 }
                 Err(connection_error) => {
-                    error!("Error connecting to backend: {}", connection_error)
+                    error!(
+                        "{} could not connect to a backend, closing the session: {}",
+                        self.log_context(),
+                        connection_error
+                    );
+                    return StateResult::CloseSession;
                 }
             }
         }
@@ -633,7 +693,8 @@ impl TcpSession {
         }
 
         let token = self.frontend_token;
-        while counter < MAX_LOOP_ITERATIONS {
+        let max_loop_iterations = max_loop_iterations();
+        while counter < max_loop_iterations {
             let front_interest = self.front_readiness().interest & self.front_readiness().event;
             let back_interest = self
                 .back_readiness()
@@ -676,7 +737,12 @@ impl TcpSession {
                                 return StateResult::Continue;
                             }
                             Err(connection_error) => {
-                                error!("Error connecting to backend: {}", connection_error)
+                                error!(
+                                    "{} could not connect to a backend, closing the session: {}",
+                                    self.log_context(),
+                                    connection_error
+                                );
+                                return StateResult::CloseSession;
                             }
                         }
                     }
@@ -753,12 +819,18 @@ impl TcpSession {
             counter += 1;
         }
 
-        if counter >= MAX_LOOP_ITERATIONS {
+        if counter >= max_loop_iterations {
             error!(
-                "PROXY\thandling session {:?} went through {} iterations, there's a probable infinite loop bug, closing the connection",
-                self.frontend_token, MAX_LOOP_ITERATIONS
+                "PROXY\thandling session {:?} went through {} iterations in the {:?} state, there's a probable infinite loop bug, closing the connection",
+                self.frontend_token, max_loop_iterations, self.state.marker()
             );
             incr!("tcp.infinite_loop.error");
+            match self.state.marker() {
+                StateMarker::Pipe => incr!("tcp.infinite_loop.pipe"),
+                StateMarker::SendProxyProtocol => incr!("tcp.infinite_loop.send"),
+                StateMarker::RelayProxyProtocol => incr!("tcp.infinite_loop.relay"),
+                StateMarker::ExpectProxyProtocol => incr!("tcp.infinite_loop.expect"),
+            }
 
             let front_interest = self.front_readiness().interest & self.front_readiness().event;
             let back_interest = self
@@ -827,20 +899,171 @@ impl TcpSession {
         self.set_back_connected(BackendConnectionStatus::NotConnected);
     }
 
+    /// Pick the cluster of the session from the server name of the ClientHello,
+    /// peeked from the frontend socket so that it is still relayed to the backend.
+    /// Returns a result if the session has to wait for more data or be closed.
+    fn route_by_sni(&mut self) -> Option<StateResult> {
+        // the ClientHello is peeked in the free space of the frontend buffer
+        let TcpStateMachine::Pipe(pipe) = &mut self.state else {
+            error!(
+                "{} a session routed by server name must start as a pipe",
+                self.log_context()
+            );
+            return Some(StateResult::CloseSession);
+        };
+        let (sni, buffer_full) = match pipe.peek_frontend() {
+            Ok(([], _)) => return Some(StateResult::CloseSession),
+            // a ClientHello bigger than a TLS record is not read any further
+            Ok((data, is_full)) => (
+                parse_sni(data),
+                is_full || data.len() >= MAX_TLS_RECORD_SIZE,
+            ),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                self.front_readiness().event.remove(Ready::READABLE);
+                return Some(StateResult::Continue);
+            }
+            Err(e) => {
+                error!(
+                    "{} could not read the ClientHello: {}",
+                    self.log_context(),
+                    e
+                );
+                return Some(StateResult::CloseSession);
+            }
+        };
+
+        // the front timeout bounds the wait for the rest of the ClientHello
+        if sni == ClientHelloSni::Incomplete && !buffer_full {
+            self.front_readiness().event.remove(Ready::READABLE);
+            return Some(StateResult::Continue);
+        }
+
+        let cluster_id = self.listener.borrow().cluster_for_sni(&sni).cloned();
+        debug!(
+            "{} routing {:?} to cluster {:?}",
+            self.log_context(),
+            sni,
+            cluster_id
+        );
+        let Some(cluster_id) = cluster_id else {
+            error!(
+                "{} no cluster for the server name {:?}, closing the session",
+                self.log_context(),
+                sni
+            );
+            return Some(StateResult::CloseSession);
+        };
+
+        if let TcpStateMachine::Pipe(pipe) = &mut self.state {
+            pipe.set_cluster_id(Some(cluster_id.clone()));
+        }
+        let proxy_protocol = self
+            .proxy
+            .borrow()
+            .configs
+            .get(&cluster_id)
+            .and_then(|config| config.proxy_protocol);
+        self.set_cluster_id(cluster_id);
+        self.routing_by_sni = false;
+
+        match proxy_protocol {
+            Some(ProxyProtocolConfig::SendHeader) => self.switch_to_send_proxy_protocol(),
+            // the client would have to send its header before the ClientHello
+            Some(proxy_protocol) => warn!(
+                "{} the proxy protocol config {:?} of the cluster cannot apply to a session routed by server name",
+                self.log_context(),
+                proxy_protocol
+            ),
+            None => {}
+        }
+        None
+    }
+
+    /// The pipe of a session routed by server name has not connected yet,
+    /// it starts over by sending a proxy protocol header to the backend
+    fn switch_to_send_proxy_protocol(&mut self) {
+        if !matches!(self.state, TcpStateMachine::Pipe(_)) {
+            return;
+        }
+        let TcpStateMachine::Pipe(pipe) = self.state.take() else {
+            unreachable!()
+        };
+        let frontend_readiness = pipe.frontend_readiness;
+        let (socket, frontend_buffer, backend_buffer) = pipe.into_parts();
+        self.frontend_buffer = Some(frontend_buffer);
+        self.backend_buffer = Some(backend_buffer);
+
+        let mut send_proxy_protocol =
+            SendProxyProtocol::new(socket, self.frontend_token, self.request_id, None);
+        send_proxy_protocol.frontend_readiness.event = frontend_readiness.event;
+        gauge_add!("protocol.tcp", -1);
+        gauge_add!("protocol.proxy.send", 1);
+        self.state = TcpStateMachine::SendProxyProtocol(send_proxy_protocol);
+    }
+
+    /// Move the session to a cluster, its active connection is counted in that cluster
+    fn set_cluster_id(&mut self, cluster_id: String) {
+        if self.cluster_id.as_ref() == Some(&cluster_id) {
+            return;
+        }
+        if self.cluster_id.is_some() {
+            gauge_add!(
+                "tcp.active_connections",
+                -1,
+                self.cluster_id.as_deref(),
+                None
+            );
+        }
+        gauge_add!("tcp.active_connections", 1, Some(cluster_id.as_str()), None);
+        self.cluster_id = Some(cluster_id);
+    }
+
+    /// Record the traffic and duration of the connection in its cluster
+    fn record_connection_metrics(&self) {
+        let Some(cluster_id) = self.cluster_id.as_deref() else {
+            return;
+        };
+        let backend_id = self.metrics.backend_id.as_deref();
+
+        count!(
+            "tcp.bytes_in",
+            self.metrics.bin as i64,
+            Some(cluster_id),
+            backend_id
+        );
+        count!(
+            "tcp.bytes_out",
+            self.metrics.bout as i64,
+            Some(cluster_id),
+            backend_id
+        );
+
+        let connection_time = self.metrics.response_time().whole_milliseconds();
+        time!("tcp.connection_time", connection_time);
+        time!("tcp.connection_time", cluster_id, connection_time);
+
+        gauge_add!("tcp.active_connections", -1, Some(cluster_id), None);
+    }
+
     fn connect_to_backend(
         &mut self,
         session_rc: Rc<RefCell<dyn ProxySession>>,
     ) -> Result<BackendConnectAction, BackendConnectionError> {
         let cluster_id = self
-            .listener
-            .borrow()
             .cluster_id
             .clone()
+            .or_else(|| self.listener.borrow().cluster_id.clone())
             .ok_or(BackendConnectionError::NotFound(ObjectKind::TcpCluster))?;
 
-        self.cluster_id = Some(cluster_id.clone());
+        self.set_cluster_id(cluster_id.clone());
 
-        if self.connection_attempt >= CONN_RETRIES {
+        let connection_retries = self
+            .proxy
+            .borrow()
+            .configs
+            .get(&cluster_id)
+            .and_then(|config| config.connection_retries);
+        if self.connection_attempt >= max_connection_attempts(connection_retries) {
             error!("{} max connection attempt reached", self.log_context());
             return Err(BackendConnectionError::MaxConnectionRetries(Some(
                 cluster_id,
@@ -851,12 +1074,13 @@ impl TcpSession {
             return Err(BackendConnectionError::MaxSessionsMemory);
         }
 
+        let buffer_sizes = self.listener.borrow().get_socket_buffer_sizes();
         let (backend, mut stream) = self
             .proxy
             .borrow()
             .backends
             .borrow_mut()
-            .backend_from_cluster_id(&cluster_id)
+            .backend_from_cluster_id(&cluster_id, buffer_sizes)
             .map_err(BackendConnectionError::Backend)?;
 
         /*
@@ -922,6 +1146,7 @@ impl ProxySession for TcpSession {
         // TODO: the state should handle the timeouts
         trace!("Closing TCP session");
         self.metrics.service_stop();
+        self.record_connection_metrics();
 
         // Restore gauges
         match self.state.marker() {
@@ -1086,15 +1311,33 @@ TCP Session ({:?})
     fn frontend_token(&self) -> Token {
         self.frontend_token
     }
+
+    fn fill_session_info(&self, info: &mut SessionInfo) {
+        info.protocol = format!("TCP({:?})", self.state.marker());
+
+        // the states shared with the HTTP sessions describe themselves the same way
+        match &self.state {
+            TcpStateMachine::ExpectProxyProtocol(expect) => expect.fill_session_info(info),
+            TcpStateMachine::SendProxyProtocol(send) => send.fill_session_info(info),
+            TcpStateMachine::RelayProxyProtocol(relay) => relay.fill_session_info(info),
+            TcpStateMachine::Pipe(pipe) => pipe.fill_session_info(info),
+            TcpStateMachine::FailedUpgrade(_) => {}
+        }
+        info.cluster_id = self.cluster_id.clone();
+        info.backend_id = self.backend_id.clone();
+    }
 }
 
 pub struct TcpListener {
     active: SessionIsToBeClosed,
     address: SocketAddr,
+    /// cluster of the connections that are not routed by their server name
     cluster_id: Option<String>,
     config: TcpListenerConfig,
     listener: Option<MioTcpListener>,
     pool: Rc<RefCell<Pool>>,
+    /// lowercase server name (SNI) -> cluster
+    sni_routes: HashMap<String, ClusterId>,
     tags: BTreeMap<String, CachedTags>,
     token: Token,
 }
@@ -1114,6 +1357,13 @@ impl ListenerHandler for TcpListener {
             None => self.tags.remove(&key),
         };
     }
+
+    fn get_socket_buffer_sizes(&self) -> (Option<u32>, Option<u32>) {
+        (
+            self.config.send_buffer_size,
+            self.config.receive_buffer_size,
+        )
+    }
 }
 
 impl TcpListener {
@@ -1138,10 +1388,22 @@ impl TcpListener {
             pool,
             config,
             active: false,
+            sni_routes: HashMap::new(),
             tags: BTreeMap::new(),
         })
     }
 
+    /// the cluster of a connection whose ClientHello carries this server name,
+    /// or the default cluster of the listener if no frontend matches it
+    fn cluster_for_sni(&self, sni: &ClientHelloSni) -> Option<&ClusterId> {
+        match sni {
+            ClientHelloSni::Found(hostname) => {
+                self.sni_routes.get(hostname).or(self.cluster_id.as_ref())
+            }
+            _ => self.cluster_id.as_ref(),
+        }
+    }
+
     // TODO: return Result with context
     pub fn activate(
         &mut self,
@@ -1153,11 +1415,16 @@ impl TcpListener {
         }
 
         let mut listener = tcp_listener.or_else(|| {
-            server_bind(self.config.address.clone())
-                .map_err(|e| {
-                    error!("could not create listener {:?}: {}", self.config.address, e);
-                })
-                .ok()
+            server_bind(
+                self.config.address.clone(),
+                self.config.listen_backlog,
+                self.config.bind_device.as_deref(),
+                self.get_socket_buffer_sizes(),
+            )
+            .map_err(|e| {
+                error!("could not create listener {:?}: {}", self.config.address, e);
+            })
+            .ok()
         });
 
         if let Some(ref mut sock) = listener {
@@ -1177,6 +1444,8 @@ impl TcpListener {
 #[derive(Debug)]
 pub struct ClusterConfiguration {
     proxy_protocol: Option<ProxyProtocolConfig>,
+    /// connections attempted to the backends before giving up
+    connection_retries: Option<u32>,
     // Uncomment this when implementing new load balancing algorithms
     // load_balancing: LoadBalancingAlgorithms,
 }
@@ -1289,10 +1558,33 @@ impl TcpProxy {
             .ok_or(ProxyError::NoListenerFound(address))?
             .borrow_mut();
 
+        let hostname = front
+            .hostname
+            .as_ref()
+            .map(|hostname| hostname.to_ascii_lowercase());
+        if let Some(cluster_id) = hostname
+            .as_ref()
+            .and_then(|hostname| listener.sni_routes.get(hostname))
+        {
+            if *cluster_id != front.cluster_id {
+                return Err(ProxyError::DuplicateServerName {
+                    hostname: hostname.unwrap_or_default(),
+                    cluster_id: cluster_id.to_owned(),
+                });
+            }
+        }
+
         self.fronts
             .insert(front.cluster_id.to_string(), listener.token);
-        listener.set_tags(front.address.to_string(), Some(front.tags));
-        listener.cluster_id = Some(front.cluster_id);
+        match hostname {
+            Some(hostname) => {
+                listener.sni_routes.insert(hostname, front.cluster_id);
+            }
+            None => {
+                listener.set_tags(front.address.to_string(), Some(front.tags));
+                listener.cluster_id = Some(front.cluster_id);
+            }
+        }
         Ok(())
     }
 
@@ -1311,8 +1603,14 @@ impl TcpProxy {
             None => bail!(format!("no such listener for '{}'", front.address)),
         };
 
-        listener.set_tags(front.address, None);
-        if let Some(cluster_id) = listener.cluster_id.take() {
+        let cluster_id = match front.hostname {
+            Some(hostname) => listener.sni_routes.remove(&hostname.to_ascii_lowercase()),
+            None => {
+                listener.set_tags(front.address, None);
+                listener.cluster_id.take()
+            }
+        };
+        if let Some(cluster_id) = cluster_id {
             self.fronts.remove(&cluster_id);
         }
         Ok(())
@@ -1382,6 +1680,7 @@ impl ProxyConfiguration for TcpProxy {
                     proxy_protocol: cluster
                         .proxy_protocol
                         .and_then(|n| ProxyProtocolConfig::try_from(n).ok()),
+                    connection_retries: cluster.connection_retries,
                     //load_balancing: cluster.load_balancing,
                 };
                 self.configs.insert(cluster.cluster_id, config);
@@ -1442,6 +1741,32 @@ impl ProxyConfiguration for TcpProxy {
         }
     }
 
+    fn pause_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error> {
+        if let Some(listener) = self.listeners.get(&Token(token.0)) {
+            if let Some(sock) = listener.borrow_mut().listener.as_mut() {
+                self.registry.deregister(sock)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resume_listener(&mut self, token: ListenToken) -> Result<(), std::io::Error> {
+        if let Some(listener) = self.listeners.get(&Token(token.0)) {
+            if let Some(sock) = listener.borrow_mut().listener.as_mut() {
+                self.registry
+                    .register(sock, Token(token.0), Interest::READABLE)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn max_sessions(&self, token: ListenToken) -> Option<usize> {
+        self.listeners
+            .get(&Token(token.0))
+            .and_then(|listener| listener.borrow().config.max_sessions)
+            .map(|max_sessions| max_sessions as usize)
+    }
+
     fn create_session(
         &mut self,
         mut frontend_sock: MioTcpStream,
@@ -1473,7 +1798,7 @@ impl ProxyConfiguration for TcpProxy {
             }
         };
 
-        if owned.cluster_id.is_none() {
+        if owned.cluster_id.is_none() && owned.sni_routes.is_empty() {
             error!(
                 "listener at address {:?} has no linked cluster",
                 owned.address
@@ -1481,10 +1806,19 @@ impl ProxyConfiguration for TcpProxy {
             return Err(AcceptError::IoError);
         }
 
-        let proxy_protocol = self
-            .configs
-            .get(owned.cluster_id.as_ref().unwrap())
-            .and_then(|c| c.proxy_protocol);
+        // the cluster of a connection routed by its server name is only known
+        // once its ClientHello is read, its proxy protocol config applies then
+        let routing_by_sni = !owned.sni_routes.is_empty();
+        let (cluster_id, proxy_protocol) = if routing_by_sni {
+            (None, None)
+        } else {
+            let proxy_protocol = owned
+                .cluster_id
+                .as_ref()
+                .and_then(|cluster_id| self.configs.get(cluster_id))
+                .and_then(|c| c.proxy_protocol);
+            (owned.cluster_id.clone(), proxy_protocol)
+        };
 
         if let Err(e) = frontend_sock.set_nodelay(true) {
             error!(
@@ -1512,7 +1846,7 @@ impl ProxyConfiguration for TcpProxy {
         let session = TcpSession::new(
             back_buffer,
             None,
-            owned.cluster_id.clone(),
+            cluster_id,
             Duration::seconds(owned.config.back_timeout as i64),
             Duration::seconds(owned.config.front_timeout as i64),
             front_buffer,
@@ -1520,6 +1854,7 @@ impl ProxyConfiguration for TcpProxy {
             listener.clone(),
             proxy_protocol,
             proxy,
+            routing_by_sni,
             frontend_sock,
             wait_time,
         );
@@ -1871,6 +2206,7 @@ mod tests {
                 load_balancing_parameters: Some(LoadBalancingParams::default()),
                 sticky_id: None,
                 backup: None,
+                hostname: None,
             };
 
             command
@@ -1901,6 +2237,7 @@ mod tests {
                 load_balancing_parameters: Some(LoadBalancingParams::default()),
                 sticky_id: None,
                 backup: None,
+                hostname: None,
             };
             command
                 .write_message(&WorkerRequest {
@@ -1928,4 +2265,191 @@ mod tests {
 
         Ok(command)
     }
+
+    #[test]
+    fn sni_routing() {
+        use sozu_command::config::ListenerBuilder;
+
+        let poll = Poll::new().expect("could not create event loop");
+        let mut proxy = TcpProxy::new(
+            poll.registry()
+                .try_clone()
+                .expect("could not clone the mio registry"),
+            SessionManager::new(Slab::with_capacity(10), 10),
+            Rc::new(RefCell::new(BackendMap::new())),
+        );
+        let address = "127.0.0.1:1036";
+        proxy
+            .add_listener(
+                ListenerBuilder::new_tcp(address)
+                    .to_tcp(None)
+                    .expect("could not create the TCP listener config"),
+                Rc::new(RefCell::new(Pool::with_capacity(1, 10, 16384))),
+                Token(0),
+            )
+            .expect("could not add the listener");
+
+        for (cluster_id, hostname) in [
+            ("default", None),
+            ("lolcat", Some("LolCatHo.st")),
+            ("example", Some("example.com")),
+        ] {
+            proxy
+                .add_tcp_front(RequestTcpFrontend {
+                    cluster_id: cluster_id.to_owned(),
+                    address: address.to_owned(),
+                    hostname: hostname.map(String::from),
+                    ..Default::default()
+                })
+                .expect("could not add the frontend");
+        }
+
+        // a server name is routed to a single cluster
+        assert!(matches!(
+            proxy.add_tcp_front(RequestTcpFrontend {
+                cluster_id: "lolcat_bis".to_owned(),
+                address: address.to_owned(),
+                hostname: Some("lolcatho.st".to_owned()),
+                ..Default::default()
+            }),
+            Err(ProxyError::DuplicateServerName { .. })
+        ));
+
+        fn cluster_for(proxy: &TcpProxy, sni: ClientHelloSni) -> Option<String> {
+            proxy.listeners[&Token(0)]
+                .borrow()
+                .cluster_for_sni(&sni)
+                .cloned()
+        }
+        let found = |hostname: &str| ClientHelloSni::Found(hostname.to_owned());
+        assert_eq!(
+            cluster_for(&proxy, found("lolcatho.st")).as_deref(),
+            Some("lolcat")
+        );
+        assert_eq!(
+            cluster_for(&proxy, found("example.com")).as_deref(),
+            Some("example")
+        );
+        assert_eq!(
+            cluster_for(&proxy, found("unknown.com")).as_deref(),
+            Some("default")
+        );
+        assert_eq!(
+            cluster_for(&proxy, ClientHelloSni::Missing).as_deref(),
+            Some("default")
+        );
+        assert_eq!(
+            cluster_for(&proxy, ClientHelloSni::Invalid).as_deref(),
+            Some("default")
+        );
+
+        proxy
+            .remove_tcp_front(RequestTcpFrontend {
+                cluster_id: "default".to_owned(),
+                address: address.to_owned(),
+                ..Default::default()
+            })
+            .expect("could not remove the frontend");
+        assert_eq!(
+            cluster_for(&proxy, found("lolcatho.st")).as_deref(),
+            Some("lolcat")
+        );
+        assert_eq!(cluster_for(&proxy, found("unknown.com")), None);
+        assert_eq!(cluster_for(&proxy, ClientHelloSni::Missing), None);
+    }
+
+    #[test]
+    fn connection_metrics() {
+        use crate::metrics::METRICS;
+        use sozu_command::{
+            config::ListenerBuilder,
+            proto::command::{
+                filtered_metrics::Inner, response_content::ContentType, QueryMetricsOptions,
+            },
+        };
+
+        setup_test_logger!();
+        let poll = Poll::new().expect("could not create event loop");
+        let proxy = Rc::new(RefCell::new(TcpProxy::new(
+            poll.registry()
+                .try_clone()
+                .expect("could not clone the mio registry"),
+            SessionManager::new(Slab::with_capacity(10), 10),
+            Rc::new(RefCell::new(BackendMap::new())),
+        )));
+        let pool = Rc::new(RefCell::new(Pool::with_capacity(2, 10, 16384)));
+        proxy
+            .borrow_mut()
+            .add_listener(
+                ListenerBuilder::new_tcp("127.0.0.1:1037")
+                    .to_tcp(None)
+                    .expect("could not create the TCP listener config"),
+                pool.clone(),
+                Token(0),
+            )
+            .expect("could not add the listener");
+        let listener = proxy.borrow().listeners[&Token(0)].clone();
+
+        let server = TcpListener::bind("127.0.0.1:0").expect("could not bind");
+        let _client = TcpStream::connect(server.local_addr().unwrap()).expect("could not connect");
+        let (socket, _) = server.accept().expect("could not accept");
+        socket.set_nonblocking(true).unwrap();
+
+        let front_buffer = pool.borrow_mut().checkout().expect("no front buffer");
+        let back_buffer = pool.borrow_mut().checkout().expect("no back buffer");
+        let mut session = TcpSession::new(
+            back_buffer,
+            None,
+            Some("metered".to_owned()),
+            Duration::seconds(10),
+            Duration::seconds(10),
+            front_buffer,
+            Token(1),
+            listener,
+            None,
+            proxy.clone(),
+            false,
+            MioTcpStream::from_std(socket),
+            Duration::seconds(0),
+        );
+
+        let cluster_metric = |name: &str| {
+            let response = METRICS
+                .with(|metrics| {
+                    metrics.borrow_mut().query(&QueryMetricsOptions {
+                        cluster_ids: vec!["metered".to_owned()],
+                        metric_names: vec![name.to_owned()],
+                        ..Default::default()
+                    })
+                })
+                .expect("could not query the metrics");
+            match response.content_type {
+                Some(ContentType::WorkerMetrics(worker_metrics)) => worker_metrics.clusters
+                    ["metered"]
+                    .cluster
+                    .get(name)
+                    .and_then(|metric| metric.inner.clone()),
+                other => panic!("unexpected metrics response: {other:?}"),
+            }
+        };
+        assert_eq!(
+            cluster_metric("tcp.active_connections"),
+            Some(Inner::Gauge(1))
+        );
+
+        session.metrics.bin = 12;
+        session.metrics.bout = 34;
+        session.close();
+
+        assert_eq!(
+            cluster_metric("tcp.active_connections"),
+            Some(Inner::Gauge(0))
+        );
+        assert_eq!(cluster_metric("tcp.bytes_in"), Some(Inner::Count(12)));
+        assert_eq!(cluster_metric("tcp.bytes_out"), Some(Inner::Count(34)));
+        assert!(matches!(
+            cluster_metric("tcp.connection_time"),
+            Some(Inner::Percentiles(_))
+        ));
+    }
 }